@@ -0,0 +1,31 @@
+/* C interface of get_dir_hash (`ffi` feature). See src/ffi.rs. */
+#ifndef GET_DIR_HASH_H
+#define GET_DIR_HASH_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define GDH_OK 0
+#define GDH_ERR_ARGUMENT (-1)
+#define GDH_ERR_OPTIONS (-2)
+#define GDH_ERR_HASH (-3)
+#define GDH_ERR_BUFFER (-4)
+#define GDH_ERR_PANIC (-5) /* only from a build with `--profile ffi` */
+
+/* Hash the directory `path` with `options_json` (NULL for the defaults) and
+ * write the NUL-terminated lowercase hex digest to `out_buf` (65 bytes for
+ * the default 32-byte digest). Returns GDH_OK or a negative GDH_ERR_* code. */
+int gdh_hash_dir(const char *path, const char *options_json, char *out_buf, size_t out_len);
+
+/* Copy this thread's last error message to `buf` (truncated, NUL-terminated;
+ * `buf` may be NULL) and return its full length. */
+size_t gdh_last_error(char *buf, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
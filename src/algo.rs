@@ -0,0 +1,198 @@
+//! Digest algorithms for multi-digest runs ([`crate::get_dir_digests`]).
+//!
+//! Every other entry point hashes with BLAKE3. A multi-digest run reads each
+//! file once and feeds its bytes to one hasher per algorithm; each algorithm
+//! then frames its own content digests into its own copy of the record
+//! stream, so a SHA-256 root digest covers SHA-256 file digests only.
+//!
+//! [`SelfDescribingDigest`] names the algorithm and format version next to a
+//! root digest, so a stored digest can be checked by a later release.
+
+use crate::FormatVersion;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// A digest algorithm for [`crate::get_dir_digests`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    /// BLAKE3, as used by [`crate::get_dir_hash`].
+    #[default]
+    Blake3,
+    /// SHA-256. Requires the `sha2` feature.
+    Sha256,
+}
+
+impl Algorithm {
+    /// Lowercase name, as accepted by [`FromStr`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Blake3 => "blake3",
+            Algorithm::Sha256 => "sha256",
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = crate::ParseOptionError;
+
+    /// Parses `blake3` or `sha256`, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "blake3" => Ok(Algorithm::Blake3),
+            "sha256" => Ok(Algorithm::Sha256),
+            _ => Err(crate::ParseOptionError::new(s, "blake3 or sha256")),
+        }
+    }
+}
+
+/// A root digest with the algorithm and [`FormatVersion`] that produced it,
+/// written `blake3:v1:<hex>`.
+///
+/// Parsing accepts any case for the algorithm and the hex digits and yields
+/// lowercase hex. BLAKE3 digests may have any length (`output_len`); SHA-256
+/// ones must be 32 bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfDescribingDigest {
+    /// Algorithm of the file and root digests.
+    pub algorithm: Algorithm,
+    /// Record layout the root digest was framed with.
+    pub format_version: FormatVersion,
+    /// Lowercase hex digest.
+    pub hex: String,
+}
+
+impl SelfDescribingDigest {
+    /// `hex`, as computed with `algorithm` and `format_version`.
+    pub fn new(
+        algorithm: Algorithm,
+        format_version: FormatVersion,
+        hex: impl Into<String>,
+    ) -> Self {
+        SelfDescribingDigest {
+            algorithm,
+            format_version,
+            hex: hex.into(),
+        }
+    }
+}
+
+impl fmt::Display for SelfDescribingDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.algorithm.name(),
+            self.format_version.name(),
+            self.hex
+        )
+    }
+}
+
+impl FromStr for SelfDescribingDigest {
+    type Err = ParseDigestError;
+
+    /// Parses `ALGORITHM:vN:HEX`, e.g. `blake3:v1:00ac…`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseDigestError {
+            value: s.to_owned(),
+        };
+        let mut parts = s.splitn(3, ':');
+        let (Some(algorithm), Some(version), Some(hex)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(err());
+        };
+        let algorithm: Algorithm = algorithm.parse().map_err(|_| err())?;
+        // Only the `vN` spelling, so that the form stays unambiguous.
+        let format_version: FormatVersion = match version.strip_prefix('v') {
+            Some(_) => version.parse().map_err(|_| err())?,
+            None => return Err(err()),
+        };
+        let len_ok = match algorithm {
+            Algorithm::Blake3 => !hex.is_empty() && hex.len() % 2 == 0,
+            Algorithm::Sha256 => hex.len() == 64,
+        };
+        if !len_ok || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(err());
+        }
+        Ok(SelfDescribingDigest::new(
+            algorithm,
+            format_version,
+            hex.to_ascii_lowercase(),
+        ))
+    }
+}
+
+/// Error returned when parsing a [`SelfDescribingDigest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDigestError {
+    value: String,
+}
+
+impl fmt::Display for ParseDigestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid digest '{}' (expected ALGORITHM:vN:HEX, e.g. blake3:v1:...)",
+            self.value
+        )
+    }
+}
+
+impl std::error::Error for ParseDigestError {}
+
+/// An incremental hasher for one [`Algorithm`].
+pub(crate) enum Hasher {
+    Blake3(Box<blake3::Hasher>),
+    #[cfg(feature = "sha2")]
+    Sha256(sha2::Sha256),
+}
+
+impl Hasher {
+    /// A fresh hasher; fails if the algorithm was not compiled in.
+    pub(crate) fn new(algorithm: Algorithm) -> io::Result<Hasher> {
+        match algorithm {
+            Algorithm::Blake3 => Ok(Hasher::Blake3(Box::default())),
+            #[cfg(feature = "sha2")]
+            Algorithm::Sha256 => Ok(Hasher::Sha256(sha2::Digest::new())),
+            #[cfg(not(feature = "sha2"))]
+            Algorithm::Sha256 => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "SHA-256 requires the `sha2` feature",
+            )),
+        }
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Blake3(h) => {
+                h.update(bytes);
+            }
+            #[cfg(feature = "sha2")]
+            Hasher::Sha256(h) => sha2::Digest::update(h, bytes),
+        }
+    }
+
+    /// The first `len` bytes of BLAKE3's extendable output; other algorithms
+    /// return their fixed-size digest whatever `len` is.
+    pub(crate) fn finalize_len(self, len: usize) -> Vec<u8> {
+        match self {
+            Hasher::Blake3(h) => {
+                let mut out = vec![0; len];
+                h.finalize_xof().fill(&mut out);
+                out
+            }
+            #[cfg(feature = "sha2")]
+            Hasher::Sha256(h) => sha2::Digest::finalize(h).to_vec(),
+        }
+    }
+
+    pub(crate) fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Blake3(h) => h.finalize().as_bytes().to_vec(),
+            #[cfg(feature = "sha2")]
+            Hasher::Sha256(h) => sha2::Digest::finalize(h).to_vec(),
+        }
+    }
+}
@@ -1,76 +1,2976 @@
 //! Examples:
 //!   get_dir_hash
 //!   get_dir_hash ./mydir --ignore "target/**" --ignore-file .get_dir_hash_ignore --include-metadata
+//!   get_dir_hash ./mydir --config ci/get_dir_hash.toml
+//!   get_dir_hash ./mydir --ignore "target/**" --list
+//!   get_dir_hash ./mydir --explain target/debug/app
+//!   get_dir_hash verify ./mydir --expect blake3:v1:00ac...
+//!   get_dir_hash diff ./mydir --baseline tuesday.snap
 
-use get_dir_hash::{Options, get_dir_hash};
+use get_dir_hash::{
+    Algorithm, CONFIG_FILE_NAME, CancellationToken, Cancelled, CanonicalizeRoot,
+    CaseCollisionPolicy, Checkpoint, Chunking, Config, DumpFormat, FileDigest, FileError,
+    FileErrors, FileOp, FormatVersion, Granularity, IgnoreFile, IgnoreFileFormat, Manifest,
+    ManifestFormat, ModeNormalization, ModificationPolicy, NodeKind, NormalizationForm, Options,
+    OversizePolicy, Ownership, ParseOptionError, Policy, Preset, Progress, ProgressCallback,
+    RunStats, SelfDescribingDigest, Snapshot, SnapshotDiff, SymlinkLoopPolicy, TimedOut, Timestamp,
+    TreeNode, WarningCallback, dump_frames, enumerate_files, explain, get_dir_digests,
+    get_dir_hash, get_dir_hash_per_dir, get_dir_hash_tree, get_dir_hash_with_stats,
+    get_dir_manifest, get_files_hash, oci_layer_digest,
+};
 use pico_args::Arguments;
-use std::{env, ffi::OsString, path::PathBuf, process::ExitCode};
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 use time::OffsetDateTime;
 
 fn print_help() {
     eprintln!(
         "\
 get_dir_hash v{}
-Usage: get_dir_hash [DIR] [--ignore PATTERN]... [--ignore-file FILE]... [--follow-symlinks] [--include-metadata] [--no-dotfile]
+Usage: get_dir_hash [COMMAND] [DIR] [--config FILE] [--no-config] [--preset NAME] [--ignore PATTERN]... [--ignore-file FILE]... [--gitignore FILE]... [--dockerignore FILE]... [--global-gitignore] [--ext LIST]... [--path PATH]... [--modified-since SECS] [--exclude-vcs] [--no-hidden] [--follow-symlinks] [--confine-symlinks] [--symlink-loop-error] [--broken-symlinks POLICY] [--non-utf8-names POLICY] [--special-files POLICY] [--max-file-size BYTES [--oversize POLICY]] [--structure-only] [--concurrent-modification POLICY] [--keep-going] [--fail-on-skip] [--max-files N] [--max-total-bytes BYTES] [--max-depth N] [--deadline SECS] [--include-metadata] [--include-root-name] [--timestamps LIST] [--git-mode] [--ownership MODE] [--windows-attributes] [--windows-acl] [--file-type [--exec-ext LIST]...] [--mtime-granularity G] [--clamp-mtime SECS] [--ignore-case [--case-collision-error]] [--unicode-normalization FORM] [--normalize-newlines] [--relative-to DIR] [--canonicalize-root MODE] [--dedup-hard-links] [--walk-threads N] [--mmap-threshold BYTES] [--parallel-hash-threshold BYTES] [--threads N] [--io-concurrency N] [--throttle BYTES] [--chunking MIN:AVG:MAX] [--no-dotfile] [--stats] [--progress] [--list] [--explain PATH] [--check-config] [--print-files [--manifest-format FORMAT]] [--b3sum FILE] [--dump-frames FORMAT] [--tree] [--oci-layer] [--report-duplicates] [--per-dir DEPTH] [--snapshot FILE] [--baseline FILE] [--checkpoint FILE [--checkpoint-interval SECS]] [--files-from FILE [-0]] [--algorithm LIST] [--length BYTES] [--attestation FILE] [-o FILE] [--tag] [--self-describing] [--format-version N] [--log-format FORMAT] [--completions SHELL]
+Commands (a bare `get_dir_hash DIR` is `hash`):
+  hash                  Print the digest of DIR (the modes below are options of
+                        this command)
+  manifest              Print a `hex  path` line per file, then the digest
+  verify                Check DIR against --expect DIGEST and/or --manifest FILE;
+                        exit status 3 on any mismatch
+  diff                  Print the files added, removed or modified since
+                        --baseline FILE; exit status 3 when anything changed
+  watch                 Print the digest, then again every time it changes
+                        (polling every --interval seconds) until interrupted
+  selftest              Hash a built-in fixture tree under several option sets
+                        and compare with known digests; exit status 3 on any
+                        mismatch (takes no options)
 Options:
   DIR                   Directory to hash (default: .)
-  --ignore PATTERN      Glob pattern to ignore (can repeat)
-  --ignore-file FILE    Load patterns from a file (can repeat)
-  --follow-symlinks     Follow symlinks while walking
-  --include-metadata    Include basic metadata (mode + mtime) in the hash
-  --no-dotfile          Do not auto-load .get_dir_hash_ignore from DIR
-  -h, --help            Show help
+{}Environment (overrides the config file; flags override both):
+  GET_DIR_HASH_CONFIG            Config file to use instead of DIR/.get_dir_hash.toml
+  GET_DIR_HASH_IGNORE            Extra ignore patterns, separated by ';'
+  GET_DIR_HASH_IGNORE_FILE       Extra ignore files, separated like PATH
+  GET_DIR_HASH_EXCLUDE_VCS       true/false
+  GET_DIR_HASH_FOLLOW_SYMLINKS   true/false
+  GET_DIR_HASH_INCLUDE_METADATA  true/false
+  GET_DIR_HASH_BROKEN_SYMLINKS   ignore, error or record
+  GET_DIR_HASH_JOBS              Same as --walk-threads
+  GET_DIR_HASH_ALGO              Same as --algorithm
+  SOURCE_DATE_EPOCH              Same as --clamp-mtime
 ",
-        env!("CARGO_PKG_VERSION")
+        env!("CARGO_PKG_VERSION"),
+        flag_help(),
     );
 }
 
+/// The flag part of `--help`: the label, then the description from column
+/// 25 (on the next line if the label is too long).
+fn flag_help() -> String {
+    let mut out = String::new();
+    for flag in FLAGS {
+        let label = flag.label();
+        let mut lines = flag.help.lines();
+        match label.len() {
+            0..=21 => out += &format!("  {label:<21} {}\n", lines.next().unwrap_or_default()),
+            _ => out += &format!("  {label}\n"),
+        }
+        for line in lines {
+            out += &format!("{:24}{line}\n", "");
+        }
+    }
+    out
+}
+
+/// One command-line flag. [`FLAGS`] is the single list that `--help` and the
+/// `--completions` scripts are generated from; `parse_args` reads the same
+/// names.
+struct Flag {
+    /// Long name, without the leading `--`.
+    long: &'static str,
+    short: Option<char>,
+    /// Placeholder for the flag's value in `--help`, if it takes one.
+    value: Option<&'static str>,
+    complete: Complete,
+    /// May be given more than once.
+    repeats: bool,
+    /// Description; continuation lines are indented by `--help`.
+    help: &'static str,
+}
+
+/// How a flag's value is completed.
+enum Complete {
+    /// Free text (patterns, numbers): nothing to offer.
+    Any,
+    File,
+    Dir,
+    OneOf(&'static [&'static str]),
+}
+
+impl Flag {
+    const fn switch(long: &'static str, help: &'static str) -> Flag {
+        Flag {
+            long,
+            short: None,
+            value: None,
+            complete: Complete::Any,
+            repeats: false,
+            help,
+        }
+    }
+
+    const fn value(
+        long: &'static str,
+        value: &'static str,
+        complete: Complete,
+        help: &'static str,
+    ) -> Flag {
+        Flag {
+            value: Some(value),
+            complete,
+            ..Flag::switch(long, help)
+        }
+    }
+
+    const fn short(self, short: char) -> Flag {
+        Flag {
+            short: Some(short),
+            ..self
+        }
+    }
+
+    const fn repeats(self) -> Flag {
+        Flag {
+            repeats: true,
+            ..self
+        }
+    }
+
+    /// `-0, --null`, `--config FILE` etc., as shown by `--help`.
+    fn label(&self) -> String {
+        let short = self.short.map(|c| format!("-{c}, ")).unwrap_or_default();
+        let value = self.value.map(|v| format!(" {v}")).unwrap_or_default();
+        format!("{short}--{}{value}", self.long)
+    }
+
+    /// The description on one line.
+    fn summary(&self) -> String {
+        self.help.lines().collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// Every flag, in `--help` order.
+const FLAGS: &[Flag] = &[
+    Flag::value(
+        "config",
+        "FILE",
+        Complete::File,
+        "Read options from a TOML file (default: DIR/.get_dir_hash.toml\n\
+                     if present); flags below add to or override it",
+    ),
+    Flag::switch("no-config", "Do not load any config file"),
+    Flag::value(
+        "preset",
+        "NAME",
+        Complete::OneOf(&["portable", "strict", "fast"]),
+        "portable (case-insensitive NFC paths, CRLF read as LF, no\n\
+                     metadata), strict (errors instead of skips, metadata)\n\
+                     or fast (names and sizes only, never read, parallel; a\n\
+                     different digest); applied over the config, under other\n\
+                     flags",
+    ),
+    Flag::value(
+        "ignore",
+        "PATTERN",
+        Complete::Any,
+        "Glob pattern to ignore (can repeat)",
+    )
+    .repeats(),
+    Flag::value(
+        "ignore-file",
+        "FILE",
+        Complete::File,
+        "Load patterns from a file (can repeat)",
+    )
+    .repeats(),
+    Flag::value(
+        "gitignore",
+        "FILE",
+        Complete::File,
+        "Load patterns from a file in .gitignore (or .npmignore)\n\
+                     syntax (can repeat)",
+    )
+    .repeats(),
+    Flag::value(
+        "dockerignore",
+        "FILE",
+        Complete::File,
+        "Load patterns from a file in .dockerignore syntax, to hash\n\
+                     exactly a Docker build context (can repeat)",
+    )
+    .repeats(),
+    Flag::value(
+        "ext",
+        "LIST",
+        Complete::Any,
+        "Hash only files with these extensions, comma-separated\n\
+                     (rs,toml for **/*.rs and **/*.toml; can repeat)",
+    )
+    .repeats(),
+    Flag::value(
+        "path",
+        "PATH",
+        Complete::Any,
+        "Hash only what lies at or below PATH, relative to DIR;\n\
+                     paths stay relative to DIR (can repeat)",
+    )
+    .repeats(),
+    Flag::value(
+        "modified-since",
+        "SECS",
+        Complete::Any,
+        "Hash only entries modified after SECS since the epoch\n\
+                     (the cutoff is hashed too)",
+    ),
+    Flag::switch("exclude-vcs", "Skip .git/, .hg/ and .svn/ directories"),
+    Flag::switch(
+        "global-gitignore",
+        "Also load git's global excludes file (core.excludesFile\n\
+                     or ~/.config/git/ignore) in gitignore format",
+    ),
+    Flag::switch(
+        "no-hidden",
+        "Skip dotfiles and dot-directories (and, on Windows,\n\
+                     entries with the hidden attribute)",
+    ),
+    Flag::switch("follow-symlinks", "Follow symlinks while walking"),
+    Flag::switch(
+        "confine-symlinks",
+        "Follow symlinks, but only those that stay inside DIR;\n\
+                     the others are handled like dangling ones",
+    ),
+    Flag::switch(
+        "symlink-loop-error",
+        "Fail on symlink cycles instead of skipping them",
+    ),
+    Flag::value(
+        "broken-symlinks",
+        "P",
+        Complete::OneOf(&["ignore", "error", "record"]),
+        "Dangling symlinks: ignore (default), error, record",
+    ),
+    Flag::value(
+        "non-utf8-names",
+        "P",
+        Complete::OneOf(&["ignore", "error", "record"]),
+        "Non-UTF-8 paths: record raw bytes (default), ignore, error",
+    ),
+    Flag::value(
+        "special-files",
+        "P",
+        Complete::OneOf(&["ignore", "error", "record"]),
+        "FIFOs, sockets, devices: ignore (default), error, record",
+    ),
+    Flag::value(
+        "max-file-size",
+        "B",
+        Complete::Any,
+        "Apply --oversize to files larger than B bytes",
+    ),
+    Flag::value(
+        "oversize",
+        "P",
+        Complete::OneOf(&["skip", "skip-with-record", "error", "hash"]),
+        "Files over --max-file-size: skip (default when a size is\n\
+                     given), skip-with-record (frame path and size, never\n\
+                     read), error, hash",
+    ),
+    Flag::switch(
+        "structure-only",
+        "Frame every file by path and size without reading it",
+    ),
+    Flag::value(
+        "concurrent-modification",
+        "P",
+        Complete::OneOf(&["ignore", "warn", "retry", "error"]),
+        "Files changing while read: ignore (default), warn, retry,\n\
+                     error",
+    ),
+    Flag::switch(
+        "keep-going",
+        "Go on past files that cannot be read; list them all at\n\
+                     the end (exit status 1)",
+    ),
+    Flag::switch(
+        "fail-on-skip",
+        "Fail on entries that cannot be listed and on dropped\n\
+                     ignore rules instead of warning",
+    ),
+    Flag::value(
+        "max-files",
+        "N",
+        Complete::Any,
+        "Fail once more than N entries would be hashed",
+    ),
+    Flag::value(
+        "max-total-bytes",
+        "B",
+        Complete::Any,
+        "Fail once the files to hash exceed B bytes in total",
+    ),
+    Flag::value(
+        "max-depth",
+        "N",
+        Complete::Any,
+        "Fail before entering a directory more than N levels below\n\
+                     DIR",
+    ),
+    Flag::value(
+        "deadline",
+        "SECS",
+        Complete::Any,
+        "Stop after SECS seconds; with --print-files, print the files\n\
+                     hashed so far (exit status 1)",
+    ),
+    Flag::switch(
+        "include-metadata",
+        "Include basic metadata (mode + mtime) in the hash",
+    ),
+    Flag::switch(
+        "include-root-name",
+        "Also hash the name of DIR itself, so renaming it changes\n\
+                     the digest",
+    ),
+    Flag::switch(
+        "git-mode",
+        "With --include-metadata, frame only git's file modes\n\
+                     (644, 755, symlink) instead of the raw permissions",
+    ),
+    Flag::value(
+        "ownership",
+        "MODE",
+        Complete::OneOf(&["omit", "ids", "names"]),
+        "With --include-metadata, also frame the owner (Unix):\n\
+                     omit (default), ids (uid/gid), names (user/group)",
+    ),
+    Flag::switch(
+        "windows-attributes",
+        "With --include-metadata, also frame the readonly, hidden,\n\
+                     system and archive attributes (Windows)",
+    ),
+    Flag::switch(
+        "windows-acl",
+        "With --include-metadata, also frame the DACL (Windows)",
+    ),
+    Flag::switch(
+        "file-type",
+        "Frame each file's type (file or symlink) and executable\n\
+                     bit, without mtimes or other metadata",
+    ),
+    Flag::value(
+        "exec-ext",
+        "LIST",
+        Complete::Any,
+        "With --file-type, extensions that count as executable\n\
+                     where there is no mode (Windows; can repeat)",
+    )
+    .repeats(),
+    Flag::value(
+        "timestamps",
+        "LIST",
+        Complete::OneOf(&["mtime", "ctime", "birthtime", "none"]),
+        "Timestamps in the metadata frame, comma-separated: mtime\n\
+                     (default), ctime (Unix), birthtime; or none",
+    ),
+    Flag::value(
+        "mtime-granularity",
+        "G",
+        Complete::OneOf(&["nanos", "seconds", "two-seconds"]),
+        "mtime precision for --include-metadata: nanos (default),\n\
+                     seconds, two-seconds (FAT/exFAT)",
+    ),
+    Flag::value(
+        "clamp-mtime",
+        "SECS",
+        Complete::Any,
+        "Clamp mtimes to at most SECS since the epoch\n\
+                     (default: $SOURCE_DATE_EPOCH if set)",
+    ),
+    Flag::switch("ignore-case", "Compare and frame paths case-insensitively"),
+    Flag::switch(
+        "case-collision-error",
+        "With --ignore-case, fail on names that only differ in case",
+    ),
+    Flag::value(
+        "unicode-normalization",
+        "FORM",
+        Complete::OneOf(&["nfc", "nfd"]),
+        "Normalize paths to nfc or nfd before hashing",
+    ),
+    Flag::switch(
+        "normalize-newlines",
+        "Hash file contents with CRLF line endings read as LF",
+    ),
+    Flag::value(
+        "relative-to",
+        "DIR",
+        Complete::Dir,
+        "Frame paths relative to DIR (which must contain the\n\
+                     hashed directory) instead of the hashed directory itself",
+    ),
+    Flag::value(
+        "canonicalize-root",
+        "MODE",
+        Complete::OneOf(&["always", "never", "error-on-failure"]),
+        "Resolve symlinks in DIR and --relative-to: always\n\
+                     (default; the path as given if that fails), never, or\n\
+                     error-on-failure",
+    ),
+    Flag::switch("dedup-hard-links", "Read hard-linked files only once"),
+    Flag::value(
+        "walk-threads",
+        "N",
+        Complete::Any,
+        "List directories on N threads (parallel-walk feature)",
+    ),
+    Flag::value(
+        "mmap-threshold",
+        "B",
+        Complete::Any,
+        "Memory-map files of at least B bytes (mmap feature)",
+    ),
+    Flag::value(
+        "parallel-hash-threshold",
+        "B",
+        Complete::Any,
+        "Hash files of at least B bytes on all cores (rayon feature)",
+    ),
+    Flag::value(
+        "threads",
+        "N",
+        Complete::Any,
+        "Hash a large file on N threads (rayon feature; default:\n\
+                     one per core)",
+    ),
+    Flag::value(
+        "io-concurrency",
+        "N",
+        Complete::Any,
+        "Read up to N files at once (default 1); raise for NVMe,\n\
+                     keep low on network file systems",
+    ),
+    Flag::value(
+        "throttle",
+        "B",
+        Complete::Any,
+        "Read at most B bytes per second (for background scans)",
+    ),
+    Flag::value(
+        "chunking",
+        "MIN:AVG:MAX",
+        Complete::Any,
+        "Frame FastCDC chunk digests per file (cdc feature),\n\
+                     e.g. 16384:65536:262144",
+    ),
+    Flag::switch(
+        "no-dotfile",
+        "Do not auto-load .get_dir_hash_ignore files (DIR and below)",
+    ),
+    Flag::switch(
+        "stats",
+        "Print run statistics (files, bytes, throughput) to stderr",
+    ),
+    Flag::switch(
+        "progress",
+        "Show a progress bar on stderr (enumerates the tree first)",
+    ),
+    Flag::switch(
+        "list",
+        "Print the relative paths that would be hashed (one per\n\
+                     line, in hashing order) instead of hashing; reads no contents",
+    ),
+    Flag::value(
+        "explain",
+        "PATH",
+        Complete::File,
+        "Report whether PATH (relative to DIR) would be hashed and,\n\
+                     if not, which ignore pattern from which source excludes it",
+    ),
+    Flag::switch(
+        "check-config",
+        "Check every ignore pattern, ignore file, extension and\n\
+                     --path of the settings without hashing; list all errors\n\
+                     with their file and line",
+    ),
+    Flag::switch(
+        "print-files",
+        "Print a `hex  path` line per file before the root digest",
+    ),
+    Flag::value(
+        "manifest-format",
+        "F",
+        Complete::OneOf(&["text", "json", "binary"]),
+        "--print-files output: text (default), json, binary (compact,\n\
+                     for millions of files)",
+    ),
+    Flag::value(
+        "b3sum",
+        "FILE",
+        Complete::File,
+        "Also write a `hex  path` line per file to FILE, verifiable\n\
+                     from DIR with `b3sum --check FILE`",
+    ),
+    Flag::value(
+        "dump-frames",
+        "FORMAT",
+        Complete::OneOf(&["hex", "escaped"]),
+        "Write the exact bytes fed into the root hasher to stdout\n\
+                     (hex or escaped; one record per line) before the digest",
+    ),
+    Flag::switch(
+        "tree",
+        "Hash as a Merkle tree: print a `hex  dir/` line per\n\
+                     directory, then the Merkle root instead of the flat digest",
+    ),
+    Flag::switch(
+        "oci-layer",
+        "Print the sha256 DiffID of the tree as a reproducible,\n\
+                     uncompressed OCI layer tar instead of the digest (needs sha2)",
+    ),
+    Flag::switch(
+        "report-duplicates",
+        "Print the sets of files with identical contents and the\n\
+                     bytes their extra copies take, instead of the digest",
+    ),
+    Flag::value(
+        "per-dir",
+        "DEPTH",
+        Complete::Any,
+        "Also print the flat digest of every subdirectory down to\n\
+                     DEPTH levels (`hex  dir/` lines), from the same walk",
+    ),
+    Flag::value(
+        "snapshot",
+        "FILE",
+        Complete::File,
+        "Also save every file's size, mtime and digest to FILE\n\
+                     (binary snapshot for later comparisons)",
+    ),
+    Flag::value(
+        "baseline",
+        "FILE",
+        Complete::File,
+        "Compare against a saved snapshot or --print-files\n\
+                     manifest: print added/removed/modified files; exit\n\
+                     status 3 when anything changed",
+    ),
+    Flag::value(
+        "expect",
+        "DIGEST",
+        Complete::Any,
+        "verify: the expected digest, hex or self-describing\n\
+                     (ALGORITHM:VERSION:HEX, which also selects both)",
+    ),
+    Flag::value(
+        "manifest",
+        "FILE",
+        Complete::File,
+        "verify: a --print-files manifest or snapshot every file\n\
+                     must match",
+    ),
+    Flag::switch(
+        "fail-fast",
+        "verify: stop at the first mismatch instead of listing\n\
+                     them all",
+    ),
+    Flag::value(
+        "interval",
+        "SECS",
+        Complete::Any,
+        "watch: seconds between two scans (default: 2)",
+    ),
+    Flag::value(
+        "checkpoint",
+        "FILE",
+        Complete::File,
+        "Save progress to FILE (every 60s and on Ctrl-C/error) and\n\
+                     resume from it if it exists; removed when the run completes",
+    ),
+    Flag::switch(
+        "checkpoint-interval",
+        "SECS\n\
+                     Seconds between checkpoint saves (default: 60)",
+    ),
+    Flag::value(
+        "files-from",
+        "FILE",
+        Complete::File,
+        "Hash exactly the files listed in FILE (`-` for stdin), one\n\
+                     path per line relative to DIR, instead of walking DIR;\n\
+                     ignore patterns do not apply",
+    ),
+    Flag::switch(
+        "null",
+        "With --files-from, paths are NUL-separated (as printed\n\
+                     by `git ls-files -z` or `find -print0`)",
+    )
+    .short('0'),
+    Flag::value(
+        "length",
+        "BYTES",
+        Complete::Any,
+        "Length of the root digest in bytes (default 32), from\n\
+                     BLAKE3's extendable output",
+    ),
+    Flag::value(
+        "algorithm",
+        "LIST",
+        Complete::OneOf(&["blake3", "sha256"]),
+        "Digest algorithms, comma-separated: blake3 (default) or\n\
+                     sha256 (sha2 feature); with several, every file is read\n\
+                     once and one NAME:DIGEST line is printed per algorithm",
+    ),
+    Flag::value(
+        "attestation",
+        "FILE",
+        Complete::File,
+        "Also write the digest(s) as an in-toto Statement (JSON)\n\
+                     with the directory as subject and the digest-relevant\n\
+                     options and a timestamp as predicate",
+    ),
+    Flag::value(
+        "output",
+        "FILE",
+        Complete::File,
+        "Write the results (digest, manifest, report) to FILE\n\
+                     instead of stdout, replacing it atomically once complete",
+    )
+    .short('o'),
+    Flag::switch(
+        "tag",
+        "Print BSD-style `BLAKE3 (path) = hex` lines instead of\n\
+                     GNU-style `hex  path` ones",
+    ),
+    Flag::switch(
+        "self-describing",
+        "Print the root digest as ALGORITHM:VERSION:HEX (e.g.\n\
+                     blake3:v1:...), readable by later releases",
+    ),
+    Flag::value(
+        "format-version",
+        "N",
+        Complete::OneOf(&["1", "2"]),
+        "Record layout: 1 (default, NUL-separated) or 2\n\
+                     (length-prefixed); digests differ between the two",
+    ),
+    Flag::value(
+        "log-format",
+        "FORMAT",
+        Complete::OneOf(&["text", "json"]),
+        "Diagnostics on stderr: text (default) or json (one object\n\
+                     per line with timestamp, level and message)",
+    ),
+    Flag::value(
+        "completions",
+        "SHELL",
+        Complete::OneOf(&["bash", "zsh", "fish", "powershell"]),
+        "Print a completion script for bash, zsh, fish or powershell",
+    ),
+    Flag::switch("help", "Show help").short('h'),
+];
+
+/// Target of `--completions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            "powershell" => Ok(Shell::Powershell),
+            _ => Err(format!(
+                "unknown shell '{s}' (expected bash, zsh, fish or powershell)"
+            )),
+        }
+    }
+}
+
+/// `--completions`: a completion script for `shell`, generated from
+/// [`FLAGS`].
+fn completions(shell: Shell) -> String {
+    let mut out = String::new();
+    let commands: Vec<&str> = Command::ALL.iter().map(|c| c.name()).collect();
+    let commands = commands.join(" ");
+    let names = |f: &Flag| {
+        let long = format!("--{}", f.long);
+        f.short.map(|c| format!("-{c}")).into_iter().chain([long])
+    };
+    match shell {
+        Shell::Bash => {
+            let mut cases = String::new();
+            for f in FLAGS.iter().filter(|f| f.value.is_some()) {
+                let action = match f.complete {
+                    Complete::Any => "return".to_owned(),
+                    Complete::File => r#"COMPREPLY=($(compgen -f -- "$cur")); return"#.to_owned(),
+                    Complete::Dir => r#"COMPREPLY=($(compgen -d -- "$cur")); return"#.to_owned(),
+                    Complete::OneOf(words) => format!(
+                        r#"COMPREPLY=($(compgen -W "{}" -- "$cur")); return"#,
+                        words.join(" ")
+                    ),
+                };
+                let pattern = names(f).collect::<Vec<_>>().join("|");
+                cases += &format!("        {pattern}) {action} ;;\n");
+            }
+            let all: Vec<String> = FLAGS.iter().flat_map(names).collect();
+            out += &format!(
+                r#"_get_dir_hash() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    case "$prev" in
+{cases}    esac
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "{}" -- "$cur"))
+    elif [[ $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=($(compgen -W "{commands}" -- "$cur") $(compgen -d -- "$cur"))
+    else
+        COMPREPLY=($(compgen -d -- "$cur"))
+    fi
+}}
+complete -o filenames -F _get_dir_hash get_dir_hash
+"#,
+                all.join(" ")
+            );
+        }
+        Shell::Zsh => {
+            out += "#compdef get_dir_hash\n\n_arguments -s \\\n";
+            for f in FLAGS {
+                let help = f.summary().replace('\\', "\\\\").replace('\'', "'\\''");
+                let help = help.replace('[', "\\[").replace(']', "\\]");
+                let action = match (f.value, &f.complete) {
+                    (None, _) => String::new(),
+                    (Some(v), Complete::Any) => format!(":{v}: "),
+                    (Some(v), Complete::File) => format!(":{v}:_files"),
+                    (Some(v), Complete::Dir) => format!(":{v}:_files -/"),
+                    (Some(v), Complete::OneOf(words)) => format!(":{v}:({})", words.join(" ")),
+                };
+                let repeat = if f.repeats { "*" } else { "" };
+                for name in names(f) {
+                    out += &format!("  '{repeat}{name}[{help}]{action}' \\\n");
+                }
+            }
+            out += &format!(
+                "  '1:COMMAND or DIR:{{_alternative \"commands:command:({commands})\" \"dirs:directory:_files -/\"}}'\n"
+            );
+        }
+        Shell::Fish => {
+            out += "complete -c get_dir_hash -f -a '(__fish_complete_directories)'\n";
+            out +=
+                &format!("complete -c get_dir_hash -n __fish_use_subcommand -f -a '{commands}'\n");
+            for f in FLAGS {
+                let help = f.summary().replace('\\', "\\\\").replace('\'', "\\'");
+                let mut line = format!("complete -c get_dir_hash -l {}", f.long);
+                if let Some(c) = f.short {
+                    line += &format!(" -s {c}");
+                }
+                line += &format!(" -d '{help}'");
+                match (f.value, &f.complete) {
+                    (None, _) => {}
+                    (Some(_), Complete::Any) => line += " -r -f",
+                    (Some(_), Complete::File) => line += " -r -F",
+                    (Some(_), Complete::Dir) => line += " -r -f -a '(__fish_complete_directories)'",
+                    (Some(_), Complete::OneOf(words)) => {
+                        line += &format!(" -r -f -a '{}'", words.join(" "))
+                    }
+                }
+                out += &line;
+                out += "\n";
+            }
+        }
+        Shell::Powershell => {
+            let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+            let mut flags = String::new();
+            let mut values = String::new();
+            for f in FLAGS {
+                for name in names(f) {
+                    flags += &format!("        @({}, {})\n", quote(&name), quote(&f.summary()));
+                    if let Complete::OneOf(words) = f.complete {
+                        let words: Vec<String> = words.iter().map(|w| quote(w)).collect();
+                        values += &format!("        {} = @({})\n", quote(&name), words.join(", "));
+                    }
+                }
+            }
+            out += &format!(
+                r#"Register-ArgumentCompleter -Native -CommandName get_dir_hash, get_dir_hash.exe -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $flags = @(
+{flags}    )
+    $values = @{{
+{values}    }}
+    $elements = $commandAst.CommandElements
+    $prev = if ($wordToComplete) {{ "$($elements[-2])" }} else {{ "$($elements[-1])" }}
+    if ($values.ContainsKey($prev)) {{
+        $values[$prev] | Where-Object {{ $_ -like "$wordToComplete*" }} | ForEach-Object {{
+            [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+        }}
+        return
+    }}
+    if ($elements.Count -le 2 -and $wordToComplete -notlike '-*') {{
+        '{commands}'.Split(' ') | Where-Object {{ $_ -like "$wordToComplete*" }} | ForEach-Object {{
+            [System.Management.Automation.CompletionResult]::new($_, $_, 'Command', $_)
+        }}
+    }}
+    if ($wordToComplete -like '-*') {{
+        $flags | Where-Object {{ $_[0] -like "$wordToComplete*" }} | ForEach-Object {{
+            [System.Management.Automation.CompletionResult]::new($_[0], $_[0], 'ParameterName', $_[1])
+        }}
+    }}
+}}
+"#
+            );
+        }
+    }
+    out
+}
+
+/// Subcommand, given as the first argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Hash,
+    Manifest,
+    Verify,
+    Diff,
+    Watch,
+    Selftest,
+}
+
+impl Command {
+    const ALL: [Command; 6] = [
+        Command::Hash,
+        Command::Manifest,
+        Command::Verify,
+        Command::Diff,
+        Command::Watch,
+        Command::Selftest,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Command::Hash => "hash",
+            Command::Manifest => "manifest",
+            Command::Verify => "verify",
+            Command::Diff => "diff",
+            Command::Watch => "watch",
+            Command::Selftest => "selftest",
+        }
+    }
+
+    /// The command named by the first argument, if any. A directory that
+    /// happens to share a command's name is reachable as `./NAME`.
+    fn from_arg(arg: &OsString) -> Option<Command> {
+        Command::ALL
+            .into_iter()
+            .find(|c| arg.to_str() == Some(c.name()))
+    }
+}
+
+/// Parsed command line.
+struct Cli {
+    command: Command,
+    dir: PathBuf,
+    /// Explicit `--config` file.
+    config: Option<PathBuf>,
+    no_config: bool,
+    flags: Flags,
+    stats: bool,
+    list: bool,
+    /// `--explain` path.
+    explain: Option<PathBuf>,
+    check_config: bool,
+    print_files: bool,
+    manifest_format: Option<ManifestFormat>,
+    /// `--b3sum` manifest file.
+    b3sum: Option<PathBuf>,
+    dump_frames: Option<DumpFormat>,
+    tree: bool,
+    oci_layer: bool,
+    report_duplicates: bool,
+    /// `--per-dir` depth.
+    per_dir: Option<usize>,
+    /// `--snapshot` output file.
+    snapshot: Option<PathBuf>,
+    /// `--baseline` snapshot or manifest to compare against.
+    baseline: Option<PathBuf>,
+    /// `--files-from` list (`-` for stdin).
+    files_from: Option<PathBuf>,
+    /// `-0`: the list is NUL-separated.
+    null: bool,
+    /// `--algorithm` list.
+    algorithms: Option<Vec<Algorithm>>,
+    /// `--preset`, applied over the config file and environment.
+    preset: Option<Preset>,
+    /// `--attestation` output file.
+    attestation: Option<PathBuf>,
+    /// `--tag`: BSD-style checksum lines.
+    tag: bool,
+    /// `--self-describing`: `blake3:v1:<hex>` root digests.
+    self_describing: bool,
+    /// `verify --expect` digest.
+    expect: Option<String>,
+    /// `verify --manifest` file.
+    manifest: Option<PathBuf>,
+    /// `verify --fail-fast`.
+    fail_fast: bool,
+    /// `watch --interval`, in seconds.
+    interval: Option<u64>,
+    /// `--output` file for what would go to stdout.
+    output: Option<PathBuf>,
+}
+
+/// Option flags given on the command line; they take precedence over the
+/// config file.
+struct Flags {
+    format_version: Option<FormatVersion>,
+    length: Option<usize>,
+    ignores: Vec<String>,
+    ignore_files: Vec<IgnoreFile>,
+    extensions: Vec<String>,
+    pathspecs: Vec<String>,
+    modified_since: Option<u64>,
+    exclude_vcs: bool,
+    global_gitignore: bool,
+    no_hidden: bool,
+    follow: bool,
+    confine: bool,
+    loop_error: bool,
+    broken: Option<Policy>,
+    non_utf8: Option<Policy>,
+    special: Option<Policy>,
+    max_file_size: Option<u64>,
+    oversize: Option<OversizePolicy>,
+    structure_only: bool,
+    normalize_newlines: bool,
+    modification: Option<ModificationPolicy>,
+    keep_going: bool,
+    fail_on_skip: bool,
+    max_files: Option<u64>,
+    max_depth: Option<u64>,
+    deadline: Option<u64>,
+    max_total_bytes: Option<u64>,
+    include_meta: bool,
+    root_name: bool,
+    git_mode: bool,
+    ownership: Option<Ownership>,
+    windows_attributes: bool,
+    windows_acl: bool,
+    file_type: bool,
+    exec_extensions: Vec<String>,
+    timestamps: Option<Vec<Timestamp>>,
+    mtime_granularity: Option<Granularity>,
+    clamp_mtime: Option<u64>,
+    ignore_case: bool,
+    case_collision_error: bool,
+    normalization: Option<NormalizationForm>,
+    relative_to: Option<PathBuf>,
+    canonicalize_root: Option<CanonicalizeRoot>,
+    no_dot: bool,
+    dedup_links: bool,
+    walk_threads: Option<usize>,
+    mmap_threshold: Option<u64>,
+    parallel_hash_threshold: Option<u64>,
+    threads: Option<usize>,
+    io_concurrency: Option<usize>,
+    throttle: Option<u64>,
+    chunking: Option<Chunking>,
+    checkpoint: Option<PathBuf>,
+    checkpoint_interval: Option<u64>,
+    progress: bool,
+}
+
+impl Flags {
+    /// Layer the flags over `base`: switches can only turn behavior on,
+    /// repeated options append, valued options replace.
+    fn apply(self, base: Options) -> Options {
+        let mut ignore_patterns = base.ignore_patterns;
+        ignore_patterns.extend(self.ignores);
+        let mut ignore_files = base.ignore_files;
+        ignore_files.extend(self.ignore_files);
+        let mut extensions = base.extensions;
+        extensions.extend(self.extensions);
+        let mut executable_extensions = base.executable_extensions;
+        executable_extensions.extend(self.exec_extensions);
+        let mut pathspecs = base.pathspecs;
+        pathspecs.extend(self.pathspecs);
+
+        // Build options in one go (no field reassignments)
+        Options {
+            format_version: self.format_version.unwrap_or(base.format_version),
+            output_len: self.length.unwrap_or(base.output_len),
+            exclude_vcs_dirs: base.exclude_vcs_dirs || self.exclude_vcs,
+            global_gitignore: base.global_gitignore || self.global_gitignore,
+            skip_hidden: base.skip_hidden || self.no_hidden,
+            follow_symlinks: base.follow_symlinks || self.follow || self.confine,
+            confine_symlinks: base.confine_symlinks || self.confine,
+            include_metadata: base.include_metadata || self.include_meta,
+            include_root_name: base.include_root_name || self.root_name,
+            mode_normalization: if self.git_mode {
+                ModeNormalization::Git
+            } else {
+                base.mode_normalization
+            },
+            ownership: self.ownership.unwrap_or(base.ownership),
+            windows_attributes: base.windows_attributes || self.windows_attributes,
+            windows_acl: base.windows_acl || self.windows_acl,
+            include_file_type: base.include_file_type || self.file_type,
+            executable_extensions,
+            timestamps: self.timestamps.unwrap_or(base.timestamps),
+            mtime_granularity: self.mtime_granularity.unwrap_or(base.mtime_granularity),
+            clamp_mtime: self.clamp_mtime.map(epoch_secs).or(base.clamp_mtime),
+            case_sensitive_paths: base.case_sensitive_paths && !self.ignore_case,
+            case_collisions: if self.case_collision_error {
+                CaseCollisionPolicy::Error
+            } else {
+                base.case_collisions
+            },
+            unicode_normalization: self.normalization.or(base.unicode_normalization),
+            path_base: self.relative_to.or(base.path_base),
+            canonicalize_root: self.canonicalize_root.unwrap_or(base.canonicalize_root),
+            ignore_patterns,
+            ignore_files,
+            extensions,
+            pathspecs,
+            modified_since: self.modified_since.map(epoch_secs).or(base.modified_since),
+            load_dot_get_dir_hash_ignore: base.load_dot_get_dir_hash_ignore && !self.no_dot,
+            dedup_hard_links: base.dedup_hard_links || self.dedup_links,
+            symlink_loops: if self.loop_error {
+                SymlinkLoopPolicy::Error
+            } else {
+                base.symlink_loops
+            },
+            broken_symlinks: self.broken.unwrap_or(base.broken_symlinks),
+            non_utf8_names: self.non_utf8.unwrap_or(base.non_utf8_names),
+            special_files: self.special.unwrap_or(base.special_files),
+            max_file_size: self.max_file_size.or(base.max_file_size),
+            // A size limit on the command line means something: skip by default.
+            oversize_policy: match (self.oversize, self.max_file_size, base.oversize_policy) {
+                (Some(policy), _, _) => policy,
+                (None, Some(_), OversizePolicy::Hash) => OversizePolicy::Skip,
+                (None, _, policy) => policy,
+            },
+            structure_only: base.structure_only || self.structure_only,
+            normalize_newlines: base.normalize_newlines || self.normalize_newlines,
+            concurrent_modification: (self.modification).unwrap_or(base.concurrent_modification),
+            keep_going: base.keep_going || self.keep_going,
+            fail_on_skip: base.fail_on_skip || self.fail_on_skip,
+            max_files: self.max_files.or(base.max_files),
+            max_depth: self.max_depth.or(base.max_depth),
+            deadline: self.deadline.map(Duration::from_secs).or(base.deadline),
+            max_total_bytes: self.max_total_bytes.or(base.max_total_bytes),
+            walk_threads: self.walk_threads.unwrap_or(base.walk_threads),
+            mmap_threshold: self.mmap_threshold.or(base.mmap_threshold),
+            parallel_hash_threshold: self
+                .parallel_hash_threshold
+                .or(base.parallel_hash_threshold),
+            threads: self.threads.unwrap_or(base.threads),
+            io_concurrency: self.io_concurrency.unwrap_or(base.io_concurrency),
+            max_read_bytes_per_sec: self.throttle.or(base.max_read_bytes_per_sec),
+            chunking: self.chunking.or(base.chunking),
+            checkpoint: self
+                .checkpoint
+                .map(|path| {
+                    let mut cp = Checkpoint::new(path);
+                    if let Some(secs) = self.checkpoint_interval {
+                        cp.interval = Duration::from_secs(secs);
+                    }
+                    cp
+                })
+                .or(base.checkpoint),
+            progress: self.progress.then(progress_bar),
+            on_warning: Some(WarningCallback::new(|w| {
+                log_warn(w, &[("path", json(&w.path.display().to_string()))])
+            })),
+            ..base // keep other settings (e.g., progress, cancel)
+        }
+    }
+}
+
+fn parse_args(command: Command, pargs: &mut Arguments) -> Result<Cli, pico_args::Error> {
+    let config: Option<PathBuf> = pargs.opt_value_from_str("--config")?;
+    let no_config = pargs.contains("--no-config");
+    let flags = Flags {
+        format_version: pargs.opt_value_from_str("--format-version")?,
+        length: pargs.opt_value_from_str("--length")?,
+        // Collect repeated options
+        ignores: pargs.values_from_str("--ignore")?,
+        ignore_files: ignore_files(pargs)?,
+        extensions: (pargs.values_from_str::<_, String>("--ext")?.iter())
+            .flat_map(|list| list.split(','))
+            .filter(|ext| !ext.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        pathspecs: pargs.values_from_str("--path")?,
+        modified_since: pargs.opt_value_from_str("--modified-since")?,
+        exclude_vcs: pargs.contains("--exclude-vcs"),
+        global_gitignore: pargs.contains("--global-gitignore"),
+        no_hidden: pargs.contains("--no-hidden"),
+        follow: pargs.contains("--follow-symlinks"),
+        confine: pargs.contains("--confine-symlinks"),
+        loop_error: pargs.contains("--symlink-loop-error"),
+        broken: pargs.opt_value_from_str("--broken-symlinks")?,
+        non_utf8: pargs.opt_value_from_str("--non-utf8-names")?,
+        special: pargs.opt_value_from_str("--special-files")?,
+        max_file_size: pargs.opt_value_from_str("--max-file-size")?,
+        oversize: pargs.opt_value_from_str("--oversize")?,
+        structure_only: pargs.contains("--structure-only"),
+        normalize_newlines: pargs.contains("--normalize-newlines"),
+        modification: pargs.opt_value_from_str("--concurrent-modification")?,
+        keep_going: pargs.contains("--keep-going"),
+        fail_on_skip: pargs.contains("--fail-on-skip"),
+        max_files: pargs.opt_value_from_str("--max-files")?,
+        max_depth: pargs.opt_value_from_str("--max-depth")?,
+        deadline: pargs.opt_value_from_str("--deadline")?,
+        max_total_bytes: pargs.opt_value_from_str("--max-total-bytes")?,
+        include_meta: pargs.contains("--include-metadata"),
+        root_name: pargs.contains("--include-root-name"),
+        git_mode: pargs.contains("--git-mode"),
+        ownership: pargs.opt_value_from_str("--ownership")?,
+        windows_attributes: pargs.contains("--windows-attributes"),
+        windows_acl: pargs.contains("--windows-acl"),
+        file_type: pargs.contains("--file-type"),
+        exec_extensions: (pargs.values_from_str::<_, String>("--exec-ext")?.iter())
+            .flat_map(|list| list.split(','))
+            .filter(|ext| !ext.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        timestamps: pargs.opt_value_from_fn("--timestamps", parse_timestamps)?,
+        mtime_granularity: pargs.opt_value_from_str("--mtime-granularity")?,
+        clamp_mtime: pargs.opt_value_from_str("--clamp-mtime")?,
+        ignore_case: pargs.contains("--ignore-case"),
+        case_collision_error: pargs.contains("--case-collision-error"),
+        normalization: pargs.opt_value_from_str("--unicode-normalization")?,
+        relative_to: pargs.opt_value_from_str("--relative-to")?,
+        canonicalize_root: pargs.opt_value_from_str("--canonicalize-root")?,
+        no_dot: pargs.contains("--no-dotfile"),
+        dedup_links: pargs.contains("--dedup-hard-links"),
+        walk_threads: pargs.opt_value_from_str("--walk-threads")?,
+        mmap_threshold: pargs.opt_value_from_str("--mmap-threshold")?,
+        parallel_hash_threshold: pargs.opt_value_from_str("--parallel-hash-threshold")?,
+        threads: pargs.opt_value_from_str("--threads")?,
+        io_concurrency: pargs.opt_value_from_str("--io-concurrency")?,
+        throttle: pargs.opt_value_from_str("--throttle")?,
+        chunking: pargs.opt_value_from_str("--chunking")?,
+        checkpoint: pargs.opt_value_from_str("--checkpoint")?,
+        checkpoint_interval: pargs.opt_value_from_str("--checkpoint-interval")?,
+        progress: pargs.contains("--progress"),
+    };
+    let stats = pargs.contains("--stats");
+    let list = pargs.contains("--list");
+    let explain = pargs.opt_value_from_str("--explain")?;
+    let check_config = pargs.contains("--check-config");
+    let print_files = pargs.contains("--print-files") || command == Command::Manifest;
+    let manifest_format = pargs.opt_value_from_str("--manifest-format")?;
+    let b3sum = pargs.opt_value_from_str("--b3sum")?;
+    let dump_frames = pargs.opt_value_from_str("--dump-frames")?;
+    let tree = pargs.contains("--tree");
+    let oci_layer = pargs.contains("--oci-layer");
+    let report_duplicates = pargs.contains("--report-duplicates");
+    let per_dir = pargs.opt_value_from_str("--per-dir")?;
+    let snapshot = pargs.opt_value_from_str("--snapshot")?;
+    let baseline = pargs.opt_value_from_str("--baseline")?;
+    let files_from = pargs.opt_value_from_str("--files-from")?;
+    let null = pargs.contains(["-0", "--null"]);
+    let algorithms = pargs.opt_value_from_fn("--algorithm", parse_algorithms)?;
+    let preset = pargs.opt_value_from_str("--preset")?;
+    let attestation = pargs.opt_value_from_str("--attestation")?;
+    let tag = pargs.contains("--tag");
+    let self_describing = pargs.contains("--self-describing");
+    let expect = pargs.opt_value_from_str("--expect")?;
+    let manifest = pargs.opt_value_from_str("--manifest")?;
+    let fail_fast = pargs.contains("--fail-fast");
+    let interval = pargs.opt_value_from_str("--interval")?;
+    let output = pargs.opt_value_from_str(["-o", "--output"])?;
+
+    // Positional directory argument (default to "."), parsed after all flags
+    // so it may appear anywhere on the command line.
+    let dir: PathBuf = pargs
+        .opt_free_from_str()?
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    Ok(Cli {
+        command,
+        dir,
+        config,
+        no_config,
+        flags,
+        stats,
+        list,
+        explain,
+        check_config,
+        print_files,
+        manifest_format,
+        b3sum,
+        dump_frames,
+        tree,
+        oci_layer,
+        report_duplicates,
+        per_dir,
+        snapshot,
+        baseline,
+        files_from,
+        null,
+        algorithms,
+        preset,
+        attestation,
+        tag,
+        self_describing,
+        expect,
+        manifest,
+        fail_fast,
+        interval,
+        output,
+    })
+}
+
+/// Reject flags that do not belong to `cli.command`. `manifest` and `diff`
+/// are `hash` with `--print-files` or `--baseline` implied, so they take
+/// `hash`'s output flags too; `verify` and `watch` only take the options that
+/// shape the digest.
+fn check_command(cli: &Cli) -> Result<(), String> {
+    let command = cli.command;
+    let own = [
+        ("--expect", cli.expect.is_some(), Command::Verify),
+        ("--manifest", cli.manifest.is_some(), Command::Verify),
+        ("--fail-fast", cli.fail_fast, Command::Verify),
+        ("--interval", cli.interval.is_some(), Command::Watch),
+    ];
+    for (flag, given, owner) in own {
+        if given && command != owner {
+            return Err(format!("{flag} is an option of `{}`", owner.name()));
+        }
+    }
+    let modes = [
+        ("--list", cli.list),
+        ("--explain", cli.explain.is_some()),
+        ("--check-config", cli.check_config),
+        ("--print-files", cli.print_files),
+        ("--manifest-format", cli.manifest_format.is_some()),
+        ("--b3sum", cli.b3sum.is_some()),
+        ("--dump-frames", cli.dump_frames.is_some()),
+        ("--tree", cli.tree),
+        ("--oci-layer", cli.oci_layer),
+        ("--report-duplicates", cli.report_duplicates),
+        ("--per-dir", cli.per_dir.is_some()),
+        ("--snapshot", cli.snapshot.is_some()),
+        ("--baseline", cli.baseline.is_some()),
+        ("--files-from", cli.files_from.is_some()),
+        ("--attestation", cli.attestation.is_some()),
+        ("--tag", cli.tag),
+        ("--self-describing", cli.self_describing),
+        ("--stats", cli.stats),
+    ];
+    match command {
+        Command::Hash => {
+            // Modes with an output of their own.
+            let own = [
+                "--check-config",
+                "--oci-layer",
+                "--report-duplicates",
+                "--per-dir",
+            ];
+            let given = || modes.iter().filter(|(_, given)| *given);
+            match given().find(|(flag, _)| own.contains(flag)) {
+                Some((mode, _)) => match given().find(|(flag, _)| flag != mode) {
+                    Some((flag, _)) => Err(format!("{mode} cannot be combined with {flag}")),
+                    None => Ok(()),
+                },
+                None => Ok(()),
+            }
+        }
+        Command::Manifest | Command::Diff => {
+            match modes.iter().find(|(flag, given)| {
+                *given
+                    && matches!(
+                        *flag,
+                        "--list"
+                            | "--explain"
+                            | "--check-config"
+                            | "--tree"
+                            | "--oci-layer"
+                            | "--report-duplicates"
+                            | "--per-dir"
+                            | "--dump-frames"
+                    )
+            }) {
+                Some((flag, _)) => Err(format!(
+                    "`{}` cannot be combined with {flag}",
+                    command.name()
+                )),
+                None if command == Command::Diff && cli.baseline.is_none() => {
+                    Err("`diff` needs --baseline FILE".to_owned())
+                }
+                None => Ok(()),
+            }
+        }
+        Command::Verify | Command::Watch | Command::Selftest => {
+            if let Some((flag, _)) = modes.iter().find(|(_, given)| *given) {
+                return Err(format!(
+                    "`{}` cannot be combined with {flag}",
+                    command.name()
+                ));
+            }
+            if command == Command::Verify && cli.expect.is_none() && cli.manifest.is_none() {
+                return Err("`verify` needs --expect DIGEST or --manifest FILE".to_owned());
+            }
+            Ok(())
+        }
+    }
+}
+
+/// `verify`: compare `dir` against `--expect` and `--manifest` (`expect`
+/// and `manifest`). Prints one
+/// line per mismatch and `dir: OK` or `dir: FAILED`; exit status 3 when
+/// anything differs. With `--fail-fast`, checking stops at the first
+/// mismatch.
+fn verify(
+    dir: &Path,
+    expect: Option<&str>,
+    manifest: Option<&Path>,
+    fail_fast: bool,
+    opts: &Options,
+    out: &mut Output,
+) -> ExitCode {
+    let root = dir.display().to_string();
+    let mut ok = true;
+    if let Some(expect) = expect {
+        // A self-describing digest picks the algorithm, version and length.
+        let (algorithm, opts, want) = match expect.parse::<SelfDescribingDigest>() {
+            Ok(d) => {
+                let opts = Options {
+                    format_version: d.format_version,
+                    output_len: d.hex.len() / 2,
+                    ..opts.clone()
+                };
+                (d.algorithm, opts, d.hex)
+            }
+            Err(e) if expect.contains(':') => {
+                log_usage(e);
+                return ExitCode::from(2);
+            }
+            Err(_) => (Algorithm::Blake3, opts.clone(), expect.to_ascii_lowercase()),
+        };
+        match get_dir_digests(dir, &[algorithm], &opts) {
+            Ok(got) if got[0] == want => {}
+            Ok(got) => {
+                out.line(format_args!("{root}: digest {} instead of {want}", got[0]));
+                ok = false;
+            }
+            Err(e) => return run_failed(e, opts.progress.is_some()),
+        }
+    }
+    if let Some(path) = manifest.filter(|_| ok || !fail_fast) {
+        let expected = match Snapshot::load(path) {
+            Ok(expected) => expected,
+            Err(e) => {
+                log_usage(format_args!("manifest: {e}"));
+                return ExitCode::from(2);
+            }
+        };
+        let changes = match expected.verify(dir, opts, fail_fast) {
+            Ok(changes) => changes,
+            Err(e) => return run_failed(e, opts.progress.is_some()),
+        };
+        for (label, paths) in [
+            ("FAILED", &changes.modified),
+            ("MISSING", &changes.removed),
+            ("UNEXPECTED", &changes.added),
+        ] {
+            for path in paths {
+                out.line(format_args!("{path}: {label}"));
+            }
+        }
+        ok &= changes.is_empty();
+    }
+    out.line(format_args!("{root}: {}", if ok { "OK" } else { "FAILED" }));
+    if let Err(e) = out.commit() {
+        return output_failed(e);
+    }
+    match ok {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::from(EXIT_CHANGED),
+    }
+}
+
+/// `watch`: print the digest line now and after every change, rescanning
+/// every `--interval` seconds until interrupted. Failed scans (say, a file
+/// removed mid-walk) are reported and retried. With `--output`, the file
+/// is replaced on every change and always holds the latest digest.
+fn watch(dir: &Path, interval: Option<u64>, opts: &Options, out: &mut Output) -> ExitCode {
+    let interval = Duration::from_secs(interval.unwrap_or(2));
+    let cancelled = || opts.cancel.as_ref().is_some_and(|t| t.is_cancelled());
+    let root = dir.display().to_string();
+    let mut last: Option<String> = None;
+    loop {
+        match get_dir_hash_with_stats(dir, opts) {
+            Ok(h) if last.as_ref() != Some(&h.hex) => {
+                out.line(checksum_line(None, &h.hex, &root));
+                if let Err(e) = out.commit() {
+                    return output_failed(e);
+                }
+                last = Some(h.hex);
+            }
+            Ok(_) => {}
+            Err(e) if Cancelled::find(&e).is_some() => return ExitCode::SUCCESS,
+            Err(e) => log_error(e),
+        }
+        let start = Instant::now();
+        while start.elapsed() < interval {
+            if cancelled() {
+                return ExitCode::SUCCESS;
+            }
+            std::thread::sleep(Duration::from_millis(100).min(interval));
+        }
+    }
+}
+
+/// Where results go: stdout, or with `--output` a buffer that replaces the
+/// file atomically on [`Output::commit`], so that readers of the file never
+/// see half of it, even if the run is interrupted.
+enum Output {
+    Stdout(std::io::BufWriter<std::io::Stdout>),
+    File { path: PathBuf, buf: Vec<u8> },
+}
+
+impl Output {
+    fn new(file: Option<PathBuf>) -> Output {
+        match file {
+            Some(path) => Output::File {
+                path,
+                buf: Vec::new(),
+            },
+            None => Output::Stdout(std::io::BufWriter::new(std::io::stdout())),
+        }
+    }
+
+    /// Print one line; a stdout error (such as a closed pipe) is ignored.
+    fn line(&mut self, line: impl fmt::Display) {
+        let _ = writeln!(self, "{line}");
+    }
+
+    /// Flush stdout, or replace the `--output` file with everything written
+    /// since the last commit.
+    fn commit(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => {
+                let _ = stdout.flush();
+                Ok(())
+            }
+            Output::File { path, buf } => {
+                write_atomic(path, buf)?;
+                buf.clear();
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Stdout(stdout) => stdout.write(bytes),
+            Output::File { buf, .. } => buf.write(bytes),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => stdout.flush(),
+            Output::File { .. } => Ok(()),
+        }
+    }
+}
+
+/// Replace `path` with `bytes` through a temporary file in the same
+/// directory and a rename, so the file is either old or complete.
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{name}.{}.tmp", std::process::id()));
+    let res = std::fs::File::create(&tmp).and_then(|mut file| {
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&tmp, path)
+    });
+    if res.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    res
+}
+
+/// Report a failure to write `--output`.
+fn output_failed(e: std::io::Error) -> ExitCode {
+    log_error(format_args!("output: {e}"));
+    ExitCode::from(1)
+}
+
+/// Human-readable `--baseline` report; a summary goes to stderr.
+fn print_changes(changes: &SnapshotDiff, out: &mut Output) {
+    for (label, paths) in [
+        ("added", &changes.added),
+        ("removed", &changes.removed),
+        ("modified", &changes.modified),
+    ] {
+        for path in paths {
+            out.line(format_args!("{label:<9}{path}"));
+        }
+    }
+    let text = if changes.is_empty() {
+        "no changes since baseline".to_owned()
+    } else {
+        format!(
+            "{} added, {} removed, {} modified since baseline",
+            changes.added.len(),
+            changes.removed.len(),
+            changes.modified.len()
+        )
+    };
+    log(
+        "info",
+        &text,
+        "baseline",
+        &[
+            ("added", changes.added.len().to_string()),
+            ("removed", changes.removed.len().to_string()),
+            ("modified", changes.modified.len().to_string()),
+        ],
+    );
+}
+
+/// One `hex  path/` line per directory below `node`, depth first.
+fn dir_lines(node: &TreeNode, prefix: &str, out: &mut Vec<FileDigest>) {
+    for child in node.children.iter().filter(|c| c.kind == NodeKind::Dir) {
+        let path = format!("{prefix}{}/", child.name);
+        out.push(FileDigest {
+            path: path.clone(),
+            hex: child.hex.clone(),
+            chunks: Vec::new(),
+        });
+        dir_lines(child, &path, out);
+    }
+}
+
+/// Config from `--config` (or `GET_DIR_HASH_CONFIG`), or from
+/// `DIR/.get_dir_hash.toml` when present.
+fn base_config(cli: &Cli) -> std::io::Result<Config> {
+    if cli.no_config {
+        return Ok(Config::default());
+    }
+    match cli
+        .config
+        .clone()
+        .or_else(|| env::var_os("GET_DIR_HASH_CONFIG").map(PathBuf::from))
+    {
+        Some(path) => Config::from_file(path),
+        None => {
+            let auto = cli.dir.join(CONFIG_FILE_NAME);
+            if auto.is_file() {
+                Config::from_file(auto)
+            } else {
+                Ok(Config::default())
+            }
+        }
+    }
+}
+
+/// Layer `GET_DIR_HASH_*` environment variables over the config file.
+/// Command-line flags are applied afterwards and win over both.
+fn env_options(base: Options) -> Result<Options, String> {
+    let mut ignore_patterns = base.ignore_patterns;
+    if let Some(v) = env_var("GET_DIR_HASH_IGNORE")? {
+        ignore_patterns.extend(v.split(';').filter(|p| !p.is_empty()).map(str::to_owned));
+    }
+    let mut ignore_files = base.ignore_files;
+    if let Some(v) = env::var_os("GET_DIR_HASH_IGNORE_FILE") {
+        ignore_files.extend(env::split_paths(&v).map(IgnoreFile::from));
+    }
+    Ok(Options {
+        ignore_patterns,
+        ignore_files,
+        exclude_vcs_dirs: env_bool("GET_DIR_HASH_EXCLUDE_VCS")?.unwrap_or(base.exclude_vcs_dirs),
+        follow_symlinks: env_bool("GET_DIR_HASH_FOLLOW_SYMLINKS")?.unwrap_or(base.follow_symlinks),
+        include_metadata: env_bool("GET_DIR_HASH_INCLUDE_METADATA")?
+            .unwrap_or(base.include_metadata),
+        broken_symlinks: env_parse("GET_DIR_HASH_BROKEN_SYMLINKS")?.unwrap_or(base.broken_symlinks),
+        walk_threads: env_parse("GET_DIR_HASH_JOBS")?.unwrap_or(base.walk_threads),
+        clamp_mtime: env_parse("SOURCE_DATE_EPOCH")?
+            .map(epoch_secs)
+            .or(base.clamp_mtime),
+        ..base
+    })
+}
+
+/// `secs` after the Unix epoch.
+fn epoch_secs(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Comma-separated digest algorithms (`--algorithm`, `GET_DIR_HASH_ALGO`).
+fn parse_algorithms(s: &str) -> Result<Vec<Algorithm>, ParseOptionError> {
+    s.split(',').map(|a| a.trim().parse()).collect()
+}
+
+/// Comma-separated `--timestamps`, or `none`.
+fn parse_timestamps(s: &str) -> Result<Vec<Timestamp>, ParseOptionError> {
+    match s {
+        "none" => Ok(Vec::new()),
+        _ => s.split(',').map(|t| t.trim().parse()).collect(),
+    }
+}
+
+/// `GET_DIR_HASH_ALGO`, if set.
+fn env_algorithms() -> Result<Option<Vec<Algorithm>>, String> {
+    env_var("GET_DIR_HASH_ALGO")?
+        .map(|v| parse_algorithms(&v).map_err(|e| format!("GET_DIR_HASH_ALGO: {e}")))
+        .transpose()
+}
+
+/// Value of `name`, treating an empty variable as unset.
+fn env_var(name: &str) -> Result<Option<String>, String> {
+    match env::var(name) {
+        Ok(v) if v.is_empty() => Ok(None),
+        Ok(v) => Ok(Some(v)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(_)) => Err(format!("{name}: value is not valid UTF-8")),
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str) -> Result<Option<T>, String>
+where
+    T::Err: std::fmt::Display,
+{
+    env_var(name)?
+        .map(|v| {
+            v.parse()
+                .map_err(|e| format!("{name}: invalid value '{v}': {e}"))
+        })
+        .transpose()
+}
+
+/// `1`/`true`/`yes`/`on` or `0`/`false`/`no`/`off`.
+fn env_bool(name: &str) -> Result<Option<bool>, String> {
+    env_var(name)?
+        .map(|v| match v.to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(true),
+            "0" | "false" | "no" | "off" => Ok(false),
+            _ => Err(format!(
+                "{name}: invalid value '{v}' (expected true or false)"
+            )),
+        })
+        .transpose()
+}
+
+/// Progress bar redrawn in place on stderr, at most ten times per second.
+fn progress_bar() -> ProgressCallback {
+    let started = Instant::now();
+    let last_draw: Mutex<Option<Instant>> = Mutex::new(None);
+    ProgressCallback::new(move |p: &Progress| {
+        let done = p.files_done == p.files_total;
+        let Ok(mut last) = last_draw.lock() else {
+            return;
+        };
+        if !done && last.is_some_and(|t| t.elapsed() < Duration::from_millis(100)) {
+            return;
+        }
+        *last = Some(Instant::now());
+
+        const WIDTH: u64 = 30;
+        let frac = if p.bytes_total > 0 {
+            p.bytes_done.min(p.bytes_total) as f64 / p.bytes_total as f64
+        } else if p.files_total > 0 {
+            p.files_done as f64 / p.files_total as f64
+        } else {
+            1.0
+        };
+        let filled = (frac * WIDTH as f64) as u64;
+        let bar: String = (0..WIDTH)
+            .map(|i| {
+                if i < filled {
+                    '='
+                } else if i == filled {
+                    '>'
+                } else {
+                    ' '
+                }
+            })
+            .collect();
+        let secs = started.elapsed().as_secs_f64();
+        let mib_s = if secs > 0.0 {
+            p.bytes_done as f64 / (1024.0 * 1024.0) / secs
+        } else {
+            0.0
+        };
+        let mut err = std::io::stderr().lock();
+        let _ = write!(
+            err,
+            "\r[{bar}] {:>3}%  {}/{} files  {mib_s:.1} MiB/s",
+            (frac * 100.0) as u32,
+            p.files_done,
+            p.files_total
+        );
+        if done {
+            let _ = writeln!(err);
+        }
+        let _ = err.flush();
+    })
+}
+
+/// One-line summary of a run, written to stderr by `--stats`.
+fn print_stats(stats: &RunStats) {
+    let secs = stats.wall_time.as_secs_f64();
+    let mib_s = if secs > 0.0 {
+        stats.bytes_hashed as f64 / (1024.0 * 1024.0) / secs
+    } else {
+        0.0
+    };
+    log(
+        "info",
+        &format!(
+            "stats  files={} ignored={} bytes={} time={secs:.3}s rate={mib_s:.1}MiB/s warnings={}",
+            stats.files_hashed,
+            stats.files_ignored,
+            stats.bytes_hashed,
+            stats.warnings.len()
+        ),
+        "stats",
+        &[
+            ("files", stats.files_hashed.to_string()),
+            ("ignored", stats.files_ignored.to_string()),
+            ("bytes", stats.bytes_hashed.to_string()),
+            ("seconds", format!("{secs:.3}")),
+            ("warnings", stats.warnings.len().to_string()),
+        ],
+    );
+}
+
+/// `--list`: print what would be hashed, one path per line.
+fn list_files(dir: &Path, opts: &Options, out: &mut Output) -> ExitCode {
+    match enumerate_files(dir, opts) {
+        Ok(paths) => {
+            for p in paths {
+                if writeln!(out, "{p}").is_err() {
+                    break; // e.g. closed pipe
+                }
+            }
+            match out.commit() {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => output_failed(e),
+            }
+        }
+        Err(e) if Cancelled::find(&e).is_some() => ExitCode::from(EXIT_INTERRUPTED),
+        Err(e) => {
+            log_error(e);
+            ExitCode::from(1)
+        }
+    }
+}
+
+/// `--check-config`: print every unusable pattern of the settings (config
+/// file, environment and flags), one per line, or `config OK`. Exit status
+/// 2 when there is any.
+fn check_config(opts: &Options, out: &mut Output) -> ExitCode {
+    let errors = match opts.validate() {
+        Ok(errors) => errors,
+        Err(e) => {
+            log_error(e);
+            return ExitCode::from(1);
+        }
+    };
+    for error in &errors {
+        out.line(error);
+    }
+    if errors.is_empty() {
+        out.line("config OK");
+    }
+    match out.commit() {
+        Ok(()) if errors.is_empty() => ExitCode::SUCCESS,
+        Ok(()) => ExitCode::from(2),
+        Err(e) => output_failed(e),
+    }
+}
+
+/// `--oci-layer`: print the DiffID of the tree as a reproducible tar layer.
+fn oci_layer(dir: &Path, opts: &Options, out: &mut Output) -> ExitCode {
+    match oci_layer_digest(dir, opts) {
+        Ok(digest) => {
+            out.line(checksum_line(None, &digest, &dir.display().to_string()));
+            match out.commit() {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => output_failed(e),
+            }
+        }
+        Err(e) => run_failed(e, false),
+    }
+}
+
+/// `--report-duplicates`: print each set of files with identical contents
+/// (`hex  size x copies (wasted)`, then the paths indented and escaped as
+/// in checksum lines), most wasted bytes first, and a total.
+fn report_duplicates(dir: &Path, opts: &Options, out: &mut Output) -> ExitCode {
+    match Snapshot::capture(dir, opts) {
+        Ok(snapshot) => {
+            let sets = snapshot.duplicates();
+            for set in &sets {
+                out.line(format_args!(
+                    "{}  {} bytes x {} ({} bytes wasted)",
+                    set.hex,
+                    set.size,
+                    set.paths.len(),
+                    set.wasted()
+                ));
+                for path in &set.paths {
+                    let path = path
+                        .replace('\\', "\\\\")
+                        .replace('\n', "\\n")
+                        .replace('\r', "\\r");
+                    out.line(format_args!("  {path}"));
+                }
+            }
+            let wasted: u64 = sets.iter().map(|set| set.wasted()).sum();
+            out.line(format_args!(
+                "{} duplicate sets, {wasted} bytes wasted",
+                sets.len()
+            ));
+            match out.commit() {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => output_failed(e),
+            }
+        }
+        Err(e) => run_failed(e, opts.progress.is_some()),
+    }
+}
+
+/// `--per-dir DEPTH`: a `hex  dir/` line per subdirectory down to `depth`
+/// levels, in path order, then the root's `hex  DIR` line.
+fn per_dir(dir: &Path, depth: usize, opts: &Options, out: &mut Output) -> ExitCode {
+    match get_dir_hash_per_dir(dir, depth, opts) {
+        Ok(mut dirs) => {
+            let root = dirs.remove("").unwrap_or_default();
+            for (path, hex) in &dirs {
+                out.line(checksum_line(None, hex, &format!("{path}/")));
+            }
+            out.line(checksum_line(None, &root, &dir.display().to_string()));
+            match out.commit() {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => output_failed(e),
+            }
+        }
+        Err(e) => run_failed(e, opts.progress.is_some()),
+    }
+}
+
+/// Known-answer digests of the `selftest` fixture, by case (see
+/// [`selftest_case`]). They are the canonical results: a build that differs
+/// on any platform is wrong, and a change here is a format change.
+const SELFTEST_VECTORS: [(&str, &str); 16] = [
+    (
+        "default",
+        "28c5b99aa7052bc18ec34e1d8aa53d495cfed7a57cd9d8e567e4ae1368808586",
+    ),
+    (
+        "format-v2",
+        "7c3b7b42e2f43cf5cb180130946229124bb991cb6738d99c075f74406da79ad2",
+    ),
+    (
+        "length-64",
+        "28c5b99aa7052bc18ec34e1d8aa53d495cfed7a57cd9d8e567e4ae1368808586b1baa2bdbde9220407e50d138cd5a29ad1ada515d740b5e35c5467a01896a785",
+    ),
+    (
+        "ignore",
+        "b57ee5940bbef494175f5dc401c3bd059a2a9cf900bdfe79beac735c5636d268",
+    ),
+    (
+        "extensions",
+        "0bf2382f3bc4cf38b41bc1ed7dc20b5731b3289d3bdaa6bbee9d8aca3d5eca42",
+    ),
+    (
+        "pathspecs",
+        "94b4d778b3f97ecb729ba4fd0fb53112ae4f5da88fc9b7912f4a350ca7350e8b",
+    ),
+    (
+        "ignore-case",
+        "e2bebe13ecbba82a40b8239ab03674ac1266dbad6de74a06454df81219cb22ce",
+    ),
+    (
+        "nfd",
+        "04ebeeafe8227692b1d58a0a9f828c8bf16e8450c36e1796a0eddb5bc0ec99a6",
+    ),
+    (
+        "root-name",
+        "f5070a1fb4b4e2cea93f3e2e29fad503b2b0f1e088a003f6f4d3e9f9174a6da0",
+    ),
+    (
+        "relative-to",
+        "6c94ad46e005b5fbf8d39d4f791f8d48443a0792ee1a72b1b5d6b5646a5f1ccc",
+    ),
+    (
+        "git-metadata",
+        "6c25b3b91d1e119f3a4082c9ff644c0e8dd32b8c280a67da3c505b3d2ff1748b",
+    ),
+    (
+        "file-type",
+        "387d5017ccb78e24909086851a0114e1bd937e715287529e11682ed22b948d7e",
+    ),
+    (
+        "parallel",
+        "28c5b99aa7052bc18ec34e1d8aa53d495cfed7a57cd9d8e567e4ae1368808586",
+    ),
+    (
+        "merkle-tree",
+        "8e9fbab1bed7efdb260a123c1d7329b41c354ca4e3706273cf33d075c2074588",
+    ),
+    (
+        "chunking",
+        "f05ea621e2d29b0066ea7568990c0569f754b21492c6c4a4b81c2d5c8d3e5eb7",
+    ),
+    (
+        "sha256",
+        "3b26fde93237bc6e23d2d61efbed864a21421b19cba30a2917b1479adb1cdd65",
+    ),
+];
+
+/// `selftest`: build the fixture tree in a temporary directory, hash it for
+/// every case of [`SELFTEST_VECTORS`] and print `ok`, `FAILED` (with both
+/// digests) or `skipped` (a feature not compiled in) per case. Exit status 3
+/// on any mismatch.
+fn selftest(out: &mut Output) -> ExitCode {
+    let scratch = env::temp_dir().join(format!("get_dir_hash-selftest-{}", std::process::id()));
+    let root = scratch.join("fixture");
+    if let Err(e) = selftest_fixture(&root) {
+        let _ = std::fs::remove_dir_all(&scratch);
+        log_error(format_args!("selftest: cannot build the fixture: {e}"));
+        return ExitCode::from(1);
+    }
+    let (mut failed, mut skipped, mut errors) = (0, 0, 0);
+    for (name, expected) in SELFTEST_VECTORS {
+        let start = Instant::now();
+        let ms = || start.elapsed().as_secs_f64() * 1000.0;
+        match selftest_case(name, &root) {
+            Ok(hex) if hex == expected => {
+                out.line(format_args!("ok       {name} ({:.1} ms)", ms()))
+            }
+            Ok(hex) => {
+                failed += 1;
+                out.line(format_args!(
+                    "FAILED   {name}: got {hex}, expected {expected}"
+                ));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                skipped += 1;
+                out.line(format_args!("skipped  {name}: {e}"));
+            }
+            Err(e) => {
+                errors += 1;
+                out.line(format_args!("error    {name}: {e}"));
+            }
+        }
+    }
+    let _ = std::fs::remove_dir_all(&scratch);
+    let passed = SELFTEST_VECTORS.len() - failed - skipped - errors;
+    out.line(format_args!(
+        "selftest: {passed} passed, {failed} failed, {skipped} skipped, {errors} errors"
+    ));
+    if let Err(e) = out.commit() {
+        return output_failed(e);
+    }
+    match (failed, errors) {
+        (0, 0) => ExitCode::SUCCESS,
+        (0, _) => ExitCode::from(1),
+        _ => ExitCode::from(EXIT_CHANGED),
+    }
+}
+
+/// The `selftest` fixture: a few small files, an empty one, a blob of a few
+/// hundred KiB (many BLAKE3 chunks, several CDC chunks) and names that need
+/// case folding and Unicode normalization, all with fixed contents.
+fn selftest_fixture(root: &Path) -> std::io::Result<()> {
+    let blob: Vec<u8> = (0..300_000u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+        .collect();
+    let files: [(&str, &[u8]); 9] = [
+        ("README.md", b"get_dir_hash self-test fixture\n"),
+        ("empty", b""),
+        ("src/main.rs", b"fn main() {\n    println!(\"hello\");\n}\n"),
+        ("src/util/mod.rs", b"pub fn answer() -> u32 {\n    42\n}\n"),
+        ("src/Cargo.toml", b"[package]\nname = \"fixture\"\n"),
+        ("data/blob.bin", &blob),
+        ("logs/build.log", b"compiling fixture\n"),
+        ("Mixed/Case.TXT", b"MiXeD\n"),
+        ("caf\u{e9}/na\u{ef}ve.txt", "\u{e9}t\u{e9}\n".as_bytes()),
+    ];
+    for (rel, contents) in files {
+        let path = root.join(rel);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, contents)?;
+    }
+    Ok(())
+}
+
+/// The digest of the fixture at `root` for the `selftest` case `name`.
+fn selftest_case(name: &str, root: &Path) -> std::io::Result<String> {
+    let base = Options::default();
+    let opts = match name {
+        "default" => base,
+        "format-v2" => Options {
+            format_version: FormatVersion::V2,
+            ..base
+        },
+        "length-64" => Options {
+            output_len: 64,
+            ..base
+        },
+        "ignore" => Options {
+            ignore_patterns: vec!["logs/".to_owned(), "*.toml".to_owned()],
+            ..base
+        },
+        "extensions" => Options {
+            extensions: vec!["rs".to_owned()],
+            ..base
+        },
+        "pathspecs" => Options {
+            pathspecs: vec!["src/util".to_owned(), "data".to_owned()],
+            ..base
+        },
+        "ignore-case" => Options {
+            case_sensitive_paths: false,
+            ..base
+        },
+        "nfd" => Options {
+            unicode_normalization: Some(NormalizationForm::Nfd),
+            ..base
+        },
+        "root-name" => Options {
+            include_root_name: true,
+            ..base
+        },
+        "relative-to" => Options {
+            path_base: root.parent().map(Path::to_path_buf),
+            ..base
+        },
+        // Every mtime is clamped, and git modes are the same everywhere.
+        "git-metadata" => Options {
+            include_metadata: true,
+            mode_normalization: ModeNormalization::Git,
+            clamp_mtime: Some(UNIX_EPOCH + Duration::from_secs(1_000_000_000)),
+            ..base
+        },
+        "file-type" => Options {
+            include_file_type: true,
+            ..base
+        },
+        // Threads, memory maps and parallel hashing never change the digest.
+        "parallel" => Options {
+            walk_threads: 4,
+            io_concurrency: 4,
+            mmap_threshold: Some(1),
+            parallel_hash_threshold: Some(1),
+            threads: 4,
+            ..base
+        },
+        "merkle-tree" => return Ok(get_dir_hash_tree(root, &base)?.root.hex),
+        "chunking" => Options {
+            chunking: Some(Chunking {
+                min_size: 4096,
+                avg_size: 16384,
+                max_size: 65536,
+            }),
+            ..base
+        },
+        "sha256" => {
+            return Ok(get_dir_digests(root, &[Algorithm::Sha256], &base)?.remove(0));
+        }
+        _ => unreachable!("no selftest case {name}"),
+    };
+    get_dir_hash(root, &opts)
+}
+
+/// `--ignore-file`, `--gitignore` and `--dockerignore`, each repeatable.
+fn ignore_files(pargs: &mut Arguments) -> Result<Vec<IgnoreFile>, pico_args::Error> {
+    let mut files = Vec::new();
+    for (flag, format) in [
+        ("--ignore-file", IgnoreFileFormat::Native),
+        ("--gitignore", IgnoreFileFormat::Gitignore),
+        ("--dockerignore", IgnoreFileFormat::Dockerignore),
+    ] {
+        let paths: Vec<PathBuf> = pargs.values_from_str(flag)?;
+        files.extend(paths.into_iter().map(|p| IgnoreFile::new(p, format)));
+    }
+    Ok(files)
+}
+
+/// `--explain`: print whether `path` would be hashed, and every ignore
+/// pattern that excludes it.
+fn explain_path(dir: &Path, path: &Path, opts: &Options, out: &mut Output) -> ExitCode {
+    match explain(dir, path, opts) {
+        Ok(explanation) => {
+            let rel = &explanation.path;
+            if explanation.hashed {
+                out.line(format_args!("{rel}: hashed"));
+            } else if explanation.matches.is_empty() {
+                out.line(format_args!(
+                    "{rel}: not hashed (no regular file at this path)"
+                ));
+            }
+            for m in &explanation.matches {
+                out.line(format_args!("{rel}: ignored by {m}"));
+            }
+            match out.commit() {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => output_failed(e),
+            }
+        }
+        Err(e) => {
+            log_error(e);
+            ExitCode::from(1)
+        }
+    }
+}
+
+/// `--files-from`: read paths from `source` (`-` for stdin), separated by
+/// newlines or, with `-0`, by NUL bytes. Empty entries are skipped.
+fn read_file_list(source: &Path, null: bool) -> std::io::Result<Vec<PathBuf>> {
+    use std::io::Read;
+    let mut bytes = Vec::new();
+    if source == Path::new("-") {
+        std::io::stdin().lock().read_to_end(&mut bytes)?;
+    } else {
+        bytes = std::fs::read(source)?;
+    }
+    let sep = if null { b'\0' } else { b'\n' };
+    Ok(bytes
+        .split(|&b| b == sep)
+        .map(|entry| match entry {
+            [rest @ .., b'\r'] if !null => rest,
+            _ => entry,
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(path_from_bytes)
+        .collect())
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// A checksum line as printed by `b3sum`/`sha256sum` (`hex  path`) or, with
+/// `tag`, by their `--tag` mode (`BLAKE3 (path) = hex`). Like coreutils, a
+/// path containing a backslash or line break has them escaped and the line
+/// starts with a backslash.
+fn checksum_line(tag: Option<Algorithm>, hex: &str, path: &str) -> String {
+    // Windows paths use `/`, as in b3sum's output.
+    #[cfg(windows)]
+    let path = &path.replace('\\', "/");
+    let escaped = path.contains(['\\', '\n', '\r']);
+    let path = if escaped {
+        path.replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+    } else {
+        path.to_owned()
+    };
+    let prefix = if escaped { "\\" } else { "" };
+    match tag {
+        Some(algorithm) => format!(
+            "{prefix}{} ({path}) = {hex}",
+            algorithm.name().to_ascii_uppercase()
+        ),
+        None => format!("{prefix}{hex}  {path}"),
+    }
+}
+
+/// `predicateType` of the `--attestation` statement.
+const PREDICATE_TYPE: &str = "https://github.com/ARyaskov/get_dir_hash/attestation/v1";
+
+/// `--attestation`: an in-toto Statement (v1) with `dir` as the subject and
+/// one digest per algorithm. The predicate records the tool, the time and
+/// every option that affects the digest, under its config file key.
+fn attestation(dir: &Path, digests: &[(Algorithm, &str)], opts: &Options, ts: &str) -> String {
+    let digest_set = digests
+        .iter()
+        .map(|(algorithm, hex)| format!("{}: {}", json(algorithm.name()), json(hex)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let list = |items: Vec<String>| {
+        let items: Vec<String> = items.iter().map(|s| json(s)).collect();
+        format!("[{}]", items.join(", "))
+    };
+    let options = [
+        ("format_version", json(&kebab(opts.format_version))),
+        ("output_len", opts.output_len.to_string()),
+        ("ignore_patterns", list(opts.ignore_patterns.clone())),
+        ("extensions", list(opts.extensions.clone())),
+        ("pathspecs", list(opts.pathspecs.clone())),
+        (
+            "modified_since",
+            opts.modified_since
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or("null".into(), |d| d.as_secs().to_string()),
+        ),
+        (
+            "ignore_files",
+            format!(
+                "[{}]",
+                (opts.ignore_files.iter())
+                    .map(|f| format!(
+                        "{{\"path\": {}, \"format\": {}}}",
+                        json(&f.path.display().to_string()),
+                        json(&kebab(f.format))
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        ),
+        (
+            "load_dot_get_dir_hash_ignore",
+            opts.load_dot_get_dir_hash_ignore.to_string(),
+        ),
+        ("exclude_vcs_dirs", opts.exclude_vcs_dirs.to_string()),
+        ("global_gitignore", opts.global_gitignore.to_string()),
+        ("skip_hidden", opts.skip_hidden.to_string()),
+        ("follow_symlinks", opts.follow_symlinks.to_string()),
+        ("confine_symlinks", opts.confine_symlinks.to_string()),
+        ("symlink_loops", json(&kebab(opts.symlink_loops))),
+        ("broken_symlinks", json(&kebab(opts.broken_symlinks))),
+        ("non_utf8_names", json(&kebab(opts.non_utf8_names))),
+        ("special_files", json(&kebab(opts.special_files))),
+        ("include_metadata", opts.include_metadata.to_string()),
+        ("include_root_name", opts.include_root_name.to_string()),
+        ("mode_normalization", json(&kebab(opts.mode_normalization))),
+        ("ownership", json(&kebab(opts.ownership))),
+        ("windows_attributes", opts.windows_attributes.to_string()),
+        ("windows_acl", opts.windows_acl.to_string()),
+        ("include_file_type", opts.include_file_type.to_string()),
+        (
+            "executable_extensions",
+            list(opts.executable_extensions.clone()),
+        ),
+        (
+            "timestamps",
+            list(opts.timestamps.iter().map(kebab).collect()),
+        ),
+        ("mtime_granularity", json(&kebab(opts.mtime_granularity))),
+        (
+            "clamp_mtime",
+            opts.clamp_mtime
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or("null".into(), |d| d.as_secs().to_string()),
+        ),
+        (
+            "case_sensitive_paths",
+            opts.case_sensitive_paths.to_string(),
+        ),
+        ("case_collisions", json(&kebab(opts.case_collisions))),
+        (
+            "unicode_normalization",
+            opts.unicode_normalization
+                .map_or("null".into(), |f| json(&kebab(f))),
+        ),
+        (
+            "path_base",
+            opts.path_base
+                .as_ref()
+                .map_or("null".into(), |p| json(&p.display().to_string())),
+        ),
+        ("canonicalize_root", json(&kebab(opts.canonicalize_root))),
+        (
+            "max_file_size",
+            opts.max_file_size.map_or("null".into(), |n| n.to_string()),
+        ),
+        ("oversize_policy", json(&kebab(opts.oversize_policy))),
+        ("structure_only", opts.structure_only.to_string()),
+        ("normalize_newlines", opts.normalize_newlines.to_string()),
+        (
+            "chunking",
+            opts.chunking.map_or("null".into(), |c| {
+                json(&format!("{}:{}:{}", c.min_size, c.avg_size, c.max_size))
+            }),
+        ),
+    ]
+    .iter()
+    .map(|(key, value)| format!("      {}: {value}", json(key)))
+    .collect::<Vec<_>>()
+    .join(",\n");
+    format!(
+        "{{
+  \"_type\": \"https://in-toto.io/Statement/v1\",
+  \"subject\": [
+    {{ \"name\": {}, \"digest\": {{ {digest_set} }} }}
+  ],
+  \"predicateType\": {},
+  \"predicate\": {{
+    \"tool\": {{ \"name\": \"get_dir_hash\", \"version\": {} }},
+    \"timestamp\": {},
+    \"options\": {{
+{options}
+    }}
+  }}
+}}
+",
+        json(&dir.display().to_string()),
+        json(PREDICATE_TYPE),
+        json(env!("CARGO_PKG_VERSION")),
+        json(ts),
+    )
+}
+
+/// `s` as a JSON string literal.
+fn json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Name of an option enum value as its `FromStr` accepts it: the variant
+/// name in kebab case (`TwoSeconds` -> `two-seconds`).
+fn kebab(value: impl std::fmt::Debug) -> String {
+    let mut out = String::new();
+    for (i, c) in format!("{value:?}").chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            out.push('-');
+        }
+        out.push(c.to_ascii_lowercase());
+    }
+    out
+}
+
+/// Format of diagnostics on stderr (`--log-format`).
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LogFormat {
+    /// Plain `get_dir_hash: ...` lines.
+    Text,
+    /// One JSON object per line, for log aggregators.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format '{s}' (expected text or json)")),
+        }
+    }
+}
+
+/// Set once `--log-format json` has been parsed.
+static JSON_LOG: AtomicBool = AtomicBool::new(false);
+
+/// Write one diagnostic to stderr: the `text` line, or with `--log-format
+/// json` an object with `timestamp`, `level`, `message` and the extra
+/// `fields` (values already JSON-encoded).
+fn log(level: &str, text: &str, message: &str, fields: &[(&str, String)]) {
+    if !JSON_LOG.load(Ordering::Relaxed) {
+        eprintln!("{text}");
+        return;
+    }
+    let ts = OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_default();
+    let mut line = format!(
+        "{{\"timestamp\":{},\"level\":{},\"message\":{}",
+        json(&ts),
+        json(level),
+        json(message)
+    );
+    for (name, value) in fields {
+        line.push_str(&format!(",{}:{value}", json(name)));
+    }
+    line.push('}');
+    eprintln!("{line}");
+}
+
+/// A warning: `get_dir_hash: warn: ...` as text.
+fn log_warn(message: impl fmt::Display, fields: &[(&str, String)]) {
+    let message = message.to_string();
+    let text = format!("get_dir_hash: warn: {message}");
+    log("warn", &text, &message, fields);
+}
+
+/// A runtime error (exit status 1): `get_dir_hash: error: ...` as text.
+fn log_error(message: impl fmt::Display) {
+    let message = message.to_string();
+    log(
+        "error",
+        &format!("get_dir_hash: error: {message}"),
+        &message,
+        &[],
+    );
+}
+
+/// A file that could not be opened, read or `stat`ed, with its path and
+/// the operation as fields.
+fn log_file_error(f: &FileError) {
+    let message = f.to_string();
+    let op = match f.op {
+        FileOp::Open => "open",
+        FileOp::Read => "read",
+        FileOp::Metadata => "metadata",
+    };
+    log(
+        "error",
+        &format!("get_dir_hash: error: {message}"),
+        &message,
+        &[
+            ("path", json(&f.path.display().to_string())),
+            ("op", json(op)),
+        ],
+    );
+}
+
+/// A usage or configuration error (exit status 2): `get_dir_hash: ...` as
+/// text.
+fn log_usage(message: impl fmt::Display) {
+    let message = message.to_string();
+    log("error", &format!("get_dir_hash: {message}"), &message, &[]);
+}
+
+/// Exit code when `--baseline` found changes.
+const EXIT_CHANGED: u8 = 3;
+
+/// Exit code after Ctrl-C, following the shell's 128 + SIGINT convention.
+const EXIT_INTERRUPTED: u8 = 130;
+
+/// Cancel the run on the first Ctrl-C; a second one exits immediately.
+#[cfg(not(target_os = "wasi"))]
+fn install_interrupt_handler() -> CancellationToken {
+    let token = CancellationToken::new();
+    let handler_token = token.clone();
+    let res = ctrlc::set_handler(move || {
+        if handler_token.is_cancelled() {
+            std::process::exit(EXIT_INTERRUPTED.into());
+        }
+        handler_token.cancel();
+    });
+    if let Err(e) = res {
+        log_warn(format_args!("cannot install Ctrl-C handler: {e}"), &[]);
+    }
+    token
+}
+
+/// WASI has no signals; the token never fires.
+#[cfg(target_os = "wasi")]
+fn install_interrupt_handler() -> CancellationToken {
+    CancellationToken::new()
+}
+
 fn main() -> ExitCode {
-    let mut pargs = Arguments::from_env();
+    let mut args: Vec<OsString> = env::args_os().skip(1).collect();
+    let command = match args.first().and_then(Command::from_arg) {
+        Some(command) => {
+            args.remove(0);
+            command
+        }
+        None => Command::Hash,
+    };
+    let mut pargs = Arguments::from_vec(args);
 
     if pargs.contains(["-h", "--help"]) {
         print_help();
         return ExitCode::SUCCESS;
     }
 
-    // Positional directory argument (default to ".")
-    let dir: PathBuf = pargs.free_from_str().unwrap_or_else(|_| PathBuf::from("."));
+    match pargs.opt_value_from_str("--log-format") {
+        Ok(format) => JSON_LOG.store(format == Some(LogFormat::Json), Ordering::Relaxed),
+        Err(e) => {
+            log_usage(e);
+            return ExitCode::from(2);
+        }
+    }
 
-    // Collect repeated options
-    let ignores: Vec<String> = pargs.values_from_str("--ignore").unwrap_or_default();
-    let ignore_files: Vec<PathBuf> = pargs.values_from_str("--ignore-file").unwrap_or_default();
-    let follow = pargs.contains("--follow-symlinks");
-    let include_meta = pargs.contains("--include-metadata");
-    let no_dot = pargs.contains("--no-dotfile");
+    match pargs.opt_value_from_str::<_, Shell>("--completions") {
+        Ok(Some(shell)) => {
+            print!("{}", completions(shell));
+            return ExitCode::SUCCESS;
+        }
+        Ok(None) => {}
+        Err(e) => {
+            log_usage(e);
+            return ExitCode::from(2);
+        }
+    }
+
+    // The fixture and its options are built in; nothing else applies.
+    if command == Command::Selftest {
+        let leftover: Vec<OsString> = pargs.finish();
+        if !leftover.is_empty() {
+            log_usage(format_args!("`selftest` takes no arguments: {leftover:?}"));
+            return ExitCode::from(2);
+        }
+        return selftest(&mut Output::new(None));
+    }
+
+    let cli = match parse_args(command, &mut pargs) {
+        Ok(cli) => cli,
+        Err(e) => {
+            log_usage(e);
+            return ExitCode::from(2);
+        }
+    };
 
     let leftover: Vec<OsString> = pargs.finish();
     if !leftover.is_empty() {
-        eprintln!("get_dir_hash: unexpected argument(s): {leftover:?}");
+        log_usage(format_args!("unexpected argument(s): {leftover:?}"));
         return ExitCode::from(2);
     }
 
-    // Build options in one go (no field reassignments)
+    let Config {
+        options: base,
+        algorithms: config_algorithms,
+    } = match base_config(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            log_usage(format_args!("config: {e}"));
+            return ExitCode::from(2);
+        }
+    };
+    let base = match env_options(base) {
+        Ok(base) => base,
+        Err(e) => {
+            log_usage(e);
+            return ExitCode::from(2);
+        }
+    };
+    let base = match cli.preset {
+        Some(preset) => preset.apply(base),
+        None => base,
+    };
+    let algorithms = match cli.algorithms.clone() {
+        Some(list) => list,
+        None => match env_algorithms() {
+            Ok(list) => list
+                .or(config_algorithms)
+                .unwrap_or_else(|| vec![Algorithm::Blake3]),
+            Err(e) => {
+                log_usage(e);
+                return ExitCode::from(2);
+            }
+        },
+    };
+    if let Err(e) = check_command(&cli) {
+        log_usage(e);
+        return ExitCode::from(2);
+    }
+    let multi = algorithms != [Algorithm::Blake3];
+    if multi
+        && (cli.list
+            || cli.oci_layer
+            || cli.report_duplicates
+            || cli.per_dir.is_some()
+            || cli.print_files
+            || cli.b3sum.is_some()
+            || cli.tree
+            || cli.dump_frames.is_some()
+            || cli.snapshot.is_some()
+            || cli.baseline.is_some()
+            || cli.files_from.is_some()
+            || cli.stats)
+    {
+        log_usage(
+            "--algorithm other than blake3 cannot be combined with --list, \
+             --oci-layer, --report-duplicates, --per-dir, --print-files, --b3sum, --tree, --dump-frames, --snapshot, --baseline, --files-from or --stats",
+        );
+        return ExitCode::from(2);
+    }
+    if cli.flags.length.is_some() && (cli.tree || cli.snapshot.is_some() || cli.baseline.is_some())
+    {
+        log_usage(
+            "--length cannot be combined with --tree, --snapshot or --baseline \
+             (their digests are always 32 bytes)",
+        );
+        return ExitCode::from(2);
+    }
+    if cli.b3sum.is_some()
+        && (cli.tree
+            || cli.dump_frames.is_some()
+            || cli.snapshot.is_some()
+            || cli.baseline.is_some())
+    {
+        log_usage(
+            "--b3sum cannot be combined with --tree, --dump-frames, --snapshot \
+             or --baseline",
+        );
+        return ExitCode::from(2);
+    }
+    if cli.manifest_format.is_some() && !cli.print_files {
+        log_usage("--manifest-format needs --print-files");
+        return ExitCode::from(2);
+    }
+    if let Some(format @ (ManifestFormat::Json | ManifestFormat::Binary)) = cli.manifest_format
+        && (cli.tag
+            || cli.self_describing
+            || cli.tree
+            || cli.dump_frames.is_some()
+            || cli.snapshot.is_some()
+            || cli.baseline.is_some()
+            || cli.algorithms.is_some())
+    {
+        log_usage(format_args!(
+            "--manifest-format {format} cannot be combined with --tag, \
+             --self-describing, --tree, --dump-frames, --snapshot, --baseline \
+             or --algorithm"
+        ));
+        return ExitCode::from(2);
+    }
+    if cli.self_describing && (cli.tree || cli.tag) {
+        log_usage("--self-describing cannot be combined with --tree or --tag");
+        return ExitCode::from(2);
+    }
+    if cli.attestation.is_some() && (cli.list || cli.tree) {
+        log_usage("--attestation cannot be combined with --list or --tree");
+        return ExitCode::from(2);
+    }
+    let dir = &cli.dir;
+    let io_concurrency = cli.flags.io_concurrency;
     let opts = Options {
-        follow_symlinks: follow,
-        include_metadata: include_meta,
-        ignore_patterns: ignores,
-        ignore_files,
-        load_dot_get_dir_hash_ignore: !no_dot,
-        ..Default::default() // keep other defaults (e.g., case_sensitive_paths)
+        cancel: Some(install_interrupt_handler()),
+        ..cli.flags.apply(base)
     };
-
-    match get_dir_hash(&dir, &opts) {
-        Ok(digest) => {
+    let mut out = Output::new(cli.output.clone());
+    match cli.command {
+        Command::Verify => {
+            let (expect, manifest) = (cli.expect.as_deref(), cli.manifest.as_deref());
+            // Checking a manifest is all reading; read a file per CPU unless
+            // told otherwise.
+            let opts = match io_concurrency {
+                None if opts.io_concurrency == 1 => Options {
+                    io_concurrency: std::thread::available_parallelism().map_or(1, |n| n.get()),
+                    ..opts
+                },
+                _ => opts,
+            };
+            return verify(dir, expect, manifest, cli.fail_fast, &opts, &mut out);
+        }
+        Command::Watch => return watch(dir, cli.interval, &opts, &mut out),
+        Command::Selftest => return selftest(&mut out),
+        Command::Hash | Command::Manifest | Command::Diff => {}
+    }
+    if cli.check_config {
+        return check_config(&opts, &mut out);
+    }
+    if let Some(path) = &cli.explain {
+        return explain_path(dir, path, &opts, &mut out);
+    }
+    if cli.list {
+        return list_files(dir, &opts, &mut out);
+    }
+    if cli.oci_layer {
+        return oci_layer(dir, &opts, &mut out);
+    }
+    if cli.report_duplicates {
+        return report_duplicates(dir, &opts, &mut out);
+    }
+    if let Some(depth) = cli.per_dir {
+        return per_dir(dir, depth, &opts, &mut out);
+    }
+    let file_list = match &cli.files_from {
+        Some(_)
+            if cli.print_files
+                || cli.b3sum.is_some()
+                || cli.tree
+                || cli.dump_frames.is_some()
+                || cli.snapshot.is_some()
+                || cli.baseline.is_some() =>
+        {
+            log_usage(
+                "--files-from cannot be combined with --print-files, --b3sum, \
+                 --tree, --dump-frames, --snapshot or --baseline",
+            );
+            return ExitCode::from(2);
+        }
+        Some(source) => match read_file_list(source, cli.null) {
+            Ok(files) => Some(files),
+            Err(e) => {
+                log_usage(format_args!("--files-from: {e}"));
+                return ExitCode::from(2);
+            }
+        },
+        None => None,
+    };
+    let baseline = match cli.baseline.as_deref().map(Snapshot::load).transpose() {
+        Ok(baseline) => baseline,
+        Err(e) => {
+            log_usage(format_args!("baseline: {e}"));
+            return ExitCode::from(2);
+        }
+    };
+    let mut changes = None;
+    let mut digests = None;
+    let res = if multi {
+        get_dir_digests(dir, &algorithms, &opts).map(|hexes| {
+            digests = Some(hexes);
+            Manifest {
+                hex: String::new(),
+                files: Vec::new(),
+                stats: RunStats::default(),
+            }
+        })
+    } else if let Some(files) = &file_list {
+        get_files_hash(dir, files, &opts).map(|hex| Manifest {
+            hex,
+            files: Vec::new(),
+            stats: RunStats::default(),
+        })
+    } else if cli.print_files || cli.b3sum.is_some() {
+        get_dir_manifest(dir, &opts).and_then(|manifest| {
+            if let Some(path) = &cli.b3sum {
+                let lines: String = (manifest.files.iter())
+                    .map(|f| checksum_line(None, &f.hex, &f.path) + "\n")
+                    .collect();
+                write_atomic(path, lines.as_bytes())?;
+            }
+            Ok(Manifest {
+                files: if cli.print_files {
+                    manifest.files
+                } else {
+                    Vec::new()
+                },
+                ..manifest
+            })
+        })
+    } else if cli.snapshot.is_some() || baseline.is_some() {
+        Snapshot::capture(dir, &opts).and_then(|snap| {
+            if let Some(path) = &cli.snapshot {
+                snap.save(path)?;
+            }
+            changes = baseline.as_ref().map(|base| base.diff(&snap));
+            Ok(Manifest {
+                hex: snap.hex,
+                files: Vec::new(),
+                stats: RunStats::default(),
+            })
+        })
+    } else if cli.tree {
+        get_dir_hash_tree(dir, &opts).map(|t| {
+            let mut files = Vec::new();
+            dir_lines(&t.root, "", &mut files);
+            Manifest {
+                hex: t.root.hex,
+                files,
+                stats: t.stats,
+            }
+        })
+    } else if let Some(format) = cli.dump_frames {
+        dump_frames(dir, &opts, &mut out, format).map(|hex| Manifest {
+            hex,
+            files: Vec::new(),
+            stats: RunStats::default(),
+        })
+    } else {
+        get_dir_hash_with_stats(dir, &opts).map(|h| Manifest {
+            hex: h.hex,
+            files: Vec::new(),
+            stats: h.stats,
+        })
+    };
+    match res {
+        Ok(res) => {
             let ts = OffsetDateTime::now_utc()
                 .format(&time::format_description::well_known::Rfc3339)
                 .unwrap_or_default();
-            println!("{digest}  {}", dir.display());
-            eprintln!("ok  {ts}  {}", dir.display());
-            ExitCode::SUCCESS
+            if let Some(path) = &cli.attestation {
+                let subject: Vec<(Algorithm, &str)> = match &digests {
+                    Some(hexes) => algorithms
+                        .iter()
+                        .copied()
+                        .zip(hexes.iter().map(String::as_str))
+                        .collect(),
+                    None => vec![(Algorithm::Blake3, res.hex.as_str())],
+                };
+                let statement = attestation(dir, &subject, &opts, &ts);
+                if let Err(e) = write_atomic(path, statement.as_bytes()) {
+                    log_error(format_args!("attestation: {}: {e}", path.display()));
+                    return ExitCode::from(1);
+                }
+            }
+            match cli.manifest_format {
+                Some(format @ (ManifestFormat::Json | ManifestFormat::Binary)) => {
+                    if let Err(e) = (res.to_bytes(format)).and_then(|bytes| out.write_all(&bytes)) {
+                        return output_failed(e);
+                    }
+                }
+                _ => {
+                    let tag = cli.tag.then_some(Algorithm::Blake3);
+                    let root = dir.display().to_string();
+                    for f in &res.files {
+                        out.line(checksum_line(tag, &f.hex, &f.path));
+                    }
+                    if let Some(changes) = &changes {
+                        print_changes(changes, &mut out);
+                    }
+                    let described = |algorithm: Algorithm, hex: &str| match cli.self_describing {
+                        true => SelfDescribingDigest::new(algorithm, opts.format_version, hex)
+                            .to_string(),
+                        false => hex.to_owned(),
+                    };
+                    match digests.as_deref() {
+                        Some([hex]) => {
+                            let hex = described(algorithms[0], hex);
+                            out.line(checksum_line(tag, &hex, &root))
+                        }
+                        Some(hexes) => {
+                            for (&algorithm, hex) in algorithms.iter().zip(hexes) {
+                                let line = if cli.tag {
+                                    checksum_line(Some(algorithm), hex, &root)
+                                } else if cli.self_describing {
+                                    checksum_line(None, &described(algorithm, hex), &root)
+                                } else {
+                                    checksum_line(
+                                        None,
+                                        &format!("{}:{hex}", algorithm.name()),
+                                        &root,
+                                    )
+                                };
+                                out.line(line);
+                            }
+                        }
+                        None => {
+                            let hex = described(Algorithm::Blake3, &res.hex);
+                            out.line(checksum_line(tag, &hex, &root))
+                        }
+                    }
+                }
+            }
+            if let Err(e) = out.commit() {
+                return output_failed(e);
+            }
+            if cli.stats {
+                print_stats(&res.stats);
+            }
+            let digest = match digests.as_deref() {
+                Some([first, ..]) => first.as_str(),
+                _ => res.hex.as_str(),
+            };
+            log(
+                "info",
+                &format!("ok  {ts}  {}", dir.display()),
+                "ok",
+                &[
+                    ("dir", json(&dir.display().to_string())),
+                    ("digest", json(digest)),
+                ],
+            );
+            match changes {
+                Some(changes) if !changes.is_empty() => ExitCode::from(EXIT_CHANGED),
+                _ => ExitCode::SUCCESS,
+            }
         }
         Err(e) => {
-            eprintln!("get_dir_hash: error: {e}");
-            ExitCode::from(1)
+            // Best effort: the files hashed in time, without a root line. An
+            // `--output` file is left alone.
+            if let Some(t) = TimedOut::find(&e)
+                && cli.print_files
+                && cli
+                    .manifest_format
+                    .is_none_or(|f| f == ManifestFormat::Text)
+                && cli.output.is_none()
+            {
+                let tag = cli.tag.then_some(Algorithm::Blake3);
+                for f in &t.files {
+                    out.line(checksum_line(tag, &f.hex, &f.path));
+                }
+                let _ = out.commit();
+            }
+            run_failed(e, opts.progress.is_some())
+        }
+    }
+}
+
+/// Report a failed or interrupted run; `progress` ends the progress bar's
+/// line first. With `--keep-going`, every file that failed gets a line of
+/// its own before the count.
+fn run_failed(e: std::io::Error, progress: bool) -> ExitCode {
+    if let Some(c) = Cancelled::find(&e) {
+        if progress {
+            eprintln!();
+        }
+        let secs = c.stats.wall_time.as_secs_f64();
+        log(
+            "error",
+            &format!(
+                "get_dir_hash: interrupted after {} files ({} bytes) in {secs:.3}s",
+                c.stats.files_hashed, c.stats.bytes_hashed
+            ),
+            "interrupted",
+            &[
+                ("files", c.stats.files_hashed.to_string()),
+                ("bytes", c.stats.bytes_hashed.to_string()),
+                ("seconds", format!("{secs:.3}")),
+            ],
+        );
+        return ExitCode::from(EXIT_INTERRUPTED);
+    }
+    if let Some(t) = TimedOut::find(&e) {
+        if progress {
+            eprintln!();
+        }
+        let secs = t.stats.wall_time.as_secs_f64();
+        log(
+            "error",
+            &format!(
+                "get_dir_hash: timed out after {} files ({} bytes) in {secs:.3}s",
+                t.stats.files_hashed, t.stats.bytes_hashed
+            ),
+            "timed-out",
+            &[
+                ("files", t.stats.files_hashed.to_string()),
+                ("bytes", t.stats.bytes_hashed.to_string()),
+                ("seconds", format!("{secs:.3}")),
+            ],
+        );
+        return ExitCode::from(1);
+    }
+    if let Some(failed) = FileErrors::find(&e) {
+        for f in &failed.errors {
+            log_file_error(f);
         }
+    } else if let Some(f) = FileError::find(&e) {
+        log_file_error(f);
+        return ExitCode::from(1);
     }
+    log_error(e);
+    ExitCode::from(1)
 }
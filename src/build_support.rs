@@ -0,0 +1,35 @@
+//! Helpers for Cargo build scripts (`build-support` feature).
+
+use crate::{DirHash, Options, Outputs};
+use std::io;
+use std::path::Path;
+
+/// Hash `root` from a `build.rs` and print the `cargo:rerun-if-changed=`
+/// lines that keep the result current; returns the digest.
+///
+/// One line names `root` itself, which Cargo scans recursively and so
+/// catches added and removed files; then one line per hashed file and per
+/// file in [`Options::ignore_files`]. Typical use embeds the digest:
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// let opts = get_dir_hash::Options::default();
+/// let hex = get_dir_hash::emit_cargo_rerun_and_hash("assets".as_ref(), &opts)?;
+/// println!("cargo:rustc-env=ASSETS_HASH={hex}");
+/// # Ok(())
+/// # }
+/// ```
+pub fn emit_cargo_rerun_and_hash(root: &Path, opts: &Options) -> io::Result<String> {
+    let mut visited = Vec::new();
+    let outputs = Outputs {
+        visited: Some(&mut visited),
+        ..Outputs::default()
+    };
+    let DirHash { hex, .. } = crate::run(root, None, opts, outputs)?;
+    println!("cargo:rerun-if-changed={}", root.display());
+    let ignore_files = opts.ignore_files.iter().map(|file| &file.path);
+    for path in ignore_files.chain(&visited) {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+    Ok(hex)
+}
@@ -0,0 +1,541 @@
+//! Hashing through an already-open directory handle ([`get_dir_hash_at`],
+//! `cap-std` feature).
+//!
+//! Every entry is opened relative to its parent's handle (`openat`), never
+//! by a path from the root: a directory renamed or swapped for a symlink in
+//! the middle of a run cannot redirect the walk, and nothing outside the
+//! handle is ever read. Symlinks are not followed; a link whose target stays
+//! inside the handle is skipped as in a walk by path, any other one is
+//! handled like a dangling link ([`Options::broken_symlinks`]).
+//!
+//! Files are checked and read through the same open file, so the size seen
+//! by [`Options::max_file_size`] is that of the bytes hashed.
+
+use crate::frame::Frames;
+use crate::ignore::{IGNORE_FILE_NAME, IgnoreRules};
+use crate::throttle::Throttle;
+use crate::walk::{ListOptions, order_key, os_limit};
+use crate::{
+    CaseCollisionPolicy, FileErrors, FileOp, Limits, ModificationPolicy, Options, OversizePolicy,
+    Policy, Warning, check_cancel, check_depth, check_output_len, framed_path, hex_lower,
+    path_to_unix_bytes, report_skip, since_epoch, special_file_name, with_deadline,
+};
+use cap_std::fs::{Dir, FileType};
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Hash the directory behind the handle `dir`; the digest is the one
+/// [`get_dir_hash`](crate::get_dir_hash) computes for the same tree, except
+/// that symlinks leading out of `dir` count as dangling.
+///
+/// Options that need a path on disk ([`Options::follow_symlinks`],
+/// [`Options::include_metadata`], [`Options::include_file_type`],
+/// [`Options::include_root_name`],
+/// [`Options::path_base`], [`Options::chunking`], [`Options::checkpoint`],
+/// [`Options::concurrent_modification`]) and [`CaseCollisionPolicy::Error`] are rejected with `InvalidInput`;
+/// thread, memory-mapping and progress settings have no effect. The files of
+/// [`Options::ignore_files`] are the caller's and read by path, while
+/// `.get_dir_hash_ignore` files are read through the handle. Warnings name
+/// paths relative to `dir`.
+pub fn get_dir_hash_at(dir: &Dir, opts: &Options) -> io::Result<String> {
+    let opts = &*with_deadline(opts, Instant::now());
+    check_supported(opts)?;
+    check_output_len(opts)?;
+    let dotfile = match opts.load_dot_get_dir_hash_ignore && dir.is_file(IGNORE_FILE_NAME) {
+        true => Some((
+            dir.read_to_string(IGNORE_FILE_NAME)?,
+            PathBuf::from(IGNORE_FILE_NAME),
+        )),
+        false => None,
+    };
+    let rules = IgnoreRules::with_dotfile(dotfile, opts)?;
+    for warning in &rules.warnings {
+        report_skip(warning, opts)?;
+    }
+    let mut throttle = Throttle::new(opts.max_read_bytes_per_sec)?;
+    let mut out = Frames::new(opts.format_version, None);
+    if let Some(cutoff) = opts.modified_since {
+        out.modified_since(since_epoch(cutoff)?);
+    }
+    let mut walk = Walk {
+        opts,
+        rules,
+        stack: Vec::new(),
+    };
+    let mut limits = Limits::default();
+    let mut failed = FileErrors::default();
+    walk.enter(dir.try_clone()?, String::new(), Vec::new(), None)?;
+
+    while let Some((name, file_type)) = walk.next_child() {
+        check_cancel(opts)?;
+        let frame = walk.parent();
+        let rel = format!("{}{}", frame.rel, name.to_string_lossy());
+        let mut raw = frame.raw.clone();
+        raw.extend(path_to_unix_bytes(Path::new(&name)));
+        let norm = walk.normalize(&rel);
+
+        if file_type.is_dir() {
+            let depth = walk.stack.len();
+            check_depth(depth, opts)?;
+            let sub = match frame.dir.open_dir(&name) {
+                Ok(sub) => sub,
+                Err(e) => match os_limit(Path::new(&rel), depth, &e) {
+                    Some(fatal) => return Err(fatal),
+                    None => {
+                        report_skip(&Warning::unreadable(Path::new(&rel), e), opts)?;
+                        continue;
+                    }
+                },
+            };
+            raw.push(b'/');
+            walk.enter(sub, format!("{rel}/"), raw, Some(norm))?;
+            continue;
+        }
+
+        if walk.is_ignored(&norm) {
+            continue;
+        }
+        if let Some(cutoff) = opts.modified_since {
+            let md = match file_type.is_file() {
+                true => frame.dir.metadata(&name)?,
+                false => frame.dir.symlink_metadata(&name)?,
+            };
+            if md.modified()?.into_std() <= cutoff {
+                continue;
+            }
+        }
+        // The lossy `rel` differs from the exact bytes for names that are
+        // not valid UTF-8.
+        let path = match raw == rel.as_bytes() {
+            true => framed_path(norm.clone(), None, opts),
+            false => match opts.non_utf8_names {
+                Policy::Ignore => continue,
+                Policy::Error => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("file name is not valid UTF-8: {norm}"),
+                    ));
+                }
+                Policy::Record => framed_path(norm.clone(), Some(raw), opts),
+            },
+        };
+        if file_type.is_symlink() {
+            // Links that resolve inside the handle are skipped like valid
+            // links in a walk by path; the others count as dangling.
+            let escapes = match frame.dir.metadata(&name) {
+                Ok(_) => continue,
+                Err(e) => e.kind() == io::ErrorKind::PermissionDenied,
+            };
+            let target = match frame.dir.read_link_contents(&name) {
+                Ok(target) => target.to_string_lossy().replace('\\', "/"),
+                Err(_) => continue, // gone since it was listed
+            };
+            match opts.broken_symlinks {
+                Policy::Ignore => continue,
+                Policy::Error if escapes => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!("symlink leaves the root: {norm} -> {target}"),
+                    ));
+                }
+                Policy::Error => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("broken symlink: {norm} -> {target}"),
+                    ));
+                }
+                Policy::Record => {}
+            }
+            limits.entry(opts)?;
+            out.link(&path, target.as_bytes());
+            continue;
+        }
+
+        if !file_type.is_file() {
+            let file_type = special_file_type(file_type);
+            match opts.special_files {
+                Policy::Ignore => continue,
+                Policy::Error => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("special file: {norm} ({})", special_file_name(file_type)),
+                    ));
+                }
+                Policy::Record => {}
+            }
+            limits.entry(opts)?;
+            out.special(&path, file_type);
+            continue;
+        }
+
+        let file = match frame.dir.open(&name) {
+            Ok(file) => file.into_std(),
+            Err(e) => match os_limit(Path::new(&rel), walk.stack.len(), &e) {
+                Some(fatal) => return Err(fatal),
+                None => {
+                    report_skip(&Warning::unreadable(Path::new(&rel), e), opts)?;
+                    continue;
+                }
+            },
+        };
+        let md = match file.metadata() {
+            Ok(md) => md,
+            Err(e) => {
+                failed.set_aside(FileOp::Metadata.on(Path::new(&rel))(e), opts)?;
+                continue;
+            }
+        };
+        if !md.is_file() {
+            // Replaced since it was listed; the next run sees what it is now.
+            continue;
+        }
+        let size = md.len();
+        if opts.structure_only {
+            limits.entry(opts)?;
+            out.oversize(&path, size);
+            continue;
+        }
+        if let Some(limit) = opts.max_file_size
+            && size > limit
+        {
+            match opts.oversize_policy {
+                OversizePolicy::Skip => continue,
+                OversizePolicy::Error => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::FileTooLarge,
+                        format!("file too large: {norm} ({size} bytes, limit {limit})"),
+                    ));
+                }
+                OversizePolicy::SkipWithRecord => {
+                    limits.entry(opts)?;
+                    out.oversize(&path, size);
+                    continue;
+                }
+                OversizePolicy::Hash => {}
+            }
+        }
+        limits.entry(opts)?;
+        limits.bytes(size, opts)?;
+        let mut inner = blake3::Hasher::new();
+        let mut reader = throttle.reader(file);
+        let copied = if opts.normalize_newlines {
+            crate::newline::copy(&mut reader, &mut inner)
+        } else {
+            io::copy(&mut reader, &mut inner)
+        };
+        if let Err(e) = copied {
+            failed.set_aside(FileOp::Read.on(Path::new(&rel))(e), opts)?;
+            continue;
+        }
+        out.file(&path, inner.finalize().as_bytes(), &[]);
+    }
+
+    failed.finish()?;
+    Ok(hex_lower(&out.finalize(opts.output_len)?))
+}
+
+/// Reject the options [`get_dir_hash_at`] cannot honour.
+fn check_supported(opts: &Options) -> io::Result<()> {
+    let unsupported = [
+        (opts.follow_symlinks, "follow_symlinks"),
+        (opts.include_metadata, "include_metadata"),
+        (opts.include_file_type, "include_file_type"),
+        (opts.include_root_name, "include_root_name"),
+        (opts.path_base.is_some(), "path_base"),
+        (opts.chunking.is_some(), "chunking"),
+        (opts.checkpoint.is_some(), "checkpoint"),
+        (
+            opts.concurrent_modification != ModificationPolicy::Ignore,
+            "concurrent_modification",
+        ),
+        (
+            !opts.case_sensitive_paths && opts.case_collisions == CaseCollisionPolicy::Error,
+            "case_collisions = error",
+        ),
+    ];
+    match unsupported.iter().find(|(set, _)| *set) {
+        Some((_, name)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{name} is not supported when hashing a directory handle"),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// State of one [`get_dir_hash_at`] run.
+struct Walk<'a> {
+    opts: &'a Options,
+    rules: IgnoreRules,
+    /// Open directories, innermost last.
+    stack: Vec<Frame>,
+}
+
+struct Frame {
+    dir: Dir,
+    /// Relative path of the directory with a trailing `/` (empty for the
+    /// root), lossy and not normalized.
+    rel: String,
+    /// Exact bytes of `rel`.
+    raw: Vec<u8>,
+    /// Remaining children, in [`order_key`] order.
+    children: VecDeque<(OsString, FileType)>,
+    /// Rules from this directory's own ignore file, with the directory's
+    /// normalized relative path and a trailing `/`.
+    rules: Option<(String, IgnoreRules)>,
+}
+
+impl Walk<'_> {
+    /// List `dir` and push its frame; `prefix` is its normalized relative
+    /// path, `None` for the root. Pruned directories are left out here.
+    fn enter(
+        &mut self,
+        dir: Dir,
+        rel: String,
+        raw: Vec<u8>,
+        prefix: Option<String>,
+    ) -> io::Result<()> {
+        let lo = ListOptions::new(self.opts);
+        let mut children = Vec::new();
+        let entries = match dir.entries() {
+            Ok(entries) => entries,
+            Err(e) => match os_limit(Path::new(&rel), self.stack.len(), &e) {
+                Some(fatal) => return Err(fatal),
+                None => {
+                    report_skip(&Warning::unreadable(Path::new(&rel), e), self.opts)?;
+                    return Ok(());
+                }
+            },
+        };
+        for entry in entries {
+            let typed = entry.and_then(|e| Ok((e.file_name(), e.file_type()?)));
+            match typed {
+                Ok(child) => children.push(child),
+                Err(e) => report_skip(&Warning::unreadable(Path::new(&rel), e), self.opts)?,
+            }
+        }
+
+        let rules = match prefix {
+            Some(prefix) if self.opts.load_dot_get_dir_hash_ignore => {
+                let has_file = children
+                    .iter()
+                    .any(|(name, ft)| ft.is_file() && name == IGNORE_FILE_NAME);
+                match has_file {
+                    true => {
+                        let path = PathBuf::from(format!("{rel}{IGNORE_FILE_NAME}"));
+                        let txt = dir.read_to_string(IGNORE_FILE_NAME)?;
+                        let rules = IgnoreRules::from_text(&txt, &path).map_err(|e| {
+                            io::Error::new(e.kind(), format!("{}: {e}", path.display()))
+                        })?;
+                        for warning in &rules.warnings {
+                            report_skip(warning, self.opts)?;
+                        }
+                        Some((format!("{prefix}/"), rules))
+                    }
+                    false => None,
+                }
+            }
+            _ => None,
+        };
+        self.stack.push(Frame {
+            dir,
+            rel,
+            raw,
+            children: VecDeque::new(),
+            rules,
+        });
+
+        // Prune with this directory's own rules in place.
+        let frame = self.parent();
+        children.retain(|(name, ft)| {
+            let rel = format!("{}{}", frame.rel, name.to_string_lossy());
+            !ft.is_dir() || !self.is_dir_ignored(&self.normalize(&rel))
+        });
+        children.sort_by_cached_key(|(name, ft)| order_key(name, ft.is_dir(), lo));
+        if let Some(frame) = self.stack.last_mut() {
+            frame.children = children.into();
+        }
+        Ok(())
+    }
+
+    /// The next child of the innermost open directory, closing directories
+    /// that are done.
+    fn next_child(&mut self) -> Option<(OsString, FileType)> {
+        loop {
+            let frame = self.stack.last_mut()?;
+            match frame.children.pop_front() {
+                Some(child) => return Some(child),
+                None => self.stack.pop(),
+            };
+        }
+    }
+
+    /// The innermost open directory.
+    fn parent(&self) -> &Frame {
+        self.stack.last().expect("a directory is open")
+    }
+
+    /// `rel` as ignore rules and frames see it.
+    fn normalize(&self, rel: &str) -> String {
+        match self.opts.unicode_normalization {
+            Some(form) => form.apply(rel),
+            None => rel.to_owned(),
+        }
+    }
+
+    /// Whether the file at `rel` is excluded by the root rules or the ignore
+    /// file of any open directory.
+    fn is_ignored(&self, rel: &str) -> bool {
+        self.rules.is_ignored(rel)
+            || self.nested().any(|(prefix, rules)| {
+                rel.strip_prefix(prefix.as_str())
+                    .is_some_and(|r| rules.is_ignored(r))
+            })
+    }
+
+    /// Directory counterpart of [`Walk::is_ignored`].
+    fn is_dir_ignored(&self, rel: &str) -> bool {
+        self.rules.is_dir_ignored(rel)
+            || self.nested().any(|(prefix, rules)| {
+                rel.strip_prefix(prefix.as_str())
+                    .is_some_and(|r| rules.is_dir_ignored(r))
+            })
+    }
+
+    fn nested(&self) -> impl Iterator<Item = &(String, IgnoreRules)> {
+        self.stack.iter().filter_map(|f| f.rules.as_ref())
+    }
+}
+
+/// [`special_file_type`](crate::special_file_type) for a listed entry.
+fn special_file_type(file_type: FileType) -> u8 {
+    #[cfg(unix)]
+    {
+        use cap_std::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            return b'p';
+        } else if file_type.is_socket() {
+            return b's';
+        } else if file_type.is_block_device() {
+            return b'b';
+        } else if file_type.is_char_device() {
+            return b'c';
+        }
+    }
+    let _ = file_type;
+    b'?'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_dir_hash;
+    use std::fs;
+
+    fn open(path: &Path) -> Dir {
+        Dir::open_ambient_dir(path, cap_std::ambient_authority()).unwrap()
+    }
+
+    #[test]
+    fn matches_a_walk_by_path() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path();
+        fs::create_dir_all(path.join("a/b")).unwrap();
+        fs::create_dir(path.join("empty")).unwrap();
+        fs::write(path.join(".get_dir_hash_ignore"), "*.log\n").unwrap();
+        fs::write(path.join("a/.get_dir_hash_ignore"), "skip\n").unwrap();
+        fs::write(path.join("top.txt"), "one\r\ntwo\n").unwrap();
+        fs::write(path.join("run.log"), "ignored").unwrap();
+        fs::write(path.join("a/skip"), "ignored").unwrap();
+        fs::write(path.join("a/b/c.bin"), [0, 159, 146, 150]).unwrap();
+        fs::write(path.join("a-b"), "").unwrap();
+        let cases = [
+            Options::default(),
+            Options {
+                normalize_newlines: true,
+                ..Options::default()
+            },
+            Options {
+                structure_only: true,
+                ..Options::default()
+            },
+            Options {
+                ignore_patterns: vec!["a/b/**".into()],
+                max_file_size: Some(3),
+                oversize_policy: OversizePolicy::SkipWithRecord,
+                ..Options::default()
+            },
+        ];
+        let dir = open(path);
+        let mut digests = Vec::new();
+        for opts in &cases {
+            let digest = get_dir_hash_at(&dir, opts).unwrap();
+            assert_eq!(digest, get_dir_hash(path, opts).unwrap());
+            assert!(!digests.contains(&digest));
+            digests.push(digest);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_out_of_the_handle_count_as_dangling() {
+        use std::os::unix::fs::symlink;
+
+        let parent = tempfile::tempdir().unwrap();
+        let (path, outside) = (parent.path().join("tree"), parent.path().join("outside"));
+        fs::create_dir(&path).unwrap();
+        fs::write(&outside, "secret").unwrap();
+        fs::write(path.join("a.txt"), "a").unwrap();
+        symlink("a.txt", path.join("inside")).unwrap();
+        symlink("../outside", path.join("escape")).unwrap();
+        let record = Options {
+            broken_symlinks: Policy::Record,
+            ..Options::default()
+        };
+        let dir = open(&path);
+        let digest = get_dir_hash_at(&dir, &record).unwrap();
+        // By path the link resolves and is skipped, until its target goes.
+        assert_ne!(digest, get_dir_hash(&path, &record).unwrap());
+        fs::remove_file(&outside).unwrap();
+        assert_eq!(digest, get_dir_hash(&path, &record).unwrap());
+        // Left out by default, like any dangling link.
+        assert_eq!(
+            get_dir_hash_at(&dir, &Options::default()).unwrap(),
+            get_dir_hash(&path, &Options::default()).unwrap()
+        );
+
+        fs::write(&outside, "secret").unwrap();
+        let error = Options {
+            broken_symlinks: Policy::Error,
+            ..Options::default()
+        };
+        let err = get_dir_hash_at(&dir, &error).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn options_needing_a_path_are_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = open(root.path());
+        let cases = [
+            Options {
+                follow_symlinks: true,
+                ..Options::default()
+            },
+            Options {
+                include_metadata: true,
+                ..Options::default()
+            },
+            Options {
+                concurrent_modification: ModificationPolicy::Retry,
+                ..Options::default()
+            },
+        ];
+        for opts in &cases {
+            let err = get_dir_hash_at(&dir, opts).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{err}");
+        }
+    }
+}
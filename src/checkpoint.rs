@@ -0,0 +1,389 @@
+//! Resumable runs ([`crate::Options::checkpoint`]).
+//!
+//! BLAKE3's internal state cannot be saved, but the outer stream is a pure
+//! function of the per-file records. A checkpoint therefore lists the files
+//! completed so far (path, size, mtime, content digest, in the snapshot entry
+//! encoding); a resumed run walks the tree again and re-feeds the stream from
+//! those digests, reading only files that are new or whose size or mtime
+//! changed since.
+//!
+//! ```text
+//! get_dir_hash-checkpoint-v1 \0  count  entry*
+//! ```
+//!
+//! Several runs may share a checkpoint path (CI runners on one volume): the
+//! run holding the advisory lock on `<path>.lock` owns the checkpoint, any
+//! other one hashes without it. The lock file is never deleted: a run that
+//! opened it just before the unlink would lock the orphaned file while the
+//! next run locks a new one, and both would own the checkpoint. Saves go
+//! through a per-process temporary file and a rename, so the file is never
+//! seen half-written, and one that cannot be decoded all the same is
+//! dropped instead of failing the run; both cases are reported as
+//! [`WarningReason::Checkpoint`].
+
+use crate::codec::{Reader, put_u64};
+use crate::snapshot::{self, put_entry, read_entry};
+use crate::{SnapshotEntry, Warning, WarningReason};
+use std::collections::HashMap;
+use std::fs::{self, File, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8] = b"get_dir_hash-checkpoint-v1\0";
+
+/// Where and how often to save progress.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Checkpoint {
+    /// Checkpoint file. An existing one is resumed from; it is deleted once
+    /// the run completes (its `.lock` file stays).
+    pub path: PathBuf,
+    /// Minimum time between two saves. The file is also written when the
+    /// run stops early (error or cancellation).
+    pub interval: Duration,
+}
+
+impl Checkpoint {
+    /// Save to `path` every minute.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Checkpoint bookkeeping for one run.
+pub(crate) struct State {
+    config: Checkpoint,
+    /// Files completed by the run being resumed and not yet seen again, by
+    /// framed path.
+    previous: HashMap<String, SnapshotEntry>,
+    /// Files completed by this run, in order.
+    done: Vec<SnapshotEntry>,
+    last_save: Instant,
+    /// Held for the whole run, released on drop; `None` where file locks
+    /// are unsupported.
+    _lock: Option<File>,
+}
+
+impl State {
+    /// Lock and load the checkpoint at `config.path`, if there is one.
+    ///
+    /// `None` if another run holds the lock. A checkpoint that cannot be
+    /// decoded is started over. Either case comes with a warning.
+    pub(crate) fn open(config: &Checkpoint) -> io::Result<(Option<State>, Option<Warning>)> {
+        let warning = |why: String| Warning {
+            path: config.path.clone(),
+            reason: WarningReason::Checkpoint(why),
+        };
+        let lock = match lock(&config.path) {
+            Ok(lock) => lock,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                return Ok((None, Some(warning("in use by another run".into()))));
+            }
+            Err(e) => return Err(e),
+        };
+        let (previous, warning) = match fs::read(&config.path) {
+            Ok(bytes) => match decode(&bytes) {
+                Ok(previous) => (previous, None),
+                Err(e) => (Vec::new(), Some(warning(format!("{e}; starting over")))),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (Vec::new(), None),
+            Err(e) => return Err(e),
+        };
+        let state = State {
+            config: config.clone(),
+            previous: previous.into_iter().map(|e| (e.path.clone(), e)).collect(),
+            done: Vec::new(),
+            last_save: Instant::now(),
+            _lock: lock,
+        };
+        Ok((Some(state), warning))
+    }
+
+    /// Content digest recorded for the file by the resumed run, if it still
+    /// has the same size and mtime.
+    pub(crate) fn reuse(&self, current: &SnapshotEntry) -> Option<blake3::Hash> {
+        snapshot::reuse(&self.previous, current)
+    }
+
+    /// Note a completed file and save if the interval has passed.
+    pub(crate) fn record(&mut self, entry: SnapshotEntry) -> io::Result<()> {
+        self.previous.remove(&entry.path);
+        self.done.push(entry);
+        if self.last_save.elapsed() >= self.config.interval {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// The checkpoint file.
+    pub(crate) fn path(&self) -> &Path {
+        &self.config.path
+    }
+
+    /// The run completed: remove the checkpoint file. The lock is released
+    /// when `self` drops.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match fs::remove_file(&self.config.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// The run stopped early (error, cancellation, deadline): save the
+    /// files it completed, if any.
+    pub(crate) fn stop(mut self) -> io::Result<()> {
+        if self.done.is_empty() {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    /// Write all completed files, replacing the previous checkpoint
+    /// atomically. Files of the resumed run that this run has not reached
+    /// yet are kept, so stopping early never loses progress.
+    fn save(&mut self) -> io::Result<()> {
+        let mut out = MAGIC.to_vec();
+        put_u64(&mut out, (self.done.len() + self.previous.len()) as u64);
+        for entry in self.done.iter().chain(self.previous.values()) {
+            put_entry(&mut out, entry)?;
+        }
+        let mut tmp = self.config.path.clone().into_os_string();
+        tmp.push(format!(".{}.tmp", std::process::id()));
+        let res = fs::write(&tmp, out).and_then(|()| fs::rename(&tmp, &self.config.path));
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        res?;
+        self.last_save = Instant::now();
+        Ok(())
+    }
+}
+
+/// The lock file of the checkpoint at `path`.
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    PathBuf::from(lock)
+}
+
+/// Take the advisory lock of the checkpoint at `path`: `WouldBlock` if
+/// another run holds it, `None` where the platform has no file locks.
+fn lock(path: &Path) -> io::Result<Option<File>> {
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path(path))?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(file)),
+        Err(TryLockError::WouldBlock) => Err(io::ErrorKind::WouldBlock.into()),
+        Err(TryLockError::Error(e)) if e.kind() == io::ErrorKind::Unsupported => Ok(None),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
+
+fn decode(bytes: &[u8]) -> io::Result<Vec<SnapshotEntry>> {
+    let mut r = Reader::new(bytes, MAGIC, "checkpoint")?;
+    let mut entries = Vec::new();
+    for _ in 0..r.u64()? {
+        entries.push(read_entry(&mut r)?);
+    }
+    r.finish()?;
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CancellationToken, Cancelled, Options, ProgressCallback, WarningCallback,
+        get_dir_hash_with_stats,
+    };
+    use std::time::SystemTime;
+
+    /// Ten files of 100 bytes each.
+    fn tree(root: &Path) {
+        for i in 0..10 {
+            fs::write(root.join(format!("f{i}")), [b'0' + i; 100]).unwrap();
+        }
+    }
+
+    fn options(checkpoint: &Path) -> Options {
+        Options {
+            checkpoint: Some(Checkpoint {
+                path: checkpoint.to_path_buf(),
+                interval: Duration::ZERO,
+            }),
+            ..Options::default()
+        }
+    }
+
+    /// Run with `checkpoint`, cancelled once `stop_after` files are done.
+    fn interrupted(root: &Path, checkpoint: &Path, stop_after: u64) {
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        let opts = Options {
+            cancel: Some(token),
+            progress: Some(ProgressCallback::new(move |p| {
+                if p.files_done == stop_after {
+                    cancel.cancel();
+                }
+            })),
+            ..options(checkpoint)
+        };
+        let e = get_dir_hash_with_stats(root, &opts).unwrap_err();
+        assert_eq!(Cancelled::find(&e).unwrap().stats.files_hashed, stop_after);
+        assert!(checkpoint.exists());
+    }
+
+    #[test]
+    fn resumed_run_matches_a_fresh_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, checkpoint) = (dir.path().join("tree"), dir.path().join("ckpt"));
+        fs::create_dir(&root).unwrap();
+        tree(&root);
+        let fresh = get_dir_hash_with_stats(&root, &Options::default()).unwrap();
+
+        interrupted(&root, &checkpoint, 4);
+        let resumed = get_dir_hash_with_stats(&root, &options(&checkpoint)).unwrap();
+        assert_eq!(resumed.hex, fresh.hex);
+        assert_eq!(resumed.stats.files_hashed, 10);
+        // The four completed files are not read again.
+        assert_eq!(resumed.stats.bytes_hashed, 600);
+        assert!(!checkpoint.exists());
+    }
+
+    #[test]
+    fn files_changed_since_the_checkpoint_are_read_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, checkpoint) = (dir.path().join("tree"), dir.path().join("ckpt"));
+        fs::create_dir(&root).unwrap();
+        tree(&root);
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        for i in 0..10 {
+            File::options()
+                .write(true)
+                .open(root.join(format!("f{i}")))
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+
+        interrupted(&root, &checkpoint, 4);
+        // A new size, and the same size with a new mtime.
+        fs::write(root.join("f0"), [b'x'; 150]).unwrap();
+        fs::write(root.join("f1"), [b'y'; 100]).unwrap();
+        let fresh = get_dir_hash_with_stats(&root, &Options::default()).unwrap();
+        let resumed = get_dir_hash_with_stats(&root, &options(&checkpoint)).unwrap();
+        assert_eq!(resumed.hex, fresh.hex);
+        assert_eq!(resumed.stats.bytes_hashed, 150 + 100 + 600);
+    }
+
+    #[test]
+    fn one_run_owns_a_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Checkpoint::new(dir.path().join("ckpt"));
+        let (owner, warning) = State::open(&config).unwrap();
+        assert!(owner.is_some() && warning.is_none());
+
+        let (other, warning) = State::open(&config).unwrap();
+        assert!(other.is_none());
+        let warning = warning.unwrap();
+        assert_eq!(warning.path, config.path);
+        assert!(
+            matches!(warning.reason, WarningReason::Checkpoint(ref why) if why.contains("in use"))
+        );
+
+        // The lock file outlives the run; only the checkpoint goes.
+        owner.unwrap().finish().unwrap();
+        assert!(lock_path(&config.path).exists());
+        assert!(!config.path.exists());
+        let (next, warning) = State::open(&config).unwrap();
+        assert!(next.is_some() && warning.is_none());
+    }
+
+    #[test]
+    fn corrupt_checkpoint_is_started_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, checkpoint) = (dir.path().join("tree"), dir.path().join("ckpt"));
+        fs::create_dir(&root).unwrap();
+        tree(&root);
+        fs::write(&checkpoint, b"get_dir_hash-checkpoint-v1\0garbage").unwrap();
+
+        let fresh = get_dir_hash_with_stats(&root, &Options::default()).unwrap();
+        let run = get_dir_hash_with_stats(&root, &options(&checkpoint)).unwrap();
+        assert_eq!(run.hex, fresh.hex);
+        assert_eq!(run.stats.bytes_hashed, 1000);
+        assert!(matches!(
+            run.stats.warnings.as_slice(),
+            [Warning { reason: WarningReason::Checkpoint(why), .. }] if why.contains("starting over")
+        ));
+        assert!(!checkpoint.exists());
+    }
+
+    #[test]
+    fn out_of_range_mtime_is_started_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, checkpoint) = (dir.path().join("tree"), dir.path().join("ckpt"));
+        fs::create_dir(&root).unwrap();
+        tree(&root);
+        let mut bytes = MAGIC.to_vec();
+        put_u64(&mut bytes, 1);
+        crate::codec::put_field(&mut bytes, b"f0");
+        put_u64(&mut bytes, 100);
+        bytes.push(1);
+        bytes.extend_from_slice(&i64::MAX.to_le_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&[0; 32]);
+        fs::write(&checkpoint, bytes).unwrap();
+
+        let run = get_dir_hash_with_stats(&root, &options(&checkpoint)).unwrap();
+        let fresh = get_dir_hash_with_stats(&root, &Options::default()).unwrap();
+        assert_eq!(run.hex, fresh.hex);
+        assert_eq!(run.stats.bytes_hashed, 1000);
+        assert!(matches!(
+            run.stats.warnings.as_slice(),
+            [Warning { reason: WarningReason::Checkpoint(why), .. }] if why.contains("mtime")
+        ));
+    }
+
+    #[test]
+    fn failed_save_is_a_warning() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, checkpoint) = (dir.path().join("tree"), dir.path().join("ckpt"));
+        fs::create_dir(&root).unwrap();
+        tree(&root);
+        let token = CancellationToken::new();
+        let (cancel, blocked) = (token.clone(), checkpoint.clone());
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = std::sync::Arc::clone(&seen);
+        let opts = Options {
+            cancel: Some(token),
+            // A directory in its place makes the save on cancellation fail.
+            progress: Some(ProgressCallback::new(move |p| {
+                if p.files_done == 4 {
+                    fs::create_dir(&blocked).unwrap();
+                    cancel.cancel();
+                }
+            })),
+            on_warning: Some(WarningCallback::new(move |w| {
+                sink.lock().unwrap().push(w.clone())
+            })),
+            ..Options {
+                checkpoint: Some(Checkpoint::new(&checkpoint)),
+                ..Options::default()
+            }
+        };
+        let e = get_dir_hash_with_stats(&root, &opts).unwrap_err();
+        let warnings = &Cancelled::find(&e).unwrap().stats.warnings;
+        assert!(matches!(
+            warnings.as_slice(),
+            [Warning { reason: WarningReason::CheckpointSave(_), path }] if *path == checkpoint
+        ));
+        assert_eq!(*seen.lock().unwrap(), *warnings);
+    }
+}
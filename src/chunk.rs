@@ -0,0 +1,185 @@
+//! Content-defined chunking ([`crate::Options::chunking`]).
+//!
+//! Files are cut with FastCDC (the 2020 variant, normalization level 1) in
+//! the same pass that computes their content digest, and every chunk is
+//! hashed with BLAKE3. The chunk list is framed after the file's metadata as
+//! `\0C\0` + count + (length + digest[32])* (u64 LE), so the root digest
+//! also pins the chunk boundaries.
+
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// FastCDC chunk size bounds, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct Chunking {
+    /// Smallest chunk, except for the last one of a file (64 B ..= 1 MiB).
+    pub min_size: u32,
+    /// Target average chunk size (256 B ..= 4 MiB).
+    pub avg_size: u32,
+    /// Largest chunk (1 KiB ..= 16 MiB).
+    pub max_size: u32,
+}
+
+impl Default for Chunking {
+    /// 16 KiB / 64 KiB / 256 KiB.
+    fn default() -> Self {
+        Self {
+            min_size: 16 * 1024,
+            avg_size: 64 * 1024,
+            max_size: 256 * 1024,
+        }
+    }
+}
+
+impl Chunking {
+    /// Check the bounds FastCDC supports: each size even and within its
+    /// range, and `min_size <= avg_size <= max_size`.
+    pub fn validate(&self) -> io::Result<()> {
+        let Chunking {
+            min_size,
+            avg_size,
+            max_size,
+        } = *self;
+        let ok = (64..=1 << 20).contains(&min_size)
+            && (256..=4 << 20).contains(&avg_size)
+            && (1024..=16 << 20).contains(&max_size)
+            && [min_size, avg_size, max_size].iter().all(|s| s % 2 == 0)
+            && min_size <= avg_size
+            && avg_size <= max_size;
+        if ok {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid chunk sizes {min_size}:{avg_size}:{max_size}"),
+            ))
+        }
+    }
+}
+
+impl FromStr for Chunking {
+    type Err = crate::ParseOptionError;
+
+    /// Parses `MIN:AVG:MAX` in bytes, e.g. `16384:65536:262144`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || crate::ParseOptionError::new(s, "MIN:AVG:MAX chunk sizes in bytes");
+        let mut parts = s.split(':').map(|p| p.parse::<u32>().map_err(|_| err()));
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(min_size), Some(avg_size), Some(max_size), None) => Ok(Chunking {
+                min_size: min_size?,
+                avg_size: avg_size?,
+                max_size: max_size?,
+            }),
+            _ => Err(err()),
+        }
+    }
+}
+
+/// One content-defined chunk of a file in a [`crate::Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Chunk {
+    /// Byte offset of the chunk in the file.
+    pub offset: u64,
+    /// Length in bytes.
+    pub length: u64,
+    /// Lowercase hex BLAKE3 digest of the chunk.
+    pub hex: String,
+}
+
+/// Lengths and digests of a file's chunks, in file order.
+pub(crate) type Chunks = Vec<(u64, blake3::Hash)>;
+
+/// Public view of `chunks`.
+pub(crate) fn describe(chunks: &Chunks) -> Vec<Chunk> {
+    let mut offset = 0;
+    chunks
+        .iter()
+        .map(|(length, digest)| {
+            let chunk = Chunk {
+                offset,
+                length: *length,
+                hex: digest.to_hex().to_string(),
+            };
+            offset += length;
+            chunk
+        })
+        .collect()
+}
+
+/// Append the chunk record for `chunks` to a file's trailing block.
+pub(crate) fn feed_chunks(out: &mut Vec<u8>, chunks: &Chunks) {
+    out.extend_from_slice(b"\0C\0");
+    out.extend_from_slice(&(chunks.len() as u64).to_le_bytes());
+    for (length, digest) in chunks {
+        out.extend_from_slice(&length.to_le_bytes());
+        out.extend_from_slice(digest.as_bytes());
+    }
+}
+
+/// Reject unusable settings before the walk starts.
+pub(crate) fn check(sizes: Chunking) -> io::Result<()> {
+    if !cfg!(feature = "cdc") {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "content-defined chunking requires the `cdc` feature",
+        ));
+    }
+    sizes.validate()
+}
+
+/// Hash `path` whole and chunk by chunk in one pass; returns the content
+/// digest, the bytes read and the chunks. The bytes are also fed to `others`.
+#[cfg(feature = "cdc")]
+pub(crate) fn hash_file(
+    path: &Path,
+    opts: &crate::Options,
+    sizes: Chunking,
+    others: &mut [crate::algo::Hasher],
+    throttle: &mut crate::throttle::Throttle,
+) -> io::Result<(blake3::Hash, u64, Chunks)> {
+    use fastcdc::v2020::{Error, StreamCDC};
+
+    let file = std::fs::File::open(path).map_err(crate::FileOp::Open.on(path))?;
+    let file = throttle.reader(file);
+    let cdc = StreamCDC::new(
+        file,
+        sizes.min_size as usize,
+        sizes.avg_size as usize,
+        sizes.max_size as usize,
+    );
+    let mut whole = blake3::Hasher::new();
+    let mut total = 0;
+    let mut chunks = Vec::new();
+    for chunk in cdc {
+        crate::check_cancel(opts)?;
+        let chunk = chunk.map_err(|e| match e {
+            Error::IoError(e) => crate::FileOp::Read.on(path)(e),
+            e => io::Error::other(e.to_string()),
+        })?;
+        whole.update(&chunk.data);
+        for other in others.iter_mut() {
+            other.update(&chunk.data);
+        }
+        total += chunk.length as u64;
+        chunks.push((chunk.length as u64, blake3::hash(&chunk.data)));
+    }
+    Ok((whole.finalize(), total, chunks))
+}
+
+#[cfg(not(feature = "cdc"))]
+pub(crate) fn hash_file(
+    _path: &Path,
+    _opts: &crate::Options,
+    sizes: Chunking,
+    _others: &mut [crate::algo::Hasher],
+    _throttle: &mut crate::throttle::Throttle,
+) -> io::Result<(blake3::Hash, u64, Chunks)> {
+    check(sizes).map(|()| unreachable!("rejected by `check`"))
+}
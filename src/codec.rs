@@ -0,0 +1,108 @@
+//! Little helpers for the binary formats this crate writes (inclusion proofs,
+//! snapshots, manifests): integers are u64 LE and byte strings are
+//! length-prefixed. Manifests, where size matters most, use LEB128 varints
+//! instead.
+
+use std::io;
+
+/// Append `n` as u64 LE.
+pub(crate) fn put_u64(out: &mut Vec<u8>, n: u64) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+/// Append `bytes` preceded by their length.
+pub(crate) fn put_field(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_u64(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Append `n` as an LEB128 varint: 7 bits per byte, low bits first.
+pub(crate) fn put_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// Append `bytes` preceded by their length as a varint.
+pub(crate) fn put_short_field(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Cursor over an encoded buffer; `what` names the format in errors.
+pub(crate) struct Reader<'a> {
+    rest: &'a [u8],
+    what: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    /// Start reading `bytes`, which must begin with `magic`.
+    pub(crate) fn new(bytes: &'a [u8], magic: &[u8], what: &'static str) -> io::Result<Self> {
+        match bytes.strip_prefix(magic) {
+            Some(rest) => Ok(Self { rest, what }),
+            None => Err(invalid(format!("not a {what}"))),
+        }
+    }
+
+    pub(crate) fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.rest.len() < n {
+            return Err(invalid(format!("truncated {}", self.what)));
+        }
+        let (head, rest) = self.rest.split_at(n);
+        self.rest = rest;
+        Ok(head)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("N bytes"))
+    }
+
+    pub(crate) fn u64(&mut self) -> io::Result<u64> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    pub(crate) fn varint(&mut self) -> io::Result<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let [byte] = self.array()?;
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return Ok(n);
+            }
+        }
+        Err(invalid(format!("oversized varint in {}", self.what)))
+    }
+
+    /// A field written by [`put_short_field`].
+    pub(crate) fn short_field(&mut self) -> io::Result<&'a [u8]> {
+        let n = usize::try_from(self.varint()?)
+            .map_err(|_| invalid(format!("oversized field in {}", self.what)))?;
+        self.take(n)
+    }
+
+    pub(crate) fn field(&mut self) -> io::Result<&'a [u8]> {
+        let n = usize::try_from(self.u64()?)
+            .map_err(|_| invalid(format!("oversized field in {}", self.what)))?;
+        self.take(n)
+    }
+
+    /// An `InvalidData` error about the value just read.
+    pub(crate) fn invalid(&self, msg: &str) -> io::Error {
+        invalid(format!("{msg} in {}", self.what))
+    }
+
+    /// Fail unless everything has been read.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        if self.rest.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid(format!("trailing bytes after {}", self.what)))
+        }
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
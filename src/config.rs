@@ -0,0 +1,277 @@
+//! `Options` loaded from a TOML file.
+//!
+//! Keys mirror the `Options` field names; anything not set keeps its default.
+//! `algorithm` has no field: it is kept in [`Config`] for callers that hash
+//! with several algorithms, as the CLI's `--algorithm` does.
+//! Unknown keys are rejected so that a typo cannot silently change a digest.
+//! A `preset` is applied first, wherever it appears; the other keys override
+//! it.
+//!
+//! ```toml
+//! preset = "portable"                 # portable, strict or fast
+//! ignore_patterns = ["target/**", "*.log"]
+//! ignore_files = ["ci/hash_ignore"]   # relative to this file
+//! extensions = ["rs", "toml"]         # only **/*.rs and **/*.toml
+//! pathspecs = ["src/", "proto/"]      # only these subtrees
+//! # or with a format: { path = ".dockerignore", format = "dockerignore" }
+//! include_metadata = false
+//! follow_symlinks = true
+//! broken_symlinks = "record"
+//! timestamps = ["mtime", "ctime"]     # with include_metadata; [] for none
+//! clamp_mtime = 1700000000            # seconds since the Unix epoch
+//! deadline = 30                       # seconds per run
+//! format_version = 2                  # or "v2"; default 1
+//! algorithm = "blake3,sha256"         # digests to print; default blake3
+//! output_len = 64                     # root digest bytes; default 32
+//! ```
+
+use crate::{
+    Algorithm, FormatVersion, IgnoreFile, IgnoreFileFormat, Options, ParseOptionError, Preset,
+};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+use toml::Spanned;
+use toml::de::{DeTable, DeValue};
+
+/// Name of the config file the CLI picks up from the hashed directory.
+pub const CONFIG_FILE_NAME: &str = ".get_dir_hash.toml";
+
+/// A parsed config file.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Every key but `algorithm`.
+    pub options: Options,
+    /// `algorithm`, a comma-separated list such as `"blake3,sha256"`;
+    /// `None` when the file leaves it out.
+    pub algorithms: Option<Vec<Algorithm>>,
+}
+
+impl Options {
+    /// Build options from a TOML config file.
+    ///
+    /// Relative `ignore_files` entries are resolved against the directory
+    /// containing `path`. Runtime-only fields (`progress`, `cancel`) cannot
+    /// be configured and stay `None`. An `algorithm` key is checked but
+    /// dropped; [`Config::from_file`] keeps it.
+    pub fn from_config_file(path: impl AsRef<Path>) -> io::Result<Options> {
+        Config::from_file(path).map(|config| config.options)
+    }
+}
+
+impl Config {
+    /// Parse a TOML config file; see [`Options::from_config_file`].
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Config> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        parse(&text, base).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", path.display()),
+            )
+        })
+    }
+}
+
+fn parse(text: &str, base: &Path) -> Result<Config, String> {
+    let table = DeTable::parse(text).map_err(|e| e.to_string())?;
+    let mut opts = Options::default();
+    let mut algorithms = None;
+    if let Some((_, value)) = table
+        .get_ref()
+        .iter()
+        .find(|(k, _)| k.get_ref() == "preset")
+    {
+        let line = line_of(text, value.span());
+        let preset: Preset =
+            parsed(value.get_ref()).map_err(|e| format!("line {line}: `preset`: {e}"))?;
+        opts = preset.apply(opts);
+    }
+    for (key, value) in table.get_ref() {
+        let line = line_of(text, value.span());
+        let err = |what: String| format!("line {line}: `{}`: {what}", key.get_ref());
+        let value = value.get_ref();
+        match key.get_ref().as_ref() {
+            "preset" => {}
+            "format_version" => opts.format_version = version(value).map_err(err)?,
+            "algorithm" => algorithms = Some(algorithm_list(value).map_err(err)?),
+            "output_len" => {
+                opts.output_len = usize::try_from(unsigned(value).map_err(&err)?)
+                    .map_err(|_| err("value too large".into()))?
+            }
+            "follow_symlinks" => opts.follow_symlinks = boolean(value).map_err(err)?,
+            "confine_symlinks" => opts.confine_symlinks = boolean(value).map_err(err)?,
+            "include_metadata" => opts.include_metadata = boolean(value).map_err(err)?,
+            "include_root_name" => opts.include_root_name = boolean(value).map_err(err)?,
+            "mode_normalization" => opts.mode_normalization = parsed(value).map_err(err)?,
+            "ownership" => opts.ownership = parsed(value).map_err(err)?,
+            "windows_attributes" => opts.windows_attributes = boolean(value).map_err(err)?,
+            "windows_acl" => opts.windows_acl = boolean(value).map_err(err)?,
+            "include_file_type" => opts.include_file_type = boolean(value).map_err(err)?,
+            "executable_extensions" => opts.executable_extensions = strings(value).map_err(err)?,
+            "timestamps" => {
+                opts.timestamps = (strings(value).map_err(&err)?.iter())
+                    .map(|s| s.parse())
+                    .collect::<Result<_, ParseOptionError>>()
+                    .map_err(|e| err(e.to_string()))?
+            }
+            "mtime_granularity" => opts.mtime_granularity = parsed(value).map_err(err)?,
+            "modified_since" => {
+                let secs = unsigned(value).map_err(err)?;
+                opts.modified_since = Some(UNIX_EPOCH + Duration::from_secs(secs))
+            }
+            "clamp_mtime" => {
+                let secs = unsigned(value).map_err(err)?;
+                opts.clamp_mtime = Some(UNIX_EPOCH + Duration::from_secs(secs))
+            }
+            "case_sensitive_paths" => opts.case_sensitive_paths = boolean(value).map_err(err)?,
+            "unicode_normalization" => {
+                opts.unicode_normalization = Some(parsed(value).map_err(err)?)
+            }
+            "load_dot_get_dir_hash_ignore" => {
+                opts.load_dot_get_dir_hash_ignore = boolean(value).map_err(err)?
+            }
+            "exclude_vcs_dirs" => opts.exclude_vcs_dirs = boolean(value).map_err(err)?,
+            "global_gitignore" => opts.global_gitignore = boolean(value).map_err(err)?,
+            "skip_hidden" => opts.skip_hidden = boolean(value).map_err(err)?,
+            "dedup_hard_links" => opts.dedup_hard_links = boolean(value).map_err(err)?,
+            "ignore_patterns" => opts.ignore_patterns = strings(value).map_err(err)?,
+            "extensions" => opts.extensions = strings(value).map_err(err)?,
+            "pathspecs" => opts.pathspecs = strings(value).map_err(err)?,
+            "ignore_files" => opts.ignore_files = ignore_files(value, base).map_err(err)?,
+            "case_collisions" => opts.case_collisions = parsed(value).map_err(err)?,
+            "canonicalize_root" => opts.canonicalize_root = parsed(value).map_err(err)?,
+            "symlink_loops" => opts.symlink_loops = parsed(value).map_err(err)?,
+            "broken_symlinks" => opts.broken_symlinks = parsed(value).map_err(err)?,
+            "special_files" => opts.special_files = parsed(value).map_err(err)?,
+            "non_utf8_names" => opts.non_utf8_names = parsed(value).map_err(err)?,
+            "walk_threads" => {
+                opts.walk_threads = usize::try_from(unsigned(value).map_err(&err)?)
+                    .map_err(|_| err("value too large".into()))?
+            }
+            "mmap_threshold" => opts.mmap_threshold = Some(unsigned(value).map_err(err)?),
+            "parallel_hash_threshold" => {
+                opts.parallel_hash_threshold = Some(unsigned(value).map_err(err)?)
+            }
+            "threads" => {
+                opts.threads = usize::try_from(unsigned(value).map_err(&err)?)
+                    .map_err(|_| err("value too large".into()))?
+            }
+            "io_concurrency" => {
+                opts.io_concurrency = usize::try_from(unsigned(value).map_err(&err)?)
+                    .map_err(|_| err("value too large".into()))?
+            }
+            "max_read_bytes_per_sec" => {
+                opts.max_read_bytes_per_sec = Some(unsigned(value).map_err(err)?)
+            }
+            "max_file_size" => opts.max_file_size = Some(unsigned(value).map_err(err)?),
+            "max_files" => opts.max_files = Some(unsigned(value).map_err(err)?),
+            "max_depth" => opts.max_depth = Some(unsigned(value).map_err(err)?),
+            "deadline" => opts.deadline = Some(Duration::from_secs(unsigned(value).map_err(err)?)),
+            "max_total_bytes" => opts.max_total_bytes = Some(unsigned(value).map_err(err)?),
+            "oversize_policy" => opts.oversize_policy = parsed(value).map_err(err)?,
+            "structure_only" => opts.structure_only = boolean(value).map_err(err)?,
+            "normalize_newlines" => opts.normalize_newlines = boolean(value).map_err(err)?,
+            "concurrent_modification" => {
+                opts.concurrent_modification = parsed(value).map_err(err)?
+            }
+            "keep_going" => opts.keep_going = boolean(value).map_err(err)?,
+            "fail_on_skip" => opts.fail_on_skip = boolean(value).map_err(err)?,
+            "chunking" => opts.chunking = Some(parsed(value).map_err(err)?),
+            _ => return Err(err("unknown key".into())),
+        }
+    }
+    Ok(Config {
+        options: opts,
+        algorithms,
+    })
+}
+
+fn boolean(v: &DeValue<'_>) -> Result<bool, String> {
+    v.as_bool().ok_or_else(|| "expected a boolean".into())
+}
+
+fn unsigned(v: &DeValue<'_>) -> Result<u64, String> {
+    v.as_integer()
+        .and_then(|i| u64::from_str_radix(i.as_str(), i.radix()).ok())
+        .ok_or_else(|| "expected a non-negative integer".into())
+}
+
+fn strings(v: &DeValue<'_>) -> Result<Vec<String>, String> {
+    v.as_array()
+        .and_then(|a| {
+            a.iter()
+                .map(|s: &Spanned<DeValue<'_>>| s.get_ref().as_str().map(str::to_owned))
+                .collect()
+        })
+        .ok_or_else(|| "expected an array of strings".into())
+}
+
+/// `ignore_files`: paths, or `{ path, format }` tables, resolved against
+/// `base`.
+fn ignore_files(v: &DeValue<'_>, base: &Path) -> Result<Vec<IgnoreFile>, String> {
+    const EXPECTED: &str = "expected an array of paths or { path, format } tables";
+    let items = v.as_array().ok_or(EXPECTED)?;
+    items
+        .iter()
+        .map(|item| match item.get_ref() {
+            DeValue::String(path) => Ok(IgnoreFile::from(base.join(path.as_ref()))),
+            DeValue::Table(table) => {
+                let mut path = None;
+                let mut format = IgnoreFileFormat::Native;
+                for (key, value) in table {
+                    match key.get_ref().as_ref() {
+                        "path" => path = value.get_ref().as_str().map(|p| base.join(p)),
+                        "format" => format = parsed(value.get_ref())?,
+                        other => return Err(format!("unknown key `{other}` in ignore file")),
+                    }
+                }
+                let path = path.ok_or("ignore file table needs a string `path`")?;
+                Ok(IgnoreFile::new(path, format))
+            }
+            _ => Err(EXPECTED.into()),
+        })
+        .collect()
+}
+
+/// A string value parsed through `FromStr` (the policy enums).
+fn parsed<T: FromStr>(v: &DeValue<'_>) -> Result<T, String>
+where
+    T::Err: fmt::Display,
+{
+    let s = v.as_str().ok_or("expected a string")?;
+    s.parse().map_err(|e: T::Err| e.to_string())
+}
+
+/// `format_version` is written as a bare number (`2`), or as `"v2"`.
+fn version(v: &DeValue<'_>) -> Result<FormatVersion, String> {
+    match v.as_integer() {
+        Some(i) => i
+            .as_str()
+            .parse()
+            .map_err(|e: ParseOptionError| e.to_string()),
+        None => parsed(v),
+    }
+}
+
+/// `algorithm`: comma-separated names, each parsed by `Algorithm::from_str`.
+fn algorithm_list(v: &DeValue<'_>) -> Result<Vec<Algorithm>, String> {
+    let s = v.as_str().ok_or("expected a string")?;
+    (s.split(','))
+        .map(|a| {
+            a.trim()
+                .parse()
+                .map_err(|e: ParseOptionError| e.to_string())
+        })
+        .collect()
+}
+
+/// 1-based line number of the start of `span` in `text`.
+fn line_of(text: &str, span: Range<usize>) -> usize {
+    text[..span.start.min(text.len())].matches('\n').count() + 1
+}
@@ -0,0 +1,205 @@
+//! Hashing many trees with the same options ([`Engine`]).
+//!
+//! [`get_dir_hash`](crate::get_dir_hash) compiles the ignore patterns, reads
+//! [`Options::ignore_files`] and builds the hashing thread pool on every
+//! call. An engine does that once and keeps, per root, the size, mtime and
+//! content digest of every file of its last run: a file whose size and mtime
+//! are unchanged is not read again. What is hashed, and so the digest, is
+//! the same as a fresh run's.
+
+use crate::ignore::IgnoreRules;
+use crate::pool::HashPool;
+use crate::{DirHash, Options, Outputs, SnapshotEntry, run, walk_root};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime};
+
+/// Files modified this close to the start of a run are read again next
+/// time: a write in the same mtime tick as the read leaves both unchanged.
+const RACY: Duration = Duration::from_secs(2);
+
+/// Files of one run, by framed path.
+type Files = HashMap<String, SnapshotEntry>;
+
+/// Options compiled once for hashing many roots, e.g. in a daemon.
+///
+/// Ignore patterns, [`Options::ignore_files`] and the global git excludes
+/// are read and compiled in [`Engine::new`]; later edits to those files are
+/// not seen. A root's `.get_dir_hash_ignore` files are read on every run.
+/// The engine can be shared between threads; runs on different roots
+/// proceed in parallel.
+///
+/// Reused digests rely on mtimes, like `make`: a file rewritten with its
+/// size and mtime restored keeps its old digest until
+/// [`Engine::clear_cache`]. Nothing is reused with [`Options::chunking`].
+pub struct Engine {
+    opts: Options,
+    rules: IgnoreRules,
+    hash_pool: HashPool,
+    /// Files of the last run under each root.
+    cache: Mutex<HashMap<PathBuf, Files>>,
+}
+
+impl Engine {
+    /// An engine hashing with `opts`. Fails on a bad pattern or ignore file,
+    /// or if the thread pool cannot be built.
+    pub fn new(opts: &Options) -> io::Result<Engine> {
+        Ok(Engine {
+            opts: opts.clone(),
+            rules: IgnoreRules::with_dotfile(None, opts)?,
+            hash_pool: HashPool::new(opts.threads)?,
+            cache: Mutex::default(),
+        })
+    }
+
+    /// The options the engine hashes with.
+    pub fn options(&self) -> &Options {
+        &self.opts
+    }
+
+    /// Like [`get_dir_hash`](crate::get_dir_hash) with the engine's options.
+    pub fn hash(&self, root: &Path) -> io::Result<String> {
+        self.hash_with_stats(root).map(|h| h.hex)
+    }
+
+    /// Like [`get_dir_hash_with_stats`](crate::get_dir_hash_with_stats) with
+    /// the engine's options. Files whose digest was reused count in
+    /// [`RunStats::files_hashed`](crate::RunStats::files_hashed) but not in
+    /// `bytes_hashed`.
+    pub fn hash_with_stats(&self, root: &Path) -> io::Result<DirHash> {
+        let root = walk_root(root, &self.opts)?;
+        // Taken out while the run goes on, so other roots are not blocked.
+        let previous = self.cache().remove(&root);
+        let started = SystemTime::now();
+        let mut files = Vec::new();
+        let outputs = Outputs {
+            snapshot: Some(&mut files),
+            // Reading ahead stops while reusing; nothing to reuse on a first run.
+            reuse: previous.as_ref().filter(|previous| !previous.is_empty()),
+            prepared: Some((&self.rules, &self.hash_pool)),
+            ..Outputs::default()
+        };
+        let hash = run(&root, None, &self.opts, outputs)?;
+        let settled = started.checked_sub(RACY).unwrap_or(SystemTime::UNIX_EPOCH);
+        let files = (files.into_iter())
+            .filter(|file| file.mtime.is_some_and(|mtime| mtime < settled))
+            .map(|file| (file.path.clone(), file))
+            .collect();
+        self.cache().insert(root, files);
+        Ok(hash)
+    }
+
+    /// Forget every digest kept from earlier runs; the next run of each root
+    /// reads all its files.
+    pub fn clear_cache(&self) {
+        self.cache().clear();
+    }
+
+    fn cache(&self) -> MutexGuard<'_, HashMap<PathBuf, Files>> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_dir_hash;
+    use std::fs;
+
+    fn set_mtime(path: &Path, mtime: SystemTime) {
+        (fs::File::options().write(true).open(path).unwrap())
+            .set_modified(mtime)
+            .unwrap();
+    }
+
+    /// Write `body` to `path` with an mtime well outside [`RACY`].
+    fn write_old(path: &Path, body: &str) {
+        fs::write(path, body).unwrap();
+        set_mtime(path, SystemTime::now() - 10 * RACY);
+    }
+
+    #[test]
+    fn settled_files_are_reused() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path();
+        fs::create_dir(path.join("sub")).unwrap();
+        write_old(&path.join("a"), "one");
+        write_old(&path.join("sub/b"), "two");
+        let engine = Engine::new(&Options::default()).unwrap();
+        let first = engine.hash_with_stats(path).unwrap();
+        assert_eq!(first.hex, get_dir_hash(path, &Options::default()).unwrap());
+        assert_eq!(first.stats.bytes_hashed, 6);
+
+        let second = engine.hash_with_stats(path).unwrap();
+        assert_eq!(second.hex, first.hex);
+        assert_eq!(second.stats.bytes_hashed, 0);
+        assert_eq!(second.stats.files_hashed, 2);
+
+        // A new size is read again, and so is a new mtime.
+        write_old(&path.join("a"), "three");
+        let third = engine.hash_with_stats(path).unwrap();
+        assert_eq!(third.hex, get_dir_hash(path, &Options::default()).unwrap());
+        assert_eq!(third.stats.bytes_hashed, 5);
+        fs::write(path.join("sub/b"), "TWO").unwrap();
+        let fourth = engine.hash_with_stats(path).unwrap();
+        assert_eq!(fourth.hex, get_dir_hash(path, &Options::default()).unwrap());
+        assert_eq!(fourth.stats.bytes_hashed, 3);
+    }
+
+    #[test]
+    fn files_modified_within_racy_are_read_again() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path();
+        write_old(&path.join("old"), "old");
+        fs::write(path.join("new"), "new!").unwrap();
+        let engine = Engine::new(&Options::default()).unwrap();
+        engine.hash(path).unwrap();
+        let again = engine.hash_with_stats(path).unwrap();
+        assert_eq!(again.stats.bytes_hashed, 4);
+
+        // Rewritten in the same tick with its size and mtime kept.
+        let mtime = fs::metadata(path.join("new")).unwrap().modified().unwrap();
+        fs::write(path.join("new"), "NEW!").unwrap();
+        set_mtime(&path.join("new"), mtime);
+        assert_eq!(
+            engine.hash(path).unwrap(),
+            get_dir_hash(path, &Options::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn restored_mtimes_keep_old_digests_until_the_cache_is_cleared() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path();
+        write_old(&path.join("a"), "one");
+        let engine = Engine::new(&Options::default()).unwrap();
+        let before = engine.hash(path).unwrap();
+
+        let mtime = fs::metadata(path.join("a")).unwrap().modified().unwrap();
+        fs::write(path.join("a"), "two").unwrap();
+        set_mtime(&path.join("a"), mtime);
+        assert_eq!(engine.hash(path).unwrap(), before);
+        engine.clear_cache();
+        let after = engine.hash(path).unwrap();
+        assert_ne!(after, before);
+        assert_eq!(after, get_dir_hash(path, &Options::default()).unwrap());
+    }
+
+    #[test]
+    fn roots_are_cached_apart() {
+        let (one, two) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        write_old(&one.path().join("f"), "one");
+        write_old(&two.path().join("f"), "two");
+        let engine = Engine::new(&Options::default()).unwrap();
+        for _ in 0..2 {
+            for root in [one.path(), two.path()] {
+                assert_eq!(
+                    engine.hash(root).unwrap(),
+                    get_dir_hash(root, &Options::default()).unwrap()
+                );
+            }
+        }
+    }
+}
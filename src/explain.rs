@@ -0,0 +1,165 @@
+//! Why a path is or is not hashed ([`explain`]), for debugging ignore
+//! patterns without trial and error.
+//!
+//! The answer is computed from the same rules a walk uses: the root rules
+//! (dotfile, ignore files, VCS directories, inline patterns) and the
+//! `.get_dir_hash_ignore` of every directory between the root and the path.
+
+use crate::ignore::{IGNORE_FILE_NAME, IgnoreRules};
+use crate::{Options, make_rel_unix, walk, walk_root};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Verdict of [`explain`] for one path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Explanation {
+    /// Relative path as the patterns see it.
+    pub path: String,
+    /// Whether a walk would hash the path: no pattern excludes it and it is
+    /// a regular file (or, with `follow_symlinks`, a link to one).
+    pub hashed: bool,
+    /// Every pattern that excludes the path, root rules first, then the
+    /// ignore files of its directories from the top down.
+    pub matches: Vec<PatternMatch>,
+}
+
+/// An ignore pattern that matched, and where it was defined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PatternMatch {
+    /// The pattern as written (relative to its ignore file's directory for
+    /// nested dotfiles).
+    pub pattern: String,
+    /// Where it came from.
+    pub source: PatternSource,
+}
+
+/// A pattern that cannot be used, and where it was defined (see
+/// [`Options::validate`]). A run that meets one fails with it, as the inner
+/// error of an `InvalidInput` `io::Error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PatternError {
+    /// The pattern as written.
+    pub pattern: String,
+    /// Where it came from.
+    pub source: PatternSource,
+    /// Its line (1-based) in the ignore file it was read from, if any.
+    pub line: Option<usize>,
+    /// What is wrong with it.
+    pub message: String,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.source, self.line) {
+            (PatternSource::IgnoreFile(path) | PatternSource::Dotfile(path), Some(line)) => {
+                write!(f, "{}:{line}", path.display())?
+            }
+            (PatternSource::Extensions, _) => f.write_str("extension filter")?,
+            (PatternSource::Pathspecs, _) => f.write_str("pathspec")?,
+            (source, _) => write!(f, "{source}")?,
+        }
+        write!(f, ": `{}`: {}", self.pattern, self.message)
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+/// Origin of an ignore pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum PatternSource {
+    /// [`Options::ignore_patterns`] (`--ignore`).
+    Inline,
+    /// A file from [`Options::ignore_files`] (`--ignore-file`).
+    IgnoreFile(PathBuf),
+    /// A `.get_dir_hash_ignore` file, the root's or a subdirectory's.
+    Dotfile(PathBuf),
+    /// [`Options::exclude_vcs_dirs`] (`--exclude-vcs`).
+    VcsDirs,
+    /// [`Options::extensions`] (`--ext`): the file matches none of them.
+    Extensions,
+    /// [`Options::pathspecs`] (`--path`): the path is outside all of them.
+    Pathspecs,
+    /// [`Options::skip_hidden`] (`--no-hidden`).
+    Hidden,
+}
+
+impl fmt::Display for PatternSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatternSource::Inline => f.write_str("inline pattern"),
+            PatternSource::IgnoreFile(path) => write!(f, "ignore file {}", path.display()),
+            PatternSource::Dotfile(path) => write!(f, "{}", path.display()),
+            PatternSource::VcsDirs => f.write_str("VCS directory exclusion"),
+            PatternSource::Extensions => f.write_str("extension filter, not matched"),
+            PatternSource::Pathspecs => f.write_str("outside the selected paths"),
+            PatternSource::Hidden => f.write_str("hidden file exclusion"),
+        }
+    }
+}
+
+impl fmt::Display for PatternMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` ({})", self.pattern, self.source)
+    }
+}
+
+/// Report whether `path` (relative to `root`, or absolute below it) would be
+/// hashed with `opts` and, if an ignore pattern excludes it, which ones from
+/// which source.
+///
+/// Only ignore patterns are explained; a path that no pattern excludes but
+/// that is not a hashable file has `hashed == false` and no matches.
+pub fn explain(root: &Path, path: &Path, opts: &Options) -> io::Result<Explanation> {
+    let root = walk_root(root, opts)?;
+    let full = walk::resolve_listed(&root, path)?;
+    let rel_of = |p: &Path| {
+        let rel = make_rel_unix(&root, p).unwrap_or_default();
+        match opts.unicode_normalization {
+            Some(form) => form.apply(&rel),
+            None => rel,
+        }
+    };
+    let rel = rel_of(&full);
+
+    let rules = IgnoreRules::build(&root, opts)?;
+    let mut matches: Vec<PatternMatch> = rules.matches(&rel).cloned().collect();
+    if opts.load_dot_get_dir_hash_ignore {
+        let dirs: Vec<&Path> = (full.ancestors().skip(1))
+            .take_while(|dir| *dir != root)
+            .collect();
+        for dir in dirs.into_iter().rev() {
+            let file = dir.join(IGNORE_FILE_NAME);
+            if !file.is_file() {
+                continue;
+            }
+            let nested = IgnoreRules::from_file(&file)?;
+            let prefix = format!("{}/", rel_of(dir));
+            if let Some(rest) = rel.strip_prefix(&prefix) {
+                matches.extend(nested.matches(rest).cloned());
+            }
+        }
+    }
+
+    let is_file = match fs::symlink_metadata(&full) {
+        Ok(md) if md.is_symlink() => {
+            opts.follow_symlinks && fs::metadata(&full).is_ok_and(|md| md.is_file())
+        }
+        Ok(md) => md.is_file(),
+        Err(_) => false,
+    };
+    Ok(Explanation {
+        path: rel,
+        hashed: is_file && matches.is_empty(),
+        matches,
+    })
+}
@@ -0,0 +1,127 @@
+//! C interface (`ffi` feature), declared in `include/get_dir_hash.h`.
+//!
+//! Build a shared library with
+//! `cargo rustc --profile ffi --lib --features ffi --crate-type cdylib`
+//! (into `target/ffi/`). The `ffi` profile unwinds on panic, which is what
+//! lets a panic come back as `GDH_ERR_PANIC`; under `release`, which
+//! aborts, it would end the host process.
+//! Options are passed as JSON in the shape of the `serde` representation of
+//! [`Options`]; digests are byte-identical to the Rust API's.
+
+use crate::Options;
+use std::cell::RefCell;
+use std::ffi::{CStr, c_char, c_int};
+use std::path::PathBuf;
+use std::ptr;
+
+/// Success.
+pub const GDH_OK: c_int = 0;
+/// A required pointer was null, or the path is not usable on this platform.
+pub const GDH_ERR_ARGUMENT: c_int = -1;
+/// `options_json` is not valid UTF-8 JSON describing [`Options`].
+pub const GDH_ERR_OPTIONS: c_int = -2;
+/// Hashing failed (I/O error, policy violation, ...).
+pub const GDH_ERR_HASH: c_int = -3;
+/// `out_buf` cannot hold the digest and its terminating NUL.
+pub const GDH_ERR_BUFFER: c_int = -4;
+/// The library panicked; nothing was written. Only with a library built
+/// under a profile that unwinds, such as `ffi`.
+pub const GDH_ERR_PANIC: c_int = -5;
+
+thread_local! {
+    /// Message of the last failed call on this thread.
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Hash the directory `path` with the options in `options_json` (or the
+/// defaults when it is null) and write the lowercase hex digest, NUL
+/// terminated, to `out_buf`. Returns `GDH_OK` or a negative `GDH_ERR_*`
+/// code; [`gdh_last_error`] describes failures.
+///
+/// # Safety
+///
+/// `path` and `options_json` (if not null) must point to NUL-terminated
+/// strings, and `out_buf` to at least `out_len` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn gdh_hash_dir(
+    path: *const c_char,
+    options_json: *const c_char,
+    out_buf: *mut c_char,
+    out_len: usize,
+) -> c_int {
+    let res = std::panic::catch_unwind(|| {
+        if path.is_null() || out_buf.is_null() {
+            return Err((GDH_ERR_ARGUMENT, "path and out_buf must not be null".into()));
+        }
+        // SAFETY: non-null and NUL-terminated per the contract above.
+        let root = path_from_c(unsafe { CStr::from_ptr(path) })?;
+        let opts = if options_json.is_null() {
+            Options::default()
+        } else {
+            // SAFETY: as above.
+            let json = unsafe { CStr::from_ptr(options_json) };
+            let json = json
+                .to_str()
+                .map_err(|e| (GDH_ERR_OPTIONS, format!("options: {e}")))?;
+            serde_json::from_str(json).map_err(|e| (GDH_ERR_OPTIONS, format!("options: {e}")))?
+        };
+        let hex = crate::get_dir_hash(&root, &opts)
+            .map_err(|e| (GDH_ERR_HASH, format!("{}: {e}", root.display())))?;
+        if hex.len() >= out_len {
+            return Err((
+                GDH_ERR_BUFFER,
+                format!("out_buf holds {out_len} bytes, {} needed", hex.len() + 1),
+            ));
+        }
+        // SAFETY: `out_buf` has room for `out_len > hex.len()` bytes.
+        unsafe {
+            ptr::copy_nonoverlapping(hex.as_ptr(), out_buf.cast::<u8>(), hex.len());
+            *out_buf.add(hex.len()) = 0;
+        }
+        Ok(())
+    });
+    let (code, message) = match res {
+        Ok(Ok(())) => (GDH_OK, String::new()),
+        Ok(Err(e)) => e,
+        Err(_) => (GDH_ERR_PANIC, "panic while hashing".into()),
+    };
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    code
+}
+
+/// Copy the message of this thread's last failed call (empty after a
+/// success) to `buf`, truncated to `len - 1` bytes and NUL terminated.
+/// Returns the full message length, so a caller can retry with a larger
+/// buffer; `buf` may be null to only query the length.
+///
+/// # Safety
+///
+/// `buf`, if not null, must point to at least `len` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn gdh_last_error(buf: *mut c_char, len: usize) -> usize {
+    LAST_ERROR.with(|last| {
+        let message = last.borrow();
+        if !buf.is_null() && len > 0 {
+            let n = message.len().min(len - 1);
+            // SAFETY: `buf` has room for `len > n` bytes.
+            unsafe {
+                ptr::copy_nonoverlapping(message.as_ptr(), buf.cast::<u8>(), n);
+                *buf.add(n) = 0;
+            }
+        }
+        message.len()
+    })
+}
+
+#[cfg(unix)]
+fn path_from_c(s: &CStr) -> Result<PathBuf, (c_int, String)> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(PathBuf::from(std::ffi::OsStr::from_bytes(s.to_bytes())))
+}
+
+#[cfg(not(unix))]
+fn path_from_c(s: &CStr) -> Result<PathBuf, (c_int, String)> {
+    s.to_str()
+        .map(PathBuf::from)
+        .map_err(|e| (GDH_ERR_ARGUMENT, format!("path: {e}")))
+}
@@ -0,0 +1,379 @@
+//! The outer hasher, optionally mirrored to a writer.
+//!
+//! Everything that goes into the root digest passes through [`Frames`]. With a
+//! dump attached (see [`crate::dump_frames`]) the exact same bytes are written
+//! out as well, one record per line; the line breaks are not part of the
+//! hashed stream.
+//!
+//! Two record layouts exist (see [`FormatVersion`]). In v1 fields are
+//! separated by NUL bytes:
+//!
+//! ```text
+//! header  get_dir_hash-v1 \0
+//! cutoff  T \0 secs[8] nanos[4]          (modified_since only)
+//! file    F \0 path \0 digest[32] [metadata]
+//! link    L \0 path \0 target
+//! ```
+//!
+//! In v2 every variable-length field carries its length (u64 LE) in front:
+//!
+//! ```text
+//! header  get_dir_hash-v2 \0
+//! cutoff  T secs[8] nanos[4]             (modified_since only)
+//! file    F len(path) path digest[32] len(metadata) metadata
+//! link    L len(path) path len(target) target
+//! ```
+//!
+//! The metadata bytes themselves are the same in both versions. The digest
+//! is BLAKE3 unless the stream belongs to another [`Algorithm`] of a
+//! multi-digest run.
+
+use crate::algo::{Algorithm, Hasher};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Rendering of the byte stream written by [`crate::dump_frames`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// Lowercase hex, two digits per byte.
+    #[default]
+    Hex,
+    /// Printable ASCII as is; `\` as `\\` and every other byte as `\xNN`.
+    Escaped,
+}
+
+impl FromStr for DumpFormat {
+    type Err = crate::ParseOptionError;
+
+    /// Parses `hex` or `escaped`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hex" => Ok(DumpFormat::Hex),
+            "escaped" => Ok(DumpFormat::Escaped),
+            _ => Err(crate::ParseOptionError::new(s, "hex or escaped")),
+        }
+    }
+}
+
+/// Layout of the records fed to the outer hasher.
+///
+/// Digests of different versions never compare equal, even for the same tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum FormatVersion {
+    /// NUL-separated fields; the historical format.
+    #[default]
+    V1,
+    /// Length-prefixed fields. Unambiguous for any path, target or metadata
+    /// bytes, including embedded NULs.
+    V2,
+}
+
+impl FormatVersion {
+    /// `v1` or `v2`, as accepted by [`FromStr`].
+    pub fn name(self) -> &'static str {
+        match self {
+            FormatVersion::V1 => "v1",
+            FormatVersion::V2 => "v2",
+        }
+    }
+}
+
+impl FromStr for FormatVersion {
+    type Err = crate::ParseOptionError;
+
+    /// Parses `1`/`v1` or `2`/`v2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1" | "v1" => Ok(FormatVersion::V1),
+            "2" | "v2" => Ok(FormatVersion::V2),
+            _ => Err(crate::ParseOptionError::new(s, "1 or 2")),
+        }
+    }
+}
+
+/// Where dumped frames go.
+pub(crate) struct Dump<'w> {
+    pub(crate) writer: &'w mut dyn Write,
+    pub(crate) format: DumpFormat,
+}
+
+/// Outer hasher plus an optional dump of everything fed to it.
+pub(crate) struct Frames<'w> {
+    version: FormatVersion,
+    hasher: Hasher,
+    dump: Option<Dump<'w>>,
+    /// First write error of the dump; reported by [`Frames::finalize`].
+    error: Option<io::Error>,
+}
+
+impl<'w> Frames<'w> {
+    /// Start a stream of `version` records, beginning with its header.
+    pub(crate) fn new(version: FormatVersion, dump: Option<Dump<'w>>) -> Self {
+        Self::with_hasher(version, Hasher::Blake3(Box::default()), dump)
+    }
+
+    /// Start a stream hashed with `algorithm` instead of BLAKE3; its file
+    /// records must carry `algorithm` content digests.
+    pub(crate) fn for_algorithm(version: FormatVersion, algorithm: Algorithm) -> io::Result<Self> {
+        Ok(Self::with_hasher(version, Hasher::new(algorithm)?, None))
+    }
+
+    fn with_hasher(version: FormatVersion, hasher: Hasher, dump: Option<Dump<'w>>) -> Self {
+        let mut frames = Self {
+            version,
+            hasher,
+            dump,
+            error: None,
+        };
+        frames.update(match version {
+            FormatVersion::V1 => b"get_dir_hash-v1\0",
+            FormatVersion::V2 => b"get_dir_hash-v2\0",
+        });
+        frames.end_record();
+        frames
+    }
+
+    /// Frame the root directory's name (`Options::include_root_name`).
+    pub(crate) fn root_name(&mut self, name: &[u8]) {
+        match self.version {
+            FormatVersion::V1 => {
+                self.update(b"N\0");
+                self.update(name);
+                self.update(b"\0");
+            }
+            FormatVersion::V2 => {
+                self.update(b"N");
+                self.field(name);
+            }
+        }
+        self.end_record();
+    }
+
+    /// Frame the cutoff of `Options::modified_since`, as seconds (u64 LE)
+    /// and nanoseconds (u32 LE) since the Unix epoch.
+    pub(crate) fn modified_since(&mut self, cutoff: Duration) {
+        match self.version {
+            FormatVersion::V1 => self.update(b"T\0"),
+            FormatVersion::V2 => self.update(b"T"),
+        }
+        self.update(&cutoff.as_secs().to_le_bytes());
+        self.update(&cutoff.subsec_nanos().to_le_bytes());
+        self.end_record();
+    }
+
+    /// Frame a regular file: its path, content digest and metadata block
+    /// (the metadata and chunk records requested, possibly none).
+    pub(crate) fn file(&mut self, path: &[u8], digest: &[u8], metadata: &[u8]) {
+        match self.version {
+            FormatVersion::V1 => {
+                self.update(b"F\0");
+                self.update(path);
+                self.update(b"\0");
+                self.update(digest);
+                self.update(metadata);
+            }
+            FormatVersion::V2 => {
+                self.update(b"F");
+                self.field(path);
+                self.update(digest);
+                self.field(metadata);
+            }
+        }
+        self.end_record();
+    }
+
+    /// Frame a symlink recorded by its target instead of its content.
+    pub(crate) fn link(&mut self, path: &[u8], target: &[u8]) {
+        match self.version {
+            FormatVersion::V1 => {
+                self.update(b"L\0");
+                self.update(path);
+                self.update(b"\0");
+                self.update(target);
+            }
+            FormatVersion::V2 => {
+                self.update(b"L");
+                self.field(path);
+                self.field(target);
+            }
+        }
+        self.end_record();
+    }
+
+    /// Frame a file that was not read because of its size.
+    pub(crate) fn oversize(&mut self, path: &[u8], size: u64) {
+        match self.version {
+            FormatVersion::V1 => {
+                self.update(b"B\0");
+                self.update(path);
+                self.update(b"\0");
+            }
+            FormatVersion::V2 => {
+                self.update(b"B");
+                self.field(path);
+            }
+        }
+        self.update(&size.to_le_bytes());
+        self.end_record();
+    }
+
+    /// Frame a special file (FIFO, socket, device) by its type byte.
+    pub(crate) fn special(&mut self, path: &[u8], file_type: u8) {
+        match self.version {
+            FormatVersion::V1 => {
+                self.update(b"S\0");
+                self.update(path);
+                self.update(b"\0");
+            }
+            FormatVersion::V2 => {
+                self.update(b"S");
+                self.field(path);
+            }
+        }
+        self.update(&[file_type]);
+        self.end_record();
+    }
+
+    /// A v2 field: its length (u64 LE), then the bytes.
+    fn field(&mut self, bytes: &[u8]) {
+        self.update(&(bytes.len() as u64).to_le_bytes());
+        self.update(bytes);
+    }
+
+    /// Feed `bytes` to the hasher (and the dump).
+    fn update(&mut self, bytes: &[u8]) {
+        self.hasher.update(bytes);
+        if let Some(d) = &mut self.dump
+            && self.error.is_none()
+        {
+            let res = match d.format {
+                DumpFormat::Hex => write_hex(d.writer, bytes),
+                DumpFormat::Escaped => write_escaped(d.writer, bytes),
+            };
+            self.error = res.err();
+        }
+    }
+
+    /// Mark the end of a record; only visible in the dump.
+    fn end_record(&mut self) {
+        if let Some(d) = &mut self.dump
+            && self.error.is_none()
+        {
+            self.error = d.writer.write_all(b"\n").err();
+        }
+    }
+
+    /// The root digest, `output_len` bytes long for BLAKE3 (see
+    /// [`crate::Options::output_len`]), once the dump (if any) is flushed.
+    pub(crate) fn finalize(mut self, output_len: usize) -> io::Result<Vec<u8>> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if let Some(d) = &mut self.dump {
+            d.writer.flush()?;
+        }
+        Ok(self.hasher.finalize_len(output_len))
+    }
+}
+
+fn write_hex(w: &mut dyn Write, bytes: &[u8]) -> io::Result<()> {
+    w.write_all(crate::hex_lower(bytes).as_bytes())
+}
+
+fn write_escaped(w: &mut dyn Write, bytes: &[u8]) -> io::Result<()> {
+    let mut s = String::with_capacity(bytes.len());
+    for &b in bytes {
+        match b {
+            b'\\' => s.push_str("\\\\"),
+            0x20..=0x7e => s.push(b as char),
+            _ => s.push_str(&format!("\\x{b:02x}")),
+        }
+    }
+    w.write_all(s.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Options, get_dir_hash};
+    use std::fs;
+    use std::path::Path;
+
+    /// A small tree whose digests are pinned below.
+    fn tree(root: &Path) {
+        fs::create_dir_all(root.join("dir/sub")).unwrap();
+        fs::write(root.join("a.txt"), "hello\n").unwrap();
+        fs::write(root.join("Z"), "top").unwrap();
+        fs::write(root.join("dir/b.bin"), (0..=255).collect::<Vec<u8>>()).unwrap();
+        fs::write(root.join("dir/sub/empty"), "").unwrap();
+        fs::write(root.join("dir/z y.md"), "x").unwrap();
+    }
+
+    fn digest(version: FormatVersion) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        tree(dir.path());
+        let opts = Options {
+            format_version: version,
+            ..Options::default()
+        };
+        get_dir_hash(dir.path(), &opts).unwrap()
+    }
+
+    #[test]
+    fn v1_matches_the_historical_format() {
+        // The digest of the first release, before format versions existed.
+        assert_eq!(
+            digest(FormatVersion::V1),
+            "c327f1f069dcb38a6a8ca4fe5edd9b735cfc780dd38dce7a372941df7c62f9ef"
+        );
+    }
+
+    #[test]
+    fn v2_known_answer() {
+        assert_eq!(
+            digest(FormatVersion::V2),
+            "939496b14c9fec85260e25211f432ebc2f93fd442088e5d399b558609156f656"
+        );
+    }
+
+    fn link_digest(version: FormatVersion, path: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut frames = Frames::new(version, None);
+        frames.link(path, target);
+        frames.finalize(32).unwrap()
+    }
+
+    #[test]
+    fn v2_separates_fields_that_v1_runs_together() {
+        // `L\0a\0b\0c` in v1 either way.
+        let (split_late, split_early) = ((&b"a"[..], &b"b\0c"[..]), (&b"a\0b"[..], &b"c"[..]));
+        assert_eq!(
+            link_digest(FormatVersion::V1, split_late.0, split_late.1),
+            link_digest(FormatVersion::V1, split_early.0, split_early.1)
+        );
+        assert_ne!(
+            link_digest(FormatVersion::V2, split_late.0, split_late.1),
+            link_digest(FormatVersion::V2, split_early.0, split_early.1)
+        );
+    }
+
+    #[test]
+    fn dump_mirrors_the_hashed_bytes() {
+        let mut out = Vec::new();
+        let mut frames = Frames::new(
+            FormatVersion::V1,
+            Some(Dump {
+                writer: &mut out,
+                format: DumpFormat::Escaped,
+            }),
+        );
+        frames.link(b"l", b"t");
+        frames.finalize(32).unwrap();
+        assert_eq!(out, b"get_dir_hash-v1\\x00\nL\\x00l\\x00t\n");
+    }
+}
@@ -0,0 +1,592 @@
+//! Ignore rules: glob patterns relative to the root.
+//!
+//! Every pattern is matched against the normalized relative path of a file.
+//! Patterns of the form `<dir>/**` additionally contribute `<dir>` to a
+//! directory set, which lets the walker prune whole subtrees: any directory
+//! matching `<dir>` only contains paths matching `<dir>/**`, so skipping it
+//! cannot change which files are hashed. A pattern with a trailing slash,
+//! `<dir>/`, is short for `<dir>/**`: it names directories only, so a file
+//! called `<dir>` is still hashed. A leading slash anchors a pattern to the
+//! root the way `.gitignore` does: `*` and `?` then stop at `/`, so
+//! `/*.lock` matches `Cargo.lock` but not `vendor/foo/Cargo.lock`.
+//!
+//! Further `.get_dir_hash_ignore` files below the root are compiled on their
+//! own (see [`IgnoreRules::from_file`]) and matched against paths relative to
+//! their directory, the way nested `.gitignore` files work.
+//!
+//! An [`Engine`](crate::Engine) compiles the rules of its options once and
+//! layers each root's `.get_dir_hash_ignore`, compiled on its own, in front
+//! of them ([`IgnoreRules::for_root`]); matching works as if both had been
+//! built together.
+//!
+//! Files in another [`IgnoreFileFormat`] are translated line by line into
+//! the same globs, with `*` no longer crossing `/`.
+//!
+//! A pattern that does not compile fails the run with a [`PatternError`]
+//! naming its origin; [`Options::validate`] collects all of them instead.
+//!
+//! [`Options::extensions`] works the other way round: one `**/*.<ext>` glob
+//! per extension, and a file matching none of them is excluded.
+//! [`Options::pathspecs`] too: a path at or below none of them is excluded,
+//! and a directory is pruned unless it leads to one or lies below one.
+
+use crate::{
+    IgnoreFileFormat, NormalizationForm, Options, PatternError, PatternMatch, PatternSource,
+    Warning, WarningReason,
+};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use std::borrow::Cow;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the per-directory ignore file.
+pub(crate) const IGNORE_FILE_NAME: &str = ".get_dir_hash_ignore";
+
+/// Directories skipped by `Options::exclude_vcs_dirs`.
+const VCS_DIRS: [&str; 3] = [".git", ".hg", ".svn"];
+
+/// Compiled ignore patterns.
+#[derive(Clone)]
+pub(crate) struct IgnoreRules {
+    files: GlobSet,
+    dirs: GlobSet,
+    /// Pattern and origin of every glob in `files`, by index.
+    sources: Vec<PatternMatch>,
+    /// Files must match one of these, if any ([`Options::extensions`]).
+    include: Option<(GlobSet, PatternMatch)>,
+    /// Paths must lie at or below one of these, if any
+    /// ([`Options::pathspecs`]).
+    pathspecs: Option<(Vec<String>, PatternMatch)>,
+    /// The root's `.get_dir_hash_ignore`, compiled apart from the rest
+    /// ([`IgnoreRules::for_root`]); its patterns come first.
+    root: Option<Box<IgnoreRules>>,
+    /// Rules dropped while loading ignore files.
+    pub(crate) warnings: Vec<Warning>,
+}
+
+impl IgnoreRules {
+    /// Build rules from patterns in `opts` and optional `.get_dir_hash_ignore`.
+    pub(crate) fn build(root: &Path, opts: &Options) -> io::Result<Self> {
+        let f = root.join(IGNORE_FILE_NAME);
+        let dotfile = match opts.load_dot_get_dir_hash_ignore && f.is_file() {
+            true => Some((fs::read_to_string(&f)?, f)),
+            false => None,
+        };
+        Self::with_dotfile(dotfile, opts)
+    }
+
+    /// Like [`IgnoreRules::build`], with the root's `.get_dir_hash_ignore`
+    /// given as its contents and path (read by the caller, if at all).
+    pub(crate) fn with_dotfile(
+        dotfile: Option<(String, PathBuf)>,
+        opts: &Options,
+    ) -> io::Result<Self> {
+        let mut builder = RulesBuilder::new();
+        builder.load(dotfile, opts)?;
+        builder.build()
+    }
+
+    /// These rules, built from options alone ([`IgnoreRules::with_dotfile`]
+    /// with `None`), plus the `.get_dir_hash_ignore` of `root`: the same
+    /// rules as [`IgnoreRules::build`], without compiling these again.
+    pub(crate) fn for_root(&self, root: &Path, opts: &Options) -> io::Result<Cow<'_, Self>> {
+        let f = root.join(IGNORE_FILE_NAME);
+        if !(opts.load_dot_get_dir_hash_ignore && f.is_file()) {
+            return Ok(Cow::Borrowed(self));
+        }
+        let mut dotfile = Self::from_text(&fs::read_to_string(&f)?, &f)?;
+        let mut warnings = std::mem::take(&mut dotfile.warnings);
+        warnings.extend(self.warnings.iter().cloned());
+        Ok(Cow::Owned(Self {
+            root: Some(Box::new(dotfile)),
+            warnings,
+            ..self.clone()
+        }))
+    }
+
+    /// Rules that exclude nothing.
+    pub(crate) fn none() -> Self {
+        RulesBuilder::new().build().expect("empty rule set")
+    }
+
+    /// Rules from a single ignore file, relative to the file's directory.
+    pub(crate) fn from_file(path: &Path) -> io::Result<Self> {
+        Self::from_text(&fs::read_to_string(path)?, path)
+    }
+
+    /// Rules from the contents `txt` of the ignore file at `path`.
+    pub(crate) fn from_text(txt: &str, path: &Path) -> io::Result<Self> {
+        let mut builder = RulesBuilder::new();
+        let source = PatternSource::Dotfile(path.to_path_buf());
+        builder.add_text(txt, path, IgnoreFileFormat::Native, source)?;
+        builder.build()
+    }
+
+    /// Whether the file at `rel` is excluded.
+    pub(crate) fn is_ignored(&self, rel: &str) -> bool {
+        self.root
+            .as_ref()
+            .is_some_and(|root| root.files.is_match(rel))
+            || self.files.is_match(rel)
+            || self.excluded_by_extension(rel)
+            || self.outside_pathspecs(rel)
+    }
+
+    /// The patterns excluding the file at `rel`, in the order they were
+    /// added, then the extension filter and the pathspecs.
+    pub(crate) fn matches(&self, rel: &str) -> impl Iterator<Item = &PatternMatch> {
+        let include = (self.include.as_ref())
+            .filter(|_| self.excluded_by_extension(rel))
+            .map(|(_, source)| source);
+        let pathspecs = (self.pathspecs.as_ref())
+            .filter(|_| self.outside_pathspecs(rel))
+            .map(|(_, source)| source);
+        let root = (self.root.iter()).flat_map(move |root| {
+            root.files
+                .matches(rel)
+                .into_iter()
+                .map(|i| &root.sources[i])
+        });
+        root.chain(
+            self.files
+                .matches(rel)
+                .into_iter()
+                .map(|i| &self.sources[i]),
+        )
+        .chain(include)
+        .chain(pathspecs)
+    }
+
+    fn excluded_by_extension(&self, rel: &str) -> bool {
+        self.include
+            .as_ref()
+            .is_some_and(|(set, _)| !set.is_match(rel))
+    }
+
+    fn outside_pathspecs(&self, rel: &str) -> bool {
+        self.pathspecs
+            .as_ref()
+            .is_some_and(|(specs, _)| !specs.iter().any(|spec| is_below(rel, spec)))
+    }
+
+    /// Whether everything below the directory at `rel` is excluded.
+    pub(crate) fn is_dir_ignored(&self, rel: &str) -> bool {
+        self.root
+            .as_ref()
+            .is_some_and(|root| root.dirs.is_match(rel))
+            || self.dirs.is_match(rel)
+            || self.pathspecs.as_ref().is_some_and(|(specs, _)| {
+                !(specs.iter()).any(|spec| is_below(rel, spec) || is_below(spec, rel))
+            })
+    }
+}
+
+impl Options {
+    /// Check every pattern these options would load without walking a
+    /// tree: inline patterns, [`Options::ignore_files`], the global git
+    /// excludes, extensions and pathspecs. Returns all the errors found,
+    /// each with its origin (and line, in a file); none means a run will
+    /// not fail on a pattern. A `.get_dir_hash_ignore` inside the tree is
+    /// not read. An ignore file that exists but cannot be read is an error.
+    pub fn validate(&self) -> io::Result<Vec<PatternError>> {
+        let mut builder = RulesBuilder::new();
+        builder.errors = Some(Vec::new());
+        builder.load(None, self)?;
+        Ok(builder.errors.unwrap_or_default())
+    }
+}
+
+struct RulesBuilder {
+    files: GlobSetBuilder,
+    dirs: GlobSetBuilder,
+    sources: Vec<PatternMatch>,
+    include: Option<(GlobSet, PatternMatch)>,
+    pathspecs: Option<(Vec<String>, PatternMatch)>,
+    warnings: Vec<Warning>,
+    /// Line of the ignore file being added, for errors.
+    line: Option<usize>,
+    /// Bad patterns so far, when collecting them ([`Options::validate`])
+    /// rather than failing on the first.
+    errors: Option<Vec<PatternError>>,
+}
+
+impl RulesBuilder {
+    fn new() -> Self {
+        Self {
+            files: GlobSetBuilder::new(),
+            dirs: GlobSetBuilder::new(),
+            sources: Vec::new(),
+            include: None,
+            pathspecs: None,
+            warnings: Vec::new(),
+            line: None,
+            errors: None,
+        }
+    }
+
+    /// Add the root rules of `opts`, starting with the root's
+    /// `.get_dir_hash_ignore` (its contents and path), if given.
+    fn load(&mut self, dotfile: Option<(String, PathBuf)>, opts: &Options) -> io::Result<()> {
+        if let Some((txt, f)) = dotfile {
+            let source = PatternSource::Dotfile(f.clone());
+            self.add_text(&txt, &f, IgnoreFileFormat::Native, source)?;
+        }
+
+        // Load any additional ignore files.
+        for file in &opts.ignore_files {
+            if file.path.is_file() {
+                let source = PatternSource::IgnoreFile(file.path.clone());
+                self.add_file(&file.path, file.format, source)?;
+            }
+        }
+
+        // The user's global git excludes, like any other gitignore file.
+        if opts.global_gitignore
+            && let Some(path) = global_gitignore().filter(|path| path.is_file())
+        {
+            let source = PatternSource::IgnoreFile(path.clone());
+            self.add_file(&path, IgnoreFileFormat::Gitignore, source)?;
+        }
+
+        // Version-control metadata, at any depth.
+        if opts.exclude_vcs_dirs {
+            for dir in VCS_DIRS {
+                self.add(&format!("**/{dir}/**"), PatternSource::VcsDirs)?;
+            }
+        }
+
+        // Dotfiles and dot-directories, at any depth.
+        if opts.skip_hidden {
+            for pat in ["**/.*/**", "**/.*"] {
+                self.add_glob(pat, true, pat, &PatternSource::Hidden)?;
+            }
+        }
+
+        // Add inline patterns.
+        for p in &opts.ignore_patterns {
+            self.add(p, PatternSource::Inline)?;
+        }
+
+        if !opts.extensions.is_empty() {
+            self.extensions(&opts.extensions)?;
+        }
+
+        self.pathspecs(&opts.pathspecs, opts.unicode_normalization)
+    }
+
+    /// Record that `pattern` from `source` is unusable: fail with the error,
+    /// or note it when collecting errors.
+    fn reject(&mut self, pattern: &str, source: &PatternSource, message: String) -> io::Result<()> {
+        let error = PatternError {
+            pattern: pattern.to_owned(),
+            source: source.clone(),
+            line: self.line,
+            message,
+        };
+        match &mut self.errors {
+            Some(errors) => {
+                // One gitignore line may compile to two globs.
+                if errors.last() != Some(&error) {
+                    errors.push(error);
+                }
+                Ok(())
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, error)),
+        }
+    }
+
+    /// Add one pattern (relative to root); separators are normalized to '/',
+    /// a leading one anchors it and a trailing one makes it match directories
+    /// only.
+    fn add(&mut self, pattern: &str, source: PatternSource) -> io::Result<()> {
+        let pat = pattern.replace('\\', "/");
+        let (pat, anchored) = match pat.strip_prefix('/') {
+            Some(rest) if !rest.is_empty() => (rest.to_owned(), true),
+            _ => (pat, false),
+        };
+        let pat = match pat.strip_suffix('/') {
+            Some(dir) if !dir.is_empty() => format!("{dir}/**"),
+            _ => pat,
+        };
+        self.add_glob(&pat, anchored, pattern, &source)
+    }
+
+    /// Add the compiled form `pat` of the line `pattern`; with `literal`,
+    /// `*` and `?` do not match `/` and `\` escapes the next character.
+    fn add_glob(
+        &mut self,
+        pat: &str,
+        literal: bool,
+        pattern: &str,
+        source: &PatternSource,
+    ) -> io::Result<()> {
+        let compile = |p: &str| match literal {
+            false => Glob::new(p),
+            true => GlobBuilder::new(p)
+                .literal_separator(true)
+                .backslash_escape(true)
+                .build(),
+        };
+        let file = compile(pat);
+        let dir = (pat.strip_suffix("/**"))
+            .filter(|dir| !dir.is_empty())
+            .map(compile)
+            .transpose();
+        let (file, dir) = match (file, dir) {
+            (Ok(file), Ok(dir)) => (file, dir),
+            (Err(e), _) | (_, Err(e)) => return self.reject(pattern, source, e.kind().to_string()),
+        };
+        self.files.add(file);
+        self.sources.push(PatternMatch {
+            pattern: pattern.to_owned(),
+            source: source.clone(),
+        });
+        if let Some(dir) = dir {
+            self.dirs.add(dir);
+        }
+        Ok(())
+    }
+
+    /// Only include files with one of `extensions` (a leading `*.` or `.`
+    /// is dropped); `*` does not cross `/`, so any depth matches.
+    fn extensions(&mut self, extensions: &[String]) -> io::Result<()> {
+        let mut set = GlobSetBuilder::new();
+        let mut names = Vec::new();
+        for ext in extensions {
+            let name = ext.trim();
+            let name = (name.strip_prefix("*.").or_else(|| name.strip_prefix('.'))).unwrap_or(name);
+            if name.is_empty() || name.contains(['/', '\\']) {
+                self.reject(ext, &PatternSource::Extensions, "invalid extension".into())?;
+                continue;
+            }
+            let pat = format!("**/*.{}", globset::escape(name));
+            set.add(
+                GlobBuilder::new(&pat)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(invalid_input)?,
+            );
+            names.push(name);
+        }
+        let pattern = match names.as_slice() {
+            [name] => format!("**/*.{name}"),
+            names => format!("**/*.{{{}}}", names.join(",")),
+        };
+        let source = PatternMatch {
+            pattern,
+            source: PatternSource::Extensions,
+        };
+        self.include = Some((set.build().map_err(invalid_input)?, source));
+        Ok(())
+    }
+
+    /// Only include paths at or below one of `specs`, as normalized relative
+    /// paths; none, or one naming the root itself, selects everything.
+    fn pathspecs(&mut self, specs: &[String], form: Option<NormalizationForm>) -> io::Result<()> {
+        let mut paths = Vec::new();
+        for spec in specs {
+            let parts: Vec<&str> = (spec.split(['/', '\\']))
+                .filter(|part| !part.is_empty() && *part != ".")
+                .collect();
+            if parts.contains(&"..") {
+                self.reject(spec, &PatternSource::Pathspecs, "leaves the root".into())?;
+                continue;
+            }
+            if parts.is_empty() {
+                return Ok(());
+            }
+            let path = parts.join("/");
+            paths.push(match form {
+                Some(form) => form.apply(&path),
+                None => path,
+            });
+        }
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let source = PatternMatch {
+            pattern: paths.join(" "),
+            source: PatternSource::Pathspecs,
+        };
+        self.pathspecs = Some((paths, source));
+        Ok(())
+    }
+
+    /// Load ignore patterns from file (one per line, '#' comments).
+    fn add_file(
+        &mut self,
+        path: &Path,
+        format: IgnoreFileFormat,
+        source: PatternSource,
+    ) -> io::Result<()> {
+        self.add_text(&fs::read_to_string(path)?, path, format, source)
+    }
+
+    /// Load ignore patterns from the contents `txt` of the file at `path`.
+    fn add_text(
+        &mut self,
+        txt: &str,
+        path: &Path,
+        format: IgnoreFileFormat,
+        source: PatternSource,
+    ) -> io::Result<()> {
+        for (number, raw) in txt.lines().enumerate() {
+            self.line = Some(number + 1);
+            let line = match format {
+                // Leading blanks are part of a gitignore pattern.
+                IgnoreFileFormat::Gitignore => raw.trim_end(),
+                _ => raw.trim(),
+            };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // We do not support leading '!' negation (keep the crate tiny).
+            if line.starts_with('!') {
+                self.warnings.push(Warning {
+                    path: path.to_path_buf(),
+                    reason: WarningReason::NegatedPattern(line.to_owned()),
+                });
+                continue;
+            }
+            match format {
+                IgnoreFileFormat::Native => self.add(line, source.clone())?,
+                IgnoreFileFormat::Gitignore => {
+                    for pat in gitignore_globs(line) {
+                        self.add_glob(&pat, true, line, &source)?;
+                    }
+                }
+                IgnoreFileFormat::Dockerignore => {
+                    for pat in dockerignore_globs(line) {
+                        self.add_glob(&pat, true, line, &source)?;
+                    }
+                }
+            }
+        }
+        self.line = None;
+        Ok(())
+    }
+
+    fn build(self) -> io::Result<IgnoreRules> {
+        Ok(IgnoreRules {
+            files: self.files.build().map_err(invalid_input)?,
+            dirs: self.dirs.build().map_err(invalid_input)?,
+            sources: self.sources,
+            include: self.include,
+            pathspecs: self.pathspecs,
+            root: None,
+            warnings: self.warnings,
+        })
+    }
+}
+
+/// Whether `rel` is `dir` or a path below it.
+fn is_below(rel: &str, dir: &str) -> bool {
+    rel.strip_prefix(dir)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Globs for one `.gitignore` line: unanchored patterns get a `**/` prefix,
+/// and anything that may name a directory also covers its contents.
+fn gitignore_globs(line: &str) -> Vec<String> {
+    // `\#` and `\!` escape a leading comment or negation marker.
+    let line = line
+        .strip_prefix('\\')
+        .filter(|l| l.starts_with(['#', '!']))
+        .unwrap_or(line);
+    let (pat, dir_only) = match line.strip_suffix('/') {
+        Some(pat) => (pat, true),
+        None => (line, false),
+    };
+    let pat = match pat.strip_prefix('/') {
+        Some(anchored) => anchored.to_owned(),
+        None if pat.contains('/') => pat.to_owned(),
+        None => format!("**/{pat}"),
+    };
+    match dir_only {
+        true => vec![format!("{pat}/**")],
+        false => vec![format!("{pat}/**"), pat],
+    }
+}
+
+/// Globs for one `.dockerignore` line: cleaned like Docker does and anchored
+/// to the root, covering a matched directory's contents.
+fn dockerignore_globs(line: &str) -> Vec<String> {
+    let parts: Vec<&str> = line
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect();
+    if parts.is_empty() {
+        return Vec::new();
+    }
+    let pat = parts.join("/");
+    vec![format!("{pat}/**"), pat]
+}
+
+/// Path of the global git excludes file ([`Options::global_gitignore`]),
+/// found the way git finds it: the last `core.excludesFile` of the XDG and
+/// home git configs, else `git/ignore` in the XDG config directory.
+fn global_gitignore() -> Option<PathBuf> {
+    let home = env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from);
+    let xdg = env::var_os("XDG_CONFIG_HOME")
+        .filter(|xdg| !xdg.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| Some(home.as_ref()?.join(".config")));
+    let configs = [
+        xdg.as_ref().map(|xdg| xdg.join("git").join("config")),
+        home.as_ref().map(|home| home.join(".gitconfig")),
+    ];
+    // The home config is read after the XDG one and wins.
+    let configured = (configs.iter().rev().flatten())
+        .find_map(|config| excludes_file(&fs::read_to_string(config).ok()?));
+    match configured {
+        Some(path) => match (path.strip_prefix("~/"), &home) {
+            (Some(rest), Some(home)) => Some(home.join(rest)),
+            _ => Some(PathBuf::from(path)),
+        },
+        None => Some(xdg?.join("git").join("ignore")),
+    }
+}
+
+/// The last `excludesFile` of the `[core]` section in the git config `txt`.
+/// Quotes and trailing comments are removed; includes are not followed.
+fn excludes_file(txt: &str) -> Option<String> {
+    let mut in_core = false;
+    let mut found = None;
+    for line in txt.lines().map(str::trim) {
+        if let Some(header) = line.strip_prefix('[') {
+            in_core = header.trim_end().eq_ignore_ascii_case("core]");
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if in_core && key.trim().eq_ignore_ascii_case("excludesfile") {
+            found = Some(config_value(value));
+        }
+    }
+    found
+}
+
+/// A git config value without its quotes, escapes and trailing comment.
+fn config_value(raw: &str) -> String {
+    let mut value = String::new();
+    let (mut quoted, mut chars) = (false, raw.trim().chars());
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => value.extend(chars.next()),
+            '#' | ';' if !quoted => break,
+            c => value.push(c),
+        }
+    }
+    value.trim_end().to_owned()
+}
+
+fn invalid_input(e: globset::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
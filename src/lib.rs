@@ -1,221 +1,2880 @@
 //! Deterministic directory hashing with simple glob ignores.
 //!
 //! Design:
-//! - Build a file list by walking `root` and filtering via `globset`.
-//! - Sort files by normalized relative path to guarantee stable order.
+//! - Build a file list by walking `root` (children sorted by name) and
+//!   filtering via `globset`; directories covered by a `dir/**` pattern are
+//!   pruned without descending. Directory listings can optionally be
+//!   prefetched by a thread pool (`parallel-walk` feature).
+//! - Visit files in order of their normalized relative path to guarantee a
+//!   stable order. Each directory's children are sorted so that a depth-first
+//!   walk already yields that order; nothing is buffered besides the listings
+//!   of the directories currently open, so memory stays bounded on huge trees.
 //! - For each file: stream its content into an *inner* blake3 hasher,
 //!   then feed the outer hasher with record-framed data:
 //!   b"F\0" + path + b"\0" + content_digest + [metadata?].
+//!   Dangling symlinks may be recorded as b"L\0" + path + b"\0" + target.
 //! - Finally, return the outer digest as lowercase hex.
 //!
 //! This crate intentionally keeps ignore semantics minimal (no `!` negations).
+//!
+//! Skipped entries and dropped ignore rules are not printed; they are
+//! collected as [`Warning`]s in [`RunStats::warnings`] and passed to
+//! [`Options::on_warning`] as they happen. With the `tracing` feature they
+//! are also emitted as `tracing` events (next to debug events for pruned
+//! directories and skipped symlink loops).
+
+/// `tracing::debug!` with the `tracing` feature, nothing otherwise.
+macro_rules! debug_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
+mod algo;
+#[cfg(feature = "build-support")]
+mod build_support;
+#[cfg(feature = "cap-std")]
+mod cap;
+mod checkpoint;
+mod chunk;
+mod codec;
+mod config;
+mod engine;
+mod explain;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod frame;
+mod ignore;
+mod link;
+mod manifest;
+mod merkle;
+mod newline;
+#[cfg(feature = "object-store")]
+mod objstore;
+mod oci;
+mod owner;
+mod pool;
+mod preset;
+#[cfg(feature = "python")]
+mod python;
+mod rollup;
+mod snapshot;
+mod source;
+mod throttle;
+mod walk;
+#[cfg(feature = "notify")]
+mod watch;
+mod winmeta;
+
+pub use algo::{Algorithm, ParseDigestError, SelfDescribingDigest};
+#[cfg(feature = "build-support")]
+pub use build_support::emit_cargo_rerun_and_hash;
+#[cfg(feature = "cap-std")]
+pub use cap::get_dir_hash_at;
+pub use checkpoint::Checkpoint;
+pub use chunk::{Chunk, Chunking};
+pub use config::{CONFIG_FILE_NAME, Config};
+pub use engine::Engine;
+pub use explain::{Explanation, PatternError, PatternMatch, PatternSource, explain};
+pub use frame::{DumpFormat, FormatVersion};
+pub use manifest::ManifestFormat;
+pub use merkle::{DirTree, InclusionProof, NodeKind, TreeNode};
+#[cfg(feature = "object-store")]
+pub use objstore::ObjectStoreSource;
+pub use oci::{oci_layer_digest, write_oci_layer};
+pub use preset::Preset;
+pub use snapshot::{DuplicateSet, Snapshot, SnapshotDiff, SnapshotEntry};
+pub use source::{SourceFile, TreeSource, get_source_hash};
+#[cfg(feature = "notify")]
+pub use watch::{DEBOUNCE, DirHashEvent, DirHashWatch, watch_dir_hash};
+
+use blake3::Hasher as Blake3;
+use chunk::Chunks;
+use frame::{Dump, Frames};
+use ignore::IgnoreRules;
+use newline::Newlines;
+use owner::NameCache;
+use pool::{HashPool, Readahead};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use throttle::Throttle;
+use walk::{Node, WalkError, Walker};
+
+/// Options controlling hashing behavior.
+///
+/// With the `serde` feature, missing fields deserialize to their defaults,
+/// unknown ones are rejected (as in config files) and the runtime-only
+/// `progress`, `on_warning` and `cancel` are skipped.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct Options {
+    /// Record layout fed to the root digest. `V1` (the default) keeps digests
+    /// compatible with earlier releases; `V2` length-prefixes every field.
+    pub format_version: FormatVersion,
+    /// Length in bytes of the root digest (default 32), taken from BLAKE3's
+    /// extendable output: a longer digest starts with the 32-byte one, and a
+    /// shorter one is its prefix. Per-file digests, Merkle nodes and snapshots
+    /// keep 32 bytes, as do other algorithms of [`get_dir_digests`].
+    pub output_len: usize,
+    /// Follow symlinks when walking the tree. On Windows, junctions count as
+    /// symlinks; symlinks created by WSL are never followed.
+    pub follow_symlinks: bool,
+    /// With `follow_symlinks`, only follow links whose canonical target lies
+    /// inside the canonical root. Links that leave it are never read; they
+    /// are handled like dangling ones (see `broken_symlinks`), so hashing an
+    /// untrusted tree cannot pull in `/etc` or a huge external mount.
+    pub confine_symlinks: bool,
+    /// Include basic metadata (mode on Unix, and the `timestamps` as
+    /// (secs,nanos) on all).
+    pub include_metadata: bool,
+    /// Timestamps in the metadata frame; empty for none. They are framed in
+    /// the order mtime, ctime, birth time whatever the order here, each
+    /// truncated to `mtime_granularity`, and left out where the platform or
+    /// file system does not report them.
+    pub timestamps: Vec<Timestamp>,
+    /// Precision of the timestamps fed into the metadata frame; coarser
+    /// values survive copies to file systems that store less.
+    pub mtime_granularity: Granularity,
+    /// How the file mode is framed in the metadata frame.
+    pub mode_normalization: ModeNormalization,
+    /// Add the file's owner to the metadata frame. Unix only; ignored
+    /// elsewhere.
+    pub ownership: Ownership,
+    /// Add the readonly, hidden, system and archive attributes to the
+    /// metadata frame. Windows only; ignored elsewhere.
+    pub windows_attributes: bool,
+    /// Add the file's DACL (as SDDL) to the metadata frame. Windows only;
+    /// ignored elsewhere.
+    pub windows_acl: bool,
+    /// Frame each file's type (regular file or followed symlink) and
+    /// executable bit, without the rest of the metadata: `chmod +x` then
+    /// changes the digest while mtimes, owners and other permission bits
+    /// do not. Independent of [`Options::include_metadata`].
+    pub include_file_type: bool,
+    /// Where there is no mode (Windows), files with one of these extensions
+    /// (`exe`, or `.exe`; compared ignoring case) count as executable for
+    /// [`Options::include_file_type`]; empty for none. Unused on Unix, which
+    /// takes the owner's execute bit.
+    pub executable_extensions: Vec<String>,
+    /// Clamp mtimes in the metadata frame to at most this instant, the
+    /// reproducible-builds `SOURCE_DATE_EPOCH` convention. The library does
+    /// not read the environment; the CLI fills this from that variable.
+    pub clamp_mtime: Option<SystemTime>,
+    /// Treat path comparison as case-sensitive. If `false`, we lowercase paths
+    /// before sorting and framing (helps Windows).
+    pub case_sensitive_paths: bool,
+    /// What to do when, with `case_sensitive_paths = false`, two entries of
+    /// one directory only differ in case and so frame to the same path.
+    pub case_collisions: CaseCollisionPolicy,
+    /// Normalize relative paths to this Unicode form before ignore matching,
+    /// sorting and framing, so that a tree copied between macOS (which may
+    /// report NFD names) and Linux (usually NFC) hashes the same.
+    pub unicode_normalization: Option<NormalizationForm>,
+    /// Frame paths relative to this directory instead of the root, which must
+    /// lie inside it: hashing `root/sub` with base `root` frames `sub/a.txt`
+    /// rather than `a.txt`. Ignore patterns still match relative to the root.
+    pub path_base: Option<PathBuf>,
+    /// How the root (and [`Options::path_base`]) is made absolute before the
+    /// walk. The default resolves symlinks, so a symlinked root frames the
+    /// name and position of its target.
+    pub canonicalize_root: CanonicalizeRoot,
+    /// Frame the root directory's own name (its final component once
+    /// resolved, normalized like the other paths) before the file records,
+    /// so renaming the top-level folder changes the digest. Off by default.
+    /// Not part of [`get_dir_hash_tree`]'s Merkle root.
+    pub include_root_name: bool,
+    /// Hash only the entries modified after this instant (their mtime, not
+    /// clamped), for a quick "anything touched since the last deploy?"
+    /// check; older ones count as ignored. The cutoff itself is framed
+    /// after the header, so such a digest never equals a full one. Not part
+    /// of [`get_dir_hash_tree`]'s Merkle root.
+    pub modified_since: Option<SystemTime>,
+    /// Extra ignore patterns (applied relative to the root).
+    pub ignore_patterns: Vec<String>,
+    /// Hash only files with one of these extensions (`rs`, or `.rs`, for
+    /// `**/*.rs`), at any depth; empty for no filter. Applies on top of the
+    /// ignore patterns, to files only: every directory is still walked.
+    pub extensions: Vec<String>,
+    /// Hash only what lies at or below these paths (`src/`, `proto/api`),
+    /// relative to the root; empty (or `.`) for the whole tree. Paths are
+    /// still framed relative to the root, so the digest is the one of a
+    /// tree holding just the selected entries; directories off the listed
+    /// paths are never walked.
+    pub pathspecs: Vec<String>,
+    /// Files with ignore patterns, each read in its own syntax (see
+    /// [`IgnoreFileFormat`]). A plain path converts to a native one.
+    pub ignore_files: Vec<IgnoreFile>,
+    /// Whether to auto-load `.get_dir_hash_ignore` from root and from every
+    /// directory below it (nested files match relative to their directory).
+    pub load_dot_get_dir_hash_ignore: bool,
+    /// Skip `.git/`, `.hg/` and `.svn/` directories at any depth, as if
+    /// `**/.git/**` etc. were ignore patterns.
+    pub exclude_vcs_dirs: bool,
+    /// Also load the user's global git excludes file, in gitignore format,
+    /// so that with the repository's `.gitignore` in `ignore_files` the
+    /// files left out are the ones `git status` leaves out. The file is
+    /// `core.excludesFile` from `$XDG_CONFIG_HOME/git/config` or
+    /// `~/.gitconfig`, or else `$XDG_CONFIG_HOME/git/ignore` (with
+    /// `~/.config` for an unset `XDG_CONFIG_HOME`); these are looked up in
+    /// the environment of the process. A missing file is not an error.
+    pub global_gitignore: bool,
+    /// Skip hidden files and directories at any depth: names starting with
+    /// `.`, as if `**/.*` were an ignore pattern, and on Windows also
+    /// entries with the hidden attribute.
+    pub skip_hidden: bool,
+    /// Read hard-linked files (same dev+inode) only once and reuse their
+    /// content digest for the other paths. No effect on non-Unix platforms.
+    pub dedup_hard_links: bool,
+    /// What to do when a followed symlink points back to one of its ancestor
+    /// directories. Only relevant with `follow_symlinks`.
+    pub symlink_loops: SymlinkLoopPolicy,
+    /// What to do with symlinks whose target does not exist.
+    pub broken_symlinks: Policy,
+    /// What to do with FIFOs, sockets and devices. `Ignore` (the default)
+    /// leaves them out, `Error` aborts and `Record` frames their path and
+    /// type, so adding a named pipe changes the digest.
+    pub special_files: Policy,
+    /// What to do with paths that are not valid UTF-8. `Record` (the
+    /// default) frames their raw bytes (WTF-8 on Windows), so distinct names
+    /// never collide; `Ignore` leaves them out and `Error` aborts.
+    pub non_utf8_names: Policy,
+    /// Number of threads listing directories ahead of the walk. `0` and `1`
+    /// mean a plain sequential walk. Requires the `parallel-walk` feature;
+    /// ignored otherwise. The digest does not depend on this value.
+    pub walk_threads: usize,
+    /// Hash files of at least this many bytes through a memory map instead of
+    /// buffered reads. Requires the `mmap` feature; ignored otherwise.
+    pub mmap_threshold: Option<u64>,
+    /// Hash files of at least this many bytes on all cores (see `threads`)
+    /// instead of one. Requires the `rayon` feature; ignored otherwise.
+    pub parallel_hash_threshold: Option<u64>,
+    /// CPU threads for hashing one large file (`parallel_hash_threshold`).
+    /// `0` (the default) uses rayon's global pool, one thread per core.
+    /// Requires the `rayon` feature; ignored otherwise.
+    pub threads: usize,
+    /// Number of files opened and read at once. Above `1`, upcoming files are
+    /// read and hashed on that many threads ahead of the framing loop: deep
+    /// queues suit NVMe, while `1` (the default) is kindest to network file
+    /// systems. Not used by multi-digest, throttled or checkpointed runs, or
+    /// with [`Options::concurrent_modification`] checks. The digest does not
+    /// depend on this value.
+    pub io_concurrency: usize,
+    /// Limit file reads to this many bytes per second over the whole run, so
+    /// background scans leave disk bandwidth to other work. Throttled runs
+    /// always use buffered reads (no memory maps or multi-threaded hashing).
+    pub max_read_bytes_per_sec: Option<u64>,
+    /// Size limit for [`Options::oversize_policy`], in bytes; files larger
+    /// than this are handled by that policy instead of being read.
+    pub max_file_size: Option<u64>,
+    /// What to do with files larger than `max_file_size`. `Hash` (the
+    /// default) reads them like any other file.
+    pub oversize_policy: OversizePolicy,
+    /// Frame every regular file by its path and size, as
+    /// [`OversizePolicy::SkipWithRecord`] frames an oversize one, without
+    /// opening it. The digest then covers the shape of the tree only: an
+    /// edit that keeps a file's size leaves it unchanged.
+    pub structure_only: bool,
+    /// Hash file contents with every CRLF (`\r\n`) read as LF (`\n`), so a
+    /// checkout with Windows line endings hashes like one with Unix endings;
+    /// a lone `\r` is kept. Files are then read through a buffer (no memory
+    /// maps or multi-threaded hashing). Cannot be combined with
+    /// [`Options::chunking`]; [`write_oci_layer`] stores the bytes as they
+    /// are.
+    pub normalize_newlines: bool,
+    /// Compare each file's size and mtime before and after reading it, and
+    /// what to do when they differ (the digest would mix old and new
+    /// contents). `Ignore` (the default) does not check.
+    pub concurrent_modification: ModificationPolicy,
+    /// Go on with the next file when one cannot be opened, read or
+    /// `stat`ed, then fail at the end with a [`FileErrors`] naming every
+    /// such file. By default the first one stops the run (its error wraps a
+    /// [`FileError`]). [`write_oci_layer`] and [`oci_layer_digest`] always
+    /// stop at the first.
+    pub keep_going: bool,
+    /// Fail instead of leaving out an entry that cannot be listed
+    /// ([`WarningReason::Unreadable`]) or dropping a `!` negation from an
+    /// ignore file ([`WarningReason::NegatedPattern`]); by default both are
+    /// reported as [`Warning`]s and the run goes on.
+    pub fail_on_skip: bool,
+    /// Stop with a [`LimitExceeded`] error once the walk yields more entries
+    /// than this, before any of the extra ones is read. Guards services
+    /// hashing user-supplied trees against huge uploads.
+    pub max_files: Option<u64>,
+    /// Stop with a [`LimitExceeded`] error once the files to hash add up to
+    /// more than this many bytes (by their size on disk, checked before
+    /// reading them).
+    pub max_total_bytes: Option<u64>,
+    /// Stop with a [`LimitExceeded`] error before entering a directory more
+    /// than this many levels below the root, rather than following
+    /// pathological nesting (`node_modules` thousands of levels deep) until
+    /// an OS limit is hit. Whatever the setting, a path too long for the OS
+    /// or running out of file handles fails the run, naming the path.
+    pub max_depth: Option<u64>,
+    /// Cut every file into content-defined chunks (FastCDC) and frame the
+    /// chunk digests after its metadata, for dedup and sync tooling. Requires
+    /// the `cdc` feature; hashing fails with `Unsupported` otherwise.
+    pub chunking: Option<Chunking>,
+    /// Save progress to a checkpoint file so an interrupted run can resume:
+    /// files already completed are not read again unless their size or
+    /// mtime changed. With `chunking` set, files are always read again
+    /// (chunk lists are not checkpointed).
+    pub checkpoint: Option<Checkpoint>,
+    /// Stop with a [`TimedOut`] error, carrying the files hashed so far, once
+    /// a run has taken this long. Checked where [`Options::cancel`] is, so a
+    /// single read blocked on a network filesystem is not interrupted.
+    pub deadline: Option<Duration>,
+    /// Called after every hashed file. Setting this makes the run two-pass:
+    /// the tree is enumerated first so that totals are known up front.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub progress: Option<ProgressCallback>,
+    /// Called for every [`Warning`] as soon as it occurs, e.g. to print it.
+    /// The same warnings end up in [`RunStats::warnings`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub on_warning: Option<WarningCallback>,
+    /// Checked between files and between read chunks; once cancelled, hashing
+    /// stops with a [`Cancelled`] error.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub cancel: Option<CancellationToken>,
+}
+
+/// Generic handling for entries that are not plain files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum Policy {
+    /// Leave the entry out of the digest.
+    #[default]
+    Ignore,
+    /// Abort hashing with an error.
+    Error,
+    /// Frame a typed record for the entry so its presence affects the digest.
+    Record,
+}
+
+/// Handling of files larger than [`Options::max_file_size`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum OversizePolicy {
+    /// Read and hash the file anyway.
+    #[default]
+    Hash,
+    /// Leave the file out of the digest without reading it.
+    Skip,
+    /// Frame the file's path and size instead of its content, so it still
+    /// affects the digest but is never read.
+    SkipWithRecord,
+    /// Abort hashing with an error before reading the file.
+    Error,
+}
+
+impl FromStr for OversizePolicy {
+    type Err = ParseOptionError;
+
+    /// Parses `hash`, `skip`, `skip-with-record` or `error`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hash" => Ok(OversizePolicy::Hash),
+            "skip" => Ok(OversizePolicy::Skip),
+            "skip-with-record" => Ok(OversizePolicy::SkipWithRecord),
+            "error" => Ok(OversizePolicy::Error),
+            _ => Err(ParseOptionError::new(
+                s,
+                "hash, skip, skip-with-record or error",
+            )),
+        }
+    }
+}
+
+impl FromStr for Policy {
+    type Err = ParseOptionError;
+
+    /// Parses `ignore`, `error` or `record`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(Policy::Ignore),
+            "error" => Ok(Policy::Error),
+            "record" => Ok(Policy::Record),
+            _ => Err(ParseOptionError::new(s, "ignore, error or record")),
+        }
+    }
+}
+
+/// Error returned when parsing one of the option enums ([`Policy`],
+/// [`OversizePolicy`], [`SymlinkLoopPolicy`], [`CaseCollisionPolicy`], [`CanonicalizeRoot`],
+/// [`ModificationPolicy`], [`NormalizationForm`],
+/// [`Granularity`], [`ModeNormalization`], [`Ownership`], [`DumpFormat`],
+/// [`FormatVersion`], [`ManifestFormat`]) from a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptionError {
+    value: String,
+    expected: &'static str,
+}
+
+impl ParseOptionError {
+    pub(crate) fn new(value: &str, expected: &'static str) -> Self {
+        Self {
+            value: value.to_owned(),
+            expected,
+        }
+    }
+}
+
+impl fmt::Display for ParseOptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid value '{}' (expected {})",
+            self.value, self.expected
+        )
+    }
+}
+
+impl std::error::Error for ParseOptionError {}
+
+/// Unicode normalization form for [`Options::unicode_normalization`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum NormalizationForm {
+    /// Canonical composition, what Linux and Windows tools usually write.
+    #[default]
+    Nfc,
+    /// Canonical decomposition, as reported by HFS+.
+    Nfd,
+}
+
+impl NormalizationForm {
+    /// `s` in this form.
+    pub(crate) fn apply(self, s: &str) -> String {
+        use unicode_normalization::UnicodeNormalization;
+        match self {
+            NormalizationForm::Nfc => s.nfc().collect(),
+            NormalizationForm::Nfd => s.nfd().collect(),
+        }
+    }
+}
+
+impl FromStr for NormalizationForm {
+    type Err = ParseOptionError;
+
+    /// Parses `nfc` or `nfd`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nfc" => Ok(NormalizationForm::Nfc),
+            "nfd" => Ok(NormalizationForm::Nfd),
+            _ => Err(ParseOptionError::new(s, "nfc or nfd")),
+        }
+    }
+}
+
+/// Timestamp precision for [`Options::mtime_granularity`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum Granularity {
+    /// Keep the full precision the file system reports.
+    #[default]
+    Nanos,
+    /// Drop sub-second precision (ext3, HFS+, many archive formats).
+    Seconds,
+    /// Round down to an even second, like FAT and exFAT store it.
+    TwoSeconds,
+}
+
+impl Granularity {
+    /// `(secs, nanos)` reduced to this precision.
+    fn truncate(self, secs: u64, nanos: u32) -> (u64, u32) {
+        match self {
+            Granularity::Nanos => (secs, nanos),
+            Granularity::Seconds => (secs, 0),
+            Granularity::TwoSeconds => (secs & !1, 0),
+        }
+    }
+}
+
+impl FromStr for Granularity {
+    type Err = ParseOptionError;
+
+    /// Parses `nanos`, `seconds` or `two-seconds`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nanos" => Ok(Granularity::Nanos),
+            "seconds" => Ok(Granularity::Seconds),
+            "two-seconds" => Ok(Granularity::TwoSeconds),
+            _ => Err(ParseOptionError::new(s, "nanos, seconds or two-seconds")),
+        }
+    }
+}
+
+/// A timestamp for [`Options::timestamps`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum Timestamp {
+    /// Last modification, framed right after the mode without a tag (as
+    /// before this option existed); subject to `clamp_mtime`.
+    Mtime,
+    /// Last status change, framed as `\0C\0` + secs + nanos. Every write,
+    /// chmod, chown or rename bumps it and it cannot be set back from user
+    /// space, which suits tamper detection. Unix only.
+    Ctime,
+    /// Creation time, framed as `\0B\0` + secs + nanos, where the platform
+    /// and file system record it (Windows, macOS, Linux with `statx`).
+    Birthtime,
+}
+
+impl FromStr for Timestamp {
+    type Err = ParseOptionError;
+
+    /// Parses `mtime`, `ctime` or `birthtime`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mtime" => Ok(Timestamp::Mtime),
+            "ctime" => Ok(Timestamp::Ctime),
+            "birthtime" => Ok(Timestamp::Birthtime),
+            _ => Err(ParseOptionError::new(s, "mtime, ctime or birthtime")),
+        }
+    }
+}
+
+/// An entry of [`Options::ignore_files`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IgnoreFile {
+    /// The file; a missing one is skipped.
+    pub path: PathBuf,
+    /// How its lines are read.
+    pub format: IgnoreFileFormat,
+}
+
+impl IgnoreFile {
+    /// `path`, read as `format`.
+    pub fn new(path: impl Into<PathBuf>, format: IgnoreFileFormat) -> Self {
+        IgnoreFile {
+            path: path.into(),
+            format,
+        }
+    }
+}
+
+impl From<PathBuf> for IgnoreFile {
+    fn from(path: PathBuf) -> Self {
+        IgnoreFile::new(path, IgnoreFileFormat::Native)
+    }
+}
+
+impl From<&Path> for IgnoreFile {
+    fn from(path: &Path) -> Self {
+        IgnoreFile::new(path, IgnoreFileFormat::Native)
+    }
+}
+
+impl From<&str> for IgnoreFile {
+    fn from(path: &str) -> Self {
+        IgnoreFile::new(path, IgnoreFileFormat::Native)
+    }
+}
+
+/// Syntax of an [`IgnoreFile`]. Negated (`!`) lines are dropped with a
+/// warning in every format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum IgnoreFileFormat {
+    /// This crate's own: one glob per line, matched against the whole
+    /// relative path (`*` crosses `/`), like `ignore_patterns`.
+    #[default]
+    Native,
+    /// `.gitignore` (and `.npmignore`): a pattern without a slash matches at
+    /// any depth, a leading or inner `/` anchors it to the root, a trailing
+    /// `/` matches directories only, and `*` stops at `/`. A matched
+    /// directory excludes everything below it.
+    Gitignore,
+    /// `.dockerignore`: every pattern is anchored to the root (a leading `/`
+    /// is optional), `*` stops at `/` and `**` spans directories; a matched
+    /// directory excludes everything below it, so the digest covers exactly
+    /// the build context.
+    Dockerignore,
+}
+
+impl FromStr for IgnoreFileFormat {
+    type Err = ParseOptionError;
+
+    /// Parses `native`, `gitignore` or `dockerignore`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(IgnoreFileFormat::Native),
+            "gitignore" => Ok(IgnoreFileFormat::Gitignore),
+            "dockerignore" => Ok(IgnoreFileFormat::Dockerignore),
+            _ => Err(ParseOptionError::new(
+                s,
+                "native, gitignore or dockerignore",
+            )),
+        }
+    }
+}
+
+/// Mode representation for [`Options::mode_normalization`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum ModeNormalization {
+    /// Full `st_mode` on Unix, the read-only bit elsewhere.
+    #[default]
+    Raw,
+    /// Git's model: `0o100755` if the owner may execute the file, `0o100644`
+    /// otherwise, and `0o120000` for a followed symlink. Umask and group or
+    /// other permission bits no longer matter, and the frame is the same on
+    /// every platform (non-Unix files are never executable).
+    Git,
+}
+
+impl FromStr for ModeNormalization {
+    type Err = ParseOptionError;
+
+    /// Parses `raw` or `git`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(ModeNormalization::Raw),
+            "git" => Ok(ModeNormalization::Git),
+            _ => Err(ParseOptionError::new(s, "raw or git")),
+        }
+    }
+}
+
+/// Owner information for [`Options::ownership`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum Ownership {
+    /// Leave ownership out of the digest.
+    #[default]
+    Omit,
+    /// Numeric `uid` and `gid`.
+    Ids,
+    /// User and group names, for comparing trees across machines whose ids
+    /// differ. Ids without a name are framed as decimal numbers.
+    Names,
+}
+
+impl FromStr for Ownership {
+    type Err = ParseOptionError;
+
+    /// Parses `omit`, `ids` or `names`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "omit" => Ok(Ownership::Omit),
+            "ids" => Ok(Ownership::Ids),
+            "names" => Ok(Ownership::Names),
+            _ => Err(ParseOptionError::new(s, "omit, ids or names")),
+        }
+    }
+}
+
+/// Snapshot of a run's progress, passed to [`ProgressCallback`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Files hashed so far.
+    pub files_done: u64,
+    /// Files that will be hashed in total.
+    pub files_total: u64,
+    /// Bytes read so far.
+    pub bytes_done: u64,
+    /// Combined size of all files to hash.
+    pub bytes_total: u64,
+}
+
+/// Shareable progress hook for [`Options::progress`].
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(&Progress) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(f: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback(..)")
+    }
+}
+
+/// Something a run skipped or dropped instead of failing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Warning {
+    /// The skipped entry, or the ignore file holding the dropped rule.
+    pub path: PathBuf,
+    /// What happened.
+    pub reason: WarningReason,
+}
+
+/// Why a [`Warning`] was raised.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum WarningReason {
+    /// The entry could not be read and was left out (the I/O error message).
+    Unreadable(String),
+    /// A `!` negation in an ignore file was dropped, since negations are not
+    /// supported (the line as written).
+    NegatedPattern(String),
+    /// The [`Options::checkpoint`] file was not resumed from, because it is
+    /// corrupt or another run holds it (why); its files are hashed again.
+    Checkpoint(String),
+    /// The run stopped early and its progress could not be saved to the
+    /// [`Options::checkpoint`] file (the I/O error message); a later run
+    /// resumes from the last save that worked.
+    CheckpointSave(String),
+    /// The file's size or mtime changed while it was read, so its digest
+    /// may mix old and new contents ([`ModificationPolicy::Warn`]).
+    Modified,
+}
+
+impl Warning {
+    pub(crate) fn unreadable(path: &Path, e: io::Error) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            reason: WarningReason::Unreadable(e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path.display();
+        match &self.reason {
+            WarningReason::Unreadable(e) => write!(f, "skipping entry: {path}: {e}"),
+            WarningReason::NegatedPattern(line) => {
+                write!(f, "{path}: dropping unsupported negated pattern `{line}`")
+            }
+            WarningReason::Checkpoint(why) => {
+                write!(f, "{path}: not resuming from checkpoint: {why}")
+            }
+            WarningReason::CheckpointSave(e) => write!(f, "{path}: cannot save checkpoint: {e}"),
+            WarningReason::Modified => write!(f, "file changed while it was read: {path}"),
+        }
+    }
+}
+
+/// Shareable warning hook for [`Options::on_warning`].
+#[derive(Clone)]
+pub struct WarningCallback(Arc<dyn Fn(&Warning) + Send + Sync>);
+
+impl WarningCallback {
+    pub fn new(f: impl Fn(&Warning) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for WarningCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WarningCallback(..)")
+    }
+}
+
+/// Cheap, cloneable flag for aborting a run from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    flag: Arc<AtomicBool>,
+    /// End of the run's [`Options::deadline`], set on the run's own copy.
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation; every clone observes it.
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+}
+
+/// `opts` for a run started at `started`: with [`Options::deadline`], its
+/// end rides on a copy of the cancellation token, where [`check_cancel`]
+/// looks for it.
+pub(crate) fn with_deadline(opts: &Options, started: Instant) -> Cow<'_, Options> {
+    match opts.deadline.and_then(|budget| started.checked_add(budget)) {
+        Some(deadline) => Cow::Owned(Options {
+            cancel: Some(CancellationToken {
+                flag: (opts.cancel.as_ref()).map_or_else(Arc::default, |t| t.flag.clone()),
+                deadline: Some(deadline),
+            }),
+            ..opts.clone()
+        }),
+        None => Cow::Borrowed(opts),
+    }
+}
+
+/// Error payload for a run stopped through [`Options::cancel`].
+///
+/// Returned wrapped in an `io::Error`; use [`Cancelled::find`] to get at it.
+#[derive(Debug, Clone, Default)]
+pub struct Cancelled {
+    /// Work completed before the run stopped.
+    pub stats: RunStats,
+}
+
+impl Cancelled {
+    /// The `Cancelled` payload inside `e`, if `e` came from a cancelled run.
+    pub fn find(e: &io::Error) -> Option<&Cancelled> {
+        e.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("hashing cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl From<Cancelled> for io::Error {
+    fn from(c: Cancelled) -> Self {
+        io::Error::other(c)
+    }
+}
+
+/// Error payload for a run stopped by [`Options::deadline`].
+///
+/// Returned wrapped in an `io::Error` of kind `TimedOut`; use
+/// [`TimedOut::find`] to get at it.
+#[derive(Debug, Clone, Default)]
+pub struct TimedOut {
+    /// The partial manifest: content digests of the files hashed before time
+    /// ran out, in canonical order.
+    pub files: Vec<FileDigest>,
+    /// Work completed before the run stopped.
+    pub stats: RunStats,
+}
+
+impl TimedOut {
+    /// The `TimedOut` payload inside `e`, if `e` came from a run that ran
+    /// out of time.
+    pub fn find(e: &io::Error) -> Option<&TimedOut> {
+        e.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("hashing timed out")
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+impl From<TimedOut> for io::Error {
+    fn from(t: TimedOut) -> Self {
+        io::Error::new(io::ErrorKind::TimedOut, t)
+    }
+}
+
+/// Error payload for a run stopped by [`Options::max_files`],
+/// [`Options::max_total_bytes`] or [`Options::max_depth`].
+///
+/// Returned wrapped in an `io::Error` of kind `QuotaExceeded`; use
+/// [`LimitExceeded::find`] to get at it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitExceeded {
+    /// Which limit was hit.
+    pub kind: LimitKind,
+    /// Its configured value.
+    pub limit: u64,
+}
+
+/// The limit behind a [`LimitExceeded`] error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    /// [`Options::max_files`].
+    Files,
+    /// [`Options::max_total_bytes`].
+    TotalBytes,
+    /// [`Options::max_depth`].
+    Depth,
+}
+
+impl LimitExceeded {
+    /// The `LimitExceeded` payload inside `e`, if `e` came from a run that
+    /// hit a limit.
+    pub fn find(e: &io::Error) -> Option<&LimitExceeded> {
+        e.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            LimitKind::Files => write!(f, "more than {} files", self.limit),
+            LimitKind::TotalBytes => write!(f, "more than {} bytes in total", self.limit),
+            LimitKind::Depth => write!(f, "more than {} directory levels", self.limit),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl From<LimitExceeded> for io::Error {
+    fn from(e: LimitExceeded) -> Self {
+        io::Error::new(io::ErrorKind::QuotaExceeded, e)
+    }
+}
+
+/// Error payload for a file that could not be opened, read or `stat`ed.
+///
+/// Returned wrapped in an `io::Error` of the underlying error's kind; use
+/// [`FileError::find`] to get at it.
+#[derive(Debug)]
+pub struct FileError {
+    /// The file, as opened.
+    pub path: PathBuf,
+    /// What failed.
+    pub op: FileOp,
+    /// The error the OS reported.
+    pub source: io::Error,
+}
+
+/// The operation behind a [`FileError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOp {
+    /// Opening the file.
+    Open,
+    /// Reading its contents.
+    Read,
+    /// Getting its metadata (size, type, times).
+    Metadata,
+}
+
+impl FileError {
+    /// The `FileError` payload inside `e`, if `e` came from a failed file.
+    pub fn find(e: &io::Error) -> Option<&FileError> {
+        e.get_ref()?.downcast_ref()
+    }
+}
+
+impl FileOp {
+    /// Wrap an error of this operation on `path` in a [`FileError`].
+    pub(crate) fn on(self, path: &Path) -> impl FnOnce(io::Error) -> io::Error + '_ {
+        move |source| {
+            FileError {
+                path: path.to_path_buf(),
+                op: self,
+                source,
+            }
+            .into()
+        }
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.op {
+            FileOp::Open => "opening",
+            FileOp::Read => "reading",
+            FileOp::Metadata => "reading metadata of",
+        };
+        write!(f, "{op} {}: {}", self.path.display(), self.source)
+    }
+}
+
+impl std::error::Error for FileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl From<FileError> for io::Error {
+    fn from(e: FileError) -> Self {
+        io::Error::new(e.source.kind(), e)
+    }
+}
+
+/// Error payload for a run with [`Options::keep_going`] in which files
+/// failed; use [`FileErrors::find`] to get at it.
+#[derive(Debug, Default)]
+pub struct FileErrors {
+    /// Every failed file, in walk order.
+    pub errors: Vec<FileError>,
+}
+
+impl FileErrors {
+    /// The `FileErrors` payload inside `e`, if `e` came from a run that
+    /// kept going past failed files.
+    pub fn find(e: &io::Error) -> Option<&FileErrors> {
+        e.get_ref()?.downcast_ref()
+    }
+
+    /// Set `e` aside if it is a [`FileError`] and `opts` keeps going past
+    /// those; any other error is returned.
+    pub(crate) fn set_aside(&mut self, e: io::Error, opts: &Options) -> io::Result<()> {
+        if !opts.keep_going || FileError::find(&e).is_none() {
+            return Err(e);
+        }
+        let inner = e.into_inner().expect("checked above");
+        self.errors
+            .push(*inner.downcast::<FileError>().expect("checked above"));
+        Ok(())
+    }
+
+    /// Fail with these errors, if there are any.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(self.into()),
+        }
+    }
+}
+
+impl fmt::Display for FileErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.errors.len() {
+            1 => f.write_str("1 file could not be hashed"),
+            n => write!(f, "{n} files could not be hashed"),
+        }
+    }
+}
+
+impl std::error::Error for FileErrors {}
+
+impl From<FileErrors> for io::Error {
+    fn from(e: FileErrors) -> Self {
+        io::Error::other(e)
+    }
+}
+
+/// Fail if a directory `depth` levels below the root is too deep for
+/// [`Options::max_depth`].
+pub(crate) fn check_depth(depth: usize, opts: &Options) -> io::Result<()> {
+    match opts.max_depth {
+        Some(limit) if depth as u64 > limit => Err(LimitExceeded {
+            kind: LimitKind::Depth,
+            limit,
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// What [`Options::max_files`] and [`Options::max_total_bytes`] count in
+/// runs that do not go through [`Entries`].
+#[derive(Default)]
+pub(crate) struct Limits {
+    /// Entries framed so far.
+    files: u64,
+    /// Size of the files read so far.
+    total_bytes: u64,
+}
+
+impl Limits {
+    /// Account for one more framed entry.
+    pub(crate) fn entry(&mut self, opts: &Options) -> io::Result<()> {
+        self.files += 1;
+        match opts.max_files {
+            Some(limit) if self.files > limit => Err(LimitExceeded {
+                kind: LimitKind::Files,
+                limit,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Account for a file of `size` bytes about to be read.
+    pub(crate) fn bytes(&mut self, size: u64, opts: &Options) -> io::Result<()> {
+        self.total_bytes = self.total_bytes.saturating_add(size);
+        match opts.max_total_bytes {
+            Some(limit) if self.total_bytes > limit => Err(LimitExceeded {
+                kind: LimitKind::TotalBytes,
+                limit,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Handling of symlink cycles encountered while following links.
+///
+/// Cycles are detected by comparing the (dev, inode) identity of every
+/// directory a link resolves to against the directories currently being
+/// walked. The walk itself runs in sorted order, so the first reported cycle
+/// is the same on every run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum SymlinkLoopPolicy {
+    /// Silently skip the looping link (nothing below it is hashed).
+    #[default]
+    Skip,
+    /// Abort hashing with an error naming the link and its ancestor.
+    Error,
+}
+
+impl FromStr for SymlinkLoopPolicy {
+    type Err = ParseOptionError;
+
+    /// Parses `skip` or `error`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(SymlinkLoopPolicy::Skip),
+            "error" => Ok(SymlinkLoopPolicy::Error),
+            _ => Err(ParseOptionError::new(s, "skip or error")),
+        }
+    }
+}
+
+/// Handling of names that only differ in case when paths are compared
+/// case-insensitively.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum CaseCollisionPolicy {
+    /// Hash every colliding entry, in byte order of the exact names (so
+    /// `README` before `readme`). The framed paths are identical, but the
+    /// order and therefore the digest are still fixed.
+    #[default]
+    Tiebreak,
+    /// Abort hashing with an error naming both entries.
+    Error,
+}
+
+impl FromStr for CaseCollisionPolicy {
+    type Err = ParseOptionError;
+
+    /// Parses `tiebreak` or `error`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tiebreak" => Ok(CaseCollisionPolicy::Tiebreak),
+            "error" => Ok(CaseCollisionPolicy::Error),
+            _ => Err(ParseOptionError::new(s, "tiebreak or error")),
+        }
+    }
+}
+
+/// How the root directory is resolved ([`Options::canonicalize_root`]).
+///
+/// Relative paths below the root are the same either way; what differs is
+/// the root's own name ([`Options::include_root_name`]), where it lies
+/// relative to [`Options::path_base`], and whether a root that cannot be
+/// resolved is an error.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum CanonicalizeRoot {
+    /// Resolve `.`, `..` and symlinks; if that fails (say, on a file system
+    /// that cannot canonicalize), fall back to the absolute path as given.
+    #[default]
+    Always,
+    /// Only make the path absolute; a symlinked root keeps its own name.
+    Never,
+    /// Resolve like `Always`, but fail instead of falling back.
+    ErrorOnFailure,
+}
+
+impl FromStr for CanonicalizeRoot {
+    type Err = ParseOptionError;
+
+    /// Parses `always`, `never` or `error-on-failure`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(CanonicalizeRoot::Always),
+            "never" => Ok(CanonicalizeRoot::Never),
+            "error-on-failure" => Ok(CanonicalizeRoot::ErrorOnFailure),
+            _ => Err(ParseOptionError::new(
+                s,
+                "always, never or error-on-failure",
+            )),
+        }
+    }
+}
+
+/// What to do with a file that changes while it is read
+/// ([`Options::concurrent_modification`]).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum ModificationPolicy {
+    /// Do not check; a file written to mid-run is hashed as read.
+    #[default]
+    Ignore,
+    /// Keep the digest as read and report a [`WarningReason::Modified`].
+    Warn,
+    /// Read the file again, up to [`MODIFIED_RETRIES`] times, then fail as
+    /// with `Error`.
+    Retry,
+    /// Abort hashing with an `InvalidData` error naming the file.
+    Error,
+}
+
+/// How often [`ModificationPolicy::Retry`] reads a changing file again.
+pub const MODIFIED_RETRIES: u32 = 3;
+
+impl FromStr for ModificationPolicy {
+    type Err = ParseOptionError;
+
+    /// Parses `ignore`, `warn`, `retry` or `error`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(ModificationPolicy::Ignore),
+            "warn" => Ok(ModificationPolicy::Warn),
+            "retry" => Ok(ModificationPolicy::Retry),
+            "error" => Ok(ModificationPolicy::Error),
+            _ => Err(ParseOptionError::new(s, "ignore, warn, retry or error")),
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            format_version: FormatVersion::V1,
+            output_len: 32,
+            follow_symlinks: false,
+            confine_symlinks: false,
+            include_metadata: false,
+            timestamps: vec![Timestamp::Mtime],
+            mtime_granularity: Granularity::Nanos,
+            mode_normalization: ModeNormalization::Raw,
+            ownership: Ownership::Omit,
+            windows_attributes: false,
+            windows_acl: false,
+            include_file_type: false,
+            executable_extensions: Vec::new(),
+            clamp_mtime: None,
+            case_sensitive_paths: true,
+            case_collisions: CaseCollisionPolicy::Tiebreak,
+            unicode_normalization: None,
+            path_base: None,
+            canonicalize_root: CanonicalizeRoot::Always,
+            concurrent_modification: ModificationPolicy::Ignore,
+            keep_going: false,
+            fail_on_skip: false,
+            include_root_name: false,
+            modified_since: None,
+            ignore_patterns: Vec::new(),
+            extensions: Vec::new(),
+            pathspecs: Vec::new(),
+            ignore_files: Vec::new(),
+            load_dot_get_dir_hash_ignore: true,
+            exclude_vcs_dirs: false,
+            global_gitignore: false,
+            skip_hidden: false,
+            dedup_hard_links: false,
+            symlink_loops: SymlinkLoopPolicy::Skip,
+            broken_symlinks: Policy::Ignore,
+            special_files: Policy::Ignore,
+            non_utf8_names: Policy::Record,
+            walk_threads: 1,
+            mmap_threshold: None,
+            parallel_hash_threshold: None,
+            threads: 0,
+            io_concurrency: 1,
+            max_read_bytes_per_sec: None,
+            max_file_size: None,
+            max_files: None,
+            max_depth: None,
+            max_total_bytes: None,
+            oversize_policy: OversizePolicy::Hash,
+            structure_only: false,
+            normalize_newlines: false,
+            chunking: None,
+            checkpoint: None,
+            deadline: None,
+            progress: None,
+            on_warning: None,
+            cancel: None,
+        }
+    }
+}
+
+/// Counters collected during one hashing run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RunStats {
+    /// Files whose content went into the digest.
+    pub files_hashed: u64,
+    /// Entries excluded by ignore patterns. A pruned directory counts once,
+    /// regardless of how many files it contains.
+    pub files_ignored: u64,
+    /// Bytes actually read from disk (hard-link reuse does not count).
+    pub bytes_hashed: u64,
+    /// Wall-clock duration of the run.
+    pub wall_time: Duration,
+    /// Entries that could not be read and were skipped, and ignore rules
+    /// that were dropped, in the order they were met.
+    pub warnings: Vec<Warning>,
+}
+
+/// Result of [`get_dir_hash_with_stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DirHash {
+    /// Lowercase hex digest, identical to [`get_dir_hash`]'s return value.
+    pub hex: String,
+    /// How the run went.
+    pub stats: RunStats,
+}
+
+/// Length of [`cache_key`]'s identifiers: 26 characters, 130 bits.
+pub const CACHE_KEY_LEN: usize = 26;
+
+impl DirHash {
+    /// A short identifier for directory names and CI cache keys: the first
+    /// `len` characters of the digest in lowercase unpadded base32 (the
+    /// RFC 4648 alphabet, `a`-`z` and `2`-`7`), at most 52 for a 32-byte
+    /// digest.
+    ///
+    /// Each character carries 5 bits of the digest, so identifiers of `len`
+    /// characters behave like random `5 * len`-bit values: among `n` trees,
+    /// two share one with a probability of about `n² / 2^(5 * len + 1)`,
+    /// below one in 10^12 for a million trees and 16 characters. A shorter
+    /// identifier is a prefix of a longer one, and none contains characters
+    /// that file systems reject or that could clash when case is folded.
+    pub fn derive_id(&self, len: usize) -> String {
+        const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+        let bytes = (self.hex.as_bytes().chunks(2))
+            .filter_map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok());
+        let mut id = String::with_capacity(len);
+        let (mut bits, mut pending) = (0u32, 0u32);
+        for byte in bytes {
+            pending = (pending << 8) | u32::from(byte);
+            bits += 8;
+            while bits >= 5 && id.len() < len {
+                bits -= 5;
+                id.push(char::from(ALPHABET[(pending >> bits) as usize & 31]));
+            }
+            if id.len() == len {
+                return id;
+            }
+            pending &= (1 << bits) - 1;
+        }
+        // The last character is padded with zero bits, as in RFC 4648.
+        if bits > 0 && id.len() < len {
+            id.push(char::from(ALPHABET[(pending << (5 - bits)) as usize & 31]));
+        }
+        id
+    }
+}
+
+/// Result of [`get_dir_manifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Manifest {
+    /// Root digest, identical to [`get_dir_hash`]'s return value.
+    pub hex: String,
+    /// Every hashed file, in canonical order.
+    pub files: Vec<FileDigest>,
+    /// How the run went.
+    pub stats: RunStats,
+}
+
+/// Content digest of one file in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileDigest {
+    /// Relative path as framed (see [`enumerate_files`]).
+    pub path: String,
+    /// Lowercase hex digest of the file's contents.
+    pub hex: String,
+    /// Content-defined chunks, in file order; empty unless
+    /// [`Options::chunking`] is set.
+    pub chunks: Vec<Chunk>,
+}
+
+/// Compute dir hash for `root` using `opts`, returning a lowercase hex digest.
+pub fn get_dir_hash(root: &Path, opts: &Options) -> io::Result<String> {
+    get_dir_hash_with_stats(root, opts).map(|h| h.hex)
+}
+
+/// Like [`get_dir_hash`], but also report [`RunStats`] for the run.
+///
+/// If [`Options::cancel`] fires, the error wraps a [`Cancelled`] carrying the
+/// statistics up to that point; past [`Options::deadline`], a [`TimedOut`]
+/// also carrying the files hashed so far.
+pub fn get_dir_hash_with_stats(root: &Path, opts: &Options) -> io::Result<DirHash> {
+    run(root, None, opts, Outputs::default())
+}
+
+/// Hash `root` and return a [`CACHE_KEY_LEN`]-character identifier of the
+/// digest ([`DirHash::derive_id`]), for naming cache entries after the
+/// tree they were built from.
+pub fn cache_key(root: &Path, opts: &Options) -> io::Result<String> {
+    get_dir_hash_with_stats(root, opts).map(|h| h.derive_id(CACHE_KEY_LEN))
+}
+
+/// Like [`get_dir_hash_with_stats`], but also return the content digest of
+/// every hashed file, in canonical order.
+///
+/// The list is kept in memory, so this costs one entry per file; plain
+/// hashing does not.
+pub fn get_dir_manifest(root: &Path, opts: &Options) -> io::Result<Manifest> {
+    let mut files = Vec::new();
+    let mut collect = |file| {
+        files.push(file);
+        Ok(())
+    };
+    let outputs = Outputs {
+        files: Some(&mut collect),
+        ..Outputs::default()
+    };
+    let DirHash { hex, stats } = run(root, None, opts, outputs)?;
+    Ok(Manifest { hex, files, stats })
+}
+
+/// A file yielded by [`hash_entries`]; the same record as a [`Manifest`]
+/// entry.
+pub type FileRecord = FileDigest;
+
+/// Files hashed ahead of the consumer of [`hash_entries`].
+const RECORDS_AHEAD: usize = 64;
+
+/// Hash `root` file by file, yielding each file's path and content digest
+/// (as in [`get_dir_manifest`]) as soon as it is computed, in canonical
+/// order.
+///
+/// Nothing accumulates: hashing runs on a background thread at most a few
+/// dozen files ahead of the consumer, so results can be streamed into a
+/// database while the walk goes on. A failure ends the iteration with an
+/// `Err` item; dropping the iterator stops the run. Where no thread can be
+/// started (e.g. on WASI), the whole run happens on the first call instead.
+pub fn hash_entries(
+    root: &Path,
+    opts: &Options,
+) -> impl Iterator<Item = io::Result<FileRecord>> + use<> {
+    let (tx, rx) = mpsc::sync_channel(RECORDS_AHEAD);
+    let (root, opts) = (root.to_path_buf(), opts.clone());
+    let worker = {
+        let (root, opts) = (root.clone(), opts.clone());
+        thread::Builder::new().spawn(move || {
+            let mut send = |file| {
+                tx.send(Ok(file))
+                    .map_err(|_| io::Error::other("file records are no longer read"))
+            };
+            let outputs = Outputs {
+                files: Some(&mut send),
+                ..Outputs::default()
+            };
+            if let Err(e) = run(&root, None, &opts, outputs) {
+                // Nobody to tell if the consumer is gone.
+                let _ = tx.send(Err(e));
+            }
+        })
+    };
+    let fallback = worker.is_err().then_some((root, opts));
+    rx.into_iter()
+        .chain(
+            fallback
+                .into_iter()
+                .flat_map(|(root, opts)| match get_dir_manifest(&root, &opts) {
+                    Ok(manifest) => manifest.files.into_iter().map(Ok).collect(),
+                    Err(e) => vec![Err(e)],
+                }),
+        )
+}
+
+/// Hash `root` while writing the exact byte stream fed into the outer hasher
+/// to `writer`, and return the digest.
+///
+/// The dump starts with the domain tag of `opts.format_version` (`get_dir_hash-v1\0`
+/// by default) and continues with one record (file or recorded symlink,
+/// including its metadata) per line.
+/// Line breaks are added for readability only and are not hashed; everything
+/// else is, byte for byte. Meant for auditing the framing or reimplementing it
+/// elsewhere.
+pub fn dump_frames(
+    root: &Path,
+    opts: &Options,
+    writer: &mut dyn io::Write,
+    format: DumpFormat,
+) -> io::Result<String> {
+    let outputs = Outputs {
+        dump: Some(Dump { writer, format }),
+        ..Outputs::default()
+    };
+    run(root, None, opts, outputs).map(|h| h.hex)
+}
+
+/// Hash `root` into a Merkle tree where every directory has its own digest
+/// derived from its children (see [`TreeNode`]).
+///
+/// The root digest differs from [`get_dir_hash`]'s, but covers the same
+/// entries with the same options. Comparing two trees with [`TreeNode::diff`]
+/// pinpoints the diverging subtrees without exchanging full manifests. The
+/// whole tree is kept in memory.
+pub fn get_dir_hash_tree(root: &Path, opts: &Options) -> io::Result<DirTree> {
+    let mut builder = merkle::Builder::default();
+    let outputs = Outputs {
+        tree: Some(&mut builder),
+        ..Outputs::default()
+    };
+    let DirHash { stats, .. } = run(root, None, opts, outputs)?;
+    Ok(DirTree {
+        root: builder.finish(),
+        stats,
+    })
+}
+
+/// Hash exactly `files` instead of walking `root`.
+///
+/// Paths are relative to `root` (or absolute below it) and are sorted and
+/// framed exactly like a walk would frame them, so listing every file a walk
+/// would find gives the same digest as [`get_dir_hash`]. Ignore patterns do
+/// not apply; every listed file must exist, and directories are rejected.
+/// Duplicates count once.
+pub fn get_files_hash(root: &Path, files: &[PathBuf], opts: &Options) -> io::Result<String> {
+    run(root, Some(files), opts, Outputs::default()).map(|h| h.hex)
+}
+
+/// Leading bytes of the stream hashed by [`get_dirs_hash`].
+const ROOTS_MAGIC: &[u8] = b"get_dir_hash-roots-v1\0";
+
+/// Hash several roots (say `src/`, `assets/` and `config/`) into one digest.
+///
+/// Each root is hashed with `opts` as by [`get_dir_hash`]. The result is
+/// BLAKE3 over `get_dir_hash-roots-v1\0`, the number of roots and, for every
+/// root in byte order of its final path component, that name and its digest
+/// (u64 LE counts and lengths). The order of `roots` does not matter, but
+/// their names must differ. [`Options::output_len`] applies to the combined
+/// digest only; [`Options::checkpoint`] is not supported here.
+pub fn get_dirs_hash(roots: &[&Path], opts: &Options) -> io::Result<String> {
+    if opts.checkpoint.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "checkpoints are not supported when hashing several roots",
+        ));
+    }
+    check_output_len(opts)?;
+    let per_root = Options {
+        output_len: 32,
+        ..opts.clone()
+    };
+    let mut named: Vec<(Vec<u8>, &Path)> = roots
+        .iter()
+        .map(|root| {
+            let name = walk_root(root, opts)?
+                .file_name()
+                .map_or_else(Vec::new, |n| n.as_encoded_bytes().to_vec());
+            Ok((name, *root))
+        })
+        .collect::<io::Result<_>>()?;
+    named.sort_by(|a, b| a.0.cmp(&b.0));
+    if let Some(pair) = named.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "roots {} and {} have the same name",
+                pair[0].1.display(),
+                pair[1].1.display()
+            ),
+        ));
+    }
 
-use blake3::Hasher as Blake3;
-use globset::{Glob, GlobSet, GlobSetBuilder};
-use std::cmp::Ordering;
-use std::fs::{self, File, Metadata};
-use std::io::{self, Read};
-use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+    let mut out = ROOTS_MAGIC.to_vec();
+    codec::put_u64(&mut out, named.len() as u64);
+    for (name, root) in &named {
+        let hex = get_dir_hash(root, &per_root)?;
+        let digest = blake3::Hash::from_hex(&hex).map_err(io::Error::other)?;
+        codec::put_field(&mut out, name);
+        out.extend_from_slice(digest.as_bytes());
+    }
+    let mut digest = vec![0; opts.output_len];
+    blake3::Hasher::new()
+        .update(&out)
+        .finalize_xof()
+        .fill(&mut digest);
+    Ok(hex_lower(&digest))
+}
 
-/// Options controlling hashing behavior.
-#[derive(Debug, Clone)]
-pub struct Options {
-    /// Follow symlinks when walking the tree.
-    pub follow_symlinks: bool,
-    /// Include basic metadata (mode on Unix, and (secs,nanos) mtime on all).
-    pub include_metadata: bool,
-    /// Treat path comparison as case-sensitive. If `false`, we lowercase paths
-    /// before sorting and framing (helps Windows).
-    pub case_sensitive_paths: bool,
-    /// Extra ignore patterns (applied relative to the root).
-    pub ignore_patterns: Vec<String>,
-    /// Paths to files with ignore patterns (line-based, `#` comments).
-    pub ignore_files: Vec<PathBuf>,
-    /// Whether to auto-load `.get_dir_hash_ignore` from root.
-    pub load_dot_get_dir_hash_ignore: bool,
+/// Hash `root` with several digest algorithms in one pass.
+///
+/// Every file is read once and its bytes go to one hasher per algorithm.
+/// Returns one lowercase hex digest per entry of `algorithms`, in the same
+/// order. The BLAKE3 digest equals [`get_dir_hash`]'s; any other algorithm
+/// hashes the same records (see [`FormatVersion`]) with its own content
+/// digests in place of BLAKE3's. Algorithms other than BLAKE3 need their
+/// Cargo feature (`sha2` for SHA-256).
+pub fn get_dir_digests(
+    root: &Path,
+    algorithms: &[Algorithm],
+    opts: &Options,
+) -> io::Result<Vec<String>> {
+    let mut others: Vec<(Algorithm, String)> = Vec::new();
+    for &algorithm in algorithms {
+        if algorithm != Algorithm::Blake3 && !others.iter().any(|(a, _)| *a == algorithm) {
+            others.push((algorithm, String::new()));
+        }
+    }
+    let outputs = Outputs {
+        digests: Some(&mut others),
+        ..Outputs::default()
+    };
+    let DirHash { hex, .. } = run(root, None, opts, outputs)?;
+    Ok(algorithms
+        .iter()
+        .map(
+            |algorithm| match others.iter().find(|(a, _)| a == algorithm) {
+                Some((_, other)) => other.clone(),
+                None => hex.clone(),
+            },
+        )
+        .collect())
 }
 
-impl Default for Options {
-    fn default() -> Self {
-        Self {
-            follow_symlinks: false,
-            include_metadata: false,
-            case_sensitive_paths: true,
-            ignore_patterns: Vec::new(),
-            ignore_files: Vec::new(),
-            load_dot_get_dir_hash_ignore: true,
+/// Flat digests of `root` and of its subdirectories down to `max_depth`
+/// levels (1 for the immediate ones, `usize::MAX` for all), from a single
+/// walk; keyed by path relative to `root` as framed (see
+/// [`enumerate_files`]), the root under `""`.
+///
+/// A subdirectory's digest is the one [`get_dir_hash`] returns for it when
+/// it holds the files this walk hashed there: ignore rules are the root's,
+/// so the two differ only where a pattern depends on where the walk starts
+/// (an anchored pattern, or an ignore file above the subdirectory). Only
+/// directories with a hashed entry are listed. [`Options::path_base`] is
+/// not supported (`InvalidInput`).
+pub fn get_dir_hash_per_dir(
+    root: &Path,
+    max_depth: usize,
+    opts: &Options,
+) -> io::Result<BTreeMap<String, String>> {
+    if opts.path_base.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "path_base is not supported with per-directory digests",
+        ));
+    }
+    let mut rollup = rollup::Rollup::new(max_depth, opts)?;
+    let outputs = Outputs {
+        rollup: Some(&mut rollup),
+        ..Outputs::default()
+    };
+    let DirHash { hex, .. } = run(root, None, opts, outputs)?;
+    let mut dirs = rollup.finish()?;
+    dirs.insert(String::new(), hex);
+    Ok(dirs)
+}
+
+/// Optional by-products of a hashing run.
+#[derive(Default)]
+struct Outputs<'a> {
+    /// Receives per-file digests ([`get_dir_manifest`], [`hash_entries`]);
+    /// an error stops the run.
+    files: Option<&'a mut dyn FnMut(FileDigest) -> io::Result<()>>,
+    /// Copy of the framed byte stream ([`dump_frames`]).
+    dump: Option<Dump<'a>>,
+    /// Merkle tree leaves ([`get_dir_hash_tree`]).
+    tree: Option<&'a mut merkle::Builder>,
+    /// Per-file state ([`Snapshot::capture`]).
+    snapshot: Option<&'a mut Vec<SnapshotEntry>>,
+    /// Root digests of further algorithms, filled in at the end
+    /// ([`get_dir_digests`]).
+    digests: Option<&'a mut [(Algorithm, String)]>,
+    /// On-disk paths of everything framed (`emit_cargo_rerun_and_hash`).
+    visited: Option<&'a mut Vec<PathBuf>>,
+    /// Files of an earlier run by framed path; their content digests are
+    /// reused while size and mtime match (`watch_dir_hash`).
+    reuse: Option<&'a HashMap<String, SnapshotEntry>>,
+    /// Digests of subdirectories ([`get_dir_hash_per_dir`]).
+    rollup: Option<&'a mut rollup::Rollup>,
+    /// Ignore rules and hash pool built once for many runs ([`Engine`]); the
+    /// rules leave out the root's `.get_dir_hash_ignore`.
+    prepared: Option<(&'a IgnoreRules, &'a HashPool)>,
+}
+
+/// Time a hashing run and attach its statistics to the outcome.
+///
+/// `list` replaces the walk with an explicit set of files ([`get_files_hash`]).
+fn run(
+    root: &Path,
+    list: Option<&[PathBuf]>,
+    opts: &Options,
+    outputs: Outputs<'_>,
+) -> io::Result<DirHash> {
+    let started = Instant::now();
+    let opts = &*with_deadline(opts, started);
+    let mut stats = RunStats::default();
+    // Files hashed so far, for a `TimedOut` error.
+    let mut partial = opts.deadline.map(|_| Vec::new());
+    // Removed once the run completes, saved if it stops early.
+    let (mut checkpoint, checkpoint_warning) = match &opts.checkpoint {
+        Some(config) => checkpoint::State::open(config)?,
+        None => (None, None),
+    };
+    if let Some(warning) = &checkpoint_warning {
+        report(warning, opts);
+    }
+    let res = hash_tree(
+        root,
+        list,
+        opts,
+        &mut stats,
+        outputs,
+        partial.as_mut(),
+        checkpoint.as_mut(),
+    );
+    stats.warnings.splice(0..0, checkpoint_warning);
+    let res = match (res, checkpoint) {
+        (Ok(hex), Some(cp)) => cp.finish().map(|()| hex),
+        (Err(e), Some(cp)) => {
+            let path = cp.path().to_path_buf();
+            if let Err(save) = cp.stop() {
+                let warning = Warning {
+                    path,
+                    reason: WarningReason::CheckpointSave(save.to_string()),
+                };
+                report(&warning, opts);
+                stats.warnings.push(warning);
+            }
+            Err(e)
         }
+        (res, None) => res,
+    };
+    stats.wall_time = started.elapsed();
+    match res {
+        Ok(hex) => Ok(DirHash { hex, stats }),
+        Err(e) if Cancelled::find(&e).is_some() => Err(Cancelled { stats }.into()),
+        Err(e) if TimedOut::find(&e).is_some() => Err(TimedOut {
+            files: partial.unwrap_or_default(),
+            stats,
+        }
+        .into()),
+        Err(e) => Err(e),
     }
 }
 
-/// Compute dir hash for `root` using `opts`, returning a lowercase hex digest.
-pub fn get_dir_hash(root: &Path, opts: &Options) -> io::Result<String> {
-    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
-    let globset = build_globset(&root, opts)?;
+/// Relative paths that [`get_dir_hash`] would hash, in hashing order,
+/// without reading any file contents.
+///
+/// Paths appear as they are framed: `/`-separated, normalized and lowercased
+/// according to `opts` (names that are not valid UTF-8 are shown lossily).
+/// Recorded dangling symlinks are included. Useful for debugging ignore
+/// patterns.
+pub fn enumerate_files(root: &Path, opts: &Options) -> io::Result<Vec<String>> {
+    let root = walk_root(root, opts)?;
+    let rules = IgnoreRules::build(&root, opts)?;
+    let prefix = base_prefix(&root, opts)?;
+    Entries::new(&root, opts, &rules)
+        .relative_to(prefix)
+        .map(|entry| {
+            let entry = entry?;
+            Ok(String::from_utf8_lossy(&framed_path(entry.rel, entry.raw, opts)).into_owned())
+        })
+        .collect()
+}
+
+/// Hash `root`, updating `stats` as files are processed, filling the
+/// requested `outputs` and recording completed files in `checkpoint`.
+fn hash_tree(
+    root: &Path,
+    list: Option<&[PathBuf]>,
+    opts: &Options,
+    stats: &mut RunStats,
+    outputs: Outputs<'_>,
+    mut partial: Option<&mut Vec<FileDigest>>,
+    mut checkpoint: Option<&mut checkpoint::State>,
+) -> io::Result<String> {
+    let Outputs {
+        mut files,
+        dump,
+        mut tree,
+        mut snapshot,
+        digests,
+        mut visited,
+        reuse,
+        mut rollup,
+        prepared,
+    } = outputs;
+    let root = walk_root(root, opts)?;
+    let rules = match (list, prepared) {
+        // An explicit list is hashed as given.
+        (Some(_), _) => Cow::Owned(IgnoreRules::none()),
+        (None, Some((rules, _))) => rules.for_root(&root, opts)?,
+        (None, None) => Cow::Owned(IgnoreRules::build(&root, opts)?),
+    };
+    if let Some(sizes) = opts.chunking {
+        if opts.normalize_newlines {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "normalize_newlines cannot be combined with chunking",
+            ));
+        }
+        chunk::check(sizes)?;
+    }
+    check_output_len(opts)?;
+    let mut throttle = Throttle::new(opts.max_read_bytes_per_sec)?;
+    let hash_pool = match prepared {
+        Some((_, pool)) => pool.clone(),
+        None => HashPool::new(opts.threads)?,
+    };
+    let prefix = base_prefix(&root, opts)?;
+    let walker = || match list {
+        Some(files) => Walker::listed(&root, opts, &rules, files),
+        None => Ok(Walker::new(&root, opts, &rules)),
+    };
+
+    // First pass: totals for the progress callback.
+    let mut progress = opts.progress.as_ref().map(|cb| {
+        let mut p = Progress::default();
+        // Stops early on cancellation (or a bad list); the main pass reports
+        // that error.
+        for entry in (walker().ok().into_iter())
+            .flat_map(|w| {
+                Entries::with_walker(w, &root, opts)
+                    .relative_to(prefix.clone())
+                    .quiet()
+            })
+            .map_while(Result::ok)
+        {
+            if let EntryKind::File = entry.kind {
+                p.files_total += 1;
+                p.bytes_total += fs::metadata(&entry.path).map_or(0, |md| md.len());
+            }
+        }
+        (cb, p)
+    });
 
-    // Collect files (not directories) first.
-    let mut files: Vec<(String, PathBuf)> = Vec::new();
+    // Outer stream hasher, plus one per further algorithm.
+    let mut out = Frames::new(opts.format_version, dump);
+    let algorithms: Vec<Algorithm> = digests
+        .iter()
+        .flat_map(|d| d.iter().map(|(a, _)| *a))
+        .collect();
+    let mut others = (algorithms.iter())
+        .map(|&a| Frames::for_algorithm(opts.format_version, a))
+        .collect::<io::Result<Vec<_>>>()?;
+    if opts.include_root_name {
+        let name = root_name(&root, opts);
+        for frames in std::iter::once(&mut out).chain(&mut others) {
+            frames.root_name(&name);
+        }
+    }
+    if let Some(cutoff) = opts.modified_since {
+        let cutoff = since_epoch(cutoff)?;
+        for frames in std::iter::once(&mut out).chain(&mut others) {
+            frames.modified_since(cutoff);
+        }
+    }
 
-    let walker = WalkDir::new(&root)
-        .follow_links(opts.follow_symlinks)
-        .into_iter();
+    // Owner names resolved so far (`Ownership::Names`).
+    let mut names = NameCache::default();
 
-    for entry in walker {
-        let entry = match entry {
-            Ok(e) => e,
+    // Content digests of multiply-linked files, keyed by (dev, inode).
+    let mut links: HashMap<(u64, u64), Content> = HashMap::new();
+
+    // Entries arrive already in canonical order, so nothing is buffered.
+    let mut entries = Entries::with_walker(walker()?, &root, opts).relative_to(prefix);
+    // A file read ahead could change between its read and our check.
+    let read_ahead = others.is_empty()
+        && !throttle.is_active()
+        && checkpoint.is_none()
+        && reuse.is_none()
+        && opts.concurrent_modification == ModificationPolicy::Ignore;
+    // Files reported under `ModificationPolicy::Warn`.
+    let mut modified = Vec::new();
+    // Files that failed, with `Options::keep_going`.
+    let mut failed = FileErrors::default();
+    for (entry, pending) in Readahead::new(entries.by_ref(), opts, &hash_pool, read_ahead) {
+        let Entry {
+            rel,
+            raw,
+            path,
+            kind,
+        } = match entry {
+            Ok(entry) => entry,
             Err(e) => {
-                // Skip unreadable entries, but keep going.
-                eprintln!("get_dir_hash: warn: skipping entry: {e}");
+                failed.set_aside(e, opts)?;
                 continue;
             }
         };
-        let path = entry.path();
+        check_cancel(opts)?;
+        let rel = framed_path(rel, raw, opts);
+        if let Some(visited) = visited.as_deref_mut() {
+            visited.push(path.clone());
+        }
 
-        if !entry.file_type().is_file() {
+        if let EntryKind::BrokenSymlink(target) = kind {
+            out.link(&rel, target.as_bytes());
+            for other in &mut others {
+                other.link(&rel, target.as_bytes());
+            }
+            if let Some(tree) = tree.as_deref_mut() {
+                tree.link(&rel, target.as_bytes());
+            }
+            if let Some(rollup) = rollup.as_deref_mut() {
+                rollup.link(&rel, target.as_bytes())?;
+            }
             continue;
         }
-        // Normalize & relativize path.
-        let rel = match make_rel_unix(&root, path) {
-            Some(s) => s,
-            None => continue, // shouldn't happen
+        if let EntryKind::Special(file_type) = kind {
+            out.special(&rel, file_type);
+            for other in &mut others {
+                other.special(&rel, file_type);
+            }
+            if let Some(tree) = tree.as_deref_mut() {
+                tree.special(&rel, file_type);
+            }
+            if let Some(rollup) = rollup.as_deref_mut() {
+                rollup.special(&rel, file_type)?;
+            }
+            continue;
+        }
+        if let EntryKind::Oversize(size) = kind {
+            out.oversize(&rel, size);
+            for other in &mut others {
+                other.oversize(&rel, size);
+            }
+            if let Some(tree) = tree.as_deref_mut() {
+                tree.oversize(&rel, size);
+            }
+            if let Some(rollup) = rollup.as_deref_mut() {
+                rollup.oversize(&rel, size)?;
+            }
+            continue;
+        }
+
+        // Files that cannot be read are set aside with `Options::keep_going`.
+        let read = (|| -> io::Result<(Option<SnapshotEntry>, Content)> {
+            let link = if opts.dedup_hard_links {
+                hard_link_key(&fs::metadata(&path).map_err(FileOp::Metadata.on(&path))?)
+            } else {
+                None
+            };
+            // Size and mtime, for snapshots, checkpoints and reuse.
+            let state = match (&snapshot, &checkpoint, reuse) {
+                (None, None, None) => None,
+                _ => Some(snapshot::entry(&path, &rel)?),
+            };
+            // Checkpoints and earlier runs only hold BLAKE3 digests.
+            let resumed = match (&checkpoint, reuse, &state) {
+                _ if opts.chunking.is_some() || !others.is_empty() => None,
+                (Some(cp), _, Some(st)) => cp.reuse(st),
+                (None, Some(previous), Some(st)) => snapshot::reuse(previous, st),
+                _ => None,
+            };
+            let known = match resumed {
+                Some(d) => Some((d, Chunks::new(), Vec::new())),
+                None => link.and_then(|k| links.get(&k).cloned()),
+            };
+            let content = match known {
+                Some(known) => known,
+                None => {
+                    let mut pending = pending;
+                    let mut retries = 0;
+                    let (d, chunks, hashers) = loop {
+                        let before = match opts.concurrent_modification {
+                            ModificationPolicy::Ignore => None,
+                            _ => Some(file_stamp(&path)?),
+                        };
+                        let mut hashers = (algorithms.iter())
+                            .map(|&a| algo::Hasher::new(a))
+                            .collect::<io::Result<Vec<_>>>()?;
+                        let (d, n, chunks) = match (pending.take(), opts.chunking) {
+                            (Some(pending), _) => pending.wait()?,
+                            (None, _) if hashers.is_empty() => {
+                                pool::content(&path, opts, &hash_pool, &mut throttle)?
+                            }
+                            (None, Some(sizes)) => {
+                                chunk::hash_file(&path, opts, sizes, &mut hashers, &mut throttle)?
+                            }
+                            (None, None) => {
+                                let (d, n) = hash_file(&path, opts, &mut hashers, &mut throttle)?;
+                                (d, n, Chunks::new())
+                            }
+                        };
+                        stats.bytes_hashed += n;
+                        if before.is_none() || before == Some(file_stamp(&path)?) {
+                            break (d, chunks, hashers);
+                        }
+                        match opts.concurrent_modification {
+                            ModificationPolicy::Retry if retries < MODIFIED_RETRIES => retries += 1,
+                            ModificationPolicy::Warn => {
+                                let warning = Warning {
+                                    path: path.clone(),
+                                    reason: WarningReason::Modified,
+                                };
+                                report(&warning, opts);
+                                modified.push(warning);
+                                break (d, chunks, hashers);
+                            }
+                            _ => {
+                                return Err(io::Error::new(
+                                    io::ErrorKind::InvalidData,
+                                    format!("file changed while it was read: {}", path.display()),
+                                ));
+                            }
+                        }
+                    };
+                    let other_digests: Vec<_> = hashers.into_iter().map(|h| h.finalize()).collect();
+                    if let Some(k) = link {
+                        links.insert(k, (d, chunks.clone(), other_digests.clone()));
+                    }
+                    (d, chunks, other_digests)
+                }
+            };
+            Ok((state, content))
+        })();
+        let (state, (content_digest, chunks, other_digests)) = match read {
+            Ok(read) => read,
+            Err(e) => {
+                failed.set_aside(e, opts)?;
+                continue;
+            }
         };
+        stats.files_hashed += 1;
+        if let Some((cb, p)) = &mut progress {
+            p.files_done = stats.files_hashed;
+            p.bytes_done = stats.bytes_hashed;
+            (cb.0)(p);
+        }
 
-        // Apply ignore patterns relative to root.
-        if globset.is_match(&rel) {
-            continue;
+        if files.is_some() || partial.is_some() {
+            let file = FileDigest {
+                path: String::from_utf8_lossy(&rel).into_owned(),
+                hex: content_digest.to_hex().to_string(),
+                chunks: chunk::describe(&chunks),
+            };
+            if let Some(partial) = partial.as_deref_mut() {
+                partial.push(file.clone());
+            }
+            if let Some(files) = files.as_deref_mut() {
+                files(file)?;
+            }
+        }
+        if let Some(mut state) = state {
+            state.hex = content_digest.to_hex().to_string();
+            if let Some(snapshot) = snapshot.as_deref_mut() {
+                snapshot.push(state.clone());
+            }
+            if let Some(cp) = &mut checkpoint {
+                cp.record(state)?;
+            }
         }
 
-        files.push((rel, path.to_path_buf()));
+        let mut metadata = Vec::new();
+        if (opts.include_metadata || opts.include_file_type)
+            && let Ok(md) = fs::metadata(&path)
+        {
+            if opts.include_metadata {
+                feed_metadata(&mut metadata, &path, &md, opts, &mut names)?;
+            }
+            if opts.include_file_type {
+                feed_file_type(&mut metadata, &path, &md, opts);
+            }
+        }
+        if opts.chunking.is_some() {
+            chunk::feed_chunks(&mut metadata, &chunks);
+        }
+        out.file(&rel, content_digest.as_bytes(), &metadata);
+        for (other, digest) in others.iter_mut().zip(&other_digests) {
+            other.file(&rel, digest, &metadata);
+        }
+        if let Some(tree) = tree.as_deref_mut() {
+            tree.file(&rel, &content_digest, &metadata);
+        }
+        if let Some(rollup) = rollup.as_deref_mut() {
+            rollup.file(&rel, content_digest.as_bytes(), &metadata)?;
+        }
     }
 
-    // Stable order (by normalized relative path).
-    files.sort_by(|a, b| {
-        if opts.case_sensitive_paths {
-            a.0.cmp(&b.0)
-        } else {
-            cmp_case_insensitive(&a.0, &b.0)
+    failed.finish()?;
+
+    stats.files_ignored = entries.ignored();
+    stats.warnings = (std::mem::take(&mut entries.warnings).into_iter())
+        .chain(modified)
+        .collect();
+    debug_event!(
+        root = %root.display(),
+        files = stats.files_hashed,
+        bytes = stats.bytes_hashed,
+        ignored = stats.files_ignored,
+        "hashed tree"
+    );
+    let digest = out.finalize(opts.output_len)?;
+    if let Some(digests) = digests {
+        for ((_, hex), other) in digests.iter_mut().zip(others) {
+            *hex = hex_lower(&other.finalize(opts.output_len)?);
         }
-    });
+    }
+    Ok(hex_lower(&digest))
+}
+
+/// What hashing one file yields: its content digest, its chunks (with
+/// [`Options::chunking`]) and its digests under further algorithms
+/// ([`get_dir_digests`]).
+type Content = (blake3::Hash, Chunks, Vec<Vec<u8>>);
+
+/// Hashable entries below `root`, in canonical (sorted relative path) order.
+struct Entries<'a> {
+    walker: Walker<'a>,
+    root: &'a Path,
+    opts: &'a Options,
+    /// Files excluded by ignore patterns (pruned directories are counted by
+    /// the walker).
+    ignored: u64,
+    /// Unreadable entries skipped and ignore rules dropped so far.
+    warnings: Vec<Warning>,
+    /// Entries yielded so far ([`Options::max_files`]).
+    yielded: u64,
+    /// Size of the files yielded so far ([`Options::max_total_bytes`]).
+    total_bytes: u64,
+    /// Do not report warnings (used for the progress pre-pass).
+    quiet: bool,
+    /// Prepended to every relative path ([`Options::path_base`]).
+    prefix: Option<PathPrefix>,
+}
+
+impl<'a> Entries<'a> {
+    fn new(root: &'a Path, opts: &'a Options, rules: &'a IgnoreRules) -> Self {
+        Self::with_walker(Walker::new(root, opts, rules), root, opts)
+    }
+
+    fn with_walker(walker: Walker<'a>, root: &'a Path, opts: &'a Options) -> Self {
+        Self {
+            walker,
+            root,
+            opts,
+            ignored: 0,
+            warnings: Vec::new(),
+            yielded: 0,
+            total_bytes: 0,
+            quiet: false,
+            prefix: None,
+        }
+    }
+
+    fn quiet(mut self) -> Self {
+        self.quiet = true;
+        self
+    }
+
+    fn relative_to(mut self, prefix: Option<PathPrefix>) -> Self {
+        self.prefix = prefix;
+        self
+    }
+
+    /// Entries excluded by ignore patterns so far.
+    fn ignored(&self) -> u64 {
+        self.ignored + self.walker.pruned()
+    }
+
+    /// Turn a walk result into an entry, or `None` if it does not take part.
+    fn classify(&mut self, node: Result<Node, WalkError>) -> io::Result<Option<Entry>> {
+        let (path, kind) = match node {
+            Ok(Node::File(path)) => (path, EntryKind::File),
+            Ok(Node::Symlink(path)) => {
+                // Links that leave the root under `confine_symlinks` arrive
+                // here unfollowed; they are handled like dangling ones.
+                let target = if self.opts.follow_symlinks && self.opts.confine_symlinks {
+                    symlink_target(&path)
+                } else {
+                    broken_symlink_target(&path)
+                };
+                match target {
+                    Some(target) => (path, EntryKind::BrokenSymlink(target)),
+                    None => return Ok(None),
+                }
+            }
+            Ok(Node::Other(_)) if self.opts.special_files == Policy::Ignore => return Ok(None),
+            Ok(Node::Other(path)) => {
+                let file_type = match fs::metadata(&path) {
+                    Ok(md) => special_file_type(&md),
+                    Err(_) => return Ok(None), // gone since it was listed
+                };
+                (path, EntryKind::Special(file_type))
+            }
+            Err(WalkError::Fatal(e)) => return Err(e),
+            Err(WalkError::Entry(warning)) => {
+                // Skip unreadable entries, but keep going.
+                if !self.quiet {
+                    report_skip(&warning, self.opts)?;
+                    self.warnings.push(warning);
+                }
+                return Ok(None);
+            }
+        };
 
-    // Outer stream hasher.
-    let mut out = Blake3::new();
-    out.update(b"get_dir_hash-v1\0");
+        // Normalize & relativize path.
+        let rel = match make_rel_unix(self.root, &path) {
+            Some(s) => s,
+            None => return Ok(None), // shouldn't happen
+        };
 
-    for (rel, path) in files {
-        let mut inner = Blake3::new();
-        stream_file(&path, &mut inner)?;
-        let content_digest = inner.finalize();
+        let rel = match self.opts.unicode_normalization {
+            Some(form) => form.apply(&rel),
+            None => rel,
+        };
 
-        out.update(b"F\0");
-        if opts.case_sensitive_paths {
-            out.update(rel.as_bytes());
-        } else {
-            out.update(rel.to_lowercase().as_bytes());
+        // Apply ignore patterns (root rules and nested ignore files).
+        if self.walker.is_ignored(&rel) {
+            self.ignored += 1;
+            return Ok(None);
         }
-        out.update(b"\0");
-        out.update(content_digest.as_bytes());
 
-        if opts.include_metadata
-            && let Ok(md) = fs::metadata(&path)
+        if let Some(cutoff) = self.opts.modified_since
+            && !modified_after(&path, &kind, cutoff)?
         {
-            feed_metadata(&mut out, &md);
+            self.ignored += 1;
+            return Ok(None);
         }
-    }
 
-    let digest = out.finalize();
-    Ok(hex_lower(digest.as_bytes()))
+        // `rel` is lossy; keep the exact bytes of names that are not UTF-8.
+        let raw = match path.strip_prefix(self.root) {
+            Ok(r) if r.to_str().is_none() => match self.opts.non_utf8_names {
+                Policy::Ignore => return Ok(None),
+                Policy::Error => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("file name is not valid UTF-8: {rel}"),
+                    ));
+                }
+                Policy::Record => Some(path_to_unix_bytes(r)),
+            },
+            _ => None,
+        };
+
+        if let EntryKind::BrokenSymlink(target) = &kind {
+            match self.opts.broken_symlinks {
+                Policy::Ignore => return Ok(None),
+                Policy::Error if fs::metadata(&path).is_ok() => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!("symlink leaves the root: {rel} -> {target}"),
+                    ));
+                }
+                Policy::Error => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("broken symlink: {rel} -> {target}"),
+                    ));
+                }
+                Policy::Record => {}
+            }
+        }
+
+        if let EntryKind::Special(file_type) = &kind
+            && self.opts.special_files == Policy::Error
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("special file: {rel} ({})", special_file_name(*file_type)),
+            ));
+        }
+
+        let kind = match (kind, self.opts.max_file_size) {
+            (EntryKind::File, _) if self.opts.structure_only => {
+                let size = fs::metadata(&path)
+                    .map_err(FileOp::Metadata.on(&path))?
+                    .len();
+                EntryKind::Oversize(size)
+            }
+            (EntryKind::File, Some(limit)) if self.opts.oversize_policy != OversizePolicy::Hash => {
+                let size = fs::metadata(&path)
+                    .map_err(FileOp::Metadata.on(&path))?
+                    .len();
+                match self.opts.oversize_policy {
+                    _ if size <= limit => EntryKind::File,
+                    OversizePolicy::Skip => {
+                        debug_event!(path = %rel, size, "skipping oversize file");
+                        self.ignored += 1;
+                        return Ok(None);
+                    }
+                    OversizePolicy::Error => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::FileTooLarge,
+                            format!("file too large: {rel} ({size} bytes, limit {limit})"),
+                        ));
+                    }
+                    OversizePolicy::SkipWithRecord => EntryKind::Oversize(size),
+                    OversizePolicy::Hash => EntryKind::File,
+                }
+            }
+            (kind, _) => kind,
+        };
+
+        // Safety limits, checked before the entry can be read.
+        if let Some(limit) = self.opts.max_files {
+            self.yielded += 1;
+            if self.yielded > limit {
+                let kind = LimitKind::Files;
+                return Err(LimitExceeded { kind, limit }.into());
+            }
+        }
+        if let (Some(limit), EntryKind::File) = (self.opts.max_total_bytes, &kind) {
+            self.total_bytes = (self.total_bytes).saturating_add(
+                fs::metadata(&path)
+                    .map_err(FileOp::Metadata.on(&path))?
+                    .len(),
+            );
+            if self.total_bytes > limit {
+                let kind = LimitKind::TotalBytes;
+                return Err(LimitExceeded { kind, limit }.into());
+            }
+        }
+
+        let (rel, raw) = match &self.prefix {
+            Some(prefix) => prefix.join(rel, raw),
+            None => (rel, raw),
+        };
+
+        Ok(Some(Entry {
+            rel,
+            raw,
+            path,
+            kind,
+        }))
+    }
 }
 
-/// Build a GlobSet from patterns in `opts` and optional `.get_dir_hash_ignore`.
-fn build_globset(root: &Path, opts: &Options) -> io::Result<GlobSet> {
-    let mut builder = GlobSetBuilder::new();
+impl Iterator for Entries<'_> {
+    type Item = io::Result<Entry>;
 
-    // Load .get_dir_hash_ignore if requested.
-    if opts.load_dot_get_dir_hash_ignore {
-        let f = root.join(".get_dir_hash_ignore");
-        if f.is_file() {
-            load_patterns_file(&f, &mut builder)?;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Err(e) = check_cancel(self.opts) {
+                return Some(Err(e));
+            }
+            let node = self.walker.next()?;
+            if let Some(entry) = self.classify(node).transpose() {
+                return Some(entry);
+            }
         }
     }
+}
 
-    // Load any additional ignore files.
-    for file in &opts.ignore_files {
-        if file.is_file() {
-            load_patterns_file(file, &mut builder)?;
+/// A hashable item discovered by the walk.
+struct Entry {
+    /// Normalized relative path (`/` separators).
+    rel: String,
+    /// Exact bytes of `rel` when the path is not valid UTF-8; framed instead
+    /// of the lossy string.
+    raw: Option<Vec<u8>>,
+    /// Path on disk.
+    path: PathBuf,
+    kind: EntryKind,
+}
+
+enum EntryKind {
+    /// Regular file: framed with its content digest.
+    File,
+    /// Dangling symlink: framed with its (normalized) target when recorded.
+    BrokenSymlink(String),
+    /// FIFO, socket or device under `Policy::Record`: framed with its type
+    /// byte (see [`special_file_type`]).
+    Special(u8),
+    /// File over [`Options::max_file_size`] under
+    /// [`OversizePolicy::SkipWithRecord`]: framed with its size, never read.
+    Oversize(u64),
+}
+
+/// Path of the walk root below [`Options::path_base`], in the form of
+/// [`Entry::rel`] and [`Entry::raw`].
+#[derive(Clone)]
+struct PathPrefix {
+    rel: String,
+    raw: Option<Vec<u8>>,
+}
+
+impl PathPrefix {
+    /// Prefix an entry's relative path (empty when the root is a file).
+    fn join(&self, rel: String, raw: Option<Vec<u8>>) -> (String, Option<Vec<u8>>) {
+        if rel.is_empty() {
+            return (self.rel.clone(), self.raw.clone());
         }
+        let raw = match (&self.raw, raw) {
+            (None, None) => None,
+            (prefix, raw) => {
+                let mut out = prefix
+                    .clone()
+                    .unwrap_or_else(|| self.rel.clone().into_bytes());
+                out.push(b'/');
+                out.extend(raw.unwrap_or_else(|| rel.clone().into_bytes()));
+                Some(out)
+            }
+        };
+        (format!("{}/{rel}", self.rel), raw)
     }
+}
 
-    // Add inline patterns.
-    for p in &opts.ignore_patterns {
-        // Patterns are relative to root; we normalize separators to '/'.
-        let pat = p.replace('\\', "/");
-        builder.add(Glob::new(&pat).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?);
+/// The prefix [`Options::path_base`] adds to paths under `root` (already
+/// resolved by [`walk_root`]); `None` without a base or when it is the root.
+fn base_prefix(root: &Path, opts: &Options) -> io::Result<Option<PathPrefix>> {
+    let Some(base) = &opts.path_base else {
+        return Ok(None);
+    };
+    let base = walk_root(base, opts)?;
+    let below = root.strip_prefix(&base).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is not inside the path base {}",
+                root.display(),
+                base.display()
+            ),
+        )
+    })?;
+    if below.as_os_str().is_empty() {
+        return Ok(None);
     }
+    let rel = path_to_unix_string(below);
+    let rel = match opts.unicode_normalization {
+        Some(form) => form.apply(&rel),
+        None => rel,
+    };
+    let raw = below.to_str().is_none().then(|| path_to_unix_bytes(below));
+    Ok(Some(PathPrefix { rel, raw }))
+}
 
-    builder
-        .build()
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+/// The name framed by [`Options::include_root_name`]: the last component of
+/// `root` (already resolved by [`walk_root`]), empty for a file system root.
+fn root_name(root: &Path, opts: &Options) -> Vec<u8> {
+    let name = Path::new(root.file_name().unwrap_or_default());
+    let rel = path_to_unix_string(name);
+    let rel = match opts.unicode_normalization {
+        Some(form) => form.apply(&rel),
+        None => rel,
+    };
+    let raw = name.to_str().is_none().then(|| path_to_unix_bytes(name));
+    framed_path(rel, raw, opts)
 }
 
-/// Load ignore patterns from file (one per line, '#' comments).
-fn load_patterns_file(path: &Path, builder: &mut GlobSetBuilder) -> io::Result<()> {
-    let txt = fs::read_to_string(path)?;
-    for raw in txt.lines() {
-        let line = raw.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
+/// Whether the entry at `path` was modified after `cutoff`
+/// ([`Options::modified_since`]); a file's own time, a link's or special
+/// file's without following it.
+fn modified_after(path: &Path, kind: &EntryKind, cutoff: SystemTime) -> io::Result<bool> {
+    let md = match kind {
+        EntryKind::File => fs::metadata(path).map_err(FileOp::Metadata.on(path))?,
+        _ => fs::symlink_metadata(path).map_err(FileOp::Metadata.on(path))?,
+    };
+    Ok(md.modified()? > cutoff)
+}
+
+/// `time` as framed for [`Options::modified_since`]; earlier instants
+/// cannot be framed.
+fn since_epoch(time: SystemTime) -> io::Result<Duration> {
+    time.duration_since(SystemTime::UNIX_EPOCH).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "modified_since must not be before the Unix epoch",
+        )
+    })
+}
+
+/// The path bytes framed for an entry: the exact bytes of a non-UTF-8 path
+/// or the (normalized) string, lowercased in case-insensitive mode.
+fn framed_path(rel: String, raw: Option<Vec<u8>>, opts: &Options) -> Vec<u8> {
+    match raw {
+        Some(raw) if opts.case_sensitive_paths => raw,
+        Some(raw) => raw.to_ascii_lowercase(),
+        None if opts.case_sensitive_paths => rel.into_bytes(),
+        None => rel.to_lowercase().into_bytes(),
+    }
+}
+
+/// Content digest of the file at `path` and the number of bytes read. The
+/// bytes are also fed to `others` (a multi-digest run); that, like an active
+/// `throttle` or [`Options::normalize_newlines`], rules out the BLAKE3-only
+/// memory-mapped and multi-threaded paths.
+fn hash_file(
+    path: &Path,
+    opts: &Options,
+    others: &mut [algo::Hasher],
+    throttle: &mut Throttle,
+) -> io::Result<(blake3::Hash, u64)> {
+    let mut inner = Blake3::new();
+    if !others.is_empty() || throttle.is_active() || opts.normalize_newlines {
+        let read = stream_file(path, &mut inner, others, opts, throttle)?;
+        return Ok((inner.finalize(), read));
+    }
+    let len = match (opts.mmap_threshold, opts.parallel_hash_threshold) {
+        (None, None) => 0,
+        _ => fs::metadata(path).map_err(FileOp::Metadata.on(path))?.len(),
+    };
+    let at_least = |t: Option<u64>| t.is_some_and(|t| len >= t);
+    let read = match (
+        at_least(opts.mmap_threshold),
+        at_least(opts.parallel_hash_threshold),
+    ) {
+        #[cfg(all(feature = "mmap", feature = "rayon"))]
+        (true, true) => {
+            inner
+                .update_mmap_rayon(path)
+                .map_err(FileOp::Read.on(path))?;
+            len
         }
-        // We do not support leading '!' negation (keep the crate tiny).
-        if line.starts_with('!') {
-            continue;
+        #[cfg(feature = "mmap")]
+        (true, _) => {
+            inner.update_mmap(path).map_err(FileOp::Read.on(path))?;
+            len
         }
-        let pat = line.replace('\\', "/");
-        let g = Glob::new(&pat).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
-        builder.add(g);
-    }
-    Ok(())
+        #[cfg(feature = "rayon")]
+        (_, true) => stream_file_parallel(path, &mut inner, opts)?,
+        _ => stream_file(path, &mut inner, &mut [], opts, throttle)?,
+    };
+    Ok((inner.finalize(), read))
 }
 
-/// Stream a file into `hasher` using a fixed-size buffer.
-fn stream_file(path: &Path, hasher: &mut Blake3) -> io::Result<()> {
-    let mut f = File::open(path)?;
+/// Stream a file into `hasher` (and `others`) using a fixed-size buffer,
+/// with [`Options::normalize_newlines`] applied; returns bytes read.
+fn stream_file(
+    path: &Path,
+    hasher: &mut Blake3,
+    others: &mut [algo::Hasher],
+    opts: &Options,
+    throttle: &mut Throttle,
+) -> io::Result<u64> {
+    let mut f = throttle.reader(File::open(path).map_err(FileOp::Open.on(path))?);
     let mut buf = [0u8; 64 * 1024];
+    let mut total = 0;
+    let mut newlines = opts.normalize_newlines.then(Newlines::default);
+    let mut update = |bytes: &[u8]| {
+        hasher.update(bytes);
+        for other in others.iter_mut() {
+            other.update(bytes);
+        }
+    };
     loop {
-        let n = f.read(&mut buf)?;
+        check_cancel(opts)?;
+        let n = f.read(&mut buf).map_err(FileOp::Read.on(path))?;
         if n == 0 {
             break;
         }
-        hasher.update(&buf[..n]);
+        match &mut newlines {
+            Some(newlines) => newlines.feed(&buf[..n], &mut update),
+            None => update(&buf[..n]),
+        }
+        total += n as u64;
+    }
+    if let Some(newlines) = newlines {
+        newlines.finish(&mut update);
+    }
+    Ok(total)
+}
+
+/// Reject an empty [`Options::output_len`].
+fn check_output_len(opts: &Options) -> io::Result<()> {
+    if opts.output_len == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "output length must be at least 1 byte",
+        ));
     }
     Ok(())
 }
 
+/// Fail with a (stats-less) [`Cancelled`] once `opts.cancel` has fired, or
+/// a [`TimedOut`] once its deadline has passed ([`with_deadline`]).
+fn check_cancel(opts: &Options) -> io::Result<()> {
+    match &opts.cancel {
+        Some(token) if token.is_cancelled() => Err(Cancelled::default().into()),
+        Some(CancellationToken {
+            deadline: Some(deadline),
+            ..
+        }) if Instant::now() >= *deadline => Err(TimedOut::default().into()),
+        _ => Ok(()),
+    }
+}
+
+/// [`report`] an entry left out of the digest or a dropped ignore rule, or
+/// with [`Options::fail_on_skip`] fail with it instead.
+fn report_skip(warning: &Warning, opts: &Options) -> io::Result<()> {
+    if !opts.fail_on_skip {
+        report(warning, opts);
+        return Ok(());
+    }
+    let path = warning.path.display();
+    Err(match &warning.reason {
+        WarningReason::NegatedPattern(line) => io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{path}: unsupported negated pattern `{line}`"),
+        ),
+        WarningReason::Unreadable(e) => io::Error::other(format!("unreadable entry: {path}: {e}")),
+        _ => io::Error::other(warning.to_string()),
+    })
+}
+
+/// Pass `warning` to [`Options::on_warning`] and, with the `tracing`
+/// feature, emit it as an event.
+fn report(warning: &Warning, opts: &Options) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(path = %warning.path.display(), "{warning}");
+    if let Some(cb) = &opts.on_warning {
+        (cb.0)(warning);
+    }
+}
+
+/// Type byte of a special file, as in `ls -l`: `p` FIFO, `s` socket, `b`
+/// block device, `c` character device; `?` for anything else.
+fn special_file_type(md: &Metadata) -> u8 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        let ft = md.file_type();
+        if ft.is_fifo() {
+            return b'p';
+        } else if ft.is_socket() {
+            return b's';
+        } else if ft.is_block_device() {
+            return b'b';
+        } else if ft.is_char_device() {
+            return b'c';
+        }
+    }
+    let _ = md;
+    b'?'
+}
+
+/// Readable name for a [`special_file_type`] byte.
+fn special_file_name(file_type: u8) -> &'static str {
+    match file_type {
+        b'p' => "FIFO",
+        b's' => "socket",
+        b'b' => "block device",
+        b'c' => "character device",
+        _ => "special file",
+    }
+}
+
+/// Target of `path` if it is a symlink that does not resolve, normalized to
+/// `/` separators.
+fn broken_symlink_target(path: &Path) -> Option<String> {
+    let md = fs::symlink_metadata(path).ok()?;
+    if !md.file_type().is_symlink() || link::resolves(path) {
+        return None;
+    }
+    symlink_target(path)
+}
+
+/// Target of the symlink at `path` (or Windows junction, see [`link`]),
+/// normalized to `/` separators.
+fn symlink_target(path: &Path) -> Option<String> {
+    let target = link::read_link(path).ok()?;
+    Some(target.to_string_lossy().replace('\\', "/"))
+}
+
+/// Stream a file into `hasher` in large chunks, each hashed on the rayon pool;
+/// returns bytes read.
+#[cfg(feature = "rayon")]
+fn stream_file_parallel(path: &Path, hasher: &mut Blake3, opts: &Options) -> io::Result<u64> {
+    let mut f = File::open(path).map_err(FileOp::Open.on(path))?;
+    let mut buf = vec![0u8; 16 * 1024 * 1024];
+    let mut total = 0;
+    loop {
+        check_cancel(opts)?;
+        let mut filled = 0;
+        while filled < buf.len() {
+            match f.read(&mut buf[filled..]).map_err(FileOp::Read.on(path))? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        if filled == 0 {
+            break;
+        }
+        hasher.update_rayon(&buf[..filled]);
+        total += filled as u64;
+    }
+    Ok(total)
+}
+
+/// `(dev, inode)` of a file with more than one hard link, if detectable.
+fn hard_link_key(md: &Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        (md.nlink() > 1).then(|| (md.dev(), md.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = md;
+        None
+    }
+}
+
 /// Feed a minimal, platform-neutral metadata frame.
-fn feed_metadata(out: &mut Blake3, md: &Metadata) {
-    out.update(b"\0M\0");
-    // Mode (Unix) or readonly bit (cross-platform fallback).
+fn feed_metadata(
+    out: &mut Vec<u8>,
+    path: &Path,
+    md: &Metadata,
+    opts: &Options,
+    names: &mut NameCache,
+) -> io::Result<()> {
+    out.extend_from_slice(b"\0M\0");
+    if opts.mode_normalization == ModeNormalization::Git {
+        out.extend_from_slice(&git_mode(path, md).to_le_bytes());
+    } else {
+        feed_raw_mode(out, md);
+    }
+
+    // Timestamps (secs, nanos) — if selected and available.
+    let feed_time = |out: &mut Vec<u8>, dur: Duration| {
+        let (secs, nanos) = opts
+            .mtime_granularity
+            .truncate(dur.as_secs(), dur.subsec_nanos());
+        out.extend_from_slice(&secs.to_le_bytes());
+        out.extend_from_slice(&nanos.to_le_bytes());
+    };
+    if opts.timestamps.contains(&Timestamp::Mtime)
+        && let Ok(mt) = md.modified()
+        && let mt = opts.clamp_mtime.map_or(mt, |clamp| mt.min(clamp))
+        && let Ok(dur) = mt.duration_since(std::time::UNIX_EPOCH)
+    {
+        feed_time(out, dur);
+    }
+    if opts.timestamps.contains(&Timestamp::Ctime)
+        && let Some(dur) = ctime(md)
+    {
+        out.extend_from_slice(b"\0C\0");
+        feed_time(out, dur);
+    }
+    if opts.timestamps.contains(&Timestamp::Birthtime)
+        && let Ok(bt) = md.created()
+        && let Ok(dur) = bt.duration_since(std::time::UNIX_EPOCH)
+    {
+        out.extend_from_slice(b"\0B\0");
+        feed_time(out, dur);
+    }
+
+    owner::feed_owner(out, md, opts.ownership, names);
+    winmeta::feed_windows(out, path, md, opts)
+}
+
+/// Feed the file type record of [`Options::include_file_type`]: `b"\0X\0"`,
+/// then `f` (regular file) or `l` (followed symlink), then 1 if executable
+/// and 0 if not.
+fn feed_file_type(out: &mut Vec<u8>, path: &Path, md: &Metadata, opts: &Options) {
+    let link = fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink());
+    let exec = match cfg!(unix) {
+        true => owner_executable(md),
+        false => path.extension().is_some_and(|ext| {
+            (opts.executable_extensions.iter()).any(|e| {
+                let e = e.strip_prefix('.').unwrap_or(e);
+                ext.eq_ignore_ascii_case(e)
+            })
+        }),
+    };
+    out.extend_from_slice(b"\0X\0");
+    out.extend_from_slice(&[if link { b'l' } else { b'f' }, exec as u8]);
+}
+
+/// Status change time since the epoch (Unix only).
+#[cfg(unix)]
+fn ctime(md: &Metadata) -> Option<Duration> {
+    use std::os::unix::fs::MetadataExt;
+    let secs = u64::try_from(md.ctime()).ok()?;
+    let nanos = u32::try_from(md.ctime_nsec()).ok()?;
+    Some(Duration::new(secs, nanos))
+}
+
+#[cfg(not(unix))]
+fn ctime(_md: &Metadata) -> Option<Duration> {
+    None
+}
+
+/// Mode (Unix) or readonly bit (cross-platform fallback).
+fn feed_raw_mode(out: &mut Vec<u8>, md: &Metadata) {
     #[cfg(unix)]
     {
         use std::os::unix::fs::PermissionsExt;
         let mode = md.permissions().mode();
-        out.update(&mode.to_le_bytes());
+        out.extend_from_slice(&mode.to_le_bytes());
     }
     #[cfg(not(unix))]
     {
         let ro = md.permissions().readonly();
-        out.update(&[ro as u8]);
+        out.extend_from_slice(&[ro as u8]);
     }
+}
 
-    // mtime (secs, nanos) — if available.
-    if let Ok(mt) = md.modified()
-        && let Ok(dur) = mt.duration_since(std::time::UNIX_EPOCH)
-    {
-        out.update(&dur.as_secs().to_le_bytes());
-        out.update(&dur.subsec_nanos().to_le_bytes());
+/// Git-style file mode of `path` (see [`ModeNormalization::Git`]).
+fn git_mode(path: &Path, md: &Metadata) -> u32 {
+    if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) {
+        0o120000
+    } else if owner_executable(md) {
+        0o100755
+    } else {
+        0o100644
     }
 }
 
+/// Size and mtime of the file at `path`, compared before and after reading
+/// it ([`Options::concurrent_modification`]).
+fn file_stamp(path: &Path) -> io::Result<(u64, Option<SystemTime>)> {
+    let md = fs::metadata(path).map_err(FileOp::Metadata.on(path))?;
+    Ok((md.len(), md.modified().ok()))
+}
+
+#[cfg(unix)]
+fn owner_executable(md: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    md.permissions().mode() & 0o100 != 0
+}
+
+#[cfg(not(unix))]
+fn owner_executable(_md: &Metadata) -> bool {
+    false
+}
+
 /// Make a Unix-style relative path (with `/` separators).
 fn make_rel_unix(root: &Path, path: &Path) -> Option<String> {
     let rel = path.strip_prefix(root).ok()?;
@@ -240,13 +2899,79 @@ fn path_to_unix_string(p: &std::path::Path) -> String {
     parts.join("/")
 }
 
-/// Case-insensitive comparison (ASCII fast-path; OK for ordering only).
-fn cmp_case_insensitive(a: &str, b: &str) -> Ordering {
-    a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase())
+/// Byte-exact counterpart of [`path_to_unix_string`]: components keep their
+/// platform encoding (raw bytes on Unix, WTF-8 on Windows).
+fn path_to_unix_bytes(p: &Path) -> Vec<u8> {
+    let mut parts: Vec<&[u8]> = Vec::new();
+    for c in p.components() {
+        match c {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                let _ = parts.pop();
+            }
+            std::path::Component::Normal(s) => parts.push(s.as_encoded_bytes()),
+            _ => {}
+        }
+    }
+    parts.join(&b'/')
+}
+
+/// Absolute form of `root` that every path below it is joined onto,
+/// resolved as [`Options::canonicalize_root`] says.
+///
+/// On Windows this is an extended-length (`\\?\`) path, so files nested
+/// deeper than `MAX_PATH` can still be opened. `canonicalize` already returns
+/// that form; otherwise (for file systems that cannot canonicalize, or
+/// [`CanonicalizeRoot::Never`]) the prefix is added here. Framed paths are
+/// relative to this root and never see the prefix.
+fn walk_root(root: &Path, opts: &Options) -> io::Result<PathBuf> {
+    let absolute = || {
+        let abs = std::path::absolute(root).unwrap_or_else(|_| root.to_path_buf());
+        extended_length(abs)
+    };
+    match opts.canonicalize_root {
+        CanonicalizeRoot::Always => Ok(root.canonicalize().unwrap_or_else(|_| absolute())),
+        CanonicalizeRoot::Never => Ok(absolute()),
+        CanonicalizeRoot::ErrorOnFailure => root
+            .canonicalize()
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", root.display()))),
+    }
+}
+
+#[cfg(windows)]
+fn extended_length(path: PathBuf) -> PathBuf {
+    use std::ffi::OsString;
+    use std::path::{Component, Prefix};
+    let Some(Component::Prefix(prefix)) = path.components().next() else {
+        return path;
+    };
+    let mut out = OsString::new();
+    match prefix.kind() {
+        Prefix::Disk(_) => out.push(r"\\?\"),
+        Prefix::UNC(server, share) => {
+            // \\server\share\.. -> \\?\UNC\server\share\..
+            out.push(r"\\?\UNC\");
+            out.push(server);
+            out.push(r"\");
+            out.push(share);
+            let mut unc = PathBuf::from(out);
+            unc.extend(path.components().skip(2));
+            return unc;
+        }
+        // Already verbatim, or a device path.
+        _ => return path,
+    }
+    out.push(path.as_os_str());
+    PathBuf::from(out)
+}
+
+#[cfg(not(windows))]
+fn extended_length(path: PathBuf) -> PathBuf {
+    path
 }
 
 /// Hex-encode to lowercase without allocation churn.
-fn hex_lower(bytes: &[u8]) -> String {
+pub(crate) fn hex_lower(bytes: &[u8]) -> String {
     const HEX: &[u8; 16] = b"0123456789abcdef";
     let mut s = String::with_capacity(bytes.len() * 2);
     for &b in bytes {
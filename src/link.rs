@@ -0,0 +1,307 @@
+//! Link targets, with Windows reparse points read explicitly.
+//!
+//! Windows has three kinds of links, all reparse points that a listing
+//! reports as symlinks: symlinks proper (to files or directories), junctions
+//! (directory links, always absolute) and the symlinks WSL creates in
+//! Windows directories. Each reparse point is read by its tag, and all three
+//! go through the options that apply to Unix symlinks: an unfollowed link is
+//! skipped, a dangling one is recorded under `Options::broken_symlinks`, and
+//! with `Options::follow_symlinks` (and `confine_symlinks`) symlinks and
+//! junctions are followed like Unix links.
+//!
+//! Windows cannot open a path through a WSL link, so one is never followed.
+//! It is dangling unless its target, taken from the link's directory,
+//! exists; an absolute target (`/usr/...`) never does. Its target is
+//! recorded as WSL wrote it, which is what the same link in a Unix checkout
+//! records.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Target of the link at `path`, as stored in the link.
+#[cfg(not(windows))]
+pub(crate) fn read_link(path: &Path) -> io::Result<PathBuf> {
+    fs::read_link(path)
+}
+
+/// Whether the link at `path` leads to something that exists.
+#[cfg(not(windows))]
+pub(crate) fn resolves(path: &Path) -> bool {
+    fs::metadata(path).is_ok()
+}
+
+/// Target of the link at `path`, as stored in the link.
+#[cfg(windows)]
+pub(crate) fn read_link(path: &Path) -> io::Result<PathBuf> {
+    match reparse::read(path)? {
+        (reparse::TAG_WSL, data) => wsl_target(&data).map(PathBuf::from),
+        // Symlinks and junctions, which `std` reads.
+        _ => fs::read_link(path),
+    }
+}
+
+/// Whether the link at `path` leads to something that exists.
+#[cfg(windows)]
+pub(crate) fn resolves(path: &Path) -> bool {
+    if fs::metadata(path).is_ok() {
+        return true;
+    }
+    match reparse::read(path) {
+        Ok((reparse::TAG_WSL, data)) => wsl_target(&data)
+            .ok()
+            .zip(path.parent())
+            .and_then(|(target, dir)| resolve_relative(dir, &target))
+            .is_some_and(|target| target.exists()),
+        _ => false,
+    }
+}
+
+/// Target of a WSL link: a version number (u32 LE), then the target as
+/// UTF-8.
+#[cfg(windows)]
+fn wsl_target(data: &[u8]) -> io::Result<String> {
+    let target = data.get(4..).unwrap_or_default();
+    String::from_utf8(target.to_vec())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "WSL link target is not UTF-8"))
+}
+
+/// `target`, a `/`-separated relative path, joined onto `dir` with `.` and
+/// `..` applied: below an extended-length (`\\?\`) root Windows takes both
+/// literally. `None` for an absolute target.
+#[cfg(windows)]
+fn resolve_relative(dir: &Path, target: &str) -> Option<PathBuf> {
+    if target.starts_with('/') {
+        return None;
+    }
+    let mut path = dir.to_path_buf();
+    for name in target.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                path.pop();
+            }
+            name => path.push(name),
+        }
+    }
+    Some(path)
+}
+
+#[cfg(windows)]
+mod reparse {
+    //! Reparse data through `FSCTL_GET_REPARSE_POINT`.
+
+    use std::fs::OpenOptions;
+    use std::io;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use std::path::Path;
+    use std::ptr;
+    use windows_sys::Win32::System::IO::DeviceIoControl;
+
+    /// IO_REPARSE_TAG_LX_SYMLINK: a symlink created by WSL.
+    pub(super) const TAG_WSL: u32 = 0xA000_001D;
+
+    const FSCTL_GET_REPARSE_POINT: u32 = 0x0009_00A8;
+    const FILE_FLAG_OPEN_REPARSE_POINT: u32 = 0x0020_0000;
+    /// Needed to open a directory.
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    /// MAXIMUM_REPARSE_DATA_BUFFER_SIZE.
+    const MAX_SIZE: usize = 16 * 1024;
+
+    /// Tag and data of the reparse point at `path`, the link itself rather
+    /// than its target.
+    pub(super) fn read(path: &Path) -> io::Result<(u32, Vec<u8>)> {
+        let file = OpenOptions::new()
+            .access_mode(0)
+            .custom_flags(FILE_FLAG_OPEN_REPARSE_POINT | FILE_FLAG_BACKUP_SEMANTICS)
+            .open(path)?;
+        let mut buf = vec![0u8; MAX_SIZE];
+        let mut len = 0;
+        // SAFETY: the handle stays open for the call, and `buf` is writable
+        // for the length passed.
+        let ok = unsafe {
+            DeviceIoControl(
+                file.as_raw_handle(),
+                FSCTL_GET_REPARSE_POINT,
+                ptr::null(),
+                0,
+                buf.as_mut_ptr().cast(),
+                MAX_SIZE as u32,
+                &mut len,
+                ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        buf.truncate(len as usize);
+        parse(&buf)
+    }
+
+    /// Tag and data of a REPARSE_DATA_BUFFER: tag (u32 LE), data length
+    /// (u16 LE), two reserved bytes, data.
+    pub(super) fn parse(buf: &[u8]) -> io::Result<(u32, Vec<u8>)> {
+        let truncated = || io::Error::new(io::ErrorKind::InvalidData, "truncated reparse point");
+        let header = buf.get(..8).ok_or_else(truncated)?;
+        let tag = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let data_len = usize::from(u16::from_le_bytes([header[4], header[5]]));
+        let data = buf.get(8..8 + data_len).ok_or_else(truncated)?;
+        Ok((tag, data.to_vec()))
+    }
+}
+
+#[cfg(all(test, windows))]
+mod tests {
+    use super::*;
+    use crate::{Options, Policy, get_dir_hash};
+    use std::os::windows::fs::symlink_dir;
+    use std::process::{Command, Stdio};
+
+    // Digests of the same tree on Unix: `data/f.txt` ("data"), and next to
+    // it `in_link -> data`, `out_link -> ../outside` (`outside/o.txt` holds
+    // "outside") and `dangling -> missing`.
+    const IGNORED: &str = "f4793c3b2b10fcb30cc79ef5a2a1cb10117504d06ff41aaff7cd6b2cbd77fb09";
+    const RECORDED: &str = "135d08e342c1064220f1d557226c8581140ba591e4b3680b5159279479622837";
+    const FOLLOWED: &str = "2c73c14ea5066fa4d67abf05e20967acda38768c5576bf65b4b9e873ef179a61";
+    const CONFINED: &str = "b3d6d0146b4a9e1f76822b688870ee185aa3e26e19dbdedc909941d280d9e420";
+    const CONFINED_RECORDED: &str =
+        "ac62b298befa302cc52c2ff9e9fef43b99daa0d9225da5a6f53db7dd437071cb";
+
+    /// IO_REPARSE_TAG_SYMLINK and IO_REPARSE_TAG_MOUNT_POINT (junctions).
+    const TAG_SYMLINK: u32 = 0xA000_000C;
+    const TAG_JUNCTION: u32 = 0xA000_0003;
+    /// ERROR_NOT_A_REPARSE_POINT.
+    const NOT_A_REPARSE_POINT: i32 = 4390;
+
+    /// `std` creates no junctions; `mklink /J` does, for missing targets too.
+    fn junction(link: &Path, target: &Path) {
+        let status = Command::new("cmd")
+            .args(["/C", "mklink", "/J"])
+            .arg(link)
+            .arg(target)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "mklink /J {}", link.display());
+    }
+
+    /// `base/root/data/f.txt` and `base/outside/o.txt`; returns the root.
+    fn tree(base: &Path) -> PathBuf {
+        let root = base.join("root");
+        fs::create_dir_all(root.join("data")).unwrap();
+        fs::create_dir(base.join("outside")).unwrap();
+        fs::write(root.join("data/f.txt"), "data").unwrap();
+        fs::write(base.join("outside/o.txt"), "outside").unwrap();
+        root
+    }
+
+    fn hash(root: &Path, follow: bool, confine: bool, broken: Policy) -> io::Result<String> {
+        let opts = Options {
+            follow_symlinks: follow,
+            confine_symlinks: confine,
+            broken_symlinks: broken,
+            ..Options::default()
+        };
+        get_dir_hash(root, &opts)
+    }
+
+    #[test]
+    fn directory_symlinks_hash_like_unix_symlinks() {
+        let base = tempfile::tempdir().unwrap();
+        let root = tree(base.path());
+        symlink_dir("data", root.join("in_link")).unwrap();
+        symlink_dir(Path::new("..").join("outside"), root.join("out_link")).unwrap();
+        symlink_dir("missing", root.join("dangling")).unwrap();
+
+        assert_eq!(hash(&root, false, false, Policy::Ignore).unwrap(), IGNORED);
+        assert_eq!(hash(&root, false, false, Policy::Record).unwrap(), RECORDED);
+        assert!(hash(&root, false, false, Policy::Error).is_err());
+        assert_eq!(hash(&root, true, false, Policy::Ignore).unwrap(), FOLLOWED);
+        assert_eq!(hash(&root, true, true, Policy::Ignore).unwrap(), CONFINED);
+        assert_eq!(
+            hash(&root, true, true, Policy::Record).unwrap(),
+            CONFINED_RECORDED
+        );
+        assert_eq!(reparse::read(&root.join("in_link")).unwrap().0, TAG_SYMLINK);
+    }
+
+    #[test]
+    fn junctions_hash_like_unix_symlinks() {
+        let base = tempfile::tempdir().unwrap();
+        let root = tree(base.path());
+        junction(&root.join("in_link"), &root.join("data"));
+        junction(&root.join("out_link"), &base.path().join("outside"));
+
+        // Resolving junctions are skipped like any unfollowed link.
+        assert_eq!(hash(&root, false, false, Policy::Ignore).unwrap(), IGNORED);
+        assert_eq!(hash(&root, false, false, Policy::Record).unwrap(), IGNORED);
+        assert_eq!(hash(&root, true, false, Policy::Ignore).unwrap(), FOLLOWED);
+        assert_eq!(hash(&root, true, true, Policy::Ignore).unwrap(), CONFINED);
+        assert_eq!(
+            reparse::read(&root.join("in_link")).unwrap().0,
+            TAG_JUNCTION
+        );
+    }
+
+    #[test]
+    fn dangling_junctions_follow_the_broken_symlink_policy() {
+        let base = tempfile::tempdir().unwrap();
+        let root = tree(base.path());
+        junction(&root.join("dangling"), &base.path().join("missing"));
+
+        assert_eq!(hash(&root, false, false, Policy::Ignore).unwrap(), IGNORED);
+        assert_eq!(hash(&root, true, false, Policy::Ignore).unwrap(), IGNORED);
+        assert!(hash(&root, false, false, Policy::Error).is_err());
+        // Recorded by its absolute target, so only the change is known.
+        assert_ne!(hash(&root, false, false, Policy::Record).unwrap(), IGNORED);
+        assert!(!resolves(&root.join("dangling")));
+    }
+
+    #[test]
+    fn reparse_read_rejects_plain_and_missing_paths() {
+        let base = tempfile::tempdir().unwrap();
+        let file = base.path().join("plain");
+        fs::write(&file, "plain").unwrap();
+        for path in [file.as_path(), base.path()] {
+            let e = reparse::read(path).unwrap_err();
+            assert_eq!(
+                e.raw_os_error(),
+                Some(NOT_A_REPARSE_POINT),
+                "{}",
+                path.display()
+            );
+        }
+        let e = reparse::read(&base.path().join("missing")).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn reparse_parse_rejects_truncated_buffers() {
+        let mut buf = reparse::TAG_WSL.to_le_bytes().to_vec();
+        buf.extend_from_slice(&6u16.to_le_bytes());
+        buf.extend_from_slice(&[0, 0]);
+        buf.extend_from_slice(&2u32.to_le_bytes());
+        buf.extend_from_slice(b"ab");
+        assert_eq!(
+            reparse::parse(&buf).unwrap(),
+            (reparse::TAG_WSL, b"\x02\0\0\0ab".to_vec())
+        );
+        for len in 0..buf.len() {
+            let e = reparse::parse(&buf[..len]).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{len}");
+        }
+    }
+
+    #[test]
+    fn wsl_targets_resolve_from_the_link_directory() {
+        assert_eq!(wsl_target(b"\x02\0\0\0../x").unwrap(), "../x");
+        assert!(wsl_target(b"\x02\0\0\0\xff").is_err());
+        let dir = Path::new(r"\\?\C:\tree\sub");
+        assert_eq!(
+            resolve_relative(dir, "./../x/y").unwrap(),
+            Path::new(r"\\?\C:\tree\x\y")
+        );
+        assert!(resolve_relative(dir, "/usr/bin").is_none());
+    }
+}
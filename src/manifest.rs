@@ -0,0 +1,772 @@
+//! Saved [`Manifest`]s in three formats ([`ManifestFormat`]).
+//!
+//! Text is what `--print-files` prints: `hex  path` per file, then the root
+//! digest (for the path `.`), paths escaped as in coreutils. It carries no
+//! chunks, and a text manifest cut at a line break reads as a shorter one;
+//! JSON and binary manifests detect any truncation.
+//!
+//! JSON is versioned by its first two members and lists one file per line:
+//!
+//! ```text
+//! {"format":"get_dir_hash-manifest","version":1,"hex":"<root>","files":[
+//! {"path":"src/lib.rs","hex":"<digest>"},
+//! {"path":"big.bin","hex":"<digest>","chunks":[{"offset":0,"length":8192,"hex":"<digest>"}]}
+//! ]}
+//! ```
+//!
+//! The binary layout is about half the size of the text (integers are LEB128
+//! varints, digests raw bytes, byte strings varint-length-prefixed):
+//!
+//! ```text
+//! get_dir_hash-manifest-v1 \0
+//! root digest  count
+//! { shared  suffix  digest  chunks { length  digest }* }*
+//! ```
+//!
+//! Paths are front-coded: `shared` bytes are taken from the previous path
+//! and `suffix` appended, which in canonical order drops most directory
+//! names. Chunk offsets follow from the lengths.
+
+use crate::codec::{Reader, put_short_field, put_varint};
+use crate::{Chunk, FileDigest, Manifest, ParseOptionError, RunStats, hex_lower};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+const MAGIC: &[u8] = b"get_dir_hash-manifest-v1\0";
+/// What every binary manifest version starts with.
+const MAGIC_PREFIX: &[u8] = b"get_dir_hash-manifest-v";
+
+/// `format` member of a JSON manifest.
+const JSON_FORMAT: &str = "get_dir_hash-manifest";
+
+/// `version` member of a JSON manifest.
+const JSON_VERSION: u64 = 1;
+
+/// Deepest nesting of arrays and objects a JSON manifest may use; a valid
+/// one needs five, and the parser recurses once per level.
+const JSON_MAX_DEPTH: usize = 64;
+
+/// Encoding of a saved [`Manifest`] ([`Manifest::to_bytes`]).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ManifestFormat {
+    /// `hex  path` lines, as printed by `--print-files`; no chunks.
+    #[default]
+    Text,
+    /// A JSON object, one file per line.
+    Json,
+    /// Compact, versioned binary, for manifests of millions of files.
+    Binary,
+}
+
+impl FromStr for ManifestFormat {
+    type Err = ParseOptionError;
+
+    /// Parses `text`, `json` or `binary`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ManifestFormat::Text),
+            "json" => Ok(ManifestFormat::Json),
+            "binary" => Ok(ManifestFormat::Binary),
+            _ => Err(ParseOptionError::new(s, "text, json or binary")),
+        }
+    }
+}
+
+impl Manifest {
+    /// Encode the root digest and files (not the statistics) in `format`.
+    /// Fails on a digest that is not hex.
+    pub fn to_bytes(&self, format: ManifestFormat) -> io::Result<Vec<u8>> {
+        match format {
+            ManifestFormat::Text => Ok(self.to_text().into_bytes()),
+            ManifestFormat::Json => Ok(self.to_json().into_bytes()),
+            ManifestFormat::Binary => self.to_binary(),
+        }
+    }
+
+    /// Decode a manifest in any [`ManifestFormat`], telling them apart by
+    /// their first bytes. Statistics are left empty.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Manifest> {
+        if bytes.starts_with(MAGIC) {
+            return Self::from_binary(bytes);
+        }
+        if bytes.starts_with(MAGIC_PREFIX) {
+            return Err(invalid("unsupported binary manifest version".into()));
+        }
+        let text = String::from_utf8_lossy(bytes);
+        match text.trim_start().starts_with('{') {
+            true => Self::from_json(&text),
+            false => Self::from_text(&text),
+        }
+    }
+
+    /// Write the manifest to `path` in `format`.
+    pub fn save(&self, path: impl AsRef<Path>, format: ManifestFormat) -> io::Result<()> {
+        fs::write(path, self.to_bytes(format)?)
+    }
+
+    /// Read a manifest written by [`Manifest::save`] (or `--print-files`).
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Manifest> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    fn to_text(&self) -> String {
+        let mut out = String::new();
+        let files = (self.files.iter()).map(|f| (f.hex.as_str(), f.path.as_str()));
+        for (hex, path) in files.chain([(self.hex.as_str(), ".")]) {
+            // A leading backslash marks an escaped path, as in coreutils.
+            if path.contains(['\\', '\n', '\r']) {
+                let path = (path.replace('\\', "\\\\"))
+                    .replace('\n', "\\n")
+                    .replace('\r', "\\r");
+                out.push_str(&format!("\\{hex}  {path}\n"));
+            } else {
+                out.push_str(&format!("{hex}  {path}\n"));
+            }
+        }
+        out
+    }
+
+    fn from_text(text: &str) -> io::Result<Manifest> {
+        let invalid = |line: usize| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {line}: expected `<hex>  <path>`"),
+            )
+        };
+        // Every line ends in a newline, so one cut short has none.
+        if !text.is_empty() && !text.ends_with('\n') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated manifest",
+            ));
+        }
+        let mut files = Vec::new();
+        for (i, line) in text.lines().enumerate().filter(|(_, l)| !l.is_empty()) {
+            let (escaped, line) = match line.strip_prefix('\\') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let (hex, path) = (line.split_once("  "))
+                .filter(|(_, path)| !path.is_empty())
+                .ok_or_else(|| invalid(i + 1))?;
+            hex_bytes(hex).ok_or_else(|| invalid(i + 1))?;
+            files.push(FileDigest {
+                path: if escaped {
+                    unescape(path).ok_or_else(|| invalid(i + 1))?
+                } else {
+                    path.to_owned()
+                },
+                hex: hex.to_owned(),
+                chunks: Vec::new(),
+            });
+        }
+        let root = files
+            .pop()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "empty manifest"))?;
+        Ok(Manifest {
+            hex: root.hex,
+            files,
+            stats: RunStats::default(),
+        })
+    }
+
+    fn to_json(&self) -> String {
+        let mut out = format!(
+            "{{\"format\":{},\"version\":{JSON_VERSION},\"hex\":{},\"files\":[",
+            json_string(JSON_FORMAT),
+            json_string(&self.hex)
+        );
+        for (i, f) in self.files.iter().enumerate() {
+            out.push_str(if i == 0 { "\n" } else { ",\n" });
+            out.push_str(&format!(
+                "{{\"path\":{},\"hex\":{}",
+                json_string(&f.path),
+                json_string(&f.hex)
+            ));
+            if !f.chunks.is_empty() {
+                let chunks: Vec<String> = (f.chunks.iter())
+                    .map(|c| {
+                        format!(
+                            "{{\"offset\":{},\"length\":{},\"hex\":{}}}",
+                            c.offset,
+                            c.length,
+                            json_string(&c.hex)
+                        )
+                    })
+                    .collect();
+                out.push_str(&format!(",\"chunks\":[{}]", chunks.join(",")));
+            }
+            out.push('}');
+        }
+        out.push_str("\n]}\n");
+        out
+    }
+
+    fn from_json(text: &str) -> io::Result<Manifest> {
+        let value = Json::parse(text)?;
+        if value.get("format")?.string()? != JSON_FORMAT {
+            return Err(invalid("not a get_dir_hash manifest".into()));
+        }
+        match value.get("version")?.number()? {
+            JSON_VERSION => {}
+            v => return Err(invalid(format!("unsupported manifest version {v}"))),
+        }
+        let hex = value.get("hex")?.digest()?;
+        let mut files = Vec::new();
+        for f in value.get("files")?.array()? {
+            let chunks = match f.member("chunks") {
+                None => Vec::new(),
+                Some(chunks) => (chunks.array()?.iter())
+                    .map(|c| {
+                        Ok(Chunk {
+                            offset: c.get("offset")?.number()?,
+                            length: c.get("length")?.number()?,
+                            hex: c.get("hex")?.digest()?,
+                        })
+                    })
+                    .collect::<io::Result<_>>()?,
+            };
+            files.push(FileDigest {
+                path: f.get("path")?.string()?.to_owned(),
+                hex: f.get("hex")?.digest()?,
+                chunks,
+            });
+        }
+        Ok(Manifest {
+            hex,
+            files,
+            stats: RunStats::default(),
+        })
+    }
+
+    fn to_binary(&self) -> io::Result<Vec<u8>> {
+        let digest = |hex: &str| {
+            hex_bytes(hex).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("not a digest: {hex}"))
+            })
+        };
+        let mut out = MAGIC.to_vec();
+        put_short_field(&mut out, &digest(&self.hex)?);
+        put_varint(&mut out, self.files.len() as u64);
+        let mut previous: &[u8] = &[];
+        for f in &self.files {
+            let path = f.path.as_bytes();
+            let shared = (previous.iter().zip(path))
+                .take_while(|(a, b)| a == b)
+                .count();
+            put_varint(&mut out, shared as u64);
+            put_short_field(&mut out, &path[shared..]);
+            put_short_field(&mut out, &digest(&f.hex)?);
+            put_varint(&mut out, f.chunks.len() as u64);
+            for c in &f.chunks {
+                put_varint(&mut out, c.length);
+                put_short_field(&mut out, &digest(&c.hex)?);
+            }
+            previous = path;
+        }
+        Ok(out)
+    }
+
+    fn from_binary(bytes: &[u8]) -> io::Result<Manifest> {
+        let mut r = Reader::new(bytes, MAGIC, "manifest")?;
+        let hex = hex_lower(r.short_field()?);
+        let count = r.varint()?;
+        let mut files = Vec::new();
+        let mut path = Vec::new();
+        for _ in 0..count {
+            let shared = usize::try_from(r.varint()?).unwrap_or(usize::MAX);
+            if shared > path.len() {
+                return Err(invalid("corrupt path in manifest".into()));
+            }
+            path.truncate(shared);
+            path.extend_from_slice(r.short_field()?);
+            let hex = hex_lower(r.short_field()?);
+            let mut chunks = Vec::new();
+            let mut offset = 0u64;
+            for _ in 0..r.varint()? {
+                let length = r.varint()?;
+                chunks.push(Chunk {
+                    offset,
+                    length,
+                    hex: hex_lower(r.short_field()?),
+                });
+                offset = offset.saturating_add(length);
+            }
+            files.push(FileDigest {
+                path: String::from_utf8(path.clone())
+                    .map_err(|_| invalid("path in manifest is not UTF-8".into()))?,
+                hex,
+                chunks,
+            });
+        }
+        r.finish()?;
+        Ok(Manifest {
+            hex,
+            files,
+            stats: RunStats::default(),
+        })
+    }
+}
+
+impl fmt::Display for ManifestFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ManifestFormat::Text => "text",
+            ManifestFormat::Json => "json",
+            ManifestFormat::Binary => "binary",
+        })
+    }
+}
+
+/// Undo the `\\`, `\n` and `\r` escapes of a checksum line's path.
+fn unescape(path: &str) -> Option<String> {
+    let mut out = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => match chars.next()? {
+                '\\' => '\\',
+                'n' => '\n',
+                'r' => '\r',
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(out)
+}
+
+/// Bytes of the hex digest `hex`; `None` unless it is non-empty hex.
+fn hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// `s` as a JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Just enough JSON to read manifests back: any document parses, and the
+/// accessors fail on what a manifest does not hold.
+enum Json {
+    Null,
+    Bool,
+    /// Unsigned integers only; manifests hold no others.
+    Number(u64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> io::Result<Json> {
+        let mut p = JsonParser {
+            rest: text.as_bytes(),
+            depth: 0,
+        };
+        let value = p.value()?;
+        p.blank();
+        match p.rest.is_empty() {
+            true => Ok(value),
+            false => Err(invalid("trailing data after JSON manifest".into())),
+        }
+    }
+
+    fn member(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn get(&self, name: &str) -> io::Result<&Json> {
+        (self.member(name)).ok_or_else(|| invalid(format!("JSON manifest: missing \"{name}\"")))
+    }
+
+    fn string(&self) -> io::Result<&str> {
+        match self {
+            Json::String(s) => Ok(s),
+            _ => Err(invalid("JSON manifest: expected a string".into())),
+        }
+    }
+
+    fn digest(&self) -> io::Result<String> {
+        let hex = self.string()?;
+        match hex_bytes(hex) {
+            Some(_) => Ok(hex.to_owned()),
+            None => Err(invalid(format!("JSON manifest: not a digest: {hex}"))),
+        }
+    }
+
+    fn number(&self) -> io::Result<u64> {
+        match self {
+            Json::Number(n) => Ok(*n),
+            _ => Err(invalid(
+                "JSON manifest: expected an unsigned integer".into(),
+            )),
+        }
+    }
+
+    fn array(&self) -> io::Result<&[Json]> {
+        match self {
+            Json::Array(items) => Ok(items),
+            _ => Err(invalid("JSON manifest: expected an array".into())),
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    rest: &'a [u8],
+    /// Arrays and objects open around the current value.
+    depth: usize,
+}
+
+impl JsonParser<'_> {
+    fn blank(&mut self) {
+        while let [b' ' | b'\t' | b'\n' | b'\r', rest @ ..] = self.rest {
+            self.rest = rest;
+        }
+    }
+
+    fn error(&self) -> io::Error {
+        invalid("malformed JSON manifest".into())
+    }
+
+    /// Consume `token` (after blanks) or fail.
+    fn expect(&mut self, token: &[u8]) -> io::Result<()> {
+        self.blank();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                Ok(())
+            }
+            None => Err(self.error()),
+        }
+    }
+
+    /// Consume `token` (after blanks) if it comes next.
+    fn eat(&mut self, token: u8) -> bool {
+        self.blank();
+        match self.rest.split_first() {
+            Some((&b, rest)) if b == token => {
+                self.rest = rest;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Enter an array or object, unless that nests too deep.
+    fn nest(&mut self) -> io::Result<()> {
+        if self.depth == JSON_MAX_DEPTH {
+            return Err(self.error());
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> io::Result<Json> {
+        self.blank();
+        match self.rest.first() {
+            Some(b'{') => {
+                self.nest()?;
+                self.expect(b"{")?;
+                let mut members = Vec::new();
+                if !self.eat(b'}') {
+                    loop {
+                        self.blank();
+                        let name = self.string()?;
+                        self.expect(b":")?;
+                        members.push((name, self.value()?));
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.expect(b",")?;
+                    }
+                }
+                self.depth -= 1;
+                Ok(Json::Object(members))
+            }
+            Some(b'[') => {
+                self.nest()?;
+                self.expect(b"[")?;
+                let mut items = Vec::new();
+                if !self.eat(b']') {
+                    loop {
+                        items.push(self.value()?);
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.expect(b",")?;
+                    }
+                }
+                self.depth -= 1;
+                Ok(Json::Array(items))
+            }
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.expect(b"true").map(|()| Json::Bool),
+            Some(b'f') => self.expect(b"false").map(|()| Json::Bool),
+            Some(b'n') => self.expect(b"null").map(|()| Json::Null),
+            Some(b'0'..=b'9') => {
+                let end = (self.rest.iter())
+                    .position(|b| !b.is_ascii_digit())
+                    .unwrap_or(self.rest.len());
+                let (digits, rest) = self.rest.split_at(end);
+                if let Some(b'.' | b'e' | b'E') = rest.first() {
+                    return Err(self.error());
+                }
+                self.rest = rest;
+                let digits = std::str::from_utf8(digits).map_err(|_| self.error())?;
+                digits.parse().map(Json::Number).map_err(|_| self.error())
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let Some(rest) = self.rest.strip_prefix(b"\"") else {
+            return Err(self.error());
+        };
+        self.rest = rest;
+        let mut out = Vec::new();
+        loop {
+            let Some((&b, rest)) = self.rest.split_first() else {
+                return Err(self.error());
+            };
+            self.rest = rest;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let Some((&e, rest)) = self.rest.split_first() else {
+                        return Err(self.error());
+                    };
+                    self.rest = rest;
+                    let c = match e {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let hi = self.hex4()?;
+                            let code = match hi {
+                                0xd800..=0xdbff => {
+                                    let rest = self.rest.strip_prefix(b"\\u");
+                                    self.rest = rest.ok_or_else(|| self.error())?;
+                                    match self.hex4()? {
+                                        lo @ 0xdc00..=0xdfff => {
+                                            0x10000 + ((hi - 0xd800) << 10) + (lo - 0xdc00)
+                                        }
+                                        _ => return Err(self.error()),
+                                    }
+                                }
+                                _ => hi,
+                            };
+                            char::from_u32(code).ok_or_else(|| self.error())?
+                        }
+                        _ => return Err(self.error()),
+                    };
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                b => out.push(b),
+            }
+        }
+        String::from_utf8(out).map_err(|_| self.error())
+    }
+
+    fn hex4(&mut self) -> io::Result<u32> {
+        let digits = self.rest.get(..4).ok_or_else(|| self.error())?;
+        let digits = std::str::from_utf8(digits).map_err(|_| self.error())?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.error())?;
+        self.rest = &self.rest[4..];
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [ManifestFormat; 3] = [
+        ManifestFormat::Text,
+        ManifestFormat::Json,
+        ManifestFormat::Binary,
+    ];
+
+    fn digest(seed: &str) -> String {
+        blake3::hash(seed.as_bytes()).to_hex().to_string()
+    }
+
+    /// A file with `chunks` chunks of 100 bytes.
+    fn file(path: &str, chunks: u64) -> FileDigest {
+        FileDigest {
+            path: path.into(),
+            hex: digest(path),
+            chunks: (0..chunks)
+                .map(|i| Chunk {
+                    offset: i * 100,
+                    length: 100,
+                    hex: digest(&format!("{path}#{i}")),
+                })
+                .collect(),
+        }
+    }
+
+    fn manifest(files: Vec<FileDigest>) -> Manifest {
+        Manifest {
+            hex: digest("root"),
+            files,
+            stats: RunStats::default(),
+        }
+    }
+
+    /// Awkward paths, with and without chunks.
+    fn sample() -> Manifest {
+        manifest(vec![
+            file("plain.txt", 0),
+            file("back\\slash", 2),
+            file("new\nline", 0),
+            file("carriage\rreturn", 1),
+            file("\"quoted\" 'name'", 0),
+            file("naïve/日本語.txt", 3),
+            file("tab\there", 0),
+        ])
+    }
+
+    /// `m` as the text format keeps it: without chunks.
+    fn without_chunks(m: &Manifest) -> Manifest {
+        let files = (m.files.iter())
+            .map(|f| FileDigest {
+                chunks: Vec::new(),
+                ..f.clone()
+            })
+            .collect();
+        manifest(files)
+    }
+
+    fn round_trip(m: &Manifest, format: ManifestFormat) -> Manifest {
+        Manifest::from_bytes(&m.to_bytes(format).unwrap()).unwrap()
+    }
+
+    fn assert_invalid(bytes: &[u8], what: &str) {
+        match Manifest::from_bytes(bytes) {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{what}: {e}"),
+            Ok(m) => panic!("{what}: accepted as {m:?}"),
+        }
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let m = sample();
+        assert_eq!(round_trip(&m, ManifestFormat::Text), without_chunks(&m));
+        assert_eq!(round_trip(&m, ManifestFormat::Json), m);
+        assert_eq!(round_trip(&m, ManifestFormat::Binary), m);
+    }
+
+    #[test]
+    fn empty_manifest_round_trips() {
+        let m = manifest(Vec::new());
+        for format in FORMATS {
+            assert_eq!(round_trip(&m, format), m, "{format}");
+        }
+    }
+
+    #[test]
+    fn binary_front_codes_shared_prefixes() {
+        let dir = "deeply/nested/directory/".repeat(10);
+        let m = manifest(
+            (0..20)
+                .map(|i| file(&format!("{dir}{i:02}.rs"), 0))
+                .collect(),
+        );
+        assert_eq!(round_trip(&m, ManifestFormat::Binary), m);
+        // Each path after the first costs its suffix, not the directory.
+        let bytes = m.to_bytes(ManifestFormat::Binary).unwrap();
+        assert!(bytes.len() < MAGIC.len() + 33 + dir.len() + 20 * (1 + 1 + 6 + 33 + 1));
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        let m = sample();
+        for format in FORMATS {
+            let bytes = m.to_bytes(format).unwrap();
+            // JSON needs no final newline; text is read line by line, so
+            // only a cut inside a line shows.
+            let cuts = (0..bytes.len()).filter(|&n| match format {
+                ManifestFormat::Text => n == 0 || bytes[n - 1] != b'\n',
+                ManifestFormat::Json => n < bytes.len() - 1,
+                ManifestFormat::Binary => true,
+            });
+            for n in cuts {
+                assert_invalid(&bytes[..n], &format!("{format} cut at {n}"));
+            }
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let m = sample();
+        for format in FORMATS {
+            for tail in [&b"x"[..], b"\0", b"junk\n"] {
+                let mut bytes = m.to_bytes(format).unwrap();
+                bytes.extend_from_slice(tail);
+                assert_invalid(&bytes, &format!("{format} + {tail:?}"));
+            }
+        }
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let m = sample();
+        let json = String::from_utf8(m.to_bytes(ManifestFormat::Json).unwrap()).unwrap();
+        assert_invalid(
+            json.replacen("\"version\":1", "\"version\":2", 1)
+                .as_bytes(),
+            "json v2",
+        );
+        let mut binary = m.to_bytes(ManifestFormat::Binary).unwrap();
+        binary[MAGIC.len() - 2] = b'2';
+        assert_invalid(&binary, "binary v2");
+    }
+
+    #[test]
+    fn deep_json_nesting_is_rejected() {
+        let deep = format!("{{\"format\":{}", "[".repeat(200_000));
+        assert_invalid(deep.as_bytes(), "200000 levels");
+        let nested = |depth| format!("{}0{}", "[".repeat(depth), "]".repeat(depth));
+        let within = format!("{{\"format\":{}}}", nested(JSON_MAX_DEPTH - 1));
+        assert!(Json::parse(&within).is_ok());
+        let beyond = format!("{{\"format\":{}}}", nested(JSON_MAX_DEPTH));
+        assert!(Json::parse(&beyond).is_err());
+    }
+}
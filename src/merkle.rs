@@ -0,0 +1,476 @@
+//! Merkle tree over the hashed entries ([`crate::get_dir_hash_tree`]).
+//!
+//! Every node digest is BLAKE3 over the tag `get_dir_hash-tree-v1\0`, a kind
+//! byte and a body:
+//!
+//! ```text
+//! file  F digest[32] len(metadata) metadata
+//! link  L len(target) target
+//! big   B size                                   (oversize file, not read)
+//! spec  S type                                   (FIFO, socket, device)
+//! dir   D { len(name) name child_digest[32] }*   (children in canonical order)
+//! ```
+//!
+//! Lengths are u64 LE; names are the framed path components and metadata is
+//! the same block the flat digest frames. A directory's digest depends only on
+//! what lies below it, so a subtree hashes the same wherever it is mounted and
+//! two trees can be compared top-down, descending only where digests differ.
+//!
+//! An [`InclusionProof`] carries, for every directory on a file's path, the
+//! names and digests of the other children: enough to recompute the root
+//! from the file's content digest alone.
+
+use crate::RunStats;
+use crate::codec::{Reader, put_field, put_u64};
+use std::io;
+
+const TAG: &[u8] = b"get_dir_hash-tree-v1\0";
+
+/// Result of [`crate::get_dir_hash_tree`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirTree {
+    /// The root directory; its `hex` is the Merkle root digest.
+    pub root: TreeNode,
+    /// How the run went.
+    pub stats: RunStats,
+}
+
+/// What a [`TreeNode`] stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    /// A hashed file.
+    File,
+    /// A dangling symlink recorded by its target.
+    Symlink,
+    /// A file over [`crate::Options::max_file_size`], recorded by its size.
+    Oversize,
+    /// A FIFO, socket or device recorded by its type
+    /// ([`crate::Options::special_files`]).
+    Special,
+    /// A directory containing at least one hashed entry (or the root).
+    Dir,
+}
+
+/// One node of a [`DirTree`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeNode {
+    /// Path component as framed; empty for the root. Names that are not
+    /// valid UTF-8 are shown lossily.
+    pub name: String,
+    /// What the node stands for.
+    pub kind: NodeKind,
+    /// Lowercase hex node digest.
+    pub hex: String,
+    /// Children in canonical order; empty unless `kind` is `Dir`.
+    pub children: Vec<TreeNode>,
+    /// Exact framed name, as hashed into the parent.
+    raw_name: Vec<u8>,
+    digest: blake3::Hash,
+    /// Metadata block of a file leaf, kept for inclusion proofs.
+    metadata: Vec<u8>,
+}
+
+impl TreeNode {
+    /// The node at `path` (`/`-separated, as framed), if any. `""` is the
+    /// node itself.
+    pub fn get(&self, path: &str) -> Option<&TreeNode> {
+        path.split('/')
+            .filter(|c| !c.is_empty())
+            .try_fold(self, |node, c| node.children.iter().find(|n| n.name == c))
+    }
+
+    /// Paths (relative to this node) of the topmost nodes whose digests differ
+    /// between `self` and `other`, including entries present on one side only.
+    /// Only subtrees with differing digests are visited.
+    pub fn diff(&self, other: &TreeNode) -> Vec<String> {
+        let mut out = Vec::new();
+        diff_into(self, other, "", &mut out);
+        out
+    }
+
+    /// Proof that the file at `path` (as framed, relative to this node) is
+    /// part of this node's digest; `None` if there is no such file.
+    pub fn prove(&self, path: &str) -> Option<InclusionProof> {
+        let mut levels = Vec::new();
+        let mut node = self;
+        for part in path.split('/').filter(|c| !c.is_empty()) {
+            let i = node.children.iter().position(|n| n.name == part)?;
+            let entries = |nodes: &[TreeNode]| {
+                nodes
+                    .iter()
+                    .map(|n| (n.raw_name.clone(), *n.digest.as_bytes()))
+                    .collect()
+            };
+            levels.push(ProofLevel {
+                before: entries(&node.children[..i]),
+                name: node.children[i].raw_name.clone(),
+                after: entries(&node.children[i + 1..]),
+            });
+            node = &node.children[i];
+        }
+        (node.kind == NodeKind::File).then(|| InclusionProof {
+            metadata: node.metadata.clone(),
+            levels,
+        })
+    }
+
+    fn new(raw_name: Vec<u8>, kind: NodeKind) -> Self {
+        Self {
+            name: String::from_utf8_lossy(&raw_name).into_owned(),
+            kind,
+            hex: String::new(),
+            children: Vec::new(),
+            raw_name,
+            digest: blake3::Hash::from_bytes([0; 32]),
+            metadata: Vec::new(),
+        }
+    }
+
+    /// Compute directory digests bottom-up.
+    fn seal(&mut self) {
+        if self.kind != NodeKind::Dir {
+            return;
+        }
+        let mut h = node_hasher(b'D');
+        for child in &mut self.children {
+            child.seal();
+            h.update(&(child.raw_name.len() as u64).to_le_bytes());
+            h.update(&child.raw_name);
+            h.update(child.digest.as_bytes());
+        }
+        self.set_digest(h.finalize());
+    }
+
+    fn set_digest(&mut self, digest: blake3::Hash) {
+        self.digest = digest;
+        self.hex = digest.to_hex().to_string();
+    }
+}
+
+fn diff_into(a: &TreeNode, b: &TreeNode, prefix: &str, out: &mut Vec<String>) {
+    if a.digest == b.digest {
+        return;
+    }
+    if a.kind != NodeKind::Dir || b.kind != NodeKind::Dir {
+        out.push(prefix.to_owned());
+        return;
+    }
+    let join = |name: &str| {
+        if prefix.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{prefix}/{name}")
+        }
+    };
+    for child in &a.children {
+        match b.children.iter().find(|c| c.raw_name == child.raw_name) {
+            Some(other) => diff_into(child, other, &join(&child.name), out),
+            None => out.push(join(&child.name)),
+        }
+    }
+    for child in &b.children {
+        if !a.children.iter().any(|c| c.raw_name == child.raw_name) {
+            out.push(join(&child.name));
+        }
+    }
+}
+
+fn node_hasher(kind: u8) -> blake3::Hasher {
+    let mut h = blake3::Hasher::new();
+    h.update(TAG);
+    h.update(&[kind]);
+    h
+}
+
+/// Digest of a file leaf.
+fn file_digest(content: &blake3::Hash, metadata: &[u8]) -> blake3::Hash {
+    let mut h = node_hasher(b'F');
+    h.update(content.as_bytes());
+    h.update(&(metadata.len() as u64).to_le_bytes());
+    h.update(metadata);
+    h.finalize()
+}
+
+/// Digest of a recorded symlink leaf.
+fn link_digest(target: &[u8]) -> blake3::Hash {
+    let mut h = node_hasher(b'L');
+    h.update(&(target.len() as u64).to_le_bytes());
+    h.update(target);
+    h.finalize()
+}
+
+/// Digest of a recorded oversize file leaf.
+fn oversize_digest(size: u64) -> blake3::Hash {
+    let mut h = node_hasher(b'B');
+    h.update(&size.to_le_bytes());
+    h.finalize()
+}
+
+/// Digest of a recorded special file leaf.
+fn special_digest(file_type: u8) -> blake3::Hash {
+    let mut h = node_hasher(b'S');
+    h.update(&[file_type]);
+    h.finalize()
+}
+
+/// Collects leaves as they are framed and assembles the tree.
+///
+/// Entries arrive in canonical (depth-first) order, so a leaf's parent is
+/// always the last directory along its path.
+pub(crate) struct Builder {
+    root: TreeNode,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            root: TreeNode::new(Vec::new(), NodeKind::Dir),
+        }
+    }
+}
+
+impl Builder {
+    /// Add a file with the given content digest and metadata block.
+    pub(crate) fn file(&mut self, rel: &[u8], content: &blake3::Hash, metadata: &[u8]) {
+        let node = self.leaf(rel, NodeKind::File, file_digest(content, metadata));
+        node.metadata = metadata.to_vec();
+    }
+
+    /// Add a recorded symlink.
+    pub(crate) fn link(&mut self, rel: &[u8], target: &[u8]) {
+        self.leaf(rel, NodeKind::Symlink, link_digest(target));
+    }
+
+    /// Add an oversize file recorded by its size.
+    pub(crate) fn oversize(&mut self, rel: &[u8], size: u64) {
+        self.leaf(rel, NodeKind::Oversize, oversize_digest(size));
+    }
+
+    /// Add a recorded special file.
+    pub(crate) fn special(&mut self, rel: &[u8], file_type: u8) {
+        self.leaf(rel, NodeKind::Special, special_digest(file_type));
+    }
+
+    /// Add a leaf at the framed path `rel` and return it.
+    fn leaf(&mut self, rel: &[u8], kind: NodeKind, digest: blake3::Hash) -> &mut TreeNode {
+        let mut parts = rel.split(|&b| b == b'/').peekable();
+        let mut dir = &mut self.root;
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                let mut node = TreeNode::new(part.to_vec(), kind);
+                node.set_digest(digest);
+                dir.children.push(node);
+                return dir.children.last_mut().expect("just pushed");
+            }
+            let reuse = dir
+                .children
+                .last()
+                .is_some_and(|n| n.kind == NodeKind::Dir && n.raw_name == part);
+            if !reuse {
+                dir.children
+                    .push(TreeNode::new(part.to_vec(), NodeKind::Dir));
+            }
+            dir = dir.children.last_mut().expect("just pushed");
+        }
+        unreachable!("split yields at least one part")
+    }
+
+    pub(crate) fn finish(mut self) -> TreeNode {
+        self.root.seal();
+        self.root
+    }
+}
+
+/// Compact evidence that one file belongs to a Merkle root
+/// ([`TreeNode::prove`]).
+///
+/// Holds the file's metadata block plus, for each directory from the root
+/// down, the names and digests of the file's path siblings. Verifying it needs
+/// nothing but the published root and the file's content digest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InclusionProof {
+    metadata: Vec<u8>,
+    levels: Vec<ProofLevel>,
+}
+
+/// One directory on the proven path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ProofLevel {
+    before: Vec<(Vec<u8>, [u8; 32])>,
+    name: Vec<u8>,
+    after: Vec<(Vec<u8>, [u8; 32])>,
+}
+
+/// Leading bytes of [`InclusionProof::to_bytes`].
+const PROOF_MAGIC: &[u8] = b"get_dir_hash-proof-v1\0";
+
+impl InclusionProof {
+    /// Relative path of the proven file, as framed (names that are not valid
+    /// UTF-8 are shown lossily).
+    pub fn path(&self) -> String {
+        let parts: Vec<_> = self
+            .levels
+            .iter()
+            .map(|l| String::from_utf8_lossy(&l.name))
+            .collect();
+        parts.join("/")
+    }
+
+    /// Whether a file whose contents hash to `content_hex` (plain BLAKE3,
+    /// as in a manifest) at [`InclusionProof::path`] is part of the tree
+    /// with Merkle root `root_hex`.
+    pub fn verify(&self, root_hex: &str, content_hex: &str) -> bool {
+        let (Ok(root), Ok(content)) = (
+            blake3::Hash::from_hex(root_hex),
+            blake3::Hash::from_hex(content_hex),
+        ) else {
+            return false;
+        };
+        let mut digest = file_digest(&content, &self.metadata);
+        for level in self.levels.iter().rev() {
+            let mut h = node_hasher(b'D');
+            let mut child = |name: &[u8], d: &[u8; 32]| {
+                h.update(&(name.len() as u64).to_le_bytes());
+                h.update(name);
+                h.update(d);
+            };
+            for (name, d) in &level.before {
+                child(name, d);
+            }
+            child(&level.name, digest.as_bytes());
+            for (name, d) in &level.after {
+                child(name, d);
+            }
+            digest = h.finalize();
+        }
+        !self.levels.is_empty() && digest == root
+    }
+
+    /// Serialize the proof for transport; see [`InclusionProof::from_bytes`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = PROOF_MAGIC.to_vec();
+        put_field(&mut out, &self.metadata);
+        put_u64(&mut out, self.levels.len() as u64);
+        for level in &self.levels {
+            for side in [&level.before, &level.after] {
+                put_u64(&mut out, side.len() as u64);
+                for (name, d) in side {
+                    put_field(&mut out, name);
+                    out.extend_from_slice(d);
+                }
+            }
+            put_field(&mut out, &level.name);
+        }
+        out
+    }
+
+    /// Parse a proof written by [`InclusionProof::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<InclusionProof> {
+        let mut r = Reader::new(bytes, PROOF_MAGIC, "inclusion proof")?;
+        let metadata = r.field()?.to_vec();
+        let mut levels = Vec::new();
+        for _ in 0..r.u64()? {
+            let mut sides = [Vec::new(), Vec::new()];
+            for side in &mut sides {
+                for _ in 0..r.u64()? {
+                    let name = r.field()?.to_vec();
+                    side.push((name, r.array()?));
+                }
+            }
+            let [before, after] = sides;
+            let name = r.field()?.to_vec();
+            levels.push(ProofLevel {
+                before,
+                name,
+                after,
+            });
+        }
+        r.finish()?;
+        Ok(InclusionProof { metadata, levels })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{InclusionProof, Options, get_dir_hash_tree};
+    use std::fs;
+    use std::io;
+
+    /// A tree with a nested file, plus the Merkle root and the plain BLAKE3
+    /// digests of two of its files.
+    fn tree() -> (tempfile::TempDir, crate::TreeNode) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("pkg/src")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("z.txt"), "z").unwrap();
+        fs::write(root.join("pkg/README"), "readme").unwrap();
+        fs::write(root.join("pkg/src/lib.rs"), "fn main() {}").unwrap();
+        fs::write(root.join("pkg/src/util.rs"), "").unwrap();
+        let tree = get_dir_hash_tree(root, &Options::default()).unwrap();
+        (dir, tree.root)
+    }
+
+    fn content(bytes: &str) -> String {
+        blake3::hash(bytes.as_bytes()).to_hex().to_string()
+    }
+
+    #[test]
+    fn nested_file_verifies() {
+        let (_dir, root) = tree();
+        let proof = root.prove("pkg/src/lib.rs").unwrap();
+        assert_eq!(proof.path(), "pkg/src/lib.rs");
+        assert!(proof.verify(&root.hex, &content("fn main() {}")));
+    }
+
+    #[test]
+    fn tampering_fails_verification() {
+        let (_dir, root) = tree();
+        let proof = root.prove("pkg/src/lib.rs").unwrap();
+        // Wrong content.
+        assert!(!proof.verify(&root.hex, &content("fn main() { evil() }")));
+        // Wrong root.
+        let other = root.prove("a.txt").unwrap().to_bytes();
+        assert!(!proof.verify(&content("not a root"), &content("fn main() {}")));
+        assert!(!proof.verify("not hex", &content("fn main() {}")));
+        // Swapped siblings at the top level (`a.txt` before, `z.txt` after).
+        let mut swapped = proof.clone();
+        let top = &mut swapped.levels[0];
+        std::mem::swap(&mut top.before[0], &mut top.after[0]);
+        assert!(!swapped.verify(&root.hex, &content("fn main() {}")));
+        // A proof for another path does not vouch for this file.
+        let other = InclusionProof::from_bytes(&other).unwrap();
+        assert!(!other.verify(&root.hex, &content("fn main() {}")));
+        assert!(other.verify(&root.hex, &content("a")));
+        assert!(root.prove("pkg/missing.rs").is_none());
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let (_dir, root) = tree();
+        for path in ["a.txt", "pkg/README", "pkg/src/util.rs"] {
+            let proof = root.prove(path).unwrap();
+            let bytes = proof.to_bytes();
+            assert_eq!(InclusionProof::from_bytes(&bytes).unwrap(), proof);
+        }
+    }
+
+    #[test]
+    fn malformed_bytes_are_rejected() {
+        let (_dir, root) = tree();
+        let bytes = root.prove("pkg/src/lib.rs").unwrap().to_bytes();
+        for len in [0, 5, bytes.len() / 2, bytes.len() - 1] {
+            let e = InclusionProof::from_bytes(&bytes[..len]).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "truncated to {len}");
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        let e = InclusionProof::from_bytes(&trailing).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let mut version = bytes;
+        let at = b"get_dir_hash-proof-v".len();
+        version[at] = b'9';
+        let e = InclusionProof::from_bytes(&version).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,91 @@
+//! CRLF to LF translation for [`crate::Options::normalize_newlines`].
+
+use std::io::{self, Read};
+
+/// Translates a file read in pieces. A `\r` at the end of one piece is held
+/// back until the next shows whether a `\n` follows it.
+#[derive(Default)]
+pub(crate) struct Newlines {
+    /// A `\r` ended the previous piece.
+    cr: bool,
+}
+
+impl Newlines {
+    /// Pass `buf` to `out`, each `\r\n` as `\n`, in one or more slices.
+    pub(crate) fn feed(&mut self, buf: &[u8], out: &mut impl FnMut(&[u8])) {
+        let Some(&first) = buf.first() else {
+            return;
+        };
+        if std::mem::take(&mut self.cr) && first != b'\n' {
+            out(b"\r");
+        }
+        let mut start = 0;
+        for (i, pair) in buf.windows(2).enumerate() {
+            if pair == b"\r\n" {
+                out(&buf[start..i]);
+                start = i + 1;
+            }
+        }
+        match buf[start..].strip_suffix(b"\r") {
+            Some(rest) => {
+                out(rest);
+                self.cr = true;
+            }
+            None => out(&buf[start..]),
+        }
+    }
+
+    /// Pass on a `\r` held back at the end of the file.
+    pub(crate) fn finish(self, out: &mut impl FnMut(&[u8])) {
+        if self.cr {
+            out(b"\r");
+        }
+    }
+}
+
+/// Copy `reader` into `hasher` with CRLF read as LF; returns bytes read.
+pub(crate) fn copy(reader: &mut impl Read, hasher: &mut blake3::Hasher) -> io::Result<u64> {
+    let mut newlines = Newlines::default();
+    let mut update = |bytes: &[u8]| {
+        hasher.update(bytes);
+    };
+    let mut buf = [0u8; 64 * 1024];
+    let mut total = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        newlines.feed(&buf[..n], &mut update);
+        total += n as u64;
+    }
+    newlines.finish(&mut update);
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `input` translated when read `piece` bytes at a time.
+    fn translate(input: &[u8], piece: usize) -> Vec<u8> {
+        let mut newlines = Newlines::default();
+        let mut out = Vec::new();
+        let mut push = |bytes: &[u8]| out.extend_from_slice(bytes);
+        for chunk in input.chunks(piece) {
+            newlines.feed(chunk, &mut push);
+        }
+        newlines.finish(&mut push);
+        out
+    }
+
+    #[test]
+    fn crlf_becomes_lf_across_piece_boundaries() {
+        let input = b"a\r\nb\r\r\n\rc\n\r";
+        for piece in 1..=input.len() {
+            assert_eq!(translate(input, piece), b"a\nb\r\n\rc\n\r", "{piece}");
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! [`TreeSource`] over an [`object_store`] backend ([`ObjectStoreSource`],
+//! `object-store` feature): a bucket prefix on S3, GCS or Azure, or a local
+//! directory through [`LocalFileSystem`](object_store::local::LocalFileSystem).
+//!
+//! ```no_run
+//! use get_dir_hash::{ObjectStoreSource, Options, get_source_hash};
+//! use object_store::local::LocalFileSystem;
+//! use std::sync::Arc;
+//!
+//! let store = Arc::new(LocalFileSystem::new_with_prefix("/srv")?);
+//! let source = ObjectStoreSource::new(store, Some("data".into()))?;
+//! let digest = get_source_hash(&source, &Options::default())?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! The cloud backends are enabled through `object_store`'s own features
+//! (`aws`, `gcp`, `azure`).
+
+use crate::source::{SourceFile, TreeSource};
+use futures_util::TryStreamExt;
+use futures_util::io::{AsyncRead, AsyncReadExt};
+use object_store::path::Path;
+use object_store::{ObjectStore, ObjectStoreExt};
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+/// The objects below a prefix of an [`ObjectStore`], as a [`TreeSource`].
+///
+/// Object names are split at `/` into directories and file names, as the
+/// store's [`Path`] does. Empty objects that share their name with a
+/// "directory" (the markers some tools create for folders) are left out.
+///
+/// Requests run on a runtime of the source's own and block the calling
+/// thread, so [`get_source_hash`](crate::get_source_hash) must not be
+/// called with this source from within an async runtime; use
+/// `spawn_blocking` there.
+pub struct ObjectStoreSource {
+    store: Arc<dyn ObjectStore>,
+    prefix: Option<Path>,
+    runtime: Runtime,
+}
+
+impl ObjectStoreSource {
+    /// The objects of `store` below `prefix`, or all of them for `None`.
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: Option<Path>) -> io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(ObjectStoreSource {
+            store,
+            prefix,
+            runtime,
+        })
+    }
+}
+
+impl TreeSource for ObjectStoreSource {
+    fn files(&self) -> io::Result<Vec<SourceFile>> {
+        let objects: Vec<_> = self
+            .runtime
+            .block_on(self.store.list(self.prefix.as_ref()).try_collect())
+            .map_err(io_error)?;
+        let mut files: Vec<SourceFile> = (objects.iter())
+            .filter_map(|object| {
+                let parts = match &self.prefix {
+                    Some(prefix) => object.location.prefix_match(prefix)?.collect(),
+                    None => object.location.parts().collect::<Vec<_>>(),
+                };
+                let parts: Vec<&str> = parts.iter().map(|part| part.as_ref()).collect();
+                Some(SourceFile {
+                    path: parts.join("/"),
+                    size: object.size,
+                })
+            })
+            .filter(|file| !file.path.is_empty())
+            .collect();
+        let mut dirs: Vec<&str> = (files.iter())
+            .flat_map(|file| {
+                file.path
+                    .match_indices('/')
+                    .map(|(end, _)| &file.path[..end])
+            })
+            .collect();
+        dirs.sort_unstable();
+        dirs.dedup();
+        let markers: Vec<String> = (files.iter())
+            .filter(|file| file.size == 0 && dirs.binary_search(&file.path.as_str()).is_ok())
+            .map(|file| file.path.clone())
+            .collect();
+        files.retain(|file| file.size > 0 || !markers.contains(&file.path));
+        Ok(files)
+    }
+
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read + '_>> {
+        let location = match &self.prefix {
+            Some(prefix) => Path::parse(format!("{prefix}/{path}")),
+            None => Path::parse(path),
+        }
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let result = self
+            .runtime
+            .block_on(self.store.get(&location))
+            .map_err(io_error)?;
+        let body = result.into_stream().map_err(io_error).into_async_read();
+        Ok(Box::new(Body {
+            runtime: &self.runtime,
+            body: Box::pin(body),
+        }))
+    }
+}
+
+/// The content of one object, read by blocking on the runtime.
+struct Body<'a> {
+    runtime: &'a Runtime,
+    body: Pin<Box<dyn AsyncRead + Send>>,
+}
+
+impl Read for Body<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.runtime.block_on(self.body.read(buf))
+    }
+}
+
+/// `object_store`'s error as an `io::Error`, keeping "not found".
+fn io_error(e: object_store::Error) -> io::Error {
+    match e {
+        object_store::Error::NotFound { .. } => io::Error::new(io::ErrorKind::NotFound, e),
+        e => io::Error::other(e),
+    }
+}
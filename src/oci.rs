@@ -0,0 +1,337 @@
+//! The digest an OCI image layer of a directory would have
+//! ([`oci_layer_digest`], [`write_oci_layer`]).
+//!
+//! The layer is the uncompressed tar stream of the files a walk hashes,
+//! written so that the same tree always gives the same bytes:
+//!
+//! - entries sorted by name, component by component (`tar --sort=name`),
+//!   each directory before its contents, without a `./` prefix or an entry
+//!   for the root;
+//! - a directory entry for every directory that holds a hashed file; empty
+//!   and fully ignored directories are left out, as in every digest;
+//! - owner and group `0`, without names (`--numeric-owner`);
+//! - mtimes in whole seconds, clamped to [`Options::clamp_mtime`], or `0`
+//!   when no clamp is set;
+//! - permission bits as on disk (Unix), or `0755` for directories and
+//!   `0644` (`0444` when read-only) for files elsewhere;
+//! - ustar headers, long paths split into prefix and name; a PAX extended
+//!   header (`PaxHeaders.0/NAME`, as Go's `archive/tar` writes it) only for
+//!   a path, link target or size that does not fit;
+//! - two zero blocks at the end, without padding to a tar record.
+//!
+//! The SHA-256 of that stream is the layer's DiffID. Where no PAX header is
+//! needed, the stream is byte for byte the one `tar --format=ustar
+//! --sort=name --numeric-owner --owner=0 --group=0 --mtime=@0 -b 1` writes
+//! for the same entries. Hard links are stored as separate copies.
+
+use crate::algo::{Algorithm, Hasher};
+use crate::ignore::IgnoreRules;
+use crate::throttle::Throttle;
+use crate::{
+    Entries, Entry, EntryKind, FileOp, Options, base_prefix, check_cancel, hex_lower, walk_root,
+    with_deadline,
+};
+use std::collections::HashSet;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::{Instant, UNIX_EPOCH};
+
+/// Size of a tar block.
+const BLOCK: usize = 512;
+
+/// Largest size a ustar header holds (11 octal digits); larger files have
+/// theirs in a PAX header.
+const MAX_SIZE: u64 = 0o77777777777;
+
+/// The OCI layer DiffID of `root`: `sha256:` and the lowercase hex SHA-256
+/// of the tar stream [`write_oci_layer`] writes. Requires the `sha2`
+/// feature.
+pub fn oci_layer_digest(root: &Path, opts: &Options) -> io::Result<String> {
+    write_oci_layer(root, opts, &mut io::sink())
+}
+
+/// Write `root` to `out` as an uncompressed, reproducible tar layer and
+/// return its DiffID (see [`oci_layer_digest`]).
+///
+/// The layer holds the files [`get_dir_hash`](crate::get_dir_hash) would
+/// hash with `opts`, under the same relative paths (below
+/// [`Options::path_base`] if set), but with their names as on disk:
+/// [`Options::case_sensitive_paths`] only changes the order of a walk.
+/// Dangling symlinks recorded under [`Options::broken_symlinks`] become
+/// symlink entries. Special files and oversize files recorded without their
+/// contents cannot be part of a layer and are `InvalidInput`; a file whose
+/// size changes while it is read is `InvalidData`.
+pub fn write_oci_layer(root: &Path, opts: &Options, out: &mut dyn Write) -> io::Result<String> {
+    let opts = &*with_deadline(opts, Instant::now());
+    let mut hasher = Hasher::new(Algorithm::Sha256)?;
+    let root = walk_root(root, opts)?;
+    let rules = IgnoreRules::build(&root, opts)?;
+    let prefix = base_prefix(&root, opts)?;
+    let mut entries = (Entries::new(&root, opts, &rules).relative_to(prefix))
+        .map(|entry| {
+            let entry = entry?;
+            let name = entry
+                .raw
+                .clone()
+                .unwrap_or_else(|| entry.rel.clone().into_bytes());
+            Ok((name, entry))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by(|(a, _), (b, _)| a.split(|&c| c == b'/').cmp(b.split(|&c| c == b'/')));
+
+    let mut layer = Layer {
+        out,
+        hasher: &mut hasher,
+    };
+    let mut throttle = Throttle::new(opts.max_read_bytes_per_sec)?;
+    let mut dirs = HashSet::new();
+    for (name, entry) in &entries {
+        check_cancel(opts)?;
+        // The directories above the entry, outermost first, each with its
+        // path on disk.
+        let ends: Vec<usize> = (name.iter().enumerate())
+            .filter(|&(_, &b)| b == b'/')
+            .map(|(i, _)| i)
+            .collect();
+        for (depth, &end) in ends.iter().enumerate() {
+            let dir = &name[..end];
+            if !dirs.insert(dir) {
+                continue;
+            }
+            let path = (entry.path.ancestors())
+                .nth(ends.len() - depth)
+                .unwrap_or(&entry.path);
+            let md = fs::metadata(path)?;
+            let mut dir = dir.to_vec();
+            dir.push(b'/');
+            layer.header(&dir, &Header::dir(&md, opts))?;
+        }
+        write_entry(&mut layer, name, entry, &mut throttle, opts)?;
+    }
+    layer.put(&[0; 2 * BLOCK])?;
+    Ok(format!("sha256:{}", hex_lower(&hasher.finalize())))
+}
+
+/// Write the header and contents of one walk entry.
+fn write_entry(
+    layer: &mut Layer<'_>,
+    name: &[u8],
+    entry: &Entry,
+    throttle: &mut Throttle,
+    opts: &Options,
+) -> io::Result<()> {
+    let unsupported = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{what} cannot be stored in a layer: {}", entry.rel),
+        )
+    };
+    match &entry.kind {
+        EntryKind::File => {
+            let file = File::open(&entry.path).map_err(FileOp::Open.on(&entry.path))?;
+            let md = (file.metadata()).map_err(FileOp::Metadata.on(&entry.path))?;
+            let size = md.len();
+            layer.header(name, &Header::file(&md, opts))?;
+            let copied = io::copy(&mut throttle.reader(file).take(size), layer)?;
+            if copied != size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("file changed while it was read: {}", entry.rel),
+                ));
+            }
+            let pad = (BLOCK - (size % BLOCK as u64) as usize) % BLOCK;
+            layer.put(&[0; BLOCK][..pad])
+        }
+        EntryKind::BrokenSymlink(target) => {
+            let md = fs::symlink_metadata(&entry.path)?;
+            layer.header(name, &Header::symlink(&md, target.as_bytes(), opts))
+        }
+        EntryKind::Special(_) => Err(unsupported("special file")),
+        EntryKind::Oversize(_) => Err(unsupported("oversize file")),
+    }
+}
+
+/// The tar stream on its way to the caller's writer and the hasher.
+struct Layer<'a> {
+    out: &'a mut dyn Write,
+    hasher: &'a mut Hasher,
+}
+
+impl Layer<'_> {
+    fn put(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.hasher.update(bytes);
+        self.out.write_all(bytes)
+    }
+
+    /// Write the header of the entry `name`, preceded by a PAX extended
+    /// header if the name, link target or size does not fit.
+    fn header(&mut self, name: &[u8], header: &Header<'_>) -> io::Result<()> {
+        let split = split_name(name);
+        let mut records = Vec::new();
+        if split.is_none() {
+            records.extend(pax_record("path", name));
+        }
+        if header.link.len() > 100 {
+            records.extend(pax_record("linkpath", header.link));
+        }
+        if header.size > MAX_SIZE {
+            records.extend(pax_record("size", header.size.to_string().as_bytes()));
+        }
+        if !records.is_empty() {
+            let trimmed = name.strip_suffix(b"/").unwrap_or(name);
+            let (dir, file) = match trimmed.iter().rposition(|&b| b == b'/') {
+                Some(i) => (&trimmed[..=i], &trimmed[i + 1..]),
+                None => (&b""[..], trimmed),
+            };
+            let mut pax_name = [dir, b"PaxHeaders.0/", file].concat();
+            pax_name.truncate(100);
+            let pax = Header {
+                kind: b'x',
+                mode: 0o644,
+                size: records.len() as u64,
+                mtime: header.mtime,
+                link: b"",
+            };
+            self.put(&pax.block(&pax_name, b""))?;
+            self.put(&records)?;
+            let pad = (BLOCK - records.len() % BLOCK) % BLOCK;
+            self.put(&[0; BLOCK][..pad])?;
+        }
+        let (prefix, short) = split.unwrap_or((b"", &name[..name.len().min(100)]));
+        self.put(&header.block(short, prefix))
+    }
+}
+
+impl Write for Layer<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.put(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// The fields of a ustar header that vary between entries.
+struct Header<'a> {
+    /// Type flag: `0` file, `2` symlink, `5` directory, `x` PAX header.
+    kind: u8,
+    mode: u32,
+    size: u64,
+    mtime: u64,
+    link: &'a [u8],
+}
+
+impl<'a> Header<'a> {
+    fn file(md: &Metadata, opts: &Options) -> Self {
+        Header {
+            kind: b'0',
+            mode: permissions(md, 0o644),
+            size: md.len(),
+            mtime: mtime(md, opts),
+            link: b"",
+        }
+    }
+
+    fn dir(md: &Metadata, opts: &Options) -> Self {
+        Header {
+            kind: b'5',
+            mode: permissions(md, 0o755),
+            size: 0,
+            mtime: mtime(md, opts),
+            link: b"",
+        }
+    }
+
+    fn symlink(md: &Metadata, target: &'a [u8], opts: &Options) -> Self {
+        Header {
+            kind: b'2',
+            mode: permissions(md, 0o777),
+            size: 0,
+            mtime: mtime(md, opts),
+            link: target,
+        }
+    }
+
+    /// The 512-byte header block, with owner and group `0` and no names.
+    fn block(&self, name: &[u8], prefix: &[u8]) -> [u8; BLOCK] {
+        let mut block = [0; BLOCK];
+        let mut put = |at: usize, bytes: &[u8]| block[at..at + bytes.len()].copy_from_slice(bytes);
+        put(0, name);
+        put(100, &octal(u64::from(self.mode), 8));
+        put(108, &octal(0, 8));
+        put(116, &octal(0, 8));
+        put(
+            124,
+            &octal(if self.size > MAX_SIZE { 0 } else { self.size }, 12),
+        );
+        put(136, &octal(self.mtime, 12));
+        put(148, b"        ");
+        put(156, &[self.kind]);
+        put(157, &self.link[..self.link.len().min(100)]);
+        put(257, b"ustar\x0000");
+        put(329, &octal(0, 8));
+        put(337, &octal(0, 8));
+        put(345, prefix);
+        let sum: u64 = block.iter().map(|&b| u64::from(b)).sum();
+        block[148..156].copy_from_slice(format!("{sum:06o}\0 ").as_bytes());
+        block
+    }
+}
+
+/// `value` as a NUL-terminated, zero-padded octal field of `width` bytes.
+fn octal(value: u64, width: usize) -> Vec<u8> {
+    format!("{value:0w$o}\0", w = width - 1).into_bytes()
+}
+
+/// Split `name` into a ustar prefix and name at a `/`, as `tar` does, or
+/// `None` if it does not fit the two fields.
+fn split_name(name: &[u8]) -> Option<(&[u8], &[u8])> {
+    if name.len() <= 100 {
+        return Some((b"", name));
+    }
+    let end = (name.len() - 1).min(156);
+    let i = name[..end].iter().rposition(|&b| b == b'/')?;
+    let (prefix, rest) = (&name[..i], &name[i + 1..]);
+    (prefix.len() <= 155 && !rest.is_empty() && rest.len() <= 100).then_some((prefix, rest))
+}
+
+/// One `LEN KEY=VALUE\n` record of a PAX extended header; `LEN` counts the
+/// whole record, itself included.
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + rest.to_string().len();
+    if len.to_string().len() + rest != len {
+        len += 1;
+    }
+    let mut record = format!("{len} {key}=").into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+/// Seconds since the epoch of the entry's mtime, clamped to
+/// [`Options::clamp_mtime`]; `0` without a clamp.
+fn mtime(md: &Metadata, opts: &Options) -> u64 {
+    let Some(clamp) = opts.clamp_mtime else {
+        return 0;
+    };
+    let mtime = md.modified().map_or(clamp, |mtime| mtime.min(clamp));
+    (mtime.duration_since(UNIX_EPOCH)).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(unix)]
+fn permissions(md: &Metadata, _default: u32) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    md.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn permissions(md: &Metadata, default: u32) -> u32 {
+    match md.permissions().readonly() && !md.is_dir() && !md.is_symlink() {
+        true => default & 0o555,
+        false => default,
+    }
+}
@@ -0,0 +1,125 @@
+//! File ownership for the metadata frame.
+//!
+//! Only Unix has a `uid`/`gid` pair; elsewhere nothing is framed. Names are
+//! looked up through the system user database (`getpwuid_r`/`getgrgid_r`)
+//! once per id and run; ids without an entry fall back to their decimal form
+//! so the frame is always defined.
+
+use crate::Ownership;
+#[cfg(unix)]
+use std::collections::HashMap;
+use std::fs::Metadata;
+
+/// User and group names resolved so far in this run.
+#[derive(Default)]
+pub(crate) struct NameCache {
+    #[cfg(unix)]
+    users: HashMap<u32, String>,
+    #[cfg(unix)]
+    groups: HashMap<u32, String>,
+}
+
+/// Append the owner record for `md` (if any) to `out`.
+///
+/// Ids are framed as `\0U\0` + uid + gid (u32 LE); names as
+/// `\0N\0` + user + `\0` + group + `\0`.
+#[cfg(unix)]
+pub(crate) fn feed_owner(out: &mut Vec<u8>, md: &Metadata, mode: Ownership, names: &mut NameCache) {
+    use std::os::unix::fs::MetadataExt;
+    let (uid, gid) = (md.uid(), md.gid());
+    match mode {
+        Ownership::Omit => {}
+        Ownership::Ids => {
+            out.extend_from_slice(b"\0U\0");
+            out.extend_from_slice(&uid.to_le_bytes());
+            out.extend_from_slice(&gid.to_le_bytes());
+        }
+        Ownership::Names => {
+            out.extend_from_slice(b"\0N\0");
+            let user = names
+                .users
+                .entry(uid)
+                .or_insert_with(|| lookup::user(uid).unwrap_or_else(|| uid.to_string()));
+            out.extend_from_slice(user.as_bytes());
+            out.extend_from_slice(b"\0");
+            let group = names
+                .groups
+                .entry(gid)
+                .or_insert_with(|| lookup::group(gid).unwrap_or_else(|| gid.to_string()));
+            out.extend_from_slice(group.as_bytes());
+            out.extend_from_slice(b"\0");
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn feed_owner(
+    _out: &mut Vec<u8>,
+    _md: &Metadata,
+    _mode: Ownership,
+    _names: &mut NameCache,
+) {
+}
+
+#[cfg(unix)]
+mod lookup {
+    //! Thin wrappers over the reentrant user database calls.
+
+    use std::ffi::{CStr, c_char};
+    use std::{mem, ptr};
+
+    /// Give up growing the lookup buffer past this size.
+    const MAX_BUF: usize = 1 << 20;
+
+    pub(super) fn user(uid: u32) -> Option<String> {
+        with_buffer(|buf| {
+            // SAFETY: `passwd` is plain old data; getpwuid_r fills it and
+            // points its strings into `buf`, which outlives the read below.
+            let mut pwd: libc::passwd = unsafe { mem::zeroed() };
+            let mut result = ptr::null_mut();
+            let rc = unsafe {
+                libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result)
+            };
+            (
+                rc,
+                (!result.is_null()).then(|| unsafe { name(pwd.pw_name) }),
+            )
+        })
+    }
+
+    pub(super) fn group(gid: u32) -> Option<String> {
+        with_buffer(|buf| {
+            // SAFETY: as in `user`, with `group` and getgrgid_r.
+            let mut grp: libc::group = unsafe { mem::zeroed() };
+            let mut result = ptr::null_mut();
+            let rc = unsafe {
+                libc::getgrgid_r(gid, &mut grp, buf.as_mut_ptr(), buf.len(), &mut result)
+            };
+            (
+                rc,
+                (!result.is_null()).then(|| unsafe { name(grp.gr_name) }),
+            )
+        })
+    }
+
+    /// Run `f` with a scratch buffer, doubling it while the call reports
+    /// `ERANGE`.
+    fn with_buffer(
+        mut f: impl FnMut(&mut [c_char]) -> (libc::c_int, Option<String>),
+    ) -> Option<String> {
+        let mut buf = vec![0 as c_char; 1024];
+        loop {
+            match f(&mut buf) {
+                (libc::ERANGE, _) if buf.len() < MAX_BUF => buf.resize(buf.len() * 2, 0),
+                (0, found) => return found,
+                _ => return None,
+            }
+        }
+    }
+
+    /// # Safety
+    /// `p` must point to a NUL-terminated string.
+    unsafe fn name(p: *const c_char) -> String {
+        unsafe { CStr::from_ptr(p) }.to_string_lossy().into_owned()
+    }
+}
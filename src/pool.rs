@@ -0,0 +1,191 @@
+//! Thread pools for reading and hashing file contents.
+//!
+//! Two independent knobs, since storage and CPUs scale differently:
+//!
+//! - [`HashPool`] ([`Options::threads`]): the threads that hash a single large
+//!   file in parallel (`rayon` feature, `parallel_hash_threshold`).
+//! - [`Readahead`] ([`Options::io_concurrency`]): how many files are opened
+//!   and read at once. Upcoming files are handed to that many reader threads
+//!   while the hashing loop consumes their digests strictly in walk order, so
+//!   the record stream (and the digest) is the same as a sequential run.
+
+use crate::chunk::{self, Chunks};
+use crate::throttle::Throttle;
+use crate::{Entry, EntryKind, Options};
+use std::collections::VecDeque;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+
+/// Content digest, bytes read and chunks of one file.
+pub(crate) type Hashed = (blake3::Hash, u64, Chunks);
+
+/// Pool for multi-threaded hashing of large files. Without a dedicated pool
+/// (or without the `rayon` feature), rayon's global pool is used.
+#[derive(Clone, Default)]
+pub(crate) struct HashPool {
+    #[cfg(feature = "rayon")]
+    pool: Option<Arc<rayon_core::ThreadPool>>,
+}
+
+impl HashPool {
+    /// A pool of `threads` threads; `0` means rayon's global pool.
+    pub(crate) fn new(threads: usize) -> io::Result<HashPool> {
+        #[cfg(feature = "rayon")]
+        {
+            let pool = match threads {
+                0 => None,
+                n => Some(Arc::new(
+                    rayon_core::ThreadPoolBuilder::new()
+                        .num_threads(n)
+                        .build()
+                        .map_err(io::Error::other)?,
+                )),
+            };
+            Ok(HashPool { pool })
+        }
+        #[cfg(not(feature = "rayon"))]
+        {
+            let _ = threads;
+            Ok(HashPool {})
+        }
+    }
+
+    /// Run `op` with this pool as the current rayon pool.
+    pub(crate) fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        #[cfg(feature = "rayon")]
+        if let Some(pool) = &self.pool {
+            return pool.install(op);
+        }
+        op()
+    }
+}
+
+/// Content of the file at `path`, hashed the way the sequential loop would.
+pub(crate) fn content(
+    path: &std::path::Path,
+    opts: &Options,
+    hash_pool: &HashPool,
+    throttle: &mut Throttle,
+) -> io::Result<Hashed> {
+    match opts.chunking {
+        Some(sizes) => chunk::hash_file(path, opts, sizes, &mut [], throttle),
+        None => {
+            let (digest, read) =
+                hash_pool.install(|| crate::hash_file(path, opts, &mut [], throttle))?;
+            Ok((digest, read, Chunks::new()))
+        }
+    }
+}
+
+type Job = (PathBuf, mpsc::Sender<io::Result<Hashed>>);
+
+/// Fixed-size pool of reader threads. Dropping it stops the workers once
+/// the queued jobs are done.
+struct Readers {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl Readers {
+    /// `None` if no thread could be started (e.g. on WASI); the loop then
+    /// reads every file itself.
+    fn new(threads: usize, opts: Arc<Options>, hash_pool: HashPool) -> Option<Readers> {
+        let (jobs, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        let mut started = 0;
+        for _ in 0..threads {
+            let rx = Arc::clone(&rx);
+            let opts = Arc::clone(&opts);
+            let hash_pool = hash_pool.clone();
+            let worker = thread::Builder::new().spawn(move || {
+                // Throttled runs never read ahead, so this one stays idle.
+                let mut throttle = Throttle::unlimited();
+                loop {
+                    let job = rx.lock().map(|rx| rx.recv());
+                    let Ok(Ok((path, reply))) = job else { break };
+                    // The loop may have stopped early; nobody to tell then.
+                    let _ = reply.send(content(&path, &opts, &hash_pool, &mut throttle));
+                }
+            });
+            started += usize::from(worker.is_ok());
+        }
+        (started > 0).then_some(Readers { jobs })
+    }
+}
+
+/// A file whose content is being read ahead.
+pub(crate) struct Pending(mpsc::Receiver<io::Result<Hashed>>);
+
+impl Pending {
+    pub(crate) fn wait(self) -> io::Result<Hashed> {
+        self.0
+            .recv()
+            .unwrap_or_else(|_| Err(io::Error::other("file reader thread exited")))
+    }
+}
+
+/// Entries in walk order, each file paired with its content being read in
+/// the background when [`Options::io_concurrency`] is above 1.
+pub(crate) struct Readahead<I> {
+    entries: I,
+    readers: Option<Readers>,
+    queue: VecDeque<(io::Result<Entry>, Option<Pending>)>,
+    depth: usize,
+}
+
+impl<I: Iterator<Item = io::Result<Entry>>> Readahead<I> {
+    /// Read ahead of `entries` with `opts.io_concurrency` threads, unless
+    /// `enabled` is false (a multi-digest, throttled or checkpointed run,
+    /// whose reads must stay in the loop).
+    pub(crate) fn new(entries: I, opts: &Options, hash_pool: &HashPool, enabled: bool) -> Self {
+        let threads = opts.io_concurrency;
+        let readers = (enabled && threads > 1)
+            .then(|| {
+                let opts = Options {
+                    progress: None,
+                    checkpoint: None,
+                    ..opts.clone()
+                };
+                Readers::new(threads, Arc::new(opts), hash_pool.clone())
+            })
+            .flatten();
+        Readahead {
+            entries,
+            readers,
+            queue: VecDeque::new(),
+            // Keep every reader busy while the loop frames earlier files.
+            depth: 2 * threads.max(1),
+        }
+    }
+}
+
+impl<I: Iterator<Item = io::Result<Entry>>> Iterator for Readahead<I> {
+    type Item = (io::Result<Entry>, Option<Pending>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(readers) = &self.readers else {
+            return self.entries.next().map(|entry| (entry, None));
+        };
+        while self.queue.len() < self.depth {
+            let Some(entry) = self.entries.next() else {
+                break;
+            };
+            let pending = match &entry {
+                Ok(Entry {
+                    path,
+                    kind: EntryKind::File,
+                    ..
+                }) => {
+                    let (tx, rx) = mpsc::channel();
+                    // If the workers are gone, `wait` reports it.
+                    let _ = readers.jobs.send((path.clone(), tx));
+                    Some(Pending(rx))
+                }
+                _ => None,
+            };
+            self.queue.push_back((entry, pending));
+        }
+        self.queue.pop_front()
+    }
+}
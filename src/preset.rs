@@ -0,0 +1,172 @@
+//! Named option bundles ([`Preset`]).
+
+use crate::{
+    CaseCollisionPolicy, NormalizationForm, Options, OversizePolicy, Policy, SymlinkLoopPolicy,
+};
+use std::str::FromStr;
+
+/// A named combination of settings, applied on top of existing [`Options`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Digests that survive moving the tree between operating systems and
+    /// file systems: case-insensitive NFC paths, CRLF line endings read as
+    /// LF, and no metadata.
+    Portable,
+    /// Fail instead of silently leaving anything out, and include metadata:
+    /// broken symlinks, special files, symlink loops, non-UTF-8 names, case
+    /// collisions, files over [`Options::max_file_size`], unreadable entries
+    /// and dropped ignore rules are all errors.
+    Strict,
+    /// Throughput over everything else: a structure-only digest (files
+    /// framed by path and size, never read) over a parallel listing. This
+    /// is not the default digest, and it misses edits that keep a file's
+    /// size. Should `structure_only` be turned back off, files are read
+    /// several at once, through memory maps and multi-threaded hashing of
+    /// large files where compiled in, and hard links only once.
+    Fast,
+}
+
+impl Preset {
+    /// Overwrite the settings this preset covers; everything else in `opts`
+    /// is kept.
+    pub fn apply(self, opts: Options) -> Options {
+        match self {
+            Preset::Portable => Options {
+                case_sensitive_paths: false,
+                unicode_normalization: Some(NormalizationForm::Nfc),
+                normalize_newlines: true,
+                include_metadata: false,
+                ..opts
+            },
+            Preset::Strict => Options {
+                include_metadata: true,
+                broken_symlinks: Policy::Error,
+                special_files: Policy::Error,
+                non_utf8_names: Policy::Error,
+                symlink_loops: SymlinkLoopPolicy::Error,
+                case_collisions: CaseCollisionPolicy::Error,
+                oversize_policy: OversizePolicy::Error,
+                fail_on_skip: true,
+                ..opts
+            },
+            Preset::Fast => Options {
+                structure_only: true,
+                walk_threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+                io_concurrency: std::thread::available_parallelism().map_or(1, |n| n.get()),
+                mmap_threshold: Some(16 << 20),
+                parallel_hash_threshold: Some(128 << 20),
+                dedup_hard_links: true,
+                ..opts
+            },
+        }
+    }
+}
+
+impl FromStr for Preset {
+    type Err = crate::ParseOptionError;
+
+    /// Parses `portable`, `strict` or `fast`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "portable" => Ok(Preset::Portable),
+            "strict" => Ok(Preset::Strict),
+            "fast" => Ok(Preset::Fast),
+            _ => Err(crate::ParseOptionError::new(s, "portable, strict or fast")),
+        }
+    }
+}
+
+impl Options {
+    /// Defaults with [`Preset::Portable`] applied.
+    pub fn portable() -> Options {
+        Preset::Portable.apply(Options::default())
+    }
+
+    /// Defaults with [`Preset::Strict`] applied.
+    pub fn strict() -> Options {
+        Preset::Strict.apply(Options::default())
+    }
+
+    /// Defaults with [`Preset::Fast`] applied.
+    pub fn fast() -> Options {
+        Preset::Fast.apply(Options::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Chunking, get_dir_hash, get_dir_hash_with_stats};
+    use std::fs;
+    use std::path::Path;
+
+    fn tree(root: &Path, text: &str) {
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("README"), text).unwrap();
+        fs::write(root.join("src/lib.rs"), text).unwrap();
+    }
+
+    #[test]
+    fn portable_reads_crlf_as_lf() {
+        let (unix, windows) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        tree(unix.path(), "one\ntwo\n\r");
+        tree(windows.path(), "one\r\ntwo\r\n\r");
+        let portable = Options::portable();
+        assert_eq!(
+            get_dir_hash(unix.path(), &portable).unwrap(),
+            get_dir_hash(windows.path(), &portable).unwrap()
+        );
+        assert_ne!(
+            get_dir_hash(unix.path(), &Options::default()).unwrap(),
+            get_dir_hash(windows.path(), &Options::default()).unwrap()
+        );
+
+        let chunked = Options {
+            chunking: Some(Chunking::default()),
+            ..portable
+        };
+        let err = get_dir_hash(unix.path(), &chunked).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn fast_frames_structure_without_reading() {
+        let dir = tempfile::tempdir().unwrap();
+        tree(dir.path(), "one\n");
+        let fast = Options::fast();
+        let before = get_dir_hash_with_stats(dir.path(), &fast).unwrap();
+        assert_eq!(before.stats.bytes_hashed, 0);
+        assert_ne!(
+            before.hex,
+            get_dir_hash(dir.path(), &Options::default()).unwrap()
+        );
+
+        // Same size: unnoticed. Another size or a new file: a new digest.
+        fs::write(dir.path().join("README"), "two\n").unwrap();
+        assert_eq!(get_dir_hash(dir.path(), &fast).unwrap(), before.hex);
+        fs::write(dir.path().join("README"), "three\n").unwrap();
+        let resized = get_dir_hash(dir.path(), &fast).unwrap();
+        assert_ne!(resized, before.hex);
+        fs::write(dir.path().join("src/main.rs"), "").unwrap();
+        assert_ne!(get_dir_hash(dir.path(), &fast).unwrap(), resized);
+    }
+
+    #[test]
+    fn strict_fails_instead_of_skipping() {
+        let dir = tempfile::tempdir().unwrap();
+        tree(dir.path(), "one\n");
+        fs::write(dir.path().join(".get_dir_hash_ignore"), "!README\n").unwrap();
+        let run = get_dir_hash_with_stats(dir.path(), &Options::default()).unwrap();
+        assert_eq!(run.stats.warnings.len(), 1);
+        let err = get_dir_hash(dir.path(), &Options::strict()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+        fs::remove_file(dir.path().join(".get_dir_hash_ignore")).unwrap();
+        let limited = Options {
+            max_file_size: Some(1),
+            ..Options::strict()
+        };
+        let err = get_dir_hash(dir.path(), &limited).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::FileTooLarge);
+    }
+}
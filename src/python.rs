@@ -0,0 +1,96 @@
+//! Python bindings (`python` feature): the `get_dir_hash` extension module.
+//!
+//! ```python
+//! import get_dir_hash
+//! get_dir_hash.hash_dir("data", ignore=["*.tmp", "cache/**"])
+//! ```
+//!
+//! Digests are identical to the Rust API's and the binary's. The GIL is
+//! released while hashing; I/O failures raise `OSError`, bad settings (a
+//! malformed config file, an invalid ignore pattern) `ValueError`.
+//! `pyproject.toml` builds with the `ffi` profile, which unwinds, so a
+//! panic raises `PanicException` rather than aborting.
+
+use crate::{FormatVersion, IgnoreFile, Options};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::io;
+use std::path::PathBuf;
+
+/// Hash the directory `path` and return the lowercase hex digest.
+///
+/// Options not given keep their defaults, or the values from the TOML
+/// `config` file when one is given.
+#[pyfunction]
+#[pyo3(signature = (
+    path,
+    *,
+    ignore = Vec::new(),
+    ignore_files = Vec::new(),
+    exclude_vcs = false,
+    follow_symlinks = false,
+    include_metadata = false,
+    format_version = None,
+    config = None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn hash_dir(
+    py: Python<'_>,
+    path: PathBuf,
+    ignore: Vec<String>,
+    ignore_files: Vec<PathBuf>,
+    exclude_vcs: bool,
+    follow_symlinks: bool,
+    include_metadata: bool,
+    format_version: Option<u8>,
+    config: Option<PathBuf>,
+) -> PyResult<String> {
+    let base = match config {
+        Some(config) => {
+            Options::from_config_file(config).map_err(|e| to_py(e, io::ErrorKind::InvalidData))?
+        }
+        None => Options::default(),
+    };
+    let format_version = match format_version {
+        None => base.format_version,
+        Some(1) => FormatVersion::V1,
+        Some(2) => FormatVersion::V2,
+        Some(v) => {
+            return Err(PyValueError::new_err(format!(
+                "unknown format_version {v} (expected 1 or 2)"
+            )));
+        }
+    };
+    let mut ignore_patterns = base.ignore_patterns;
+    ignore_patterns.extend(ignore);
+    let mut ignore_files_all = base.ignore_files;
+    ignore_files_all.extend(ignore_files.into_iter().map(IgnoreFile::from));
+    let opts = Options {
+        format_version,
+        ignore_patterns,
+        ignore_files: ignore_files_all,
+        exclude_vcs_dirs: base.exclude_vcs_dirs || exclude_vcs,
+        follow_symlinks: base.follow_symlinks || follow_symlinks,
+        include_metadata: base.include_metadata || include_metadata,
+        ..base
+    };
+    py.detach(|| crate::get_dir_hash(&path, &opts))
+        .map_err(|e| to_py(e, io::ErrorKind::InvalidInput))
+}
+
+/// `ValueError` for an error of kind `settings`, the one bad settings
+/// produce at that step (`InvalidData` from a config file, `InvalidInput`
+/// from options such as ignore patterns); `OSError` for the rest.
+fn to_py(e: io::Error, settings: io::ErrorKind) -> PyErr {
+    match e.kind() == settings {
+        true => PyValueError::new_err(e.to_string()),
+        false => e.into(),
+    }
+}
+
+#[pymodule]
+fn get_dir_hash(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(hash_dir, m)?)?;
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    Ok(())
+}
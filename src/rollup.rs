@@ -0,0 +1,118 @@
+//! Flat digests of the subdirectories of a tree, computed in the walk of the
+//! whole tree ([`crate::get_dir_hash_per_dir`]).
+//!
+//! Entries arrive in canonical order, so the entries of a directory are
+//! contiguous: a stream of frames is opened for each directory on the path
+//! of the current entry (down to the requested depth) and finalized once
+//! the walk leaves it. Each stream frames paths relative to its directory,
+//! exactly as a walk rooted there would.
+
+use crate::frame::{FormatVersion, Frames};
+use crate::{Options, hex_lower, since_epoch};
+use std::collections::BTreeMap;
+use std::io;
+use std::time::Duration;
+
+/// Per-directory frame streams of one run.
+pub(crate) struct Rollup {
+    max_depth: usize,
+    version: FormatVersion,
+    output_len: usize,
+    include_root_name: bool,
+    modified_since: Option<Duration>,
+    /// The directories the walk is in, outermost first: framed path and
+    /// frames.
+    open: Vec<(Vec<u8>, Frames<'static>)>,
+    /// Finalized digests by framed path (shown lossily).
+    done: BTreeMap<String, String>,
+}
+
+impl Rollup {
+    /// Streams for the directories at most `max_depth` levels below the
+    /// root.
+    pub(crate) fn new(max_depth: usize, opts: &Options) -> io::Result<Self> {
+        Ok(Rollup {
+            max_depth,
+            version: opts.format_version,
+            output_len: opts.output_len,
+            include_root_name: opts.include_root_name,
+            modified_since: opts.modified_since.map(since_epoch).transpose()?,
+            open: Vec::new(),
+            done: BTreeMap::new(),
+        })
+    }
+
+    pub(crate) fn file(&mut self, rel: &[u8], digest: &[u8], metadata: &[u8]) -> io::Result<()> {
+        self.enter(rel)?;
+        for (dir, frames) in &mut self.open {
+            frames.file(&rel[dir.len() + 1..], digest, metadata);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn link(&mut self, rel: &[u8], target: &[u8]) -> io::Result<()> {
+        self.enter(rel)?;
+        for (dir, frames) in &mut self.open {
+            frames.link(&rel[dir.len() + 1..], target);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn oversize(&mut self, rel: &[u8], size: u64) -> io::Result<()> {
+        self.enter(rel)?;
+        for (dir, frames) in &mut self.open {
+            frames.oversize(&rel[dir.len() + 1..], size);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn special(&mut self, rel: &[u8], file_type: u8) -> io::Result<()> {
+        self.enter(rel)?;
+        for (dir, frames) in &mut self.open {
+            frames.special(&rel[dir.len() + 1..], file_type);
+        }
+        Ok(())
+    }
+
+    /// Finalize the directories still open; every directory's digest.
+    pub(crate) fn finish(mut self) -> io::Result<BTreeMap<String, String>> {
+        self.leave(0)?;
+        Ok(self.done)
+    }
+
+    /// Make the open directories those above the entry at `rel`.
+    fn enter(&mut self, rel: &[u8]) -> io::Result<()> {
+        let ends: Vec<usize> = (rel.iter().enumerate())
+            .filter(|&(_, &b)| b == b'/')
+            .map(|(i, _)| i)
+            .take(self.max_depth)
+            .collect();
+        let same = (self.open.iter().zip(&ends))
+            .take_while(|((dir, _), end)| dir[..] == rel[..**end])
+            .count();
+        self.leave(same)?;
+        for &end in &ends[same..] {
+            let dir = &rel[..end];
+            let mut frames = Frames::new(self.version, None);
+            if self.include_root_name {
+                let name = dir.rsplit(|&b| b == b'/').next().unwrap_or(dir);
+                frames.root_name(name);
+            }
+            if let Some(cutoff) = self.modified_since {
+                frames.modified_since(cutoff);
+            }
+            self.open.push((dir.to_vec(), frames));
+        }
+        Ok(())
+    }
+
+    /// Finalize the open directories past the first `keep`.
+    fn leave(&mut self, keep: usize) -> io::Result<()> {
+        for (dir, frames) in self.open.drain(keep..) {
+            let hex = hex_lower(&frames.finalize(self.output_len)?);
+            self.done
+                .insert(String::from_utf8_lossy(&dir).into_owned(), hex);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,453 @@
+//! Saved per-file state of a tree ([`Snapshot`]), for answering "what
+//! changed?" later without keeping the old tree around.
+//!
+//! File layout (integers u64 LE, byte strings length-prefixed):
+//!
+//! ```text
+//! get_dir_hash-snapshot-v1 \0
+//! root digest[32]  count
+//! { path  size  mtime-flag[1] [secs(i64 LE) nanos(u32 LE)]  digest[32] }*
+//! ```
+//!
+//! The mtime flag is 0 when the platform reported no mtime, 1 otherwise.
+//! [`Snapshot::load`] also accepts manifests ([`crate::ManifestFormat`]),
+//! such as the text of `--print-files`.
+
+use crate::codec::{Reader, put_field, put_u64};
+use crate::{DirHash, FileDigest, FileOp, Manifest, Options, Outputs};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8] = b"get_dir_hash-snapshot-v1\0";
+
+/// The hashed files of a tree at one point in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Root digest of the tree, as returned by [`crate::get_dir_hash`].
+    pub hex: String,
+    /// Every hashed file, in canonical order.
+    pub files: Vec<SnapshotEntry>,
+}
+
+/// One file of a [`Snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    /// Relative path as framed (see [`crate::enumerate_files`]).
+    pub path: String,
+    /// Size in bytes when the snapshot was taken.
+    pub size: u64,
+    /// Modification time, if the platform reports one.
+    pub mtime: Option<SystemTime>,
+    /// Lowercase hex digest of the file's contents.
+    pub hex: String,
+}
+
+/// Differences between two snapshots ([`Snapshot::diff`]); paths in
+/// canonical order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotDiff {
+    /// Files only in the newer snapshot.
+    pub added: Vec<String>,
+    /// Files only in the older snapshot.
+    pub removed: Vec<String>,
+    /// Files in both whose contents differ.
+    pub modified: Vec<String>,
+}
+
+/// Files with identical contents ([`Snapshot::duplicates`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DuplicateSet {
+    /// Lowercase hex content digest the files share.
+    pub hex: String,
+    /// Size of each copy in bytes (0 for snapshots loaded from a manifest).
+    pub size: u64,
+    /// The files, two or more, in canonical order.
+    pub paths: Vec<String>,
+}
+
+impl DuplicateSet {
+    /// Bytes taken by all copies but one.
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+impl SnapshotDiff {
+    /// Whether the two snapshots hold the same files with the same contents.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl Snapshot {
+    /// Hash `root` with `opts` and record every file's size, mtime and
+    /// content digest. The root digest is always 32 bytes, whatever
+    /// [`Options::output_len`] says.
+    pub fn capture(root: &Path, opts: &Options) -> io::Result<Snapshot> {
+        let mut files = Vec::new();
+        let outputs = Outputs {
+            snapshot: Some(&mut files),
+            ..Outputs::default()
+        };
+        let opts = Options {
+            output_len: 32,
+            ..opts.clone()
+        };
+        let DirHash { hex, .. } = crate::run(root, None, &opts, outputs)?;
+        Ok(Snapshot { hex, files })
+    }
+
+    /// Write the snapshot to `path` in the versioned binary format.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_bytes()?)
+    }
+
+    /// Read a snapshot written by [`Snapshot::save`], or a manifest in any
+    /// [`ManifestFormat`](crate::ManifestFormat) (such as the text printed
+    /// by `get_dir_hash --print-files`). Manifests carry no sizes or mtimes;
+    /// those entries load with size 0 and no mtime.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Snapshot> {
+        let bytes = fs::read(path)?;
+        if bytes.starts_with(MAGIC) {
+            Self::from_bytes(&bytes)
+        } else {
+            Manifest::from_bytes(&bytes).map(Self::from_manifest)
+        }
+    }
+
+    /// Changes from `self` (older) to `newer`. Files count as modified when
+    /// their content digests differ; an mtime change alone is not reported.
+    pub fn diff(&self, newer: &Snapshot) -> SnapshotDiff {
+        let old: HashMap<&str, &SnapshotEntry> =
+            self.files.iter().map(|f| (f.path.as_str(), f)).collect();
+        let new: HashMap<&str, &SnapshotEntry> =
+            newer.files.iter().map(|f| (f.path.as_str(), f)).collect();
+        let mut diff = SnapshotDiff::default();
+        for f in &newer.files {
+            match old.get(f.path.as_str()) {
+                None => diff.added.push(f.path.clone()),
+                Some(o) if o.hex != f.hex => diff.modified.push(f.path.clone()),
+                Some(_) => {}
+            }
+        }
+        diff.removed = (self.files.iter())
+            .filter(|f| !new.contains_key(f.path.as_str()))
+            .map(|f| f.path.clone())
+            .collect();
+        diff
+    }
+
+    /// Sets of files with the same content digest, the most wasted bytes
+    /// first (then by first path). Empty files are not reported. Hard links
+    /// to one file count as copies.
+    pub fn duplicates(&self) -> Vec<DuplicateSet> {
+        let empty = blake3::hash(b"").to_hex();
+        let mut sets: HashMap<&str, DuplicateSet> = HashMap::new();
+        for f in self.files.iter().filter(|f| f.hex != empty.as_str()) {
+            let set = sets.entry(&f.hex).or_insert_with(|| DuplicateSet {
+                hex: f.hex.clone(),
+                size: f.size,
+                paths: Vec::new(),
+            });
+            set.paths.push(f.path.clone());
+        }
+        let mut sets: Vec<DuplicateSet> = (sets.into_values())
+            .filter(|set| set.paths.len() > 1)
+            .collect();
+        sets.sort_by(|a, b| (b.wasted(), &a.paths[0]).cmp(&(a.wasted(), &b.paths[0])));
+        sets
+    }
+
+    /// Hash `root` with `opts` and compare every file against this snapshot
+    /// as it is hashed, like `self.diff(&Snapshot::capture(root, opts)?)`
+    /// without keeping the new state. Files are read ahead on
+    /// [`Options::io_concurrency`] threads.
+    ///
+    /// With `fail_fast`, the run stops at the first file that is modified or
+    /// unexpected, and the returned diff holds just that file; files that are
+    /// missing are only known once the whole tree has been hashed.
+    pub fn verify(&self, root: &Path, opts: &Options, fail_fast: bool) -> io::Result<SnapshotDiff> {
+        let mut expected: HashMap<&str, &str> = (self.files.iter())
+            .map(|f| (f.path.as_str(), f.hex.as_str()))
+            .collect();
+        let mut diff = SnapshotDiff::default();
+        let mut check = |file: FileDigest| {
+            match expected.remove(file.path.as_str()) {
+                None => diff.added.push(file.path),
+                Some(hex) if hex != file.hex => diff.modified.push(file.path),
+                Some(_) => return Ok(()),
+            }
+            match fail_fast {
+                true => Err(io::Error::other(
+                    "verification stopped at the first mismatch",
+                )),
+                false => Ok(()),
+            }
+        };
+        let outputs = Outputs {
+            files: Some(&mut check),
+            ..Outputs::default()
+        };
+        match crate::run(root, None, opts, outputs) {
+            Ok(_) => {}
+            // Only `check` fails once something differs.
+            Err(_) if fail_fast && !diff.is_empty() => return Ok(diff),
+            Err(e) => return Err(e),
+        }
+        diff.removed = (self.files.iter())
+            .filter(|f| expected.contains_key(f.path.as_str()))
+            .map(|f| f.path.clone())
+            .collect();
+        Ok(diff)
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&digest_bytes(&self.hex)?);
+        put_u64(&mut out, self.files.len() as u64);
+        for f in &self.files {
+            put_entry(&mut out, f)?;
+        }
+        Ok(out)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> io::Result<Snapshot> {
+        let mut r = Reader::new(bytes, MAGIC, "snapshot")?;
+        let hex = crate::hex_lower(&r.array::<32>()?);
+        let mut files = Vec::new();
+        for _ in 0..r.u64()? {
+            files.push(read_entry(&mut r)?);
+        }
+        r.finish()?;
+        Ok(Snapshot { hex, files })
+    }
+
+    fn from_manifest(manifest: Manifest) -> Snapshot {
+        let files = (manifest.files.into_iter())
+            .map(|f| SnapshotEntry {
+                path: f.path,
+                size: 0,
+                mtime: None,
+                hex: f.hex,
+            })
+            .collect();
+        Snapshot {
+            hex: manifest.hex,
+            files,
+        }
+    }
+}
+
+/// Append the encoding of one entry.
+pub(crate) fn put_entry(out: &mut Vec<u8>, f: &SnapshotEntry) -> io::Result<()> {
+    put_field(out, f.path.as_bytes());
+    put_u64(out, f.size);
+    match f.mtime {
+        None => out.push(0),
+        Some(t) => {
+            out.push(1);
+            let (secs, nanos) = match t.duration_since(UNIX_EPOCH) {
+                Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+                Err(e) => {
+                    // Before the epoch: floor to whole seconds.
+                    let d = e.duration();
+                    let secs = -(d.as_secs() as i64);
+                    match d.subsec_nanos() {
+                        0 => (secs, 0),
+                        n => (secs - 1, 1_000_000_000 - n),
+                    }
+                }
+            };
+            out.extend_from_slice(&secs.to_le_bytes());
+            out.extend_from_slice(&nanos.to_le_bytes());
+        }
+    }
+    out.extend_from_slice(&digest_bytes(&f.hex)?);
+    Ok(())
+}
+
+/// Decode one entry written by [`put_entry`].
+pub(crate) fn read_entry(r: &mut Reader<'_>) -> io::Result<SnapshotEntry> {
+    let path = String::from_utf8_lossy(r.field()?).into_owned();
+    let size = r.u64()?;
+    let mtime = match r.array::<1>()? {
+        [0] => None,
+        _ => {
+            let secs = i64::from_le_bytes(r.array()?);
+            let nanos = u32::from_le_bytes(r.array()?);
+            if nanos >= 1_000_000_000 {
+                return Err(r.invalid("corrupt mtime"));
+            }
+            let whole = Duration::from_secs(secs.unsigned_abs());
+            let t = match secs >= 0 {
+                true => UNIX_EPOCH.checked_add(whole),
+                false => UNIX_EPOCH.checked_sub(whole),
+            };
+            let t = t.and_then(|t| t.checked_add(Duration::from_nanos(nanos.into())));
+            Some(t.ok_or_else(|| r.invalid("mtime out of range"))?)
+        }
+    };
+    let hex = crate::hex_lower(&r.array::<32>()?);
+    Ok(SnapshotEntry {
+        path,
+        size,
+        mtime,
+        hex,
+    })
+}
+
+/// Size and mtime of the file at `path`, framed as `rel`; the caller fills
+/// in `hex` once the contents are hashed.
+pub(crate) fn entry(path: &Path, rel: &[u8]) -> io::Result<SnapshotEntry> {
+    let md = fs::metadata(path).map_err(FileOp::Metadata.on(path))?;
+    Ok(SnapshotEntry {
+        path: String::from_utf8_lossy(rel).into_owned(),
+        size: md.len(),
+        mtime: md.modified().ok(),
+        hex: String::new(),
+    })
+}
+
+/// Content digest `previous` holds for the file `current` describes, if it
+/// has the same size and mtime there.
+pub(crate) fn reuse(
+    previous: &HashMap<String, SnapshotEntry>,
+    current: &SnapshotEntry,
+) -> Option<blake3::Hash> {
+    let old = previous.get(&current.path)?;
+    if old.size != current.size || old.mtime != current.mtime {
+        return None;
+    }
+    blake3::Hash::from_hex(&old.hex).ok()
+}
+
+fn digest_bytes(hex: &str) -> io::Result<[u8; 32]> {
+    blake3::Hash::from_hex(hex)
+        .map(|h| *h.as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ManifestFormat, get_dir_hash, get_dir_manifest};
+
+    fn entry(path: &str, mtime: Option<SystemTime>) -> SnapshotEntry {
+        SnapshotEntry {
+            path: path.into(),
+            size: 7,
+            mtime,
+            hex: blake3::hash(path.as_bytes()).to_hex().to_string(),
+        }
+    }
+
+    /// A one-file snapshot whose mtime is encoded as `secs` and `nanos`.
+    fn with_mtime(secs: i64, nanos: u32) -> Vec<u8> {
+        let snapshot = Snapshot {
+            hex: blake3::hash(b"root").to_hex().to_string(),
+            files: vec![entry("f", Some(UNIX_EPOCH))],
+        };
+        let mut bytes = snapshot.to_bytes().unwrap();
+        // After the magic, root digest, count, path, size and mtime flag.
+        let at = MAGIC.len() + 32 + 8 + 8 + 1 + 8 + 1;
+        bytes[at..at + 8].copy_from_slice(&secs.to_le_bytes());
+        bytes[at + 8..at + 12].copy_from_slice(&nanos.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let snapshot = Snapshot {
+            hex: blake3::hash(b"root").to_hex().to_string(),
+            files: vec![
+                entry("none", None),
+                entry("epoch", Some(UNIX_EPOCH)),
+                entry("before", Some(UNIX_EPOCH - Duration::from_millis(1500))),
+                entry(
+                    "after",
+                    Some(UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789)),
+                ),
+            ],
+        };
+        let bytes = snapshot.to_bytes().unwrap();
+        assert_eq!(Snapshot::from_bytes(&bytes).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn corrupt_mtimes_are_rejected() {
+        assert!(Snapshot::from_bytes(&with_mtime(0, 999_999_999)).is_ok());
+        for (secs, nanos) in [(i64::MAX, u32::MAX), (0, 1_000_000_000), (-1, u32::MAX)] {
+            let e = Snapshot::from_bytes(&with_mtime(secs, nanos)).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{secs} {nanos}");
+        }
+        // Representable or not depending on the platform, but never a panic.
+        for secs in [i64::MAX, i64::MIN] {
+            if let Err(e) = Snapshot::from_bytes(&with_mtime(secs, 999_999_999)) {
+                assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{secs}");
+            }
+        }
+    }
+
+    #[test]
+    fn saved_snapshot_loads_and_diffs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("tree");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("keep"), "keep").unwrap();
+        fs::write(root.join("edit"), "before").unwrap();
+        fs::write(root.join("sub/gone"), "gone").unwrap();
+
+        let opts = Options {
+            output_len: 16,
+            ..Options::default()
+        };
+        let old = Snapshot::capture(&root, &opts).unwrap();
+        // Always a 32-byte root digest.
+        assert_eq!(old.hex, get_dir_hash(&root, &Options::default()).unwrap());
+        let file = dir.path().join("snap");
+        old.save(&file).unwrap();
+        assert_eq!(Snapshot::load(&file).unwrap(), old);
+
+        fs::write(root.join("edit"), "after").unwrap();
+        fs::remove_file(root.join("sub/gone")).unwrap();
+        fs::write(root.join("new"), "new").unwrap();
+        let new = Snapshot::capture(&root, &Options::default()).unwrap();
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, ["new"]);
+        assert_eq!(diff.removed, ["sub/gone"]);
+        assert_eq!(diff.modified, ["edit"]);
+        assert_eq!(old.verify(&root, &Options::default(), false).unwrap(), diff);
+        assert!(new.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn manifests_load_as_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), "a").unwrap();
+        let manifest = get_dir_manifest(dir.path(), &Options::default()).unwrap();
+        let file = dir.path().join("manifest");
+        for format in [
+            ManifestFormat::Text,
+            ManifestFormat::Json,
+            ManifestFormat::Binary,
+        ] {
+            manifest.save(&file, format).unwrap();
+            let loaded = Snapshot::load(&file).unwrap();
+            assert_eq!(loaded.hex, manifest.hex, "{format}");
+            assert_eq!(
+                loaded.files,
+                [SnapshotEntry {
+                    path: "a".into(),
+                    size: 0,
+                    mtime: None,
+                    hex: manifest.files[0].hex.clone(),
+                }]
+            );
+        }
+    }
+}
@@ -0,0 +1,481 @@
+//! Hashing trees that do not live on a local file system ([`TreeSource`],
+//! [`get_source_hash`]).
+//!
+//! A source only has to list its files and open them; directories are
+//! implied by the `/`-separated paths. The files are ordered, filtered and
+//! framed exactly like a walk would order, filter and frame the same files
+//! on disk, so a bucket prefix holding a copy of a directory hashes to the
+//! directory's digest. Sources have no symlinks, special files or empty
+//! directories; the first two are not part of such a copy, and empty
+//! directories never contribute to a digest anyway.
+
+use crate::frame::Frames;
+use crate::ignore::{IGNORE_FILE_NAME, IgnoreRules};
+use crate::throttle::Throttle;
+use crate::walk::{ListOptions, order_key};
+use crate::{
+    CaseCollisionPolicy, FileErrors, FileOp, Limits, ModificationPolicy, Options, OversizePolicy,
+    Warning, check_cancel, check_output_len, framed_path, hex_lower, report_skip, with_deadline,
+};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// A tree of files to hash with [`get_source_hash`].
+pub trait TreeSource {
+    /// Every file of the tree, in any order.
+    fn files(&self) -> io::Result<Vec<SourceFile>>;
+
+    /// Open the file at `path`, as listed by [`TreeSource::files`].
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read + '_>>;
+}
+
+/// One file of a [`TreeSource`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    /// Path relative to the root of the source, components separated by
+    /// `/`, without a leading `/` and without `.` or `..` components.
+    pub path: String,
+    /// Size in bytes, as listed; checked against [`Options::max_file_size`]
+    /// and [`Options::max_total_bytes`].
+    pub size: u64,
+}
+
+/// Hash the files of `source`; the digest is the one
+/// [`get_dir_hash`](crate::get_dir_hash) computes for a directory holding
+/// the same files.
+///
+/// Ignore patterns apply as usual, and `.get_dir_hash_ignore` files are read
+/// from the source. Options that need a path on disk
+/// ([`Options::include_metadata`], [`Options::include_file_type`],
+/// [`Options::include_root_name`],
+/// [`Options::path_base`], [`Options::chunking`], [`Options::checkpoint`],
+/// [`Options::modified_since`], [`Options::concurrent_modification`]) and
+/// [`CaseCollisionPolicy::Error`] are rejected with `InvalidInput`; a listed path that is not in the form
+/// [`SourceFile::path`] describes is `InvalidData`. Files that cannot be
+/// opened are reported as unreadable and skipped (or fail the run, with
+/// [`Options::fail_on_skip`]).
+pub fn get_source_hash(source: &dyn TreeSource, opts: &Options) -> io::Result<String> {
+    let opts = &*with_deadline(opts, Instant::now());
+    check_supported(opts)?;
+    check_output_len(opts)?;
+    let mut files = source.files()?;
+    for file in &files {
+        check_path(&file.path)?;
+    }
+    let lo = ListOptions::new(opts);
+    files.sort_by_cached_key(|file| {
+        let count = file.path.split('/').count();
+        (file.path.split('/').enumerate())
+            .map(|(i, name)| order_key(OsStr::new(name), i + 1 < count, lo))
+            .collect::<Vec<_>>()
+    });
+
+    let normalize = |rel: &str| match opts.unicode_normalization {
+        Some(form) => form.apply(rel),
+        None => rel.to_owned(),
+    };
+    // Ignore files by the normalized path of their directory, with a
+    // trailing `/` (empty for the root).
+    let mut dotfiles = BTreeMap::new();
+    if opts.load_dot_get_dir_hash_ignore {
+        for file in &files {
+            if let Some(dir) = file.path.strip_suffix(IGNORE_FILE_NAME)
+                && (dir.is_empty() || dir.ends_with('/'))
+            {
+                dotfiles.insert(normalize(dir), &file.path);
+            }
+        }
+    }
+    let read_dotfile = |path: &str| -> io::Result<String> {
+        let mut txt = String::new();
+        source.open(path)?.read_to_string(&mut txt)?;
+        Ok(txt)
+    };
+    let root = match dotfiles.remove("") {
+        Some(path) => Some((read_dotfile(path)?, PathBuf::from(path))),
+        None => None,
+    };
+    let rules = IgnoreRules::with_dotfile(root, opts)?;
+    for warning in &rules.warnings {
+        report_skip(warning, opts)?;
+    }
+    let mut nested = Vec::new();
+    for (prefix, path) in dotfiles {
+        if is_pruned(&prefix, &rules, &nested) {
+            continue;
+        }
+        let txt = read_dotfile(path)?;
+        let path = Path::new(path);
+        let rules = IgnoreRules::from_text(&txt, path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+        for warning in &rules.warnings {
+            report_skip(warning, opts)?;
+        }
+        nested.push((prefix, rules));
+    }
+
+    let mut throttle = Throttle::new(opts.max_read_bytes_per_sec)?;
+    let mut out = Frames::new(opts.format_version, None);
+    let mut limits = Limits::default();
+    let mut failed = FileErrors::default();
+    for file in &files {
+        check_cancel(opts)?;
+        let norm = normalize(&file.path);
+        if is_ignored(&norm, &rules, &nested) {
+            continue;
+        }
+        let path = framed_path(norm.clone(), None, opts);
+        let size = file.size;
+        if opts.structure_only {
+            limits.entry(opts)?;
+            out.oversize(&path, size);
+            continue;
+        }
+        if let Some(limit) = opts.max_file_size
+            && size > limit
+        {
+            match opts.oversize_policy {
+                OversizePolicy::Skip => continue,
+                OversizePolicy::Error => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::FileTooLarge,
+                        format!("file too large: {norm} ({size} bytes, limit {limit})"),
+                    ));
+                }
+                OversizePolicy::SkipWithRecord => {
+                    limits.entry(opts)?;
+                    out.oversize(&path, size);
+                    continue;
+                }
+                OversizePolicy::Hash => {}
+            }
+        }
+        let reader = match source.open(&file.path) {
+            Ok(reader) => reader,
+            Err(e) => {
+                report_skip(&Warning::unreadable(Path::new(&file.path), e), opts)?;
+                continue;
+            }
+        };
+        limits.entry(opts)?;
+        limits.bytes(size, opts)?;
+        let mut inner = blake3::Hasher::new();
+        let mut reader = throttle.reader(reader);
+        let copied = if opts.normalize_newlines {
+            crate::newline::copy(&mut reader, &mut inner)
+        } else {
+            io::copy(&mut reader, &mut inner)
+        };
+        if let Err(e) = copied {
+            failed.set_aside(FileOp::Read.on(Path::new(&file.path))(e), opts)?;
+            continue;
+        }
+        out.file(&path, inner.finalize().as_bytes(), &[]);
+    }
+
+    failed.finish()?;
+
+    Ok(hex_lower(&out.finalize(opts.output_len)?))
+}
+
+/// Whether the file at the normalized path `rel` is excluded, itself or
+/// through one of its directories, by the root rules or by the ignore file
+/// of a directory above it.
+fn is_ignored(rel: &str, rules: &IgnoreRules, nested: &[(String, IgnoreRules)]) -> bool {
+    is_pruned(rel, rules, nested)
+        || rules.is_ignored(rel)
+        || applying(rel, nested).any(|(rest, rules)| rules.is_ignored(rest))
+}
+
+/// Whether a directory `rel` lies in is excluded. Like in a walk, each
+/// directory is checked against the rules in place while its parent is
+/// listed: the root's and those of the directories above it.
+fn is_pruned(rel: &str, rules: &IgnoreRules, nested: &[(String, IgnoreRules)]) -> bool {
+    (rel.match_indices('/')).any(|(end, _)| {
+        let dir = &rel[..end];
+        rules.is_dir_ignored(dir)
+            || applying(dir, nested).any(|(rest, rules)| rules.is_dir_ignored(rest))
+    })
+}
+
+/// The nested rules that apply at `rel`, each with `rel` relative to its
+/// directory.
+fn applying<'a>(
+    rel: &'a str,
+    nested: &'a [(String, IgnoreRules)],
+) -> impl Iterator<Item = (&'a str, &'a IgnoreRules)> {
+    (nested.iter()).filter_map(|(prefix, rules)| Some((rel.strip_prefix(prefix.as_str())?, rules)))
+}
+
+/// Reject a listed path that is not relative and `/`-separated.
+fn check_path(path: &str) -> io::Result<()> {
+    let valid = (path.split('/')).all(|name| !matches!(name, "" | "." | ".."));
+    match valid {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid path in tree source: {path:?}"),
+        )),
+    }
+}
+
+/// Reject the options [`get_source_hash`] cannot honour.
+fn check_supported(opts: &Options) -> io::Result<()> {
+    let unsupported = [
+        (opts.include_metadata, "include_metadata"),
+        (opts.include_file_type, "include_file_type"),
+        (opts.include_root_name, "include_root_name"),
+        (opts.path_base.is_some(), "path_base"),
+        (opts.chunking.is_some(), "chunking"),
+        (opts.checkpoint.is_some(), "checkpoint"),
+        (
+            opts.concurrent_modification != ModificationPolicy::Ignore,
+            "concurrent_modification",
+        ),
+        (opts.modified_since.is_some(), "modified_since"),
+        (
+            !opts.case_sensitive_paths && opts.case_collisions == CaseCollisionPolicy::Error,
+            "case_collisions = error",
+        ),
+    ];
+    match unsupported.iter().find(|(set, _)| *set) {
+        Some((_, name)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{name} is not supported when hashing a tree source"),
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OversizePolicy, WarningCallback, get_dir_hash};
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    /// Files held in memory.
+    struct Memory(Vec<(&'static str, &'static [u8])>);
+
+    impl Memory {
+        /// A directory holding the same files.
+        fn to_dir(&self) -> tempfile::TempDir {
+            let dir = tempfile::tempdir().unwrap();
+            for (path, body) in &self.0 {
+                let path = dir.path().join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, body).unwrap();
+            }
+            dir
+        }
+    }
+
+    impl TreeSource for Memory {
+        fn files(&self) -> io::Result<Vec<SourceFile>> {
+            let file = |&(path, body): &(&str, &[u8])| SourceFile {
+                path: path.to_owned(),
+                size: body.len() as u64,
+            };
+            Ok(self.0.iter().map(file).collect())
+        }
+
+        fn open(&self, path: &str) -> io::Result<Box<dyn Read + '_>> {
+            match self.0.iter().find(|(p, _)| *p == path) {
+                Some((_, body)) => Ok(Box::new(*body)),
+                None => Err(io::ErrorKind::NotFound.into()),
+            }
+        }
+    }
+
+    /// A source that lists `extra` on top of `files` but cannot open it.
+    struct Unopenable {
+        files: Memory,
+        extra: &'static str,
+    }
+
+    impl TreeSource for Unopenable {
+        fn files(&self) -> io::Result<Vec<SourceFile>> {
+            let mut files = self.files.files()?;
+            files.push(SourceFile {
+                path: self.extra.to_owned(),
+                size: 1,
+            });
+            Ok(files)
+        }
+
+        fn open(&self, path: &str) -> io::Result<Box<dyn Read + '_>> {
+            self.files.open(path)
+        }
+    }
+
+    #[test]
+    fn matches_a_directory_holding_the_same_files() {
+        let source = Memory(vec![
+            ("b.txt", b"one\r\ntwo\r\n"),
+            ("a/c.txt", b"three\rfour\n"),
+            ("a/d/e.bin", b"\r\n\r\r\n"),
+        ]);
+        let dir = source.to_dir();
+        let cases = [
+            Options::default(),
+            Options {
+                normalize_newlines: true,
+                ..Options::default()
+            },
+            Options {
+                structure_only: true,
+                ..Options::default()
+            },
+        ];
+        let mut digests = Vec::new();
+        for opts in &cases {
+            let digest = get_source_hash(&source, opts).unwrap();
+            assert_eq!(digest, get_dir_hash(dir.path(), opts).unwrap());
+            assert!(!digests.contains(&digest));
+            digests.push(digest);
+        }
+    }
+
+    #[test]
+    fn listing_order_does_not_matter() {
+        // Names whose order differs between bytes and path components.
+        let mut source = Memory(vec![
+            ("a/b", b"1"),
+            ("a-b", b"2"),
+            ("a.b/c", b"3"),
+            ("A", b"4"),
+            ("a0", b"5"),
+        ]);
+        let dir = source.to_dir();
+        let expected = get_dir_hash(dir.path(), &Options::default()).unwrap();
+        assert_eq!(
+            get_source_hash(&source, &Options::default()).unwrap(),
+            expected
+        );
+        source.0.reverse();
+        assert_eq!(
+            get_source_hash(&source, &Options::default()).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn ignore_rules_apply_like_on_disk() {
+        let source = Memory(vec![
+            (".get_dir_hash_ignore", b"*.log\n"),
+            ("keep.txt", b"kept"),
+            ("run.log", b"ignored"),
+            ("sub/.get_dir_hash_ignore", b"tmp/\n"),
+            ("sub/tmp/x", b"ignored"),
+            ("sub/y.log", b"ignored"),
+            ("sub/z", b"kept"),
+            ("tmp/w", b"kept: sub's rules stop at sub"),
+            ("build/out", b"ignored by pattern"),
+        ]);
+        let dir = source.to_dir();
+        let opts = Options {
+            ignore_patterns: vec!["build/**".into()],
+            ..Options::default()
+        };
+        let digest = get_source_hash(&source, &opts).unwrap();
+        assert_eq!(digest, get_dir_hash(dir.path(), &opts).unwrap());
+
+        let without = Options {
+            load_dot_get_dir_hash_ignore: false,
+            ..opts
+        };
+        assert_ne!(get_source_hash(&source, &without).unwrap(), digest);
+        assert_eq!(
+            get_source_hash(&source, &without).unwrap(),
+            get_dir_hash(dir.path(), &without).unwrap()
+        );
+    }
+
+    #[test]
+    fn oversize_files_are_handled_like_on_disk() {
+        let source = Memory(vec![("small", b"1"), ("large", b"1234567890")]);
+        let dir = source.to_dir();
+        for policy in [OversizePolicy::Skip, OversizePolicy::SkipWithRecord] {
+            let opts = Options {
+                max_file_size: Some(4),
+                oversize_policy: policy,
+                ..Options::default()
+            };
+            assert_eq!(
+                get_source_hash(&source, &opts).unwrap(),
+                get_dir_hash(dir.path(), &opts).unwrap(),
+                "{policy:?}"
+            );
+        }
+        let opts = Options {
+            max_file_size: Some(4),
+            oversize_policy: OversizePolicy::Error,
+            ..Options::default()
+        };
+        let err = get_source_hash(&source, &opts).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::FileTooLarge);
+    }
+
+    #[test]
+    fn malformed_paths_are_rejected() {
+        for path in ["", "/a", "a/", "a//b", "./a", "a/../b", ".."] {
+            let source = Memory(vec![("ok", b""), (path, b"")]);
+            let err = get_source_hash(&source, &Options::default()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{path:?}");
+        }
+    }
+
+    #[test]
+    fn options_needing_a_disk_are_rejected() {
+        let source = Memory(vec![("a", b"1")]);
+        let cases = [
+            Options {
+                include_metadata: true,
+                ..Options::default()
+            },
+            Options {
+                path_base: Some(PathBuf::from("base")),
+                ..Options::default()
+            },
+            Options {
+                case_sensitive_paths: false,
+                case_collisions: CaseCollisionPolicy::Error,
+                ..Options::default()
+            },
+        ];
+        for opts in &cases {
+            let err = get_source_hash(&source, opts).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{err}");
+        }
+    }
+
+    #[test]
+    fn unopenable_files_are_skipped_with_a_warning() {
+        let source = Unopenable {
+            files: Memory(vec![("a", b"1")]),
+            extra: "gone",
+        };
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&warnings);
+        let opts = Options {
+            on_warning: Some(WarningCallback::new(move |w| {
+                sink.lock().unwrap().push(w.path.clone())
+            })),
+            ..Options::default()
+        };
+        assert_eq!(
+            get_source_hash(&source, &opts).unwrap(),
+            get_source_hash(&source.files, &Options::default()).unwrap()
+        );
+        assert_eq!(*warnings.lock().unwrap(), [PathBuf::from("gone")]);
+
+        let strict = Options {
+            fail_on_skip: true,
+            ..Options::default()
+        };
+        assert!(get_source_hash(&source, &strict).is_err());
+    }
+}
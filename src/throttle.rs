@@ -0,0 +1,78 @@
+//! Read rate limiting for [`crate::Options::max_read_bytes_per_sec`].
+
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+/// Paces the file reads of one run. Every read pushes a virtual clock
+/// forward by the time its bytes take at the allowed rate; the reader then
+/// sleeps until the real clock catches up. Idle time (walking, metadata) is
+/// not banked, so the limit also holds for short bursts.
+pub(crate) struct Throttle {
+    /// Bytes per second; `None` when reads are not limited.
+    rate: Option<u64>,
+    /// When the bytes accounted so far may have been read.
+    ready_at: Instant,
+}
+
+impl Throttle {
+    /// A throttle for `rate` bytes per second, or a no-op one for `None`.
+    pub(crate) fn new(rate: Option<u64>) -> io::Result<Throttle> {
+        if rate == Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "read rate limit must be at least 1 byte per second",
+            ));
+        }
+        Ok(Throttle {
+            rate,
+            ready_at: Instant::now(),
+        })
+    }
+
+    /// A throttle that never sleeps.
+    pub(crate) fn unlimited() -> Throttle {
+        Throttle {
+            rate: None,
+            ready_at: Instant::now(),
+        }
+    }
+
+    /// Whether reads are limited at all.
+    pub(crate) fn is_active(&self) -> bool {
+        self.rate.is_some()
+    }
+
+    /// Account for `n` bytes just read, sleeping as long as needed.
+    pub(crate) fn consume(&mut self, n: usize) {
+        let Some(rate) = self.rate else {
+            return;
+        };
+        let now = Instant::now();
+        self.ready_at = self.ready_at.max(now) + Duration::from_secs_f64(n as f64 / rate as f64);
+        if self.ready_at > now {
+            std::thread::sleep(self.ready_at - now);
+        }
+    }
+
+    /// `reader`, paced by this throttle.
+    pub(crate) fn reader<R: Read>(&mut self, reader: R) -> Throttled<'_, R> {
+        Throttled {
+            reader,
+            throttle: self,
+        }
+    }
+}
+
+/// A reader whose reads go through a [`Throttle`].
+pub(crate) struct Throttled<'t, R> {
+    reader: R,
+    throttle: &'t mut Throttle,
+}
+
+impl<R: Read> Read for Throttled<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.throttle.consume(n);
+        Ok(n)
+    }
+}
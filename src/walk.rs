@@ -0,0 +1,779 @@
+//! Directory traversal.
+//!
+//! A depth-first walk that lists each directory once and prunes ignored
+//! subdirectories before they are ever read. Children are sorted so that the
+//! walk yields files in the order of their full relative paths (see
+//! [`order_key`]); the walk order (and therefore the first reported error) is
+//! identical on every run.
+//!
+//! With the `parallel-walk` feature and `Options::walk_threads > 1`, directory
+//! listings (`read_dir` plus the `stat` calls needed to resolve symlinks) are
+//! prefetched by a small thread pool: as soon as a directory is entered, all
+//! of its surviving subdirectories are queued for listing. All decisions
+//! (pruning, loop detection, what gets yielded) stay on the consuming thread,
+//! so both modes produce exactly the same sequence of nodes.
+//!
+//! With `Options::confine_symlinks`, every followed link is resolved to its
+//! canonical target first; links leading outside the canonical root are
+//! turned back into plain symlinks (handled like dangling ones) before their
+//! targets are listed or read.
+//!
+//! Directories below the root may carry their own `.get_dir_hash_ignore`; its
+//! rules live in the directory's frame and apply to everything underneath
+//! while the frame is open.
+//!
+//! The walk keeps an explicit stack of open directories, so nesting depth
+//! costs heap rather than call stack. Unreadable entries are skipped with a
+//! warning, but a path the OS cannot handle (see [`os_limit`]) stops the
+//! walk: skipping it would quietly leave out everything below.
+
+use crate::ignore::{IGNORE_FILE_NAME, IgnoreRules};
+use crate::winmeta::is_hidden;
+use crate::{
+    CaseCollisionPolicy, NormalizationForm, Options, SymlinkLoopPolicy, Warning, check_depth,
+    make_rel_unix,
+};
+use same_file::Handle;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A non-directory node reached by the walk.
+pub(crate) enum Node {
+    /// Regular file (or a followed link to one).
+    File(PathBuf),
+    /// Symlink that was not followed, or whose target could not be resolved.
+    Symlink(PathBuf),
+    /// Anything else: FIFOs, sockets, devices.
+    Other(PathBuf),
+}
+
+/// Why the walk could not produce a node.
+pub(crate) enum WalkError {
+    /// An entry or directory could not be read, or an ignore rule was
+    /// dropped; the walk continues past it.
+    Entry(Warning),
+    /// The walk must stop, e.g. a symlink loop under `SymlinkLoopPolicy::Error`
+    /// or a case collision under `CaseCollisionPolicy::Error`.
+    Fatal(io::Error),
+}
+
+/// Sorted-order, pruning walker over `root`.
+pub(crate) struct Walker<'a> {
+    root: &'a Path,
+    opts: &'a Options,
+    rules: &'a IgnoreRules,
+    /// Open directories, innermost last.
+    stack: Vec<Frame>,
+    /// Errors waiting to be yielded before the next node.
+    errors: VecDeque<WalkError>,
+    /// Root itself when it is not a directory.
+    single: Option<Node>,
+    /// Directories skipped because an ignore pattern covers them.
+    pruned: u64,
+    /// Canonical root that followed links must stay inside
+    /// (`Options::confine_symlinks`).
+    confine: Option<PathBuf>,
+    #[cfg(feature = "parallel-walk")]
+    prefetch: Option<prefetch::Prefetcher>,
+}
+
+struct Frame {
+    children: VecDeque<Child>,
+    /// Identity of this directory, kept only for symlink loop detection.
+    handle: Option<Handle>,
+    /// Rules from this directory's own ignore file.
+    rules: Option<NestedRules>,
+}
+
+/// Ignore rules loaded from a directory below the root.
+struct NestedRules {
+    /// Relative path of the directory, with a trailing `/`.
+    prefix: String,
+    rules: IgnoreRules,
+}
+
+impl NestedRules {
+    /// `rel` relative to this directory, if it lies below it.
+    fn strip<'r>(&self, rel: &'r str) -> Option<&'r str> {
+        rel.strip_prefix(&self.prefix)
+    }
+}
+
+struct Child {
+    path: PathBuf,
+    kind: ChildKind,
+    /// Reached by following a symlink.
+    followed: bool,
+    #[cfg(feature = "parallel-walk")]
+    listing: Option<prefetch::Pending>,
+}
+
+#[derive(Clone, Copy)]
+enum ChildKind {
+    Dir { via_link: bool },
+    File,
+    Symlink,
+    Other,
+}
+
+/// What a directory listing needs to know from `Options`.
+#[derive(Clone, Copy)]
+pub(crate) struct ListOptions {
+    follow_symlinks: bool,
+    case_sensitive: bool,
+    normalization: Option<NormalizationForm>,
+    /// Leave out entries with the Windows hidden attribute.
+    skip_hidden: bool,
+}
+
+impl ListOptions {
+    pub(crate) fn new(opts: &Options) -> Self {
+        Self {
+            follow_symlinks: opts.follow_symlinks,
+            case_sensitive: opts.case_sensitive_paths,
+            normalization: opts.unicode_normalization,
+            skip_hidden: opts.skip_hidden,
+        }
+    }
+}
+
+/// Children of one directory plus any per-entry errors hit while listing it.
+struct Listing {
+    children: Vec<Child>,
+    errors: Vec<Warning>,
+}
+
+impl<'a> Walker<'a> {
+    pub(crate) fn new(root: &'a Path, opts: &'a Options, rules: &'a IgnoreRules) -> Self {
+        let mut walker = Self {
+            root,
+            opts,
+            rules,
+            stack: Vec::new(),
+            errors: rules_warnings(rules),
+            single: None,
+            pruned: 0,
+            confine: confine_root(root, opts),
+            #[cfg(feature = "parallel-walk")]
+            prefetch: (opts.walk_threads > 1)
+                .then(|| prefetch::Prefetcher::new(opts.walk_threads, ListOptions::new(opts))),
+        };
+        match fs::metadata(root) {
+            Ok(md) if md.is_dir() => {
+                let listing = list_dir(root, ListOptions::new(opts));
+                walker.enter(root, None, listing);
+            }
+            Ok(md) if md.is_file() => walker.single = Some(Node::File(root.to_path_buf())),
+            Ok(_) => walker.single = Some(Node::Other(root.to_path_buf())),
+            Err(e) => walker
+                .errors
+                .push_back(WalkError::Entry(Warning::unreadable(root, e))),
+        }
+        walker
+    }
+
+    /// A walker that yields exactly `files` (relative to `root`, or absolute
+    /// below it) in canonical order instead of listing directories.
+    ///
+    /// Every file must exist and lie below `root`; directories are rejected.
+    /// Symlinks are classified as during a walk. Duplicates are yielded once.
+    pub(crate) fn listed(
+        root: &'a Path,
+        opts: &'a Options,
+        rules: &'a IgnoreRules,
+        files: &[PathBuf],
+    ) -> io::Result<Self> {
+        let lo = ListOptions::new(opts);
+        let confine = confine_root(root, opts);
+        let mut children = Vec::with_capacity(files.len());
+        for file in files {
+            let path = resolve_listed(root, file)?;
+            let md = fs::symlink_metadata(&path).map_err(|e| with_path(&path, e))?;
+            let kind = if md.is_dir() || (lo.follow_symlinks && path.is_dir()) {
+                return Err(with_path(
+                    &path,
+                    io::Error::new(io::ErrorKind::InvalidInput, "is a directory"),
+                ));
+            } else if md.is_file() {
+                ChildKind::File
+            } else if md.is_symlink() {
+                match fs::metadata(&path) {
+                    Ok(md) if lo.follow_symlinks && md.is_file() => ChildKind::File,
+                    Ok(_) if lo.follow_symlinks => ChildKind::Other,
+                    _ => ChildKind::Symlink,
+                }
+            } else {
+                ChildKind::Other
+            };
+            let mut child = Child {
+                path,
+                kind,
+                followed: lo.follow_symlinks && md.is_symlink(),
+                #[cfg(feature = "parallel-walk")]
+                listing: None,
+            };
+            if let Some(confine) = &confine {
+                child.confine(confine);
+            }
+            children.push(child);
+        }
+        children.sort_by_cached_key(|c| listed_key(root, &c.path, lo));
+        children.dedup_by(|a, b| a.path == b.path);
+        Ok(Self {
+            root,
+            opts,
+            rules,
+            stack: vec![Frame {
+                children: children.into(),
+                handle: None,
+                rules: None,
+            }],
+            errors: rules_warnings(rules),
+            single: None,
+            pruned: 0,
+            confine,
+            #[cfg(feature = "parallel-walk")]
+            prefetch: None,
+        })
+    }
+
+    /// Push a frame for `dir` from its (possibly prefetched) listing.
+    fn enter(&mut self, dir: &Path, handle: Option<Handle>, listing: io::Result<Listing>) {
+        let listing = match listing {
+            Ok(l) => l,
+            Err(e) => {
+                let error = match os_limit(dir, self.stack.len(), &e) {
+                    Some(fatal) => WalkError::Fatal(fatal),
+                    None => WalkError::Entry(Warning::unreadable(dir, e)),
+                };
+                self.errors.push_back(error);
+                return;
+            }
+        };
+        let rules = self.nested_rules(dir, &listing);
+        self.errors
+            .extend(listing.errors.into_iter().map(WalkError::Entry));
+        let rules = match rules {
+            Ok(mut rules) => {
+                if let Some(nested) = &mut rules {
+                    let warnings = std::mem::take(&mut nested.rules.warnings);
+                    self.errors
+                        .extend(warnings.into_iter().map(WalkError::Entry));
+                }
+                rules
+            }
+            Err(e) => {
+                self.errors.push_back(WalkError::Fatal(e));
+                return;
+            }
+        };
+
+        let handle = match handle {
+            Some(h) => Some(h),
+            None if self.opts.follow_symlinks => Handle::from_path(dir).ok(),
+            None => None,
+        };
+
+        let mut children = VecDeque::with_capacity(listing.children.len());
+        for mut child in listing.children {
+            if let Some(confine) = &self.confine {
+                child.confine(confine);
+            }
+            if let ChildKind::Dir { .. } = child.kind
+                && self.rel(&child.path).is_some_and(|rel| {
+                    self.is_dir_ignored(&rel)
+                        || rules.as_ref().is_some_and(|n| {
+                            n.strip(&rel).is_some_and(|r| n.rules.is_dir_ignored(r))
+                        })
+                })
+            {
+                debug_event!(dir = %child.path.display(), "pruned ignored directory");
+                self.pruned += 1;
+                continue;
+            }
+            children.push_back(child);
+        }
+
+        #[cfg(feature = "parallel-walk")]
+        if let Some(prefetch) = &self.prefetch {
+            for child in children.iter_mut() {
+                if let ChildKind::Dir { .. } = child.kind {
+                    child.listing = Some(prefetch.submit(child.path.clone()));
+                }
+            }
+        }
+
+        self.stack.push(Frame {
+            children,
+            handle,
+            rules,
+        });
+
+        if !self.opts.case_sensitive_paths
+            && self.opts.case_collisions == CaseCollisionPolicy::Error
+            && let Some(e) = self.case_collision()
+        {
+            self.errors.push_back(WalkError::Fatal(e));
+        }
+    }
+
+    /// First pair of children of the innermost directory that frame to the
+    /// same lowercased path. Ignored files do not count.
+    fn case_collision(&self) -> Option<io::Error> {
+        let frame = self.stack.last()?;
+        let mut seen: HashMap<(bool, Vec<u8>), &Path> = HashMap::new();
+        for child in &frame.children {
+            let is_dir = matches!(child.kind, ChildKind::Dir { .. });
+            if !is_dir
+                && self
+                    .rel(&child.path)
+                    .is_some_and(|rel| self.is_ignored(&rel))
+            {
+                continue;
+            }
+            let name = child.path.file_name().unwrap_or_default();
+            let key = match name.to_str() {
+                Some(n) => match self.opts.unicode_normalization {
+                    Some(form) => form.apply(n).to_lowercase().into_bytes(),
+                    None => n.to_lowercase().into_bytes(),
+                },
+                None => name.as_encoded_bytes().to_ascii_lowercase(),
+            };
+            if let Some(first) = seen.insert((is_dir, key), &child.path) {
+                return Some(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "case collision: {} and {} only differ in case",
+                        first.display(),
+                        child.path.display()
+                    ),
+                ));
+            }
+        }
+        None
+    }
+
+    /// Rules from `dir`'s own ignore file, unless `dir` is the root (whose
+    /// file is part of the root rules).
+    fn nested_rules(&self, dir: &Path, listing: &Listing) -> io::Result<Option<NestedRules>> {
+        if !self.opts.load_dot_get_dir_hash_ignore || self.stack.is_empty() {
+            return Ok(None);
+        }
+        let Some(file) = listing.children.iter().find(|c| {
+            matches!(c.kind, ChildKind::File)
+                && c.path.file_name() == Some(OsStr::new(IGNORE_FILE_NAME))
+        }) else {
+            return Ok(None);
+        };
+        let Some(rel) = self.rel(dir) else {
+            return Ok(None);
+        };
+        let rules = IgnoreRules::from_file(&file.path).map_err(|e| with_path(&file.path, e))?;
+        Ok(Some(NestedRules {
+            prefix: format!("{rel}/"),
+            rules,
+        }))
+    }
+
+    /// Relative path of `path` as ignore rules see it.
+    fn rel(&self, path: &Path) -> Option<String> {
+        let rel = make_rel_unix(self.root, path)?;
+        Some(match self.opts.unicode_normalization {
+            Some(form) => form.apply(&rel),
+            None => rel,
+        })
+    }
+
+    /// Whether the file at `rel` is excluded by the root rules or by the
+    /// ignore file of any directory currently open above it.
+    pub(crate) fn is_ignored(&self, rel: &str) -> bool {
+        self.rules.is_ignored(rel)
+            || self
+                .nested()
+                .any(|n| n.strip(rel).is_some_and(|r| n.rules.is_ignored(r)))
+    }
+
+    /// Directory counterpart of [`Walker::is_ignored`].
+    fn is_dir_ignored(&self, rel: &str) -> bool {
+        self.rules.is_dir_ignored(rel)
+            || self
+                .nested()
+                .any(|n| n.strip(rel).is_some_and(|r| n.rules.is_dir_ignored(r)))
+    }
+
+    fn nested(&self) -> impl Iterator<Item = &NestedRules> {
+        self.stack.iter().filter_map(|f| f.rules.as_ref())
+    }
+
+    /// Number of directories pruned so far.
+    pub(crate) fn pruned(&self) -> u64 {
+        self.pruned
+    }
+
+    /// Whether the directory behind `handle` is already open further up.
+    fn ancestor_of(&self, handle: &Handle) -> Option<usize> {
+        self.stack
+            .iter()
+            .position(|f| f.handle.as_ref() == Some(handle))
+    }
+
+    /// Path of the ancestor directory at stack position `idx`.
+    fn ancestor_path(&self, idx: usize, child: &Path) -> PathBuf {
+        let mut p = child.to_path_buf();
+        for _ in idx..self.stack.len() {
+            p.pop();
+        }
+        p
+    }
+}
+
+impl Iterator for Walker<'_> {
+    type Item = Result<Node, WalkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(e) = self.errors.pop_front() {
+                return Some(Err(e));
+            }
+            if let Some(node) = self.single.take() {
+                return Some(Ok(node));
+            }
+            let frame = self.stack.last_mut()?;
+            let Some(child) = frame.children.pop_front() else {
+                self.stack.pop();
+                continue;
+            };
+            match child.kind {
+                ChildKind::File => return Some(Ok(Node::File(child.path))),
+                ChildKind::Symlink => return Some(Ok(Node::Symlink(child.path))),
+                ChildKind::Other => return Some(Ok(Node::Other(child.path))),
+                ChildKind::Dir { via_link } => {
+                    if let Err(e) = check_depth(self.stack.len(), self.opts) {
+                        return Some(Err(WalkError::Fatal(e)));
+                    }
+                    let mut handle = None;
+                    if via_link {
+                        let h = match Handle::from_path(&child.path) {
+                            Ok(h) => h,
+                            Err(e) => {
+                                if let Some(fatal) = os_limit(&child.path, self.stack.len(), &e) {
+                                    return Some(Err(WalkError::Fatal(fatal)));
+                                }
+                                let warning = Warning::unreadable(&child.path, e);
+                                return Some(Err(WalkError::Entry(warning)));
+                            }
+                        };
+                        if let Some(idx) = self.ancestor_of(&h) {
+                            match self.opts.symlink_loops {
+                                SymlinkLoopPolicy::Skip => {
+                                    debug_event!(
+                                        link = %child.path.display(),
+                                        "skipped symlink loop"
+                                    );
+                                    continue;
+                                }
+                                SymlinkLoopPolicy::Error => {
+                                    let ancestor = self.ancestor_path(idx, &child.path);
+                                    return Some(Err(WalkError::Fatal(io::Error::other(format!(
+                                        "File system loop found: {} points to an ancestor {}",
+                                        child.path.display(),
+                                        ancestor.display()
+                                    )))));
+                                }
+                            }
+                        }
+                        handle = Some(h);
+                    }
+                    #[cfg(feature = "parallel-walk")]
+                    let listing = match child.listing {
+                        Some(pending) => pending.wait(),
+                        None => list_dir(&child.path, ListOptions::new(self.opts)),
+                    };
+                    #[cfg(not(feature = "parallel-walk"))]
+                    let listing = list_dir(&child.path, ListOptions::new(self.opts));
+                    self.enter(&child.path, handle, listing);
+                }
+            }
+        }
+    }
+}
+
+/// Rules dropped from the root's ignore files, reported before anything
+/// else is walked.
+fn rules_warnings(rules: &IgnoreRules) -> VecDeque<WalkError> {
+    rules
+        .warnings
+        .iter()
+        .cloned()
+        .map(WalkError::Entry)
+        .collect()
+}
+
+/// Canonical root for `Options::confine_symlinks`, if links are followed
+/// and must stay inside it.
+fn confine_root(root: &Path, opts: &Options) -> Option<PathBuf> {
+    if !(opts.follow_symlinks && opts.confine_symlinks) {
+        return None;
+    }
+    fs::canonicalize(root).ok()
+}
+
+impl Child {
+    /// Stop following this link if its target lies outside `root`.
+    fn confine(&mut self, root: &Path) {
+        if self.followed && !fs::canonicalize(&self.path).is_ok_and(|t| t.starts_with(root)) {
+            debug_event!(link = %self.path.display(), "symlink leaves the root");
+            self.kind = ChildKind::Symlink;
+            self.followed = false;
+        }
+    }
+}
+
+/// Read and classify the entries of `dir`, sorted by [`order_key`].
+fn list_dir(dir: &Path, lo: ListOptions) -> io::Result<Listing> {
+    let follow_symlinks = lo.follow_symlinks;
+    let mut children = Vec::new();
+    let mut errors = Vec::new();
+    for de in fs::read_dir(dir)? {
+        let de = match de {
+            Ok(de) => de,
+            Err(e) => {
+                errors.push(Warning::unreadable(dir, e));
+                continue;
+            }
+        };
+        let path = de.path();
+        // Cheap on Windows, where the listing carries the attributes.
+        if cfg!(windows) && lo.skip_hidden && de.metadata().is_ok_and(|md| is_hidden(&md)) {
+            continue;
+        }
+        let ft = match de.file_type() {
+            Ok(ft) => ft,
+            Err(e) => {
+                errors.push(Warning::unreadable(&path, e));
+                continue;
+            }
+        };
+        let kind = if ft.is_dir() {
+            ChildKind::Dir { via_link: false }
+        } else if ft.is_file() {
+            ChildKind::File
+        } else if ft.is_symlink() {
+            match fs::metadata(&path) {
+                Ok(md) if follow_symlinks && md.is_dir() => ChildKind::Dir { via_link: true },
+                Ok(md) if follow_symlinks && md.is_file() => ChildKind::File,
+                Ok(_) if follow_symlinks => ChildKind::Other,
+                _ => ChildKind::Symlink,
+            }
+        } else {
+            ChildKind::Other
+        };
+        children.push(Child {
+            path,
+            kind,
+            followed: follow_symlinks && ft.is_symlink(),
+            #[cfg(feature = "parallel-walk")]
+            listing: None,
+        });
+    }
+    children.sort_by_cached_key(|c| {
+        let name = c.path.file_name().unwrap_or_default();
+        order_key(name, matches!(c.kind, ChildKind::Dir { .. }), lo)
+    });
+    Ok(Listing { children, errors })
+}
+
+/// Sort key for one directory child.
+///
+/// A directory sorts as if its name ended in `/`, which is exactly how it
+/// compares once it is part of a longer relative path; with that, sorting
+/// siblings sorts every path below them. Names compare as raw bytes (WTF-8 on
+/// Windows), so names that are not valid UTF-8 get a fixed order too.
+/// Case-insensitive mode compares ASCII-folded names first and Unicode
+/// normalization compares normalized names first; either way the exact name
+/// breaks ties, so names that only differ in case or form still get a fixed
+/// order.
+pub(crate) fn order_key(
+    name: &OsStr,
+    is_dir: bool,
+    lo: ListOptions,
+) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let mut exact = name.as_encoded_bytes().to_vec();
+    if is_dir {
+        exact.push(b'/');
+    }
+    let normalized = match (lo.normalization, std::str::from_utf8(&exact)) {
+        (Some(form), Ok(s)) => form.apply(s).into_bytes(),
+        (Some(_), Err(_)) => exact.clone(),
+        (None, _) => Vec::new(),
+    };
+    let folded = match (lo.case_sensitive, lo.normalization) {
+        (true, _) => Vec::new(),
+        (false, None) => exact.to_ascii_lowercase(),
+        (false, Some(_)) => normalized.to_ascii_lowercase(),
+    };
+    (folded, normalized, exact)
+}
+
+/// Sort key of a listed file: the [`order_key`] of each component, the
+/// leading ones as directories. Comparing these component by component
+/// yields the order in which a walk would reach the files.
+fn listed_key(root: &Path, path: &Path, lo: ListOptions) -> Vec<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+    let rel = path.strip_prefix(root).unwrap_or(path);
+    let count = rel.components().count();
+    rel.components()
+        .enumerate()
+        .map(|(i, c)| order_key(c.as_os_str(), i + 1 < count, lo))
+        .collect()
+}
+
+/// Absolute path of a listed file below `root`, with `.`/`..` and symlinked
+/// parent directories resolved (the file itself is left as is). The result
+/// is joined onto `root` as given, which need not be canonical.
+pub(crate) fn resolve_listed(root: &Path, file: &Path) -> io::Result<PathBuf> {
+    let joined = root.join(file);
+    let (Some(parent), Some(name)) = (joined.parent(), joined.file_name()) else {
+        return Err(with_path(
+            file,
+            io::Error::new(io::ErrorKind::InvalidInput, "not a file path"),
+        ));
+    };
+    let parent = parent.canonicalize().map_err(|e| with_path(file, e))?;
+    let path = parent.join(name);
+    // A root that is not canonical only matches once resolved itself.
+    let rel = match path.strip_prefix(root) {
+        Ok(rel) => Some(rel.to_path_buf()),
+        Err(_) => (root.canonicalize().ok())
+            .and_then(|canonical| Some(path.strip_prefix(canonical).ok()?.to_path_buf())),
+    };
+    match rel {
+        Some(rel) if !rel.as_os_str().is_empty() => Ok(root.join(rel)),
+        _ => Err(with_path(
+            file,
+            io::Error::new(io::ErrorKind::InvalidInput, "outside of the root directory"),
+        )),
+    }
+}
+
+/// The error to stop with if `e`, hit at `path` `depth` levels below the
+/// root, is an OS limit rather than a problem with the entry: a path longer
+/// than the OS allows, or no file handles left. It names the path, its
+/// length and depth, and what to do about it.
+pub(crate) fn os_limit(path: &Path, depth: usize, e: &io::Error) -> Option<io::Error> {
+    let hint = if is_name_too_long(e) {
+        "hash a subdirectory, or hash through a directory handle \
+         (get_dir_hash_at, `cap-std` feature) where paths are opened relative \
+         to their parent"
+    } else if is_out_of_handles(e) {
+        "raise the open file limit (ulimit -n), or set a maximum depth"
+    } else {
+        return None;
+    };
+    Some(io::Error::new(
+        e.kind(),
+        format!(
+            "{}: {e} (path of {} bytes, {depth} levels deep); {hint}",
+            path.display(),
+            path.as_os_str().len()
+        ),
+    ))
+}
+
+#[cfg(unix)]
+fn is_name_too_long(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::ENAMETOOLONG)
+}
+
+#[cfg(windows)]
+fn is_name_too_long(e: &io::Error) -> bool {
+    use windows_sys::Win32::Foundation::ERROR_FILENAME_EXCED_RANGE;
+    e.raw_os_error() == Some(ERROR_FILENAME_EXCED_RANGE as i32)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_name_too_long(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::InvalidFilename
+}
+
+#[cfg(unix)]
+fn is_out_of_handles(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE))
+}
+
+#[cfg(windows)]
+fn is_out_of_handles(e: &io::Error) -> bool {
+    use windows_sys::Win32::Foundation::ERROR_TOO_MANY_OPEN_FILES;
+    e.raw_os_error() == Some(ERROR_TOO_MANY_OPEN_FILES as i32)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_out_of_handles(_e: &io::Error) -> bool {
+    false
+}
+
+/// Attach the offending path to an I/O error.
+fn with_path(path: &Path, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {e}", path.display()))
+}
+
+#[cfg(feature = "parallel-walk")]
+mod prefetch {
+    //! Background directory listing.
+
+    use super::{ListOptions, Listing, list_dir};
+    use std::io;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex, mpsc};
+    use std::thread;
+
+    type Job = (PathBuf, mpsc::Sender<io::Result<Listing>>);
+
+    /// Fixed-size pool of listing threads. Dropping it stops the workers once
+    /// the queued jobs are done.
+    pub(super) struct Prefetcher {
+        jobs: mpsc::Sender<Job>,
+        lo: ListOptions,
+    }
+
+    /// A listing that has been queued (or is already done).
+    pub(super) struct Pending(mpsc::Receiver<io::Result<Listing>>);
+
+    impl Prefetcher {
+        pub(super) fn new(threads: usize, lo: ListOptions) -> Self {
+            let (jobs, rx) = mpsc::channel::<Job>();
+            let rx = Arc::new(Mutex::new(rx));
+            for _ in 0..threads {
+                let rx = Arc::clone(&rx);
+                thread::spawn(move || {
+                    loop {
+                        let job = rx.lock().map(|rx| rx.recv());
+                        let Ok(Ok((dir, reply))) = job else { break };
+                        // The walker may have stopped early; nobody to tell then.
+                        let _ = reply.send(list_dir(&dir, lo));
+                    }
+                });
+            }
+            Self { jobs, lo }
+        }
+
+        pub(super) fn submit(&self, dir: PathBuf) -> Pending {
+            let (tx, rx) = mpsc::channel();
+            if let Err(mpsc::SendError((dir, tx))) = self.jobs.send((dir, tx)) {
+                // All workers are gone; list inline instead.
+                let _ = tx.send(list_dir(&dir, self.lo));
+            }
+            Pending(rx)
+        }
+    }
+
+    impl Pending {
+        pub(super) fn wait(self) -> io::Result<Listing> {
+            self.0
+                .recv()
+                .unwrap_or_else(|_| Err(io::Error::other("directory listing worker exited")))
+        }
+    }
+}
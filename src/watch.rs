@@ -0,0 +1,329 @@
+//! Following a tree as it changes ([`watch_dir_hash`], `notify` feature).
+//!
+//! File system events are collected until the tree has been quiet for
+//! [`DEBOUNCE`], then the tree is hashed again. Files that no event touched
+//! and whose size and mtime are unchanged keep their content digest from
+//! the run before, so a rerun reads only what changed; walking and ignore
+//! rules work as for [`get_dir_hash`](crate::get_dir_hash).
+//!
+//! ```no_run
+//! use futures_util::StreamExt;
+//! use get_dir_hash::{DirHashEvent, Options, watch_dir_hash};
+//! # async fn serve() -> std::io::Result<()> {
+//! let mut digests = watch_dir_hash("assets".as_ref(), &Options::default())?;
+//! while let Some(event) = digests.next().await {
+//!     match event {
+//!         DirHashEvent::Digest(hash) => println!("{}", hash.hex),
+//!         DirHashEvent::Error(e) => eprintln!("{e}"),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    CancellationToken, Cancelled, DirHash, Options, Outputs, PathPrefix, SnapshotEntry,
+    base_prefix, framed_path, make_rel_unix, run, walk_root,
+};
+use futures_channel::mpsc::{UnboundedReceiver, UnboundedSender, unbounded};
+use futures_util::Stream;
+use notify::event::{MetadataKind, ModifyKind};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+
+/// How long the tree must be free of events before it is hashed again.
+pub const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// What a [`DirHashWatch`] yields.
+#[derive(Debug)]
+pub enum DirHashEvent {
+    /// The tree's digest: first the initial one, then a new one each time a
+    /// change alters it. Events that leave the digest as it was (an ignored
+    /// file, a touched mtime without [`Options::include_metadata`]) yield
+    /// nothing.
+    Digest(DirHash),
+    /// Hashing the tree failed, or the watcher reported an error; watching
+    /// goes on.
+    Error(io::Error),
+}
+
+/// The stream [`watch_dir_hash`] returns. Dropping it stops the watcher and
+/// cancels a run in progress (unless [`Options::cancel`] was set, in which
+/// case the run finishes first).
+pub struct DirHashWatch {
+    events: UnboundedReceiver<DirHashEvent>,
+    _watcher: RecommendedWatcher,
+    /// Our own token, cancelled on drop; `None` with [`Options::cancel`].
+    cancel: Option<CancellationToken>,
+}
+
+impl Stream for DirHashWatch {
+    type Item = DirHashEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<DirHashEvent>> {
+        Pin::new(&mut self.events).poll_next(cx)
+    }
+}
+
+impl Drop for DirHashWatch {
+    fn drop(&mut self) {
+        if let Some(cancel) = &self.cancel {
+            cancel.cancel();
+        }
+    }
+}
+
+/// Watch `root` and yield its digest with `opts`, then a new digest after
+/// every change that alters it (see [`DirHashEvent`]).
+///
+/// Hashing runs on a thread of its own, so the stream can be polled from
+/// any executor. Setting up the watch fails like opening the root does, or
+/// with the watcher's error (e.g. too many watches). Changes behind
+/// symlinks followed out of the root are not seen; cancelling
+/// [`Options::cancel`] ends the stream.
+pub fn watch_dir_hash(root: &Path, opts: &Options) -> io::Result<DirHashWatch> {
+    let root = walk_root(root, opts)?;
+    let prefix = base_prefix(&root, opts)?;
+    let (cancel, opts) = match &opts.cancel {
+        Some(_) => (None, opts.clone()),
+        None => {
+            let token = CancellationToken::new();
+            let opts = Options {
+                cancel: Some(token.clone()),
+                ..opts.clone()
+            };
+            (Some(token), opts)
+        }
+    };
+    let (changes_tx, changes) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(changes_tx).map_err(io_error)?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .map_err(io_error)?;
+    let (tx, events) = unbounded();
+    let follow = Follow {
+        root,
+        prefix,
+        opts,
+        tx,
+    };
+    thread::Builder::new()
+        .name("get_dir_hash-watch".into())
+        .spawn(move || follow.run(changes))?;
+    Ok(DirHashWatch {
+        events,
+        _watcher: watcher,
+        cancel,
+    })
+}
+
+/// The hashing side of a watch.
+struct Follow {
+    root: PathBuf,
+    prefix: Option<PathPrefix>,
+    opts: Options,
+    tx: UnboundedSender<DirHashEvent>,
+}
+
+impl Follow {
+    /// Hash, wait for changes, hash again; until the stream or the watcher
+    /// is dropped or the run is cancelled.
+    fn run(self, changes: mpsc::Receiver<notify::Result<Event>>) {
+        // Files of the last run, by framed path.
+        let mut previous: HashMap<String, SnapshotEntry> = HashMap::new();
+        let mut last: Option<String> = None;
+        loop {
+            let mut files = Vec::new();
+            let outputs = Outputs {
+                snapshot: Some(&mut files),
+                reuse: Some(&previous),
+                ..Outputs::default()
+            };
+            let event = match run(&self.root, None, &self.opts, outputs) {
+                Ok(hash) => {
+                    previous = (files.into_iter())
+                        .map(|file| (file.path.clone(), file))
+                        .collect();
+                    match last.replace(hash.hex.clone()) {
+                        Some(hex) if hex == hash.hex => None,
+                        _ => Some(DirHashEvent::Digest(hash)),
+                    }
+                }
+                Err(e) if Cancelled::find(&e).is_some() => return,
+                Err(e) => Some(DirHashEvent::Error(e)),
+            };
+            if let Some(event) = event
+                && self.tx.unbounded_send(event).is_err()
+            {
+                return;
+            }
+
+            // Wait for a change, then for the tree to settle.
+            let first = loop {
+                match changes.recv() {
+                    Ok(Ok(event)) if is_access(&event.kind) => continue,
+                    Ok(change) => break change,
+                    Err(_) => return,
+                }
+            };
+            let mut batch = vec![first];
+            loop {
+                match changes.recv_timeout(DEBOUNCE) {
+                    Ok(change) => batch.push(change),
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            let mut touched = HashSet::new();
+            for change in batch {
+                match change {
+                    Ok(event) if event.need_rescan() => previous.clear(),
+                    // Opening and reading, our own runs included.
+                    Ok(event) if is_access(&event.kind) => {}
+                    Ok(event) => {
+                        for path in &event.paths {
+                            match self.key(path) {
+                                Some(key) if !key.is_empty() => {
+                                    touched.insert(key);
+                                }
+                                _ => previous.clear(),
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        if self
+                            .tx
+                            .unbounded_send(DirHashEvent::Error(io_error(e)))
+                            .is_err()
+                        {
+                            return;
+                        }
+                    }
+                }
+            }
+            // A touched path may be a directory: forget everything below it.
+            previous.retain(|path, _| {
+                !touched.contains(path)
+                    && !(path.match_indices('/')).any(|(end, _)| touched.contains(&path[..end]))
+            });
+        }
+    }
+
+    /// The framed path of the file at `path`, as in [`SnapshotEntry::path`];
+    /// `None` outside the root.
+    fn key(&self, path: &Path) -> Option<String> {
+        let rel = make_rel_unix(&self.root, path)?;
+        let rel = match self.opts.unicode_normalization {
+            Some(form) => form.apply(&rel),
+            None => rel,
+        };
+        let (rel, raw) = match &self.prefix {
+            Some(prefix) => prefix.join(rel, None),
+            None => (rel, None),
+        };
+        Some(String::from_utf8_lossy(&framed_path(rel, raw, &self.opts)).into_owned())
+    }
+}
+
+/// Whether an event only reports that something was read.
+fn is_access(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(MetadataKind::AccessTime))
+    )
+}
+
+/// `notify`'s error as an `io::Error`, keeping the underlying one.
+fn io_error(e: notify::Error) -> io::Error {
+    let kind = match e.kind {
+        notify::ErrorKind::Io(e) => return e,
+        notify::ErrorKind::PathNotFound => io::ErrorKind::NotFound,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_dir_hash;
+    use std::fs;
+    use std::task::Waker;
+    use std::time::Instant;
+
+    /// The next event, polled from this thread; panics after ten seconds.
+    fn next(watch: &mut DirHashWatch) -> Option<DirHashEvent> {
+        let mut cx = Context::from_waker(Waker::noop());
+        let started = Instant::now();
+        loop {
+            if let Poll::Ready(event) = Pin::new(&mut *watch).poll_next(&mut cx) {
+                return event;
+            }
+            assert!(started.elapsed() < Duration::from_secs(10), "no event");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn next_digest(watch: &mut DirHashWatch) -> String {
+        match next(watch) {
+            Some(DirHashEvent::Digest(hash)) => hash.hex,
+            event => panic!("expected a digest, got {event:?}"),
+        }
+    }
+
+    #[test]
+    fn yields_a_digest_per_change() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path();
+        fs::create_dir(path.join("sub")).unwrap();
+        fs::write(path.join("a"), "one").unwrap();
+        fs::write(path.join("sub/b"), "two").unwrap();
+        let opts = Options {
+            ignore_patterns: vec!["*.tmp".into()],
+            ..Options::default()
+        };
+        let mut watch = watch_dir_hash(path, &opts).unwrap();
+        assert_eq!(next_digest(&mut watch), get_dir_hash(path, &opts).unwrap());
+
+        // Same size: the event, not the size or mtime, has it read again.
+        fs::write(path.join("sub/b"), "TWO").unwrap();
+        assert_eq!(next_digest(&mut watch), get_dir_hash(path, &opts).unwrap());
+
+        // An ignored file leaves the digest as it was: nothing is yielded
+        // for it, so the next digest is the one after the removal.
+        fs::write(path.join("scratch.tmp"), "ignored").unwrap();
+        thread::sleep(4 * DEBOUNCE);
+        fs::remove_dir_all(path.join("sub")).unwrap();
+        assert_eq!(next_digest(&mut watch), get_dir_hash(path, &opts).unwrap());
+    }
+
+    #[test]
+    fn cancelling_ends_the_stream() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("a"), "one").unwrap();
+        let cancel = CancellationToken::new();
+        let opts = Options {
+            cancel: Some(cancel.clone()),
+            ..Options::default()
+        };
+        let mut watch = watch_dir_hash(root.path(), &opts).unwrap();
+        next_digest(&mut watch);
+        cancel.cancel();
+        fs::write(root.path().join("a"), "two").unwrap();
+        assert!(next(&mut watch).is_none());
+    }
+
+    #[test]
+    fn missing_root_fails_up_front() {
+        let root = tempfile::tempdir().unwrap();
+        let err = watch_dir_hash(&root.path().join("missing"), &Options::default()).err();
+        assert_eq!(err.map(|e| e.kind()), Some(io::ErrorKind::NotFound));
+    }
+}
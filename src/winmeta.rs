@@ -0,0 +1,133 @@
+//! Windows-only parts of the metadata frame: file attributes and the DACL.
+//!
+//! Attributes are framed as `\0A\0` + the readonly, hidden, system and
+//! archive bits (u32 LE). The DACL is framed as `\0D\0` + its SDDL string
+//! (`D:...`, UTF-8) + `\0`; SDDL lists the ACEs in ACL order, so two files
+//! with the same effective DACL frame the same bytes. Nothing is framed on
+//! other platforms.
+
+use crate::Options;
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+
+/// Append the Windows records requested in `opts` for the file at `path`.
+#[cfg(windows)]
+pub(crate) fn feed_windows(
+    out: &mut Vec<u8>,
+    path: &Path,
+    md: &Metadata,
+    opts: &Options,
+) -> io::Result<()> {
+    use std::os::windows::fs::MetadataExt;
+
+    /// FILE_ATTRIBUTE_{READONLY, HIDDEN, SYSTEM, ARCHIVE}.
+    const ATTRIBUTES: u32 = 0x1 | 0x2 | 0x4 | 0x20;
+
+    if opts.windows_attributes {
+        out.extend_from_slice(b"\0A\0");
+        out.extend_from_slice(&(md.file_attributes() & ATTRIBUTES).to_le_bytes());
+    }
+    if opts.windows_acl {
+        out.extend_from_slice(b"\0D\0");
+        out.extend_from_slice(dacl::sddl(path)?.as_bytes());
+        out.extend_from_slice(b"\0");
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+pub(crate) fn feed_windows(
+    _out: &mut Vec<u8>,
+    _path: &Path,
+    _md: &Metadata,
+    _opts: &Options,
+) -> io::Result<()> {
+    Ok(())
+}
+
+/// Whether the entry has the hidden attribute (`Options::skip_hidden`);
+/// never on other platforms.
+#[cfg(windows)]
+pub(crate) fn is_hidden(md: &Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    /// FILE_ATTRIBUTE_HIDDEN.
+    const HIDDEN: u32 = 0x2;
+
+    md.file_attributes() & HIDDEN != 0
+}
+
+#[cfg(not(windows))]
+pub(crate) fn is_hidden(_md: &Metadata) -> bool {
+    false
+}
+
+#[cfg(windows)]
+mod dacl {
+    //! DACL lookup through the security descriptor APIs.
+
+    use std::ffi::c_void;
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+    use windows_sys::Win32::Foundation::{ERROR_SUCCESS, LocalFree};
+    use windows_sys::Win32::Security::Authorization::{
+        ConvertSecurityDescriptorToStringSecurityDescriptorW, GetNamedSecurityInfoW,
+        SDDL_REVISION_1, SE_FILE_OBJECT,
+    };
+    use windows_sys::Win32::Security::{DACL_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR};
+
+    /// The DACL of `path` as an SDDL string.
+    pub(super) fn sddl(path: &Path) -> io::Result<String> {
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let mut sd: PSECURITY_DESCRIPTOR = ptr::null_mut();
+        // SAFETY: `wide` is NUL-terminated; on success `sd` is allocated by
+        // the system and released with LocalFree below.
+        let rc = unsafe {
+            GetNamedSecurityInfoW(
+                wide.as_ptr(),
+                SE_FILE_OBJECT,
+                DACL_SECURITY_INFORMATION,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut sd,
+            )
+        };
+        if rc != ERROR_SUCCESS {
+            return Err(io::Error::from_raw_os_error(rc as i32));
+        }
+
+        let mut s = ptr::null_mut();
+        let mut len = 0u32;
+        // SAFETY: `sd` is a valid descriptor; `s` receives a LocalAlloc'd,
+        // NUL-terminated wide string of `len` characters.
+        let ok = unsafe {
+            ConvertSecurityDescriptorToStringSecurityDescriptorW(
+                sd,
+                SDDL_REVISION_1,
+                DACL_SECURITY_INFORMATION,
+                &mut s,
+                &mut len,
+            )
+        };
+        let res = if ok == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            // `len` includes the terminating NUL.
+            let chars = unsafe { std::slice::from_raw_parts(s, len.saturating_sub(1) as usize) };
+            Ok(String::from_utf16_lossy(chars))
+        };
+        // SAFETY: both buffers were allocated by the calls above.
+        unsafe {
+            if !s.is_null() {
+                LocalFree(s as *mut c_void);
+            }
+            LocalFree(sd);
+        }
+        res
+    }
+}